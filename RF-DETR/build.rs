@@ -1,13 +1,18 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let proto_dir = Path::new("proto");
     let proto_file = proto_dir.join("result.proto");
-    tonic_build::compile_protos(proto_file).map_err(|e| {
-        eprintln!("Failed to compile protos: {}", e);
-        e
-    })?;
+    // descriptors of the proto, served by gRPC reflection
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR")?).join("grpc_descriptor.bin");
+    tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
+        .compile(&[proto_file], &[proto_dir])
+        .map_err(|e| {
+            eprintln!("Failed to compile protos: {}", e);
+            e
+        })?;
     Ok(())
 }
\ No newline at end of file
@@ -1,37 +1,93 @@
 use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::nms::NmsStrategy;
 
 
 #[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// ONNX model path
-    #[arg(long, required = true)]
+    /// TOML config file, its values apply where neither a flag nor an env var is set
+    #[arg(long, env = "RFDETR_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// address the gRPC server listens on
+    #[arg(long, env = "RFDETR_BIND", default_value = "[::1]:50052")]
+    pub bind: SocketAddr,
+
+    /// address of the Prometheus `/metrics` endpoint, disabled if unset
+    #[arg(long, env = "RFDETR_METRICS_BIND")]
+    pub metrics_bind: Option<SocketAddr>,
+
+    /// address of the HTTP/JSON gateway, disabled if unset
+    #[arg(long, env = "RFDETR_HTTP_BIND")]
+    pub http_bind: Option<SocketAddr>,
+
+    /// ONNX model path, required here or in the config file
+    #[arg(long, env = "RFDETR_MODEL", default_value = "", hide_default_value = true)]
     pub model: String,
 
+    /// model name used as the `model` label of the metrics
+    #[arg(long, env = "RFDETR_NAME", default_value = "rf-detr")]
+    pub name: String,
+
+    /// class names, one per line starting at class id 1
+    #[arg(long, env = "RFDETR_LABELS")]
+    pub labels: Option<String>,
+
+    /// poll the model file every N ms and reload it when changed, 0 disables
+    #[arg(long, env = "RFDETR_WATCH_MS", default_value_t = 0)]
+    pub watch_ms: u64,
+
     /// using CUDA EP
-    #[arg(long)]
+    #[arg(long, env = "RFDETR_CUDA")]
     pub cuda: bool,
 
-    #[arg(long)]
+    /// number of ORT sessions serving requests in parallel
+    #[arg(long, env = "RFDETR_SESSIONS", default_value_t = 1)]
+    pub sessions: usize,
+
+    /// intra-op threads per ORT session (ORT default if unset)
+    #[arg(long, env = "RFDETR_INTRA_THREADS")]
+    pub intra_threads: Option<usize>,
+
+    #[arg(long, env = "RFDETR_PROFILE")]
     pub profile: bool,
 
-    #[arg(long)]
+    #[arg(long, env = "RFDETR_DEEP_PROFILE")]
     pub deep_profile: bool,
 
-    #[arg(long, default_value_t = String::from(r"output\"))]
+    /// OTLP/gRPC collector receiving trace spans, e.g. `http://localhost:4317`
+    #[arg(long, env = "RFDETR_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    #[arg(long, env = "RFDETR_OUTPUT", default_value_t = String::from(r"output\"))]
     pub output: String,
 
-    #[arg(long, default_value_t = 560)]
+    #[arg(long, env = "RFDETR_IMG_W", default_value_t = 560)]
     pub img_w: usize,
 
-    #[arg(long, default_value_t = 560)]
+    #[arg(long, env = "RFDETR_IMG_H", default_value_t = 560)]
     pub img_h: usize,
     
-    #[arg(long, default_value_t = 0.5)]
+    #[arg(long, env = "RFDETR_CONF_TH", default_value_t = 0.5)]
     pub conf_th: f32,
     
-    #[arg(long, default_value_t = 0.25)]
+    #[arg(long, env = "RFDETR_IOU_TH", default_value_t = 0.25)]
     pub iou_th: f32,
+
+    /// NMS strategy
+    #[arg(long, env = "RFDETR_NMS", value_enum, default_value_t = NmsStrategy::ClassAware)]
+    pub nms: NmsStrategy,
+
+    /// score decay of gaussian Soft-NMS
+    #[arg(long, env = "RFDETR_NMS_SIGMA", default_value_t = 0.5)]
+    pub nms_sigma: f32,
+
+    /// max detections kept per image after NMS, 0 keeps all
+    #[arg(long, env = "RFDETR_MAX_DET", default_value_t = 300)]
+    pub max_det: usize,
     
     #[arg(skip = 3)]
     pub ch: i32,
@@ -0,0 +1,254 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches, ValueEnum};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::error::VisionError;
+use crate::nms::NmsStrategy;
+
+/// Server settings from a TOML file, every key is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSection,
+    pub model: ModelSection,
+    pub device: DeviceSection,
+    pub thresholds: ThresholdsSection,
+    pub nms: NmsSection,
+    pub logging: LoggingSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind: Option<SocketAddr>,
+    pub metrics_bind: Option<SocketAddr>,
+    pub http_bind: Option<SocketAddr>,
+    pub sessions: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelSection {
+    pub path: Option<String>,
+    pub name: Option<String>,
+    pub labels: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub watch_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceSection {
+    /// Execution provider: cpu or cuda.
+    pub ep: Option<String>,
+    pub intra_threads: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThresholdsSection {
+    pub conf: Option<f32>,
+    pub iou: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NmsSection {
+    pub strategy: Option<String>,
+    pub sigma: Option<f32>,
+    pub max_det: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub profile: Option<bool>,
+    pub deep_profile: Option<bool>,
+    pub otlp_endpoint: Option<String>,
+    pub output: Option<String>,
+}
+
+/// Sets `$args.$field` to a config file value, if any, unless `$matches` took the
+/// argument from a flag or an env var.
+#[macro_export]
+macro_rules! set_arg {
+    ($args:ident, $matches:ident, $field:ident, $value:expr) => {
+        if let Some(value) = $value {
+            if $crate::config::unset($matches, stringify!($field)) {
+                $args.$field = value;
+            }
+        }
+    };
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, VisionError> {
+        read_toml(path)
+    }
+
+    /// Sets every argument of `args` that `matches` did not take from a flag or an env var.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<(), VisionError> {
+        macro_rules! set {
+            ($field:ident, $value:expr) => {
+                crate::set_arg!(args, matches, $field, $value)
+            };
+        }
+
+        let Config {
+            server,
+            model,
+            device,
+            thresholds,
+            nms,
+            logging,
+        } = self;
+
+        set!(bind, server.bind);
+        set!(metrics_bind, server.metrics_bind.map(Some));
+        set!(http_bind, server.http_bind.map(Some));
+        set!(sessions, server.sessions);
+
+        set!(model, model.path);
+        set!(name, model.name);
+        set!(labels, model.labels.map(Some));
+        set!(img_w, model.width);
+        set!(img_h, model.height);
+        set!(watch_ms, model.watch_ms);
+
+        let cuda = match device.ep.map(|ep| ep.to_lowercase()).as_deref() {
+            None => None,
+            Some("cpu") => Some(false),
+            Some("cuda") => Some(true),
+            Some(x) => {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Invalid `device.ep`: {}, expected cpu or cuda",
+                    x
+                )));
+            }
+        };
+        set!(cuda, cuda);
+        set!(intra_threads, device.intra_threads.map(Some));
+
+        set!(conf_th, thresholds.conf);
+        set!(iou_th, thresholds.iou);
+
+        set!(nms, parse_enum::<NmsStrategy>("nms.strategy", nms.strategy)?);
+        set!(nms_sigma, nms.sigma);
+        set!(max_det, nms.max_det);
+
+        set!(profile, logging.profile);
+        set!(deep_profile, logging.deep_profile);
+        set!(otlp_endpoint, logging.otlp_endpoint.map(Some));
+        set!(output, logging.output);
+        Ok(())
+    }
+}
+
+impl LoadArgs for Args {
+    type Error = VisionError;
+
+    fn load_config(mut self, matches: &ArgMatches) -> Result<Self, VisionError> {
+        if let Some(path) = self.config.clone() {
+            Config::from_file(&path)?.apply(&mut self, matches)?;
+        }
+        if self.model.is_empty() {
+            return Err(VisionError::InvalidArgument(
+                "No model, set `--model`, `RFDETR_MODEL` or `model.path` in the config file".to_string(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+/// Command line arguments completed by a `--config` file.
+pub trait LoadArgs: CommandFactory + FromArgMatches + Sized {
+    type Error: From<VisionError>;
+
+    /// Applies the `--config` file where `matches` has neither a flag nor an env var,
+    /// then checks the result.
+    fn load_config(self, matches: &ArgMatches) -> Result<Self, Self::Error>;
+
+    /// The command parsing the arguments.
+    fn cli() -> Command {
+        Self::command()
+    }
+
+    /// Parses the command line and environment, then the `--config` file.
+    /// Precedence: flags, then env vars, then the file, then defaults.
+    fn load() -> Result<Self, Self::Error> {
+        Self::load_from(std::env::args_os())
+    }
+
+    fn load_from<I, T>(itr: I) -> Result<Self, Self::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::cli().get_matches_from(itr);
+        let args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        args.load_config(&matches)
+    }
+
+    /// Same as `load_from`, but returns bad arguments as errors instead of exiting.
+    fn try_load_from<I, T>(itr: I) -> Result<Self, Self::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        try_load_with(Self::cli(), itr)
+    }
+
+    /// Same as `try_load_from`, but without env vars: only the flags and the `--config` file
+    /// apply, so the settings of the running process do not leak into e.g. registry entries.
+    fn try_load_without_env<I, T>(itr: I) -> Result<Self, Self::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        try_load_with(Self::cli().mut_args(|arg| arg.env(None)), itr)
+    }
+}
+
+fn try_load_with<A, I, T>(cli: Command, itr: I) -> Result<A, A::Error>
+where
+    A: LoadArgs,
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let invalid = |e: clap::Error| VisionError::InvalidArgument(e.to_string());
+    let matches = cli.try_get_matches_from(itr).map_err(invalid)?;
+    let args = A::from_arg_matches(&matches).map_err(invalid)?;
+    args.load_config(&matches)
+}
+
+/// Whether `matches` took the argument `id` from neither a flag nor an env var.
+pub fn unset(matches: &ArgMatches, id: &str) -> bool {
+    !matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// Reads a TOML config file.
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<T, VisionError> {
+    let text = std::fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| {
+        VisionError::InvalidArgument(format!("Invalid config file {}: {}", path.display(), e))
+    })
+}
+
+/// Parses an enum with the same spelling as the command line.
+pub fn parse_enum<T: ValueEnum>(key: &str, value: Option<String>) -> Result<Option<T>, VisionError> {
+    value
+        .map(|value| {
+            T::from_str(&value, true)
+                .map_err(|e| VisionError::InvalidArgument(format!("Invalid `{}`: {}", key, e)))
+        })
+        .transpose()
+}
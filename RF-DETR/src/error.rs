@@ -0,0 +1,70 @@
+use tonic::Status;
+
+/// Errors of model loading, inference and the gRPC service.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VisionError {
+    /// The request is malformed: undecodable image, out-of-range option.
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    /// The model or its outputs do not match what the server expects.
+    #[error("Model error: {0}")]
+    Model(String),
+    /// A pixel type or tensor layout this crate can not handle.
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    /// The model is still loading, retry later.
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+    /// Out of memory while running, a smaller load may succeed.
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+    #[error("ONNXRuntime error: {0}")]
+    Ort(String),
+    #[error("IO error: {0}")]
+    Io(String),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl From<ort::Error> for VisionError {
+    fn from(e: ort::Error) -> Self {
+        // Allocation failures, e.g. CUDA OOM under load.
+        let msg = e.message().to_lowercase();
+        if msg.contains("failed to allocate") || msg.contains("out of memory") {
+            VisionError::ResourceExhausted(e.to_string())
+        } else {
+            VisionError::Ort(e.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for VisionError {
+    fn from(e: std::io::Error) -> Self {
+        VisionError::Io(e.to_string())
+    }
+}
+
+impl From<ndarray::ShapeError> for VisionError {
+    fn from(e: ndarray::ShapeError) -> Self {
+        VisionError::Model(format!("Unexpected tensor shape: {}", e))
+    }
+}
+
+impl From<fast_image_resize::ResizeError> for VisionError {
+    fn from(e: fast_image_resize::ResizeError) -> Self {
+        VisionError::Internal(format!("Resize failed: {}", e))
+    }
+}
+
+impl From<VisionError> for Status {
+    fn from(e: VisionError) -> Self {
+        let msg = e.to_string();
+        match e {
+            VisionError::InvalidArgument(_) => Status::invalid_argument(msg),
+            VisionError::Model(_) | VisionError::Unsupported(_) => Status::failed_precondition(msg),
+            VisionError::Unavailable(_) => Status::unavailable(msg),
+            VisionError::ResourceExhausted(_) => Status::resource_exhausted(msg),
+            VisionError::Ort(_) | VisionError::Io(_) | VisionError::Internal(_) => Status::internal(msg),
+        }
+    }
+}
@@ -1 +1,4 @@
 tonic::include_proto!("grpc");
+
+/// Encoded descriptors of the `grpc` package, served by gRPC reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("grpc_descriptor");
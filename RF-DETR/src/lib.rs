@@ -1,15 +1,33 @@
+#![allow(non_snake_case)]
+#![allow(clippy::type_complexity)]
+
 pub mod grpc;
 pub mod preprocess;
 pub mod model;
 pub mod cli;
+pub mod config;
 pub mod mapping;
 pub mod postprocess;
 pub mod service;
+pub mod pool;
+pub mod nms;
+pub mod error;
+pub mod reload;
+pub mod metrics;
+pub mod telemetry;
+pub mod rest;
 
 pub use crate::model::OnnxModel;
 pub use crate::grpc::{ImageRequest, DetectionResponse};
 pub use crate::preprocess::PreProcessor;
 pub use crate::cli::Args;
+pub use crate::config::{Config, LoadArgs};
 pub use crate::mapping::load_class_mapping;
 pub use crate::postprocess::PostProcessor;
-pub use crate::service::MyImageProcessor;
\ No newline at end of file
+pub use crate::service::{LazyImageProcessor, MyImageProcessor};
+pub use crate::pool::SessionPool;
+pub use crate::nms::{Nms, NmsItem, NmsStrategy};
+pub use crate::error::VisionError;
+pub use crate::reload::Watcher;
+pub use crate::metrics::{Metrics, METRICS};
+pub use crate::telemetry::Telemetry;
\ No newline at end of file
@@ -1,32 +1,79 @@
-use clap::Parser;
+use std::sync::Arc;
 use tonic::transport::Server;
-use RF_DETR::service::MyImageProcessor;
-use RF_DETR::{PreProcessor, PostProcessor, OnnxModel, Args};
+use tonic_health::server::health_reporter;
+use tonic_health::ServingStatus;
+use tracing::error;
+use RF_DETR::service::{LazyImageProcessor, MyImageProcessor};
+use RF_DETR::{Args, LoadArgs};
+use RF_DETR::grpc::FILE_DESCRIPTOR_SET;
 use RF_DETR::grpc::image_processor_server::ImageProcessorServer;
+use RF_DETR::telemetry;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Load the command line arguments
-    let args = Args::parse();
+    // Load the command line arguments, env vars and the config file
+    let args = Args::load()?;
+    // Log lines and trace spans, flushed to the OTLP collector on exit
+    let _telemetry = telemetry::init("rf-detr", args.otlp_endpoint.as_deref())?;
     // Define gRPC server address
-    let addr = "[::1]:50051".parse()?;
-    // Load the model, preprocessors, and postprocessors
-    let model = OnnxModel::new(args.clone().cuda).load_model(&args.model)?;
-    let preprocessor = PreProcessor::new(args.clone());
-    let postprocessor = PostProcessor::new(args.clone());
-    
-    let server = ImageProcessorServer::new(
-        MyImageProcessor::new(
-            model,
-            preprocessor,
-            postprocessor,
-            args)
-        );
-    println!("RF-DETR Object Detection server listening on {}", addr);
-    Server::builder()
-    .add_service(server)
-    .serve(addr)
-    .await?;
+    let addr = args.bind;
+
+    // Health reports NOT_SERVING until the model is loaded and warmed up, the server as a
+    // whole (the empty service name) too
+    let (mut health, health_service) = health_reporter();
+    health.set_service_status("", ServingStatus::NotServing).await;
+    health.set_not_serving::<ImageProcessorServer<LazyImageProcessor>>().await;
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
+    let processor = LazyImageProcessor::new();
+
+    let serve = async {
+        println!("RF-DETR Object Detection server listening on {}", addr);
+        Server::builder()
+            .trace_fn(telemetry::grpc_span)
+            .add_service(health_service)
+            .add_service(reflection)
+            .add_service(ImageProcessorServer::new(processor.clone()))
+            .serve(addr)
+            .await?;
+        Ok::<_, Box<dyn std::error::Error>>(())
+    };
+
+    // Prometheus metrics on their own HTTP port, if enabled
+    if let Some(metrics_bind) = args.metrics_bind {
+        tokio::spawn(async move {
+            if let Err(e) = RF_DETR::metrics::serve(metrics_bind).await {
+                error!(error = %e, "Metrics endpoint failed");
+            }
+        });
+    }
+
+    // JSON inference over HTTP on its own port, if enabled
+    if let Some(http_bind) = args.http_bind {
+        let router = RF_DETR::rest::router(processor.clone());
+        tokio::spawn(async move {
+            if let Err(e) = RF_DETR::rest::serve(http_bind, router).await {
+                error!(error = %e, "HTTP gateway failed");
+            }
+        });
+    }
+
+    // Load the model sessions, preprocessors, and postprocessors meanwhile
+    let load = async {
+        let loaded = Arc::new(tokio::task::spawn_blocking(move || MyImageProcessor::load(args)).await??);
+        loaded.warmup().await?;
+        // Reload the model when its file changes, kept alive while serving
+        let watcher = loaded.watch();
+        processor.set(loaded);
+        health.set_serving::<ImageProcessorServer<LazyImageProcessor>>().await;
+        health.set_service_status("", ServingStatus::Serving).await;
+        Ok::<_, Box<dyn std::error::Error>>(watcher)
+    };
+
+    // A model failing to load stops the server
+    let ((), _watcher) = tokio::try_join!(serve, load)?;
 
     Ok(())
 }
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
-pub fn load_class_mapping(file_path: &str) -> Result<HashMap<usize, String>, Box<dyn std::error::Error>> {
+use crate::error::VisionError;
+
+pub fn load_class_mapping(file_path: &str) -> Result<HashMap<usize, String>, VisionError> {
     let file = File::open(file_path)?;
     let reader = BufReader::new(file);
     
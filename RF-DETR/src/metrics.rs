@@ -0,0 +1,165 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder, exponential_buckets,
+    histogram_opts, opts,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tonic::Status;
+use tracing::info;
+
+use crate::error::VisionError;
+
+/// Metrics of every model served by the process, in the default Prometheus registry.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::register);
+
+/// Prometheus metrics, all labelled by model name.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Latency of the decode, preprocess, inference and postprocess stages.
+    pub stage_seconds: HistogramVec,
+    /// Finished calls by method and gRPC status code.
+    pub requests: IntCounterVec,
+    /// Images per engine run.
+    pub batch_size: HistogramVec,
+    /// Detections returned per image.
+    pub detections: HistogramVec,
+    /// Images waiting for an inference session.
+    pub queue_depth: IntGaugeVec,
+}
+
+impl Metrics {
+    fn register() -> Self {
+        let stage_seconds = HistogramVec::new(
+            histogram_opts!(
+                "vision_stage_seconds",
+                "Latency of an inference stage",
+                exponential_buckets(0.0005, 2.0, 16).unwrap()
+            ),
+            &["model", "stage"],
+        )
+        .unwrap();
+        let requests = IntCounterVec::new(
+            opts!(
+                "vision_requests_total",
+                "Finished calls by gRPC status code"
+            ),
+            &["model", "method", "code"],
+        )
+        .unwrap();
+        let batch_size = HistogramVec::new(
+            histogram_opts!(
+                "vision_batch_size",
+                "Images per engine run",
+                exponential_buckets(1.0, 2.0, 8).unwrap()
+            ),
+            &["model"],
+        )
+        .unwrap();
+        let detections = HistogramVec::new(
+            histogram_opts!(
+                "vision_detections_per_image",
+                "Detections returned per image",
+                vec![0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 300.0]
+            ),
+            &["model"],
+        )
+        .unwrap();
+        let queue_depth = IntGaugeVec::new(
+            opts!(
+                "vision_queue_depth",
+                "Images waiting for an inference session"
+            ),
+            &["model"],
+        )
+        .unwrap();
+
+        let registry = prometheus::default_registry();
+        registry.register(Box::new(stage_seconds.clone())).unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(batch_size.clone())).unwrap();
+        registry.register(Box::new(detections.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        Self {
+            stage_seconds,
+            requests,
+            batch_size,
+            detections,
+            queue_depth,
+        }
+    }
+
+    pub fn observe_stage(&self, model: &str, stage: &str, elapsed: Duration) {
+        self.stage_seconds
+            .with_label_values(&[model, stage])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a finished call under the status code of its result.
+    pub fn observe_request<T>(&self, model: &str, method: &str, result: &Result<T, Status>) {
+        let code = match result {
+            Ok(_) => tonic::Code::Ok,
+            Err(status) => status.code(),
+        };
+        self.requests
+            .with_label_values(&[model, method, &format!("{:?}", code)])
+            .inc();
+    }
+
+    pub fn observe_batch(&self, model: &str, size: usize) {
+        self.batch_size
+            .with_label_values(&[model])
+            .observe(size as f64);
+    }
+
+    pub fn observe_detections(&self, model: &str, count: usize) {
+        self.detections
+            .with_label_values(&[model])
+            .observe(count as f64);
+    }
+}
+
+/// Metrics of the process in the Prometheus text format.
+pub fn gather() -> Result<String, VisionError> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| VisionError::Internal(format!("Failed to encode metrics: {}", e)))?;
+    String::from_utf8(buffer).map_err(|e| VisionError::Internal(e.to_string()))
+}
+
+/// Serves `GET /metrics` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr) -> Result<(), VisionError> {
+    // registered up front, so families show before the first request
+    LazyLock::force(&METRICS);
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    info!(addr = %addr, "Metrics endpoint listening on /metrics");
+    hyper::Server::try_bind(&addr)
+        .map_err(|e| VisionError::Io(format!("Failed to bind {}: {}", addr, e)))?
+        .serve(make_service)
+        .await
+        .map_err(|e| VisionError::Io(e.to_string()))
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => match gather() {
+            Ok(text) => Response::builder()
+                .header(
+                    hyper::header::CONTENT_TYPE,
+                    TextEncoder::new().format_type(),
+                )
+                .body(Body::from(text)),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e.to_string())),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap_or_default())
+}
@@ -1,25 +1,31 @@
 use ort::session::builder::SessionBuilder;
 use ort::execution_providers::{CPUExecutionProvider, CUDAExecutionProvider};
 
-pub struct OnnxModel {
-    provider: [ort::execution_providers::ExecutionProviderDispatch; 1]
+use crate::error::VisionError;
 
+pub struct OnnxModel {
+    provider: [ort::execution_providers::ExecutionProviderDispatch; 1],
+    intra_threads: Option<usize>,
 }
 impl OnnxModel {
-    pub fn new(cuda: bool) -> Self {
+    pub fn new(cuda: bool, intra_threads: Option<usize>) -> Self {
         let provider = if cuda {
             [CUDAExecutionProvider::default().build().error_on_failure()]
         } else {
             [CPUExecutionProvider::default().build()]
         };
         Self {
-            provider
+            provider,
+            intra_threads,
         }
     }
-    pub fn load_model(&self, model_path: &str) -> Result<ort::session::Session, Box<dyn std::error::Error>> {
-        let session = SessionBuilder::new()?
-            .with_execution_providers(self.provider.clone())?
-            // .with_intra_threads(4)?
+    pub fn load_model(&self, model_path: &str) -> Result<ort::session::Session, VisionError> {
+        let mut builder = SessionBuilder::new()?
+            .with_execution_providers(self.provider.clone())?;
+        if let Some(intra_threads) = self.intra_threads {
+            builder = builder.with_intra_threads(intra_threads)?;
+        }
+        let session = builder
             .with_optimization_level(ort::session::builder::GraphOptimizationLevel::Level3)?
             .commit_from_file(model_path)?;
         Ok(session)
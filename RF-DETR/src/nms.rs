@@ -0,0 +1,474 @@
+use clap::ValueEnum;
+use ndarray::Array1;
+
+use crate::error::VisionError;
+use crate::grpc;
+
+/// NMS strategy applied to the filtered detections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum NmsStrategy {
+    /// Greedy NMS across all classes.
+    #[default]
+    Agnostic,
+    /// Greedy NMS within each class.
+    ClassAware,
+    /// Soft-NMS, scores decay by exp(-iou^2 / sigma).
+    SoftGaussian,
+    /// Soft-NMS, scores above the IoU threshold decay by (1 - iou).
+    SoftLinear,
+    /// Greedy NMS on distance-IoU within each class, keeps close but separate objects.
+    Diou,
+    /// Weighted boxes fusion, overlapping boxes are averaged instead of dropped.
+    Wbf,
+}
+
+impl NmsStrategy {
+    /// Converts a proto `NmsStrategy` value, `NMS_DEFAULT` keeps `default`.
+    pub fn from_proto(strategy: i32, default: NmsStrategy) -> Result<NmsStrategy, VisionError> {
+        match grpc::NmsStrategy::from_i32(strategy) {
+            Some(grpc::NmsStrategy::NmsDefault) => Ok(default),
+            Some(grpc::NmsStrategy::NmsAgnostic) => Ok(NmsStrategy::Agnostic),
+            Some(grpc::NmsStrategy::NmsClassAware) => Ok(NmsStrategy::ClassAware),
+            Some(grpc::NmsStrategy::NmsSoftGaussian) => Ok(NmsStrategy::SoftGaussian),
+            Some(grpc::NmsStrategy::NmsSoftLinear) => Ok(NmsStrategy::SoftLinear),
+            Some(grpc::NmsStrategy::NmsDiou) => Ok(NmsStrategy::Diou),
+            Some(grpc::NmsStrategy::NmsWbf) => Ok(NmsStrategy::Wbf),
+            None => Err(VisionError::InvalidArgument(format!(
+                "Unknown NMS strategy: {}",
+                strategy
+            ))),
+        }
+    }
+}
+
+/// NMS settings of the server, or of a single request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nms {
+    pub strategy: NmsStrategy,
+    pub iou: f32,
+    pub sigma: f32,
+    /// Max detections kept, 0 keeps all.
+    pub max_det: usize,
+}
+
+impl Default for Nms {
+    fn default() -> Self {
+        Self {
+            strategy: NmsStrategy::Agnostic,
+            iou: 0.45,
+            sigma: 0.5,
+            max_det: 300,
+        }
+    }
+}
+
+/// A detection that NMS can rank, compare and rewrite.
+pub trait NmsItem {
+    fn confidence(&self) -> f32;
+    fn set_confidence(&mut self, confidence: f32);
+    fn class_id(&self) -> i64;
+    /// Box corners (xmin, ymin, xmax, ymax).
+    fn corners(&self) -> [f32; 4];
+    fn set_corners(&mut self, corners: [f32; 4]);
+
+    /// Overlap of two detections, the IoU of their corners unless overridden.
+    fn iou(&self, other: &Self) -> f32
+    where
+        Self: Sized,
+    {
+        corners_iou(self.corners(), other.corners())
+    }
+}
+
+/// A detection with extra data (keypoints, mask coefficients...) that follows its box.
+impl<T: NmsItem, A, B> NmsItem for (T, A, B) {
+    fn confidence(&self) -> f32 {
+        self.0.confidence()
+    }
+
+    fn set_confidence(&mut self, confidence: f32) {
+        self.0.set_confidence(confidence);
+    }
+
+    fn class_id(&self) -> i64 {
+        self.0.class_id()
+    }
+
+    fn corners(&self) -> [f32; 4] {
+        self.0.corners()
+    }
+
+    fn set_corners(&mut self, corners: [f32; 4]) {
+        self.0.set_corners(corners);
+    }
+
+    fn iou(&self, other: &Self) -> f32 {
+        self.0.iou(&other.0)
+    }
+}
+
+/// A detection with its box in normalized center format (cx, cy, w, h).
+#[derive(Debug, Clone)]
+struct Detection {
+    conf: f32,
+    class_id: i32,
+    bbox: Array1<f32>,
+}
+
+impl NmsItem for Detection {
+    fn confidence(&self) -> f32 {
+        self.conf
+    }
+
+    fn set_confidence(&mut self, confidence: f32) {
+        self.conf = confidence;
+    }
+
+    fn class_id(&self) -> i64 {
+        self.class_id as i64
+    }
+
+    fn corners(&self) -> [f32; 4] {
+        corners_of(&self.bbox)
+    }
+
+    fn set_corners(&mut self, [x1, y1, x2, y2]: [f32; 4]) {
+        self.bbox = Array1::from(vec![(x1 + x2) / 2.0, (y1 + y2) / 2.0, x2 - x1, y2 - y1]);
+    }
+}
+
+impl Nms {
+    /// Applies the NMS overrides of a request on top of these settings.
+    pub fn merge(&self, config: &grpc::NmsConfig) -> Result<Nms, VisionError> {
+        let mut nms = *self;
+        nms.strategy = NmsStrategy::from_proto(config.strategy, self.strategy)?;
+        if let Some(iou) = config.iou {
+            nms.iou = iou;
+        }
+        if let Some(sigma) = config.sigma {
+            nms.sigma = sigma;
+        }
+        if let Some(max_det) = config.max_det {
+            nms.max_det = max_det as usize;
+        }
+        nms.check()?;
+        Ok(nms)
+    }
+
+    /// Checks the IoU threshold is within [0, 1] and the Soft-NMS sigma is positive.
+    pub fn check(&self) -> Result<(), VisionError> {
+        if !(0.0..=1.0).contains(&self.iou) {
+            return Err(VisionError::InvalidArgument(format!(
+                "NMS iou must be within [0, 1], got {}",
+                self.iou
+            )));
+        }
+        // the gaussian decay divides by sigma
+        if !(self.sigma > 0.0 && self.sigma.is_finite()) {
+            return Err(VisionError::InvalidArgument(format!(
+                "NMS sigma must be positive, got {}",
+                self.sigma
+            )));
+        }
+        Ok(())
+    }
+
+    /// Filters parallel conf/class/box vectors, highest confidence first.
+    /// Soft-NMS drops boxes whose decayed score falls under `conf_th`.
+    pub fn filter(
+        &self,
+        confs: Vec<f32>,
+        class_ids: Vec<i32>,
+        boxes: Vec<Array1<f32>>,
+        conf_th: f32,
+    ) -> (Vec<f32>, Vec<i32>, Vec<Array1<f32>>) {
+        let mut xs: Vec<Detection> = confs
+            .into_iter()
+            .zip(class_ids)
+            .zip(boxes)
+            .map(|((conf, class_id), bbox)| Detection { conf, class_id, bbox })
+            .collect();
+        self.apply(&mut xs, conf_th);
+
+        let mut confs = Vec::with_capacity(xs.len());
+        let mut class_ids = Vec::with_capacity(xs.len());
+        let mut boxes = Vec::with_capacity(xs.len());
+        for x in xs {
+            confs.push(x.conf);
+            class_ids.push(x.class_id);
+            boxes.push(x.bbox);
+        }
+        (confs, class_ids, boxes)
+    }
+
+    /// Filters `xs` in place, highest confidence first. Soft-NMS drops boxes whose
+    /// decayed score falls under `conf_th`.
+    pub fn apply<T: NmsItem>(&self, xs: &mut Vec<T>, conf_th: f32) {
+        match self.strategy {
+            NmsStrategy::Agnostic => greedy(xs, self.iou, T::confidence, T::iou),
+            NmsStrategy::ClassAware => greedy(xs, self.iou, T::confidence, same_class(T::iou)),
+            NmsStrategy::Diou => greedy(xs, self.iou, T::confidence, same_class(diou)),
+            NmsStrategy::SoftGaussian | NmsStrategy::SoftLinear => self.soft(xs, conf_th),
+            NmsStrategy::Wbf => self.fuse(xs),
+        }
+        if self.max_det > 0 {
+            xs.truncate(self.max_det);
+        }
+    }
+
+    fn soft<T: NmsItem>(&self, xs: &mut Vec<T>, conf_th: f32) {
+        let mut pool = std::mem::take(xs);
+        while !pool.is_empty() {
+            if self.max_det > 0 && xs.len() >= self.max_det {
+                break;
+            }
+            let best = (0..pool.len())
+                .max_by(|&a, &b| pool[a].confidence().total_cmp(&pool[b].confidence()))
+                .unwrap_or_default();
+            let top = pool.swap_remove(best);
+
+            // Decay the scores of same-class boxes overlapping the kept one.
+            for x in pool.iter_mut().filter(|x| x.class_id() == top.class_id()) {
+                let iou = top.iou(x);
+                let decay = match self.strategy {
+                    NmsStrategy::SoftGaussian => (-(iou * iou) / self.sigma).exp(),
+                    _ if iou > self.iou => 1.0 - iou,
+                    _ => 1.0,
+                };
+                x.set_confidence(x.confidence() * decay);
+            }
+            pool.retain(|x| x.confidence() >= conf_th);
+            xs.push(top);
+        }
+    }
+
+    fn fuse<T: NmsItem>(&self, xs: &mut Vec<T>) {
+        xs.sort_by(|a, b| b.confidence().total_cmp(&a.confidence()));
+
+        // Clusters of same-class boxes: the first member rewritten with the fused box,
+        // and the confidence and corners of every member.
+        let mut clusters: Vec<(T, Vec<(f32, [f32; 4])>)> = Vec::new();
+        for x in std::mem::take(xs) {
+            let matched = clusters
+                .iter()
+                .enumerate()
+                .filter(|(_, (fused, _))| fused.class_id() == x.class_id())
+                .map(|(i, (fused, _))| (i, fused.iou(&x)))
+                .filter(|&(_, iou)| iou > self.iou)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            match matched {
+                Some((i, _)) => {
+                    let (fused, members) = &mut clusters[i];
+                    members.push((x.confidence(), x.corners()));
+                    let (conf, corners) = weighted_box(members);
+                    fused.set_confidence(conf);
+                    fused.set_corners(corners);
+                }
+                None => {
+                    let member = (x.confidence(), x.corners());
+                    clusters.push((x, vec![member]));
+                }
+            }
+        }
+
+        xs.extend(clusters.into_iter().map(|(fused, _)| fused));
+        xs.sort_by(|a, b| b.confidence().total_cmp(&a.confidence()));
+    }
+}
+
+/// Greedy NMS: keeps `xs` by descending `confidence`, dropping those whose `overlap` with
+/// a kept one exceeds `iou_threshold`.
+pub fn greedy<T>(
+    xs: &mut Vec<T>,
+    iou_threshold: f32,
+    confidence: impl Fn(&T) -> f32,
+    overlap: impl Fn(&T, &T) -> f32,
+) {
+    xs.sort_by(|b1, b2| confidence(b2).total_cmp(&confidence(b1)));
+
+    let mut current_index = 0;
+    for index in 0..xs.len() {
+        let drop = (0..current_index)
+            .any(|prev_index| overlap(&xs[prev_index], &xs[index]) > iou_threshold);
+        if !drop {
+            xs.swap(current_index, index);
+            current_index += 1;
+        }
+    }
+    xs.truncate(current_index);
+}
+
+/// Restricts `overlap` to detections of the same class, other pairs never suppress.
+fn same_class<T: NmsItem>(overlap: fn(&T, &T) -> f32) -> impl Fn(&T, &T) -> f32 {
+    move |a, b| {
+        if a.class_id() == b.class_id() { overlap(a, b) } else { f32::NEG_INFINITY }
+    }
+}
+
+/// Confidence-weighted corners and mean confidence of a cluster.
+fn weighted_box(members: &[(f32, [f32; 4])]) -> (f32, [f32; 4]) {
+    let total: f32 = members.iter().map(|(conf, _)| conf).sum();
+    let mut corners = [0.0f32; 4];
+    for (conf, member) in members {
+        for (c, v) in corners.iter_mut().zip(member) {
+            *c += v * conf / total;
+        }
+    }
+    (total / members.len() as f32, corners)
+}
+
+fn corners_of(b: &Array1<f32>) -> [f32; 4] {
+    [b[0] - b[2] / 2.0, b[1] - b[3] / 2.0, b[0] + b[2] / 2.0, b[1] + b[3] / 2.0]
+}
+
+/// IoU of two boxes in center format.
+pub fn iou(b1: &Array1<f32>, b2: &Array1<f32>) -> f32 {
+    corners_iou(corners_of(b1), corners_of(b2))
+}
+
+fn corners_iou([x1_1, y1_1, x2_1, y2_1]: [f32; 4], [x1_2, y1_2, x2_2, y2_2]: [f32; 4]) -> f32 {
+    let inter_w = (x2_1.min(x2_2) - x1_1.max(x1_2)).max(0.0);
+    let inter_h = (y2_1.min(y2_2) - y1_1.max(y1_2)).max(0.0);
+    let inter_area = inter_w * inter_h;
+    let area1 = (x2_1 - x1_1).max(0.0) * (y2_1 - y1_1).max(0.0);
+    let area2 = (x2_2 - x1_2).max(0.0) * (y2_2 - y1_2).max(0.0);
+    let union_area = area1 + area2 - inter_area;
+    if union_area <= 0.0 { 0.0 } else { inter_area / union_area }
+}
+
+/// IoU minus the squared center distance over the squared enclosing diagonal.
+fn diou<T: NmsItem>(a: &T, b: &T) -> f32 {
+    let [x1_1, y1_1, x2_1, y2_1] = a.corners();
+    let [x1_2, y1_2, x2_2, y2_2] = b.corners();
+    let d2 = ((x1_1 + x2_1 - x1_2 - x2_2) / 2.0).powi(2) + ((y1_1 + y2_1 - y1_2 - y2_2) / 2.0).powi(2);
+    let c2 = (x2_1.max(x2_2) - x1_1.min(x1_2)).powi(2) + (y2_1.max(y2_2) - y1_1.min(y1_2)).powi(2);
+    if c2 > 0.0 { a.iou(b) - d2 / c2 } else { a.iou(b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Item {
+        conf: f32,
+        class_id: i64,
+        corners: [f32; 4],
+    }
+
+    impl NmsItem for Item {
+        fn confidence(&self) -> f32 {
+            self.conf
+        }
+
+        fn set_confidence(&mut self, confidence: f32) {
+            self.conf = confidence;
+        }
+
+        fn class_id(&self) -> i64 {
+            self.class_id
+        }
+
+        fn corners(&self) -> [f32; 4] {
+            self.corners
+        }
+
+        fn set_corners(&mut self, corners: [f32; 4]) {
+            self.corners = corners;
+        }
+    }
+
+    fn item(conf: f32, class_id: i64, corners: [f32; 4]) -> Item {
+        Item { conf, class_id, corners }
+    }
+
+    fn nms(strategy: NmsStrategy, iou: f32) -> Nms {
+        Nms { strategy, iou, sigma: 0.5, max_det: 0 }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    // `b` overlaps `a` with IoU 1/3, `c` is `a` in another class
+    fn items() -> Vec<Item> {
+        vec![
+            item(0.9, 0, [0.0, 0.0, 10.0, 10.0]),
+            item(0.8, 0, [0.0, 5.0, 10.0, 15.0]),
+            item(0.7, 1, [0.0, 0.0, 10.0, 10.0]),
+        ]
+    }
+
+    #[test]
+    fn soft_gaussian_decays_by_iou() {
+        let mut xs = items();
+        // a duplicate of `a`, decayed by exp(-1 / 0.5) below the confidence threshold
+        xs.push(item(0.5, 0, [0.0, 0.0, 10.0, 10.0]));
+        nms(NmsStrategy::SoftGaussian, 0.45).apply(&mut xs, 0.1);
+        let confs: Vec<f32> = xs.iter().map(|x| x.conf).collect();
+        let decayed = 0.8 * (-(1.0f32 / 9.0) / 0.5).exp();
+        assert_eq!(confs.len(), 3, "{:?}", confs);
+        assert!(close(confs[0], 0.9) && close(confs[1], 0.7) && close(confs[2], decayed));
+        assert_eq!(xs[2].corners, [0.0, 5.0, 10.0, 15.0]);
+    }
+
+    #[test]
+    fn soft_linear_decays_above_the_iou_threshold() {
+        let mut xs = items();
+        nms(NmsStrategy::SoftLinear, 0.3).apply(&mut xs, 0.1);
+        let confs: Vec<f32> = xs.iter().map(|x| x.conf).collect();
+        assert!(close(confs[0], 0.9) && close(confs[1], 0.7) && close(confs[2], 0.8 * 2.0 / 3.0));
+
+        let mut xs = items();
+        nms(NmsStrategy::SoftLinear, 0.45).apply(&mut xs, 0.1);
+        let confs: Vec<f32> = xs.iter().map(|x| x.conf).collect();
+        assert_eq!(confs, vec![0.9, 0.8, 0.7]);
+    }
+
+    #[test]
+    fn diou_keeps_overlapping_boxes_with_distant_centers() {
+        let xs = items();
+        // IoU 1/3, less the squared center distance 25 over the squared diagonal 325
+        assert!(close(diou(&xs[0], &xs[1]), 1.0 / 3.0 - 25.0 / 325.0));
+        assert!(close(diou(&xs[0], &xs[0]), 1.0));
+
+        let mut greedy = items();
+        nms(NmsStrategy::ClassAware, 0.3).apply(&mut greedy, 0.0);
+        assert_eq!(greedy.len(), 2);
+        let mut kept = items();
+        nms(NmsStrategy::Diou, 0.3).apply(&mut kept, 0.0);
+        assert_eq!(kept, items());
+    }
+
+    #[test]
+    fn fuse_averages_boxes_by_confidence() {
+        let mut xs = vec![
+            item(0.9, 0, [0.0, 0.0, 10.0, 10.0]),
+            // IoU 2/3 with the first
+            item(0.6, 0, [2.0, 0.0, 12.0, 10.0]),
+            item(0.8, 1, [2.0, 0.0, 12.0, 10.0]),
+            item(0.5, 0, [50.0, 50.0, 60.0, 60.0]),
+        ];
+        nms(NmsStrategy::Wbf, 0.45).apply(&mut xs, 0.0);
+        assert_eq!(xs.len(), 3);
+        assert_eq!(xs[0], item(0.8, 1, [2.0, 0.0, 12.0, 10.0]));
+        let fused = &xs[1];
+        assert_eq!(fused.class_id, 0);
+        // mean confidence, corners weighted 0.9 : 0.6
+        assert!(close(fused.conf, 0.75));
+        let expected = [0.8, 0.0, 10.8, 10.0];
+        assert!(fused.corners.iter().zip(expected).all(|(&a, b)| close(a, b)), "{:?}", fused);
+        assert_eq!(xs[2], item(0.5, 0, [50.0, 50.0, 60.0, 60.0]));
+    }
+
+    #[test]
+    fn check_rejects_bad_iou_and_sigma() {
+        assert!(Nms::default().check().is_ok());
+        for (iou, sigma) in [(1.5, 0.5), (-0.1, 0.5), (0.45, 0.0), (0.45, -1.0), (0.45, f32::NAN)] {
+            let nms = Nms { iou, sigma, ..Nms::default() };
+            assert!(matches!(nms.check(), Err(VisionError::InvalidArgument(_))), "{:?}", nms);
+        }
+        let config = grpc::NmsConfig { sigma: Some(0.0), ..Default::default() };
+        assert!(Nms::default().merge(&config).is_err());
+    }
+}
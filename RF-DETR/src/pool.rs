@@ -0,0 +1,71 @@
+use prometheus::IntGauge;
+use std::sync::Mutex;
+use tokio::sync::Semaphore;
+use tracing::Span;
+
+use crate::error::VisionError;
+use crate::metrics::METRICS;
+
+/// A fixed set of ORT sessions shared by concurrent requests.
+///
+/// Each call checks out an idle session and runs on the blocking thread pool,
+/// so inference never stalls the async executor.
+#[derive(Debug)]
+pub struct SessionPool {
+    sessions: Mutex<Vec<ort::session::Session>>,
+    available: Semaphore,
+    /// Requests waiting for an idle session.
+    queued: IntGauge,
+}
+
+impl SessionPool {
+    /// `name` labels the queue depth metric of the pool.
+    pub fn new(name: &str, sessions: Vec<ort::session::Session>) -> Self {
+        let available = Semaphore::new(sessions.len());
+        Self {
+            sessions: Mutex::new(sessions),
+            available,
+            queued: METRICS.queue_depth.with_label_values(&[name]),
+        }
+    }
+
+    /// Runs `f` with an idle session on a blocking thread.
+    pub async fn run<F, T>(&self, f: F) -> Result<T, VisionError>
+    where
+        F: FnOnce(&ort::session::Session) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.queued.inc();
+        let permit = self.available.acquire().await;
+        self.queued.dec();
+        let permit =
+            permit.map_err(|e| VisionError::Internal(format!("Session pool closed: {}", e)))?;
+        let session = self
+            .sessions
+            .lock()
+            .map_err(|e| VisionError::Internal(format!("Session pool poisoned: {}", e)))?
+            .pop()
+            .ok_or_else(|| VisionError::Internal("No idle session".to_string()))?;
+
+        // the blocking thread keeps the caller's span
+        let span = Span::current();
+        match tokio::task::spawn_blocking(move || {
+            let ys = span.in_scope(|| f(&session));
+            (session, ys)
+        })
+        .await
+        {
+            Ok((session, ys)) => {
+                if let Ok(mut sessions) = self.sessions.lock() {
+                    sessions.push(session);
+                }
+                Ok(ys)
+            }
+            Err(e) => {
+                // the session was lost with the panicking task, shrink the pool
+                permit.forget();
+                Err(VisionError::Internal(format!("Inference task failed: {}", e)))
+            }
+        }
+    }
+}
@@ -1,9 +1,7 @@
-use anyhow::Result;
-use tonic::Status;
-use std::{collections::HashMap, error::Error};
 use ndarray::{Array, Array1, Array3, ArrayBase, Axis, IxDynImpl, OwnedRepr};
-use ordered_float::OrderedFloat;
 use crate::cli::Args;
+use crate::nms::Nms;
+use crate::error::VisionError;
 
 #[derive(Debug)]
 pub struct PostProcessor {
@@ -38,7 +36,7 @@ impl PostProcessor {
         &self,
         classes_dyn: &Array<f32, ndarray::IxDyn>,
         boxes_dyn: &Array<f32, ndarray::IxDyn>,
-    ) -> Result<(Vec<i32>, Vec<f32>, Vec<Array1<f32>>), Box<dyn Error>> {
+    ) -> Result<(Vec<i32>, Vec<f32>, Vec<Array1<f32>>), VisionError> {
         // Reshape the dynamic arrays to fixed dimensions.
         // We assume the shape is (1, num_boxes, num_classes) for classes and (1, num_boxes, 4) for boxes.
         let classes_fixed: Array3<f32> = classes_dyn
@@ -69,7 +67,7 @@ impl PostProcessor {
             if confidence >= self.config.conf_th {
                 let ids_and_classes = self.argmax_and_max(&softmaxed);
                 filtered_classes.push(ids_and_classes.0 as i32);
-                filtered_conf.push(ids_and_classes.1 as f32);
+                filtered_conf.push(ids_and_classes.1);
                 // Also push the corresponding box. Convert the 1D view to an owned Array1.
                 filtered_boxes.push(box_row.to_owned());
             }
@@ -79,73 +77,27 @@ impl PostProcessor {
     
     /// Compute the Intersection over Union (IoU) of two boxes in center-format.
     pub fn compute_iou(&self, b1: &Array1<f32>, b2: &Array1<f32>) -> f32 {
-        let (x1_1, y1_1, x2_1, y2_1) = (b1[0], b1[1], b1[2], b1[3]);
-        let (x1_2, y1_2, x2_2, y2_2) = (b2[0], b2[1], b2[2], b2[3]);
-    
-        let inter_x1 = x1_1.max(x1_2);
-        let inter_y1 = y1_1.max(y1_2);
-        let inter_x2 = x2_1.min(x2_2);
-        let inter_y2 = y2_1.min(y2_2);
-    
-        let inter_area = ((inter_x2 - inter_x1).max(0.0)) * ((inter_y2 - inter_y1).max(0.0));
-        let area1 = (x2_1 - x1_1).max(0.0) * (y2_1 - y1_1).max(0.0);
-        let area2 = (x2_2 - x1_2).max(0.0) * (y2_2 - y1_2).max(0.0);
-        let union_area = area1 + area2 - inter_area;
-        if union_area <= 0.0 { 0.0 } else { inter_area / union_area }
+        crate::nms::iou(b1, b2)
+    }
+
+    /// NMS settings from the command line.
+    pub fn nms(&self) -> Nms {
+        Nms {
+            strategy: self.config.nms,
+            iou: self.config.iou_th,
+            sigma: self.config.nms_sigma,
+            max_det: self.config.max_det,
+        }
     }
+
     pub fn non_maximum_suppression(
         &self,
         class_confs: Vec<f32>,
         class_ids: Vec<i32>,
-        boxes: Vec<Array1<f32>>
+        boxes: Vec<Array1<f32>>,
+        nms: &Nms,
     ) -> (Vec<f32>, Vec<i32>, Vec<Array1<f32>>) {
-        // Group indices by class id.
-        let mut by_class: HashMap<OrderedFloat<f32>, Vec<usize>> = HashMap::new();
-        for (i, &cid) in class_ids.iter().enumerate() {
-            by_class.entry(OrderedFloat(cid as f32)).or_default().push(i);
-        }
-    
-        let mut keep_indices: Vec<usize> = Vec::new();
-    
-        // Process each class group separately.
-        for (_cid, indices) in by_class.iter_mut() {
-            // Sort the indices in descending order of confidence.
-            indices.sort_by(|&i1, &i2| {
-                // Convert confidence to f32 for sorting.
-                (class_confs[i2])
-                    .partial_cmp(&(class_confs[i1]))
-                    .unwrap()
-            });
-    
-            // Standard NMS: iterate and suppress boxes with high overlap.
-            let mut suppressed = vec![false; indices.len()];
-            for i in 0..indices.len() {
-                if suppressed[i] {
-                    continue;
-                }
-                let idx_i = indices[i];
-                keep_indices.push(idx_i);
-                for j in (i + 1)..indices.len() {
-                    if suppressed[j] {
-                        continue;
-                    }
-                    let idx_j = indices[j];
-                    let iou = self.compute_iou(&boxes[idx_i], &boxes[idx_j]);
-                    if iou > self.config.iou_th {
-                        suppressed[j] = true;
-                    }
-                }
-            }
-        }
-    
-        // Optionally, sort the kept indices in ascending order.
-        keep_indices.sort_unstable();
-    
-        let filtered_confs: Vec<f32> = keep_indices.iter().map(|&i| class_confs[i]).collect();
-        let filtered_ids: Vec<i32> = keep_indices.iter().map(|&i| class_ids[i]).collect();
-        let filtered_boxes: Vec<Array1<f32>> = keep_indices.iter().map(|&i| boxes[i].clone()).collect();
-    
-        (filtered_confs, filtered_ids, filtered_boxes)
+        nms.filter(class_confs, class_ids, boxes, self.config.conf_th)
     }
 
     pub fn denormalize(
@@ -181,12 +133,15 @@ impl PostProcessor {
         output
     }
 
-    pub fn postprocess(&self, model_output: Vec<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>>>, orig_w: f32, orig_h: f32, offset: Vec<(u32, u32)>) -> Result<(Vec<[i32; 4]>, Vec<i32>, Vec<f32>), Box<dyn Error>> {
-        let boxes: &ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>> = &model_output[0];
-        let classes: &ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>> = &model_output[1];
-        let (filtered_classes, filtered_conf, filtered_boxes) = self.softmax_and_filter(classes, boxes)
-            .map_err(|e| Status::internal(format!("Softmax and filter error: {}", e)))?;
-        let (filtered_conf, filtered_classes, filtered_boxes) = self.non_maximum_suppression(filtered_conf, filtered_classes, filtered_boxes);
+    pub fn postprocess(&self, model_output: Vec<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>>>, orig_w: f32, orig_h: f32, offset: Vec<(u32, u32)>, nms: &Nms) -> Result<(Vec<[i32; 4]>, Vec<i32>, Vec<f32>), VisionError> {
+        let [boxes, classes, ..] = model_output.as_slice() else {
+            return Err(VisionError::Model(format!(
+                "Expected boxes and class logits outputs, got {} outputs",
+                model_output.len()
+            )));
+        };
+        let (filtered_classes, filtered_conf, filtered_boxes) = self.softmax_and_filter(classes, boxes)?;
+        let (filtered_conf, filtered_classes, filtered_boxes) = self.non_maximum_suppression(filtered_conf, filtered_classes, filtered_boxes, nms);
         
         let filtered_boxes = self.denormalize(
             orig_w, orig_h,
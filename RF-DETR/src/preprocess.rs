@@ -1,9 +1,10 @@
-use anyhow::Result;
 use rayon::prelude::*;
 use image::DynamicImage;
 use fast_image_resize::images::Image;
 use fast_image_resize::{IntoImageView, Resizer};
+use tracing::{Span, info, info_span};
 use crate::cli::Args;
+use crate::error::VisionError;
 
 #[derive(Debug)]
 pub  struct PreProcessor {
@@ -17,30 +18,40 @@ impl PreProcessor {
             config,
         }
     }
-    pub fn convert_to_dynamic(&self, image: Image<'static>) -> DynamicImage {
-        DynamicImage::ImageRgb8(
-            image::ImageBuffer::from_raw(image.width(), image.height(), image.buffer().to_vec())
-                .expect("Failed to create ImageBuffer")
-        )
+    pub fn convert_to_dynamic(&self, image: Image<'static>) -> Result<DynamicImage, VisionError> {
+        image::ImageBuffer::from_raw(image.width(), image.height(), image.buffer().to_vec())
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| VisionError::Internal("Resized buffer does not fit an RGB image".to_string()))
     }
     /// Preprocess the input images
     /// Applying image normalization and resizing with padding
     /// Returns a tuple containing the preprocessed images and the offsets
-    pub fn preprocess(&self, xs: &Vec<DynamicImage>, deep_profile: bool) -> Result<(ndarray::Array<f32, ndarray::IxDyn>, Vec<(u32, u32)>), Box<dyn std::error::Error>> {
-        let ys_vec: Vec<(ndarray::Array<f32, ndarray::Dim<[usize; 3]>>, (u32, u32))> = xs.par_iter().enumerate().map(|(_, x)| {
+    pub fn preprocess(&self, xs: &Vec<DynamicImage>, deep_profile: bool) -> Result<(ndarray::Array<f32, ndarray::IxDyn>, Vec<(u32, u32)>), VisionError> {
+        // rayon threads do not inherit the caller's span
+        let parent = Span::current();
+        let ys_vec: Vec<(ndarray::Array<f32, ndarray::Dim<[usize; 3]>>, (u32, u32))> = xs.par_iter().enumerate().map(|(i, x)| {
+            let _span = info_span!(parent: &parent, "preprocess_image", index = i).entered();
             let t = std::time::Instant::now();
+            // Grayscale or RGBA inputs are resized as RGB.
+            let rgb;
+            let x = if matches!(x, DynamicImage::ImageRgb8(_)) {
+                x
+            } else {
+                rgb = DynamicImage::ImageRgb8(x.to_rgb8());
+                &rgb
+            };
             let (orig_width, orig_height) = (x.width(), x.height());
             let scale = (self.config.img_w as f32 / orig_width as f32).min(self.config.img_h as f32 / orig_height as f32);
             let new_width = (orig_width as f32 * scale) as u32;
             let new_height = (orig_height as f32 * scale) as u32;
             if deep_profile{
-                println!("[preprocessing - 1]: {:?}", t.elapsed());
+                info!(step = "scale", elapsed = ?t.elapsed(), "preprocessing");
             }
             let t = std::time::Instant::now();
             let mut dst_image = Image::new(
                 new_width,
                 new_height,
-                x.pixel_type().unwrap(),
+                x.pixel_type().ok_or_else(|| VisionError::Unsupported("Pixel type of the input image".to_string()))?,
             );
 
             // Create Resizer instance and resize source image
@@ -48,10 +59,10 @@ impl PreProcessor {
             let mut resizer = Resizer::new();
             let resize_options = fast_image_resize::ResizeOptions::new();
             resize_options.resize_alg(fast_image_resize::ResizeAlg::Nearest);
-            resizer.resize(x, &mut dst_image, Some(&resize_options)).unwrap();
-            let resized = self.convert_to_dynamic(dst_image).to_rgb8();
+            resizer.resize(x, &mut dst_image, Some(&resize_options))?;
+            let resized = self.convert_to_dynamic(dst_image)?.to_rgb8();
             if deep_profile{
-                println!("[preprocessing - 2]: {:?}", t.elapsed());
+                info!(step = "resize", elapsed = ?t.elapsed(), "preprocessing");
             }
             let t = std::time::Instant::now();
             let mean_r = (self.config.mean[0] * 255.0) as u8;
@@ -63,7 +74,7 @@ impl PreProcessor {
                 image::Rgb([mean_r, mean_g, mean_b])
             );
             if deep_profile{
-                println!("[preprocessing - 3]: {:?}", t.elapsed());
+                info!(step = "pad", elapsed = ?t.elapsed(), "preprocessing");
             }
             let t = std::time::Instant::now();
             // Compute offsets to center the resized image in the padded image
@@ -72,7 +83,7 @@ impl PreProcessor {
             // Overlay the resized image onto the padded image at the calculated offsets
             image::imageops::overlay(&mut padded, &resized, x_offset as i64, y_offset as i64);
             if deep_profile{
-                println!("[preprocessing - 4]: {:?}", t.elapsed());
+                info!(step = "overlay", elapsed = ?t.elapsed(), "preprocessing");
             }
             let t = std::time::Instant::now();
             let img = DynamicImage::ImageRgb8(padded).to_rgb8();
@@ -80,23 +91,23 @@ impl PreProcessor {
 
             let mut img_arr = ndarray::Array::from_elem((self.config.ch as usize, self.config.img_h, self.config.img_w), 0 as f32); //144.0 / 255.0);
             if deep_profile {
-                println!("[preprocessing - 5]: {:?}", t.elapsed());
+                info!(step = "allocate", elapsed = ?t.elapsed(), "preprocessing");
             }
             let t = std::time::Instant::now();
             // Populate the array with normalized pixel values
             for (i, rgb) in pixels.enumerate() {
-                let y = i / self.config.img_w as usize;
-                let x = i % self.config.img_w as usize;
+                let y = i / self.config.img_w;
+                let x = i % self.config.img_w;
                 img_arr[[0, y, x]] = (rgb[0] as f32 / 255.0 - self.config.mean[0]) / self.config.std[0];
                 img_arr[[1, y, x]] = (rgb[1] as f32 / 255.0 - self.config.mean[1]) / self.config.std[1];
                 img_arr[[2, y, x]] = (rgb[2] as f32 / 255.0 - self.config.mean[2]) / self.config.std[2];
             }
             if deep_profile {
-                println!("[preprocessing - 6]: {:?}", t.elapsed());
+                info!(step = "normalize", elapsed = ?t.elapsed(), "preprocessing");
             }
-            (img_arr, (x_offset, y_offset))
+            Ok((img_arr, (x_offset, y_offset)))
         })
-        .collect();
+        .collect::<Result<_, VisionError>>()?;
     
         // Separate the image arrays and the offsets
         let (img_arrs, offsets): (Vec<_>, Vec<_>) = ys_vec.into_iter().unzip();
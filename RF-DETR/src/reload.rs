@@ -0,0 +1,56 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Polls a model file and reloads the model after the file changed.
+///
+/// The polling task stops when the watcher is dropped.
+#[derive(Debug)]
+pub struct Watcher(tokio::task::JoinHandle<()>);
+
+impl Watcher {
+    pub fn spawn<F, Fut, E>(path: PathBuf, interval: Duration, reload: F) -> Self
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: Display,
+    {
+        Self(tokio::spawn(async move {
+            let mut last = stamp(&path);
+            loop {
+                tokio::time::sleep(interval).await;
+                let now = stamp(&path);
+                if now.is_none() || now == last {
+                    continue;
+                }
+
+                // wait until the file is no longer being written
+                tokio::time::sleep(interval).await;
+                if stamp(&path) != now {
+                    continue;
+                }
+                last = now;
+                match reload().await {
+                    Ok(()) => info!(path = %path.display(), "Model reloaded"),
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "Model reload failed, kept the old model")
+                    }
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    // modification time and size, a replaced file changes either
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
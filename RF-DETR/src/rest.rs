@@ -0,0 +1,225 @@
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, State};
+use axum::http::{Request, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{Code, Status};
+use tracing::{Instrument, info};
+
+use crate::error::VisionError;
+use crate::metrics::METRICS;
+use crate::service::{LazyImageProcessor, MyImageProcessor};
+use crate::telemetry;
+
+/// Largest accepted request body, raw or multipart.
+pub const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+/// A detected box in image coordinates, `(x, y)` is the top-left corner.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub class_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonResult {
+    boxes: Vec<JsonBox>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonResponse {
+    results: Vec<JsonResult>,
+}
+
+/// A failed call as JSON, `{"code": "InvalidArgument", "error": "..."}`, with the HTTP
+/// status matching its gRPC code.
+#[derive(Debug)]
+pub struct ErrorResponse(pub Status);
+
+impl From<VisionError> for ErrorResponse {
+    fn from(e: VisionError) -> Self {
+        Self(e.into())
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "code": format!("{:?}", self.0.code()),
+            "error": self.0.message(),
+        });
+        (http_status(self.0.code()), Json(body)).into_response()
+    }
+}
+
+/// HTTP status of a gRPC code, as mapped by grpc-gateway.
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Cancelled | Code::Unknown | Code::Internal | Code::DataLoss => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Encoded images of an upload: every file part of a `multipart/form-data` body,
+/// or the whole body otherwise.
+pub async fn read_images(request: Request<Body>) -> Result<Vec<Bytes>, VisionError> {
+    let multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+    let images = if multipart {
+        let mut form = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| VisionError::InvalidArgument(e.body_text()))?;
+        let mut images = Vec::new();
+        while let Some(field) = form
+            .next_field()
+            .await
+            .map_err(|e| VisionError::InvalidArgument(e.body_text()))?
+        {
+            // plain form fields are not images
+            if field.file_name().is_none() {
+                continue;
+            }
+            images.push(
+                field
+                    .bytes()
+                    .await
+                    .map_err(|e| VisionError::InvalidArgument(e.body_text()))?,
+            );
+        }
+        images
+    } else {
+        let body = Bytes::from_request(request, &())
+            .await
+            .map_err(|e| VisionError::InvalidArgument(e.body_text()))?;
+        if body.is_empty() { vec![] } else { vec![body] }
+    };
+
+    if images.is_empty() {
+        return Err(VisionError::InvalidArgument(
+            "No image in the request body".to_string(),
+        ));
+    }
+    Ok(images)
+}
+
+/// Runs every HTTP request in a span, a child of the caller's W3C `traceparent`.
+pub async fn trace<B>(request: Request<B>, next: Next<B>) -> Response {
+    let span = telemetry::http_span(&request);
+    next.run(request).instrument(span).await
+}
+
+/// Serves `router` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, router: Router) -> Result<(), VisionError> {
+    info!(addr = %addr, "HTTP gateway listening");
+    axum::Server::try_bind(&addr)
+        .map_err(|e| VisionError::Io(format!("Failed to bind {}: {}", addr, e)))?
+        .serve(router.into_make_service())
+        .await
+        .map_err(|e| VisionError::Io(e.to_string()))
+}
+
+/// `POST /v1/detect`, the JSON counterpart of `ImageProcessor::ProcessImage`.
+pub fn router(processor: LazyImageProcessor) -> Router {
+    Router::new()
+        .route("/v1/detect", post(detect))
+        .layer(middleware::from_fn(trace))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(processor)
+}
+
+async fn detect(
+    State(processor): State<LazyImageProcessor>,
+    request: Request<Body>,
+) -> Result<Json<JsonResponse>, ErrorResponse> {
+    let processor = processor.get_arc()?;
+    let result = detect_all(&processor, request).await;
+    METRICS.observe_request(
+        &processor.args().name,
+        "HttpDetect",
+        &result.as_ref().map_err(|e| e.0.clone()),
+    );
+    result.map(Json)
+}
+
+async fn detect_all(
+    processor: &Arc<MyImageProcessor>,
+    request: Request<Body>,
+) -> Result<JsonResponse, ErrorResponse> {
+    let name = &processor.args().name;
+    let nms = processor.nms();
+    let mut xs = Vec::new();
+    for image in read_images(request).await? {
+        let t = std::time::Instant::now();
+        let x = tracing::info_span!("decode", bytes = image.len())
+            .in_scope(|| image::load_from_memory(&image))
+            .map_err(|e| VisionError::InvalidArgument(format!("Invalid image: {}", e)))?;
+        METRICS.observe_stage(name, "decode", t.elapsed());
+        xs.push(x);
+    }
+
+    // one task per image, so a pool of sessions runs them in parallel
+    let tasks: Vec<_> = xs
+        .into_iter()
+        .map(|x| {
+            let processor = Arc::clone(processor);
+            tokio::spawn(
+                async move { processor.detect(x, &nms).await }.instrument(tracing::Span::current()),
+            )
+        })
+        .collect();
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let (boxes, classes, confs) = task
+            .await
+            .map_err(|e| VisionError::Internal(format!("Inference task failed: {}", e)))??;
+        METRICS.observe_detections(name, confs.len());
+        let boxes = boxes
+            .into_iter()
+            .zip(classes)
+            .zip(confs)
+            .map(|(([cx, cy, w, h], id), confidence)| {
+                let (cx, cy, w, h) = (cx as f32, cy as f32, w as f32, h as f32);
+                let class_id = id.max(0) as usize;
+                JsonBox {
+                    x: cx - w / 2.,
+                    y: cy - h / 2.,
+                    width: w,
+                    height: h,
+                    class_id,
+                    class_name: processor.class_name(class_id).map(str::to_string),
+                    confidence,
+                }
+            })
+            .collect();
+        results.push(JsonResult { boxes });
+    }
+    Ok(JsonResponse { results })
+}
@@ -1,104 +1,271 @@
-use image::GenericImageView;
+use image::{DynamicImage, GenericImageView};
 use ndarray::{Array, ArrayBase, CowArray, IxDynImpl, OwnedRepr};
-use tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
 
-use tonic::{Response, Status};
+use tonic::Response;
+use tracing::{Instrument, info, info_span};
 use crate::cli::Args;
 use crate::grpc;
+use crate::error::VisionError;
+use crate::mapping::load_class_mapping;
+use crate::metrics::METRICS;
+use crate::model::OnnxModel;
+use crate::nms::Nms;
+use crate::pool::SessionPool;
 use crate::preprocess::PreProcessor;
 use crate::postprocess::PostProcessor;
+use crate::reload::Watcher;
 
+type ModelOutputs = Vec<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>>>;
 
 #[derive(Debug)]
 pub struct MyImageProcessor {
-        model: Mutex<ort::session::Session>,
-        preprocessor: Mutex<PreProcessor>,
-        postprocessor: Mutex<PostProcessor>,
+        /// Swapped on reload, requests keep the pool they started on.
+        sessions: RwLock<Arc<SessionPool>>,
+        preprocessor: PreProcessor,
+        postprocessor: PostProcessor,
         args: Args,
-}
-
-impl Default for MyImageProcessor {
-    fn default() -> Self {
-        panic!("Default is not implemented for MyImageProcessor because ort::session::Session does not implement Default");
-    }
+        reloading: tokio::sync::Mutex<()>,
+        /// Class names by id, from `args.labels`.
+        names: HashMap<usize, String>,
 }
 
 impl MyImageProcessor {
-    /// Creates a new instance of MyImageProcessor with the provided model and processor.
-    pub fn new(model: ort::session::Session, preprocessor: PreProcessor, postprocessor: PostProcessor, args: Args) -> Self {
+    /// Creates a new instance of MyImageProcessor with the provided sessions and processors.
+    pub fn new(sessions: Vec<ort::session::Session>, preprocessor: PreProcessor, postprocessor: PostProcessor, args: Args) -> Self {
         Self {
-            model: Mutex::new(model),
-            preprocessor: Mutex::new(preprocessor),
-            postprocessor: Mutex::new(postprocessor),
-            args: args,
+            sessions: RwLock::new(Arc::new(SessionPool::new(&args.name, sessions))),
+            preprocessor,
+            postprocessor,
+            args,
+            reloading: tokio::sync::Mutex::new(()),
+            names: HashMap::new(),
         }
     }
-}
 
-#[tonic::async_trait]
-impl grpc::image_processor_server::ImageProcessor for MyImageProcessor {
-    async fn process_image(
+    /// Loads `args.sessions` sessions of `args.model`.
+    pub fn load(args: Args) -> Result<Self, VisionError> {
+        let sessions = load_sessions(&args)?;
+        let preprocessor = PreProcessor::new(args.clone());
+        let postprocessor = PostProcessor::new(args.clone());
+        postprocessor.nms().check()?;
+        let names = match &args.labels {
+            Some(labels) => load_class_mapping(labels)?,
+            None => HashMap::new(),
+        };
+        let mut processor = Self::new(sessions, preprocessor, postprocessor, args);
+        processor.names = names;
+        Ok(processor)
+    }
+
+    /// Loads the model file again and swaps it in once a warm-up inference passed,
+    /// requests already running finish on the old sessions.
+    /// On failure the old sessions keep serving.
+    pub async fn reload(&self) -> Result<(), VisionError> {
+        let _reloading = self.reloading.lock().await;
+        let args = self.args.clone();
+        let sessions = tokio::task::spawn_blocking(move || load_sessions(&args))
+            .await
+            .map_err(|e| VisionError::Internal(format!("Model loading failed: {}", e)))??;
+        let pool = SessionPool::new(&self.args.name, sessions);
+        self.warmup_with(&pool).await?;
+        *self.sessions.write().map_err(|_| poisoned())? = Arc::new(pool);
+        Ok(())
+    }
+
+    /// Runs a blank image, a broken model fails here instead of on a request.
+    pub async fn warmup(&self) -> Result<(), VisionError> {
+        let sessions = Arc::clone(&*self.sessions.read().map_err(|_| poisoned())?);
+        self.warmup_with(&sessions).await
+    }
+
+    async fn warmup_with(&self, sessions: &SessionPool) -> Result<(), VisionError> {
+        let blank = DynamicImage::new_rgb8(self.args.img_w as u32, self.args.img_h as u32);
+        self.detect_with(sessions, blank, &self.nms()).await?;
+        Ok(())
+    }
+
+    /// Reloads the model whenever its file changes, if `watch_ms` is set.
+    pub fn watch(self: &Arc<Self>) -> Option<Watcher> {
+        if self.args.watch_ms == 0 {
+            return None;
+        }
+        let processor = Arc::clone(self);
+        Some(Watcher::spawn(
+            PathBuf::from(&self.args.model),
+            Duration::from_millis(self.args.watch_ms),
+            move || {
+                let processor = Arc::clone(&processor);
+                async move { processor.reload().await }
+            },
+        ))
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    /// Name of a class id, if labels were loaded.
+    pub fn class_name(&self, id: usize) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    /// Class names by id, empty without labels.
+    pub fn names(&self) -> &HashMap<usize, String> {
+        &self.names
+    }
+
+    /// NMS settings of the server.
+    pub fn nms(&self) -> Nms {
+        self.postprocessor.nms()
+    }
+
+    /// Detects objects in a decoded image, returns (cx, cy, w, h) boxes, classes and confidences.
+    pub async fn detect(
         &self,
-        request: tonic::Request<crate::grpc::ImageRequest>,
-    ) -> Result<tonic::Response<crate::grpc::DetectionResponse>, tonic::Status> {
-        let t = std::time::Instant::now();
-        // 1. Decode image bytes
-        let image_data = &request.into_inner().image_data;
-        let image = image::load_from_memory(image_data)
-        .map_err(|e| Status::invalid_argument(format!("Invalid image: {}", e)))?;
+        image: DynamicImage,
+        nms: &Nms,
+    ) -> Result<(Vec<[i32; 4]>, Vec<i32>, Vec<f32>), VisionError> {
+        let sessions = Arc::clone(&*self.sessions.read().map_err(|_| poisoned())?);
+        self.detect_with(&sessions, image, nms).await
+    }
+
+    #[tracing::instrument(name = "detect", skip_all, fields(model = %self.args.name))]
+    async fn detect_with(
+        &self,
+        sessions: &SessionPool,
+        image: DynamicImage,
+        nms: &Nms,
+    ) -> Result<(Vec<[i32; 4]>, Vec<i32>, Vec<f32>), VisionError> {
         let (orig_w, orig_h) = image.dimensions();
-        if self.args.profile {
-            println!("[image loading]: {:?}", t.elapsed());
-        }
-        
-        let t = std::time::Instant::now();
-        // 2. Run your image processing logic (replace with your actual function)
-        let (xs, offset) = self.preprocessor.lock().await.preprocess(&vec![image.clone()], self.args.deep_profile)
-            .map_err(|e| Status::internal(format!("Preprocessing error: {}", e)))?;
-        if self.args.profile {
-            println!("[preprocessing]: {:?}", t.elapsed());
-        }
         let t = std::time::Instant::now();
-        let xs = CowArray::from(xs);
-        let input_data = ort::inputs![xs.view()].map_err(|e| Status::internal(format!("ORT input error: {}", e)))?;
+        let (xs, offset) = info_span!("preprocess")
+            .in_scope(|| self.preprocessor.preprocess(&vec![image], self.args.deep_profile))?;
+        METRICS.observe_stage(&self.args.name, "preprocess", t.elapsed());
         if self.args.profile {
-            println!("[input tensor preparation]: {:?}", t.elapsed());
+            info!(elapsed = ?t.elapsed(), "preprocessing");
         }
         let t = std::time::Instant::now();
-        let session = self.model.lock().await;
-        let ys = session.run(input_data)
-            .map_err(|e| Status::internal(format!("Model run error: {}", e)))?;
+        // Inference on an idle session from the pool, off the async executor
+        let i: ModelOutputs = sessions.run(move |session| -> Result<ModelOutputs, VisionError> {
+            let xs = CowArray::from(xs);
+            let input_data = ort::inputs![xs.view()]?;
+            let ys = session.run(input_data)?;
+            ys.iter()
+                .map(|(_k, v)| Ok(v.try_extract_tensor::<f32>()?.into_owned()))
+                .collect::<Result<Vec<Array<_, _>>, VisionError>>()
+        })
+        .instrument(info_span!("inference"))
+        .await??;
+        METRICS.observe_stage(&self.args.name, "inference", t.elapsed());
+        METRICS.observe_batch(&self.args.name, 1);
         if self.args.profile {
-            println!("[model run]: {:?}", t.elapsed());
+            info!(elapsed = ?t.elapsed(), "model run");
         }
         let t = std::time::Instant::now();
-        let i: Vec<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>>> = ys
-            .iter()
-            .map(|(_k, v)| {
-                let v = v.try_extract_tensor::<f32>().unwrap().into_owned();
-                v
-            })
-            .collect::<Vec<Array<_, _>>>();
+        let ys = info_span!("postprocess")
+            .in_scope(|| self.postprocessor.postprocess(i, orig_w as f32, orig_h as f32, offset, nms))?;
+        METRICS.observe_stage(&self.args.name, "postprocess", t.elapsed());
         if self.args.profile {
-            println!("[model output]: {:?}", t.elapsed());
+            info!(elapsed = ?t.elapsed(), detections = ys.2.len(), "postprocessing");
         }
-        let t = std::time::Instant::now();
+        Ok(ys)
+    }
+}
 
-        let (filtered_boxes, filtered_classes, filtered_conf ) = self.postprocessor.lock().await
-            .postprocess(i, orig_w as f32, orig_h as f32, offset)
-            .map_err(|e| Status::internal(format!("Postprocessing error: {}", e)))?;
-        if self.args.profile {
-            println!("[postprocessing]: {:?}", t.elapsed());
+fn load_sessions(args: &Args) -> Result<Vec<ort::session::Session>, VisionError> {
+    let onnx_model = OnnxModel::new(args.cuda, args.intra_threads);
+    let mut sessions = Vec::with_capacity(args.sessions.max(1));
+    for _ in 0..args.sessions.max(1) {
+        sessions.push(onnx_model.load_model(&args.model)?);
+    }
+    Ok(sessions)
+}
+
+fn poisoned() -> VisionError {
+    VisionError::Internal("Session pool lock poisoned".to_string())
+}
+
+/// `ImageProcessor` answering `UNAVAILABLE` until its model is set, so the server can
+/// report health while the model loads.
+#[derive(Debug, Clone, Default)]
+pub struct LazyImageProcessor(Arc<OnceLock<Arc<MyImageProcessor>>>);
+
+impl LazyImageProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts serving `processor`, only the first call has an effect.
+    pub fn set(&self, processor: Arc<MyImageProcessor>) {
+        let _ = self.0.set(processor);
+    }
+
+    pub(crate) fn get_arc(&self) -> Result<Arc<MyImageProcessor>, VisionError> {
+        self.0
+            .get()
+            .cloned()
+            .ok_or_else(|| VisionError::Unavailable("The model is still loading".to_string()))
+    }
+
+    fn get(&self) -> Result<&MyImageProcessor, VisionError> {
+        self.0
+            .get()
+            .map(|processor| processor.as_ref())
+            .ok_or_else(|| VisionError::Unavailable("The model is still loading".to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl grpc::image_processor_server::ImageProcessor for LazyImageProcessor {
+    async fn process_image(
+        &self,
+        request: tonic::Request<crate::grpc::ImageRequest>,
+    ) -> Result<tonic::Response<crate::grpc::DetectionResponse>, tonic::Status> {
+        self.get()?.process_image(request).await
+    }
+}
+
+#[tonic::async_trait]
+impl grpc::image_processor_server::ImageProcessor for MyImageProcessor {
+    async fn process_image(
+        &self,
+        request: tonic::Request<crate::grpc::ImageRequest>,
+    ) -> Result<tonic::Response<crate::grpc::DetectionResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let result = async {
+            let t = std::time::Instant::now();
+            // 1. Decode image bytes
+            let nms = match &request.nms {
+                Some(config) => self.postprocessor.nms().merge(config)?,
+                None => self.postprocessor.nms(),
+            };
+            let image = info_span!("decode", bytes = request.image_data.len())
+                .in_scope(|| image::load_from_memory(&request.image_data))
+                .map_err(|e| VisionError::InvalidArgument(format!("Invalid image: {}", e)))?;
+            METRICS.observe_stage(&self.args.name, "decode", t.elapsed());
+            if self.args.profile {
+                info!(elapsed = ?t.elapsed(), width = image.width(), height = image.height(), "image loading");
+            }
+
+            // 2. Preprocess, run and postprocess
+            let (filtered_boxes, filtered_classes, filtered_conf) = self.detect(image, &nms).await?;
+            METRICS.observe_detections(&self.args.name, filtered_conf.len());
+            // 3. Prepare response
+            Ok::<_, tonic::Status>(Response::new(crate::grpc::DetectionResponse {
+                filtered_conf,
+                filtered_classes,
+                filtered_boxes: filtered_boxes.into_iter().flatten().collect(),
+            }))
         }
-        // 3. Prepare response
-        Ok(Response::new(crate::grpc::DetectionResponse {
-            filtered_conf,
-            filtered_classes,
-            filtered_boxes: filtered_boxes.into_iter().flat_map(|b| b).collect(),
-        }))
+        .await;
+        METRICS.observe_request(&self.args.name, "ProcessImage", &result);
+        result
     }
 
     
-    
 
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{KeyValue, global};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{Resource, runtime, trace};
+use tonic::codegen::http;
+use tracing::{Span, field, info_span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, fmt};
+
+use crate::error::VisionError;
+
+/// Flushes pending spans to the collector when dropped.
+#[derive(Debug)]
+pub struct Telemetry {
+    otlp: bool,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if self.otlp {
+            global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber: log lines on stdout filtered by `RUST_LOG`
+/// (`info` by default), and spans exported over OTLP/gRPC if `otlp_endpoint` is set.
+/// Must be called within the tokio runtime.
+pub fn init(service: &str, otlp_endpoint: Option<&str>) -> Result<Telemetry, VisionError> {
+    // W3C `traceparent` and `tracestate`, read from incoming calls by `grpc_span`
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otlp = match otlp_endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", service.to_string()),
+                ])))
+                .install_batch(runtime::Tokio)
+                .map_err(|e| VisionError::InvalidArgument(format!("OTLP exporter: {}", e)))?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+    let exporting = otlp.is_some();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(otlp)
+        .try_init()
+        .map_err(|e| VisionError::Internal(format!("Tracing subscriber: {}", e)))?;
+    if let Some(endpoint) = otlp_endpoint {
+        tracing::info!(endpoint, "Exporting traces over OTLP");
+    }
+    Ok(Telemetry { otlp: exporting })
+}
+
+/// Span of one gRPC call, a child of the caller's span when the call carries a
+/// W3C `traceparent` header. Used as the tonic `trace_fn`.
+pub fn grpc_span(request: &http::Request<()>) -> Span {
+    let method = request.uri().path();
+    let span = info_span!(
+        "grpc",
+        otel.name = method,
+        otel.kind = "server",
+        rpc.system = "grpc",
+        rpc.method = method,
+        trace_id = field::Empty,
+    );
+    with_parent(span, request.headers())
+}
+
+/// Span of one HTTP request, like `grpc_span`.
+pub fn http_span<B>(request: &http::Request<B>) -> Span {
+    let route = format!("{} {}", request.method(), request.uri().path());
+    let span = info_span!(
+        "http",
+        otel.name = route,
+        otel.kind = "server",
+        http.method = %request.method(),
+        http.target = %request.uri(),
+        trace_id = field::Empty,
+    );
+    with_parent(span, request.headers())
+}
+
+fn with_parent(span: Span, headers: &http::HeaderMap) -> Span {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&Headers(headers)));
+    // also in the log lines, so they can be matched with the client trace
+    let context = parent.span().span_context().clone();
+    if context.is_valid() {
+        span.record("trace_id", field::display(context.trace_id()));
+    }
+    span.set_parent(parent);
+    span
+}
+
+struct Headers<'a>(&'a http::HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
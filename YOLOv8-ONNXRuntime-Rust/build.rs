@@ -1,14 +1,18 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env::set_var("PROTOC", r"C:\protoc-30.1-win64\bin\protoc.exe");
     let proto_dir = Path::new("proto");
-    let proto_file = proto_dir.join("result.proto");
-    tonic_build::compile_protos(proto_file).map_err(|e| {
-        eprintln!("Failed to compile protos: {}", e);
-        e
-    })?;
+    let proto_files = [proto_dir.join("result.proto"), proto_dir.join("registry.proto")];
+    // descriptors of the protos, served by gRPC reflection
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR")?).join("grpc_descriptor.bin");
+    tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
+        .compile(&proto_files, &[proto_dir])
+        .map_err(|e| {
+            eprintln!("Failed to compile protos: {}", e);
+            e
+        })?;
     Ok(())
 }
\ No newline at end of file
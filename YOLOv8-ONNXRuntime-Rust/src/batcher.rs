@@ -0,0 +1,208 @@
+use image::DynamicImage;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::{info_span, Span};
+
+use crate::error::{Result, VisionError};
+use crate::metrics::{detections, METRICS};
+use crate::{RunOptions, YOLOResult, YOLOv8};
+
+#[derive(Debug)]
+struct Job {
+    // a single image waiting for inference and where to send its result
+    image: DynamicImage,
+    options: Option<RunOptions>,
+    reply: oneshot::Sender<Result<YOLOResult>>,
+    // span of the caller, the parent of the batch it runs in
+    span: Span,
+}
+
+/// Groups images from one or many concurrent callers into engine-sized batches.
+///
+/// Each model (one ORT session) is owned by a dedicated scheduler thread, and all threads
+/// pull from one shared queue. The first queued image opens a batch, which is closed when
+/// it reaches the engine's max batch or after `max_wait`.
+#[derive(Debug, Clone)]
+pub struct Batcher {
+    queue: Arc<RwLock<Sender<Job>>>,
+    max_wait: Duration,
+    // model label of the queue depth gauge
+    name: String,
+}
+
+impl Batcher {
+    pub fn new(models: Vec<YOLOv8>, max_wait: Duration) -> Result<Self> {
+        let name = models.first().map_or_else(String::new, |model| model.name().to_string());
+        Ok(Self {
+            queue: Arc::new(RwLock::new(spawn(models, max_wait)?)),
+            max_wait,
+            name,
+        })
+    }
+
+    /// Replaces the models of every clone of this batcher.
+    /// Images already queued still run on the old models, whose threads exit once done.
+    pub fn swap(&self, models: Vec<YOLOv8>) -> Result<()> {
+        let queue = spawn(models, self.max_wait)?;
+        *self
+            .queue
+            .write()
+            .map_err(|_| VisionError::Internal("Batcher queue poisoned".to_string()))? = queue;
+        Ok(())
+    }
+
+    /// Queues one image without waiting, the result arrives on the returned receiver.
+    /// `options` overrides the model settings for this image.
+    pub fn enqueue(&self, image: DynamicImage, options: Option<RunOptions>) -> Result<Pending> {
+        let (reply, result) = oneshot::channel();
+        // counted before sending, a scheduler may take the job right away
+        let depth = METRICS.queue_depth.with_label_values(&[&self.name]);
+        depth.inc();
+        self.queue
+            .read()
+            .map_err(|_| VisionError::Internal("Batcher queue poisoned".to_string()))?
+            .send(Job {
+                image,
+                options,
+                reply,
+                span: Span::current(),
+            })
+            .map_err(|_| {
+                depth.dec();
+                VisionError::Internal("Batcher is not running".to_string())
+            })?;
+        Ok(Pending(result))
+    }
+
+    /// Queues one image and waits for its result.
+    pub async fn submit(
+        &self,
+        image: DynamicImage,
+        options: Option<RunOptions>,
+    ) -> Result<YOLOResult> {
+        self.enqueue(image, options)?.wait().await
+    }
+
+    /// Queues all images at once so they can share batches, results keep input order.
+    pub async fn submit_all(
+        &self,
+        images: Vec<DynamicImage>,
+        options: Option<RunOptions>,
+    ) -> Result<Vec<YOLOResult>> {
+        let pending = images
+            .into_iter()
+            .map(|image| self.enqueue(image, options.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ys = Vec::with_capacity(pending.len());
+        for result in pending {
+            ys.push(result.wait().await?);
+        }
+        Ok(ys)
+    }
+}
+
+/// A queued image whose result has not been awaited yet.
+#[derive(Debug)]
+pub struct Pending(oneshot::Receiver<Result<YOLOResult>>);
+
+impl Pending {
+    pub async fn wait(self) -> Result<YOLOResult> {
+        self.0
+            .await
+            .map_err(|_| VisionError::Internal("Batcher dropped the request".to_string()))?
+    }
+}
+
+fn spawn(models: Vec<YOLOv8>, max_wait: Duration) -> Result<Sender<Job>> {
+    // one scheduler thread per model, all pulling from a new queue
+    let (queue, jobs) = mpsc::channel();
+    let jobs = Arc::new(Mutex::new(jobs));
+    for (i, model) in models.into_iter().enumerate() {
+        let jobs = Arc::clone(&jobs);
+        std::thread::Builder::new()
+            .name(format!("yolo-batcher-{}", i))
+            .spawn(move || schedule(model, jobs, max_wait))
+            .map_err(|e| {
+                VisionError::Internal(format!("Failed to spawn batcher thread: {}", e))
+            })?;
+    }
+    Ok(queue)
+}
+
+fn schedule(mut model: YOLOv8, jobs: Arc<Mutex<Receiver<Job>>>, max_wait: Duration) {
+    let max_batch = model.max_batch() as usize;
+
+    loop {
+        // only one idle thread gathers a batch at a time, the lock is released before running
+        let batch = {
+            let jobs = match jobs.lock() {
+                Ok(jobs) => jobs,
+                Err(_) => return,
+            };
+
+            // block for the first job, exit once every sender is gone
+            let Ok(first) = jobs.recv() else {
+                return;
+            };
+            let mut batch = vec![first];
+            let deadline = Instant::now() + max_wait;
+            while batch.len() < max_batch {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match jobs.recv_timeout(deadline - now) {
+                    Ok(job) => batch.push(job),
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            batch
+        };
+        let name = model.name().to_string();
+        METRICS.queue_depth.with_label_values(&[&name]).sub(batch.len() as i64);
+        METRICS.observe_batch(&name, batch.len());
+
+        // a child of the first caller, linked from the others
+        let span = info_span!(parent: &batch[0].span, "batch", model = %name, size = batch.len());
+        for job in &batch[1..] {
+            span.follows_from(&job.span);
+        }
+        let _span = span.enter();
+
+        let mut xs = Vec::with_capacity(batch.len());
+        let mut options = Vec::with_capacity(batch.len());
+        let mut replies = Vec::with_capacity(batch.len());
+        for job in batch {
+            xs.push(job.image);
+            options.push(job.options.unwrap_or_else(|| model.options()));
+            replies.push(job.reply);
+        }
+        let ys = match model.run_with(&xs, &options) {
+            Ok(ys) => ys.into_iter().map(Ok).collect(),
+            // the batch may mix callers, run each image alone so one bad image
+            // only fails its own request
+            Err(_) if xs.len() > 1 => xs
+                .iter()
+                .zip(&options)
+                .map(|(x, options)| run_one(&mut model, x, options))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        for (reply, y) in replies.into_iter().zip(ys) {
+            if let Ok(y) = &y {
+                METRICS.observe_detections(&name, detections(y));
+            }
+            let _ = reply.send(y);
+        }
+    }
+}
+
+fn run_one(model: &mut YOLOv8, x: &DynamicImage, options: &RunOptions) -> Result<YOLOResult> {
+    model
+        .run_with(std::slice::from_ref(x), std::slice::from_ref(options))?
+        .pop()
+        .ok_or_else(|| VisionError::Internal("The model returned no result".to_string()))
+}
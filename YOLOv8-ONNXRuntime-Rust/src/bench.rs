@@ -0,0 +1,357 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::cli::BenchArgs;
+use crate::utils::find_images;
+use crate::{Args, RunOptions, VisionError, YOLOv8};
+
+/// An image or model input size, `WIDTHxHEIGHT`, or a single side for a square.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let side = |v: &str| v.trim().parse::<u32>().ok().filter(|&v| v > 0);
+        let size = match s.split_once(['x', 'X']) {
+            Some((width, height)) => side(width).zip(side(height)),
+            None => side(s).map(|side| (side, side)),
+        };
+        size.map(|(width, height)| Self { width, height })
+            .ok_or_else(|| format!("Invalid size {:?}, expected WIDTHxHEIGHT", s))
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Latency of a stage over the timed iterations, nearest-rank percentiles.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Latency {
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Latency {
+    pub fn new(samples: &[Duration]) -> Self {
+        let mut ms: Vec<f64> = samples.iter().map(|d| d.as_secs_f64() * 1e3).collect();
+        ms.sort_by(f64::total_cmp);
+        let Some(&max_ms) = ms.last() else {
+            return Self::default();
+        };
+        let at = |p: f64| ms[((p / 100. * ms.len() as f64).ceil() as usize).clamp(1, ms.len()) - 1];
+        Self {
+            mean_ms: ms.iter().sum::<f64>() / ms.len() as f64,
+            p50_ms: at(50.),
+            p90_ms: at(90.),
+            p99_ms: at(99.),
+            max_ms,
+        }
+    }
+}
+
+/// Timings of one batch size and input size.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchResult {
+    pub batch: u32,
+    pub resolution: Resolution,
+    pub preprocess: Latency,
+    pub inference: Latency,
+    pub postprocess: Latency,
+    // the three stages of a batch
+    pub total: Latency,
+    // over the time spent in the stages
+    pub images_per_sec: f64,
+    // peak resident memory of the process while timing, Linux only
+    pub peak_memory_bytes: Option<u64>,
+    // false if the kernel refused to reset the peak, which then covers the run so far
+    pub peak_memory_reset: bool,
+}
+
+/// Result file of a `bench` run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub model: String,
+    // execution provider and task, as debug-printed
+    pub ep: String,
+    pub task: String,
+    // `--source`, or `synthetic WIDTHxHEIGHT`
+    pub images: String,
+    // of the machine, and of each ORT session if set
+    pub threads: usize,
+    pub intra_threads: Option<usize>,
+    pub warmup: usize,
+    pub iterations: usize,
+    pub results: Vec<BenchResult>,
+}
+
+/// Runs the `--model` model at every input size and batch size of `bench`: `warmup`
+/// untimed batches, then `iterations` timed ones, each stage timed on its own. Prints the
+/// latencies, throughput and peak memory of each configuration and writes them as JSON to
+/// `bench.output`. Input sizes of fixed-size models and batch sizes of fixed-batch models
+/// other than their own are skipped.
+pub fn bench(args: &Args, bench: &BenchArgs) -> Result<BenchReport, VisionError> {
+    if args.model.is_empty() {
+        return Err(VisionError::InvalidArgument(
+            "`bench` runs the `--model` model, none is set".to_string(),
+        ));
+    }
+    if bench.iterations == 0 || bench.batches.contains(&0) {
+        return Err(VisionError::InvalidArgument(
+            "`--iterations` and `--batches` must be positive".to_string(),
+        ));
+    }
+    let (images, source) = match &bench.source {
+        Some(source) => {
+            let paths = find_images(source)?;
+            if paths.is_empty() {
+                return Err(VisionError::NotFound(format!("No image in {}", source)));
+            }
+            let images = paths
+                .iter()
+                .map(image::open)
+                .collect::<Result<Vec<_>, _>>()?;
+            (images, source.clone())
+        }
+        None => (
+            vec![synthetic(bench.image_size)],
+            format!("synthetic {}", bench.image_size),
+        ),
+    };
+
+    let mut args = args.clone();
+    args.plot = false;
+    args.profile = false;
+    // dynamic TensorRT profiles must cover the largest batch
+    if let Some(&batch) = bench.batches.iter().max() {
+        args.batch_max = args.batch_max.max(batch);
+    }
+    let mut report = BenchReport {
+        model: args.model.clone(),
+        ep: String::new(),
+        task: String::new(),
+        images: source,
+        threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        intra_threads: args.intra_threads,
+        warmup: bench.warmup,
+        iterations: bench.iterations,
+        results: vec![],
+    };
+
+    // one model per input size, the `--width` and `--height` of dynamic-size models
+    let resolutions: Vec<Option<Resolution>> = if bench.resolutions.is_empty() {
+        vec![None]
+    } else {
+        bench.resolutions.iter().copied().map(Some).collect()
+    };
+    for resolution in resolutions {
+        let mut args = args.clone();
+        if let Some(resolution) = resolution {
+            args.width = Some(resolution.width);
+            args.height = Some(resolution.height);
+        }
+        let mut model = YOLOv8::new(args)?;
+        report.ep = format!("{:?}", model.engine().ep());
+        report.task = format!("{:?}", model.task());
+        let input = Resolution {
+            width: model.width(),
+            height: model.height(),
+        };
+        if resolution.is_some_and(|resolution| resolution != input) {
+            println!(
+                "[Bench]: skipped {}, the model input is fixed at {}",
+                resolution.unwrap_or(input),
+                input
+            );
+            continue;
+        }
+
+        let batches = if bench.batches.is_empty() {
+            vec![model.batch()]
+        } else {
+            bench.batches.clone()
+        };
+        for batch in batches {
+            if !model.engine().is_batch_dynamic() && batch != model.batch() {
+                println!(
+                    "[Bench]: skipped batch {}, the model batch is fixed at {}",
+                    batch,
+                    model.batch()
+                );
+                continue;
+            }
+            let result = measure(&mut model, &images, batch, input, bench)?;
+            println!(
+                "[Bench]: batch {} {}: preprocess {:.2}/{:.2}ms, inference {:.2}/{:.2}ms, postprocess {:.2}/{:.2}ms (p50/p99), {:.1} images/s, peak {}",
+                batch,
+                input,
+                result.preprocess.p50_ms,
+                result.preprocess.p99_ms,
+                result.inference.p50_ms,
+                result.inference.p99_ms,
+                result.postprocess.p50_ms,
+                result.postprocess.p99_ms,
+                result.images_per_sec,
+                result.peak_memory_bytes.map_or("n/a".to_string(), |bytes| format!(
+                    "{:.1} MiB{}",
+                    bytes as f64 / 1048576.,
+                    if result.peak_memory_reset { "" } else { " (since start)" }
+                )),
+            );
+            report.results.push(result);
+        }
+    }
+
+    if let Some(dir) = bench.output.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&bench.output)?), &report)
+        .map_err(|e| VisionError::Io(e.to_string()))?;
+    println!("[Bench]: results in {}", bench.output.display());
+    Ok(report)
+}
+
+fn measure(
+    model: &mut YOLOv8,
+    images: &[DynamicImage],
+    batch: u32,
+    resolution: Resolution,
+    bench: &BenchArgs,
+) -> Result<BenchResult, VisionError> {
+    // the images in turn, as many batches as iterations
+    let batch_at = |k: usize| -> Vec<DynamicImage> {
+        (0..batch as usize)
+            .map(|i| images[(k * batch as usize + i) % images.len()].clone())
+            .collect()
+    };
+    let options = vec![model.options(); batch as usize];
+    for k in 0..bench.warmup {
+        run(model, &batch_at(k), &options)?;
+    }
+
+    let peak_memory_reset = reset_peak_memory();
+    let mut stages: [Vec<Duration>; 4] = Default::default();
+    for k in 0..bench.iterations {
+        let times = run(model, &batch_at(bench.warmup + k), &options)?;
+        for (stage, time) in stages
+            .iter_mut()
+            .zip(times.iter().chain([&times.iter().sum()]))
+        {
+            stage.push(*time);
+        }
+    }
+    let busy: Duration = stages[3].iter().sum();
+    Ok(BenchResult {
+        batch,
+        resolution,
+        preprocess: Latency::new(&stages[0]),
+        inference: Latency::new(&stages[1]),
+        postprocess: Latency::new(&stages[2]),
+        total: Latency::new(&stages[3]),
+        images_per_sec: (bench.iterations * batch as usize) as f64 / busy.as_secs_f64().max(1e-9),
+        peak_memory_bytes: peak_memory(),
+        peak_memory_reset,
+    })
+}
+
+// preprocess, inference and postprocess times of a batch
+fn run(
+    model: &mut YOLOv8,
+    xs: &[DynamicImage],
+    options: &[RunOptions],
+) -> Result<[Duration; 3], VisionError> {
+    let t = Instant::now();
+    model.preprocess(xs)?;
+    let preprocess = t.elapsed();
+    let t = Instant::now();
+    let ys = model.engine().run(model.input(), false)?;
+    let inference = t.elapsed();
+    let t = Instant::now();
+    model.postprocess(ys, xs, options)?;
+    Ok([preprocess, inference, t.elapsed()])
+}
+
+/// Uniform noise, the same for every run.
+fn synthetic(size: Resolution) -> DynamicImage {
+    let mut rng = StdRng::seed_from_u64(0);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(size.width, size.height, |_, _| {
+        Rgb(rng.gen())
+    }))
+}
+
+/// Peak resident memory of the process (`VmHWM`), Linux only.
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// restarts the peak at the current resident memory, false where the kernel does not allow it
+fn reset_peak_memory() -> bool {
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(values: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        values.into_iter().map(Duration::from_millis).collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn latency_of_one_sample() {
+        let latency = Latency::new(&ms([7]));
+        for v in [
+            latency.mean_ms,
+            latency.p50_ms,
+            latency.p90_ms,
+            latency.p99_ms,
+            latency.max_ms,
+        ] {
+            assert!(close(v, 7.));
+        }
+        assert_eq!(Latency::new(&[]), Latency::default());
+    }
+
+    #[test]
+    fn latency_percentiles_are_nearest_rank() {
+        // 10 samples: ranks ceil(5) = 5, ceil(9) = 9 and ceil(9.9) = 10
+        let latency = Latency::new(&ms([10, 3, 8, 1, 6, 2, 9, 4, 7, 5]));
+        assert!(close(latency.mean_ms, 5.5));
+        assert!(close(latency.p50_ms, 5.));
+        assert!(close(latency.p90_ms, 9.));
+        assert!(close(latency.p99_ms, 10.));
+        assert!(close(latency.max_ms, 10.));
+
+        // 100 samples in a scrambled order, 37 is coprime with 100
+        let latency = Latency::new(&ms((0..100).map(|i| (i * 37) % 100 + 1)));
+        assert!(close(latency.mean_ms, 50.5));
+        assert!(close(latency.p50_ms, 50.));
+        assert!(close(latency.p90_ms, 90.));
+        assert!(close(latency.p99_ms, 99.));
+        assert!(close(latency.max_ms, 100.));
+    }
+}
@@ -1,83 +1,351 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
-use crate::YOLOTask;
+use crate::bench::Resolution;
+use crate::eval::MetricKind;
+use crate::{ExportFormat, MaskFormat, ModelKind, ModelSpec, NmsStrategy, Segmentation, YOLOTask};
 
-#[derive(Parser, Clone)]
+#[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// ONNX model path
-    #[arg(long, required = true)]
+    /// TOML config file, its values apply where neither a flag nor an env var is set
+    #[arg(long, env = "YOLO_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// address the gRPC server listens on
+    #[arg(long, env = "YOLO_BIND", default_value = "[::1]:50051")]
+    pub bind: SocketAddr,
+
+    /// address of the Prometheus `/metrics` endpoint, disabled if unset
+    #[arg(long, env = "YOLO_METRICS_BIND")]
+    pub metrics_bind: Option<SocketAddr>,
+
+    /// address of the HTTP/JSON gateway, disabled if unset
+    #[arg(long, env = "YOLO_HTTP_BIND")]
+    pub http_bind: Option<SocketAddr>,
+
+    /// directory the LoadModel RPC may load model and config files from, disabled if unset
+    #[arg(long, env = "YOLO_MODEL_DIR")]
+    pub model_dir: Option<PathBuf>,
+
+    /// ONNX model path, required here or in the config file
+    #[arg(long, env = "YOLO_MODEL", default_value = "", hide_default_value = true)]
     pub model: String,
 
+    /// model name used as the `model` label of the metrics
+    #[arg(long, env = "YOLO_NAME", default_value = "yolo")]
+    pub name: String,
+
     /// device id
-    #[arg(long, default_value_t = 0)]
+    #[arg(long, env = "YOLO_DEVICE_ID", default_value_t = 0)]
     pub device_id: i32,
 
     /// using TensorRT EP
-    #[arg(long)]
+    #[arg(long, env = "YOLO_TRT")]
     pub trt: bool,
 
+    /// poll the model file every N ms and reload it when changed, 0 disables
+    #[arg(long, env = "YOLO_WATCH_MS", default_value_t = 0)]
+    pub watch_ms: u64,
+
     /// using CUDA EP
-    #[arg(long)]
+    #[arg(long, env = "YOLO_CUDA")]
     pub cuda: bool,
 
     /// input batch size
-    #[arg(long, default_value_t = 1)]
+    #[arg(long, env = "YOLO_BATCH", default_value_t = 1)]
     pub batch: u32,
 
     /// trt input min_batch size
-    #[arg(long, default_value_t = 1)]
+    #[arg(long, env = "YOLO_BATCH_MIN", default_value_t = 1)]
     pub batch_min: u32,
 
     /// trt input max_batch size
-    #[arg(long, default_value_t = 32)]
+    #[arg(long, env = "YOLO_BATCH_MAX", default_value_t = 32)]
     pub batch_max: u32,
 
+    /// number of ORT sessions serving requests in parallel
+    #[arg(long, env = "YOLO_SESSIONS", default_value_t = 1)]
+    pub sessions: usize,
+
+    /// intra-op threads per ORT session (ORT default if unset)
+    #[arg(long, env = "YOLO_INTRA_THREADS")]
+    pub intra_threads: Option<usize>,
+
+    /// max time (ms) to wait for more images before running a batch
+    #[arg(long, env = "YOLO_MAX_WAIT_MS", default_value_t = 5)]
+    pub max_wait_ms: u64,
+
     /// using TensorRT --fp16
-    #[arg(long)]
+    #[arg(long, env = "YOLO_FP16")]
     pub fp16: bool,
 
     /// specify YOLO task
-    #[arg(long, value_enum)]
+    #[arg(long, env = "YOLO_TASK", value_enum)]
     pub task: Option<YOLOTask>,
 
     /// num_classes
-    #[arg(long)]
+    #[arg(long, env = "YOLO_NC")]
     pub nc: Option<u32>,
 
     /// num_keypoints
-    #[arg(long)]
+    #[arg(long, env = "YOLO_NK")]
     pub nk: Option<u32>,
 
     /// num_masks
-    #[arg(long)]
+    #[arg(long, env = "YOLO_NM")]
     pub nm: Option<u32>,
 
     /// input image width
-    #[arg(long)]
+    #[arg(long, env = "YOLO_WIDTH")]
     pub width: Option<u32>,
 
     /// input image height
-    #[arg(long)]
+    #[arg(long, env = "YOLO_HEIGHT")]
     pub height: Option<u32>,
 
     /// confidence threshold
-    #[arg(long, required = false, default_value_t = 0.3)]
+    #[arg(long, env = "YOLO_CONF", required = false, default_value_t = 0.3)]
     pub conf: f32,
 
     /// iou threshold in NMS
-    #[arg(long, required = false, default_value_t = 0.45)]
+    #[arg(long, env = "YOLO_IOU", required = false, default_value_t = 0.45)]
     pub iou: f32,
 
+    /// NMS strategy
+    #[arg(long, env = "YOLO_NMS", value_enum, default_value_t = NmsStrategy::Agnostic)]
+    pub nms: NmsStrategy,
+
+    /// score decay of gaussian Soft-NMS
+    #[arg(long, env = "YOLO_NMS_SIGMA", default_value_t = 0.5)]
+    pub nms_sigma: f32,
+
+    /// max detections kept per image after NMS, 0 keeps all
+    #[arg(long, env = "YOLO_MAX_DET", default_value_t = 300)]
+    pub max_det: usize,
+
     /// confidence threshold of keypoint
-    #[arg(long, required = false, default_value_t = 0.55)]
+    #[arg(long, env = "YOLO_KCONF", required = false, default_value_t = 0.55)]
     pub kconf: f32,
 
+    /// sliced (SAHI) inference for large images
+    #[arg(long, env = "YOLO_SLICE")]
+    pub slice: bool,
+
+    /// slice width
+    #[arg(long, env = "YOLO_SLICE_WIDTH", default_value_t = 640)]
+    pub slice_width: u32,
+
+    /// slice height
+    #[arg(long, env = "YOLO_SLICE_HEIGHT", default_value_t = 640)]
+    pub slice_height: u32,
+
+    /// overlap ratio between neighbouring slices
+    #[arg(long, env = "YOLO_SLICE_OVERLAP", default_value_t = 0.2)]
+    pub slice_overlap: f32,
+
+    /// also run the whole image, merged with the slices
+    #[arg(long, env = "YOLO_SLICE_FULL_IMAGE")]
+    pub slice_full_image: bool,
+
+    /// strategy merging detections across slices
+    #[arg(long, env = "YOLO_SLICE_MERGE", value_enum, default_value_t = NmsStrategy::ClassAware)]
+    pub slice_merge: NmsStrategy,
+
+    /// iou threshold of the slice merge
+    #[arg(long, env = "YOLO_SLICE_MERGE_IOU", default_value_t = 0.5)]
+    pub slice_merge_iou: f32,
+
+    /// test-time augmentation: flipped and multi-scale copies of each image
+    #[arg(long, env = "YOLO_TTA")]
+    pub tta: bool,
+
+    /// scales of the test-time augmentation copies
+    #[arg(long, env = "YOLO_TTA_SCALES", value_delimiter = ',', default_values_t = [1.0, 0.83, 0.67])]
+    pub tta_scales: Vec<f32>,
+
+    /// skip the horizontally flipped test-time augmentation copies, needed by pose models
+    /// without the 17-point COCO keypoint layout
+    #[arg(long, env = "YOLO_TTA_NO_FLIP")]
+    pub tta_no_flip: bool,
+
+    /// strategy fusing detections across test-time augmentation copies
+    #[arg(long, env = "YOLO_TTA_MERGE", value_enum, default_value_t = NmsStrategy::Wbf)]
+    pub tta_merge: NmsStrategy,
+
+    /// iou threshold of the test-time augmentation merge
+    #[arg(long, env = "YOLO_TTA_MERGE_IOU", default_value_t = 0.55)]
+    pub tta_merge_iou: f32,
+
+    /// mask probability threshold of segmentation
+    #[arg(long, env = "YOLO_MASK_THRESHOLD", required = false, default_value_t = 0.5)]
+    pub mask_threshold: f32,
+
+    /// keep mask probabilities (0-255) instead of binary masks, RLE, polygons, exports and
+    /// mask mAP still cut them at `--mask-threshold`
+    #[arg(long, env = "YOLO_SOFT_MASKS")]
+    pub soft_masks: bool,
+
     /// plot inference result and save
-    #[arg(long)]
+    #[arg(long, env = "YOLO_PLOT")]
     pub plot: bool,
 
     /// check time consumed in each stage
-    #[arg(long)]
+    #[arg(long, env = "YOLO_PROFILE")]
     pub profile: bool,
+
+    /// OTLP/gRPC collector receiving trace spans, e.g. `http://localhost:4317`
+    #[arg(long, env = "YOLO_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// named models of the model registry, from `[[models]]` in the config file
+    #[arg(skip)]
+    pub models: Vec<ModelSpec>,
+
+    /// what to run, `serve` if unset
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Start the gRPC server (default)
+    Serve,
+    /// Run the model over image files and save the results
+    Predict(PredictArgs),
+    /// Evaluate the model against an annotated dataset and report its mAP
+    Val(ValArgs),
+    /// Time each stage of the model over a sweep of batch sizes and input sizes
+    Bench(BenchArgs),
+    /// Run the model over the frames of a clip and save the annotated clip
+    Video(VideoArgs),
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct PredictArgs {
+    /// image file, directory, or `*`/`?` pattern of file names, e.g. `images/*.jpg`
+    #[arg(long)]
+    pub source: String,
+
+    /// directory receiving the annotated images and result files
+    #[arg(long, default_value = "runs/predict")]
+    pub output: PathBuf,
+
+    /// mask encoding of the result files
+    #[arg(long, value_enum, default_value_t = MaskFormat::Rle)]
+    pub mask_format: MaskFormat,
+
+    /// only write the result files, without annotated images
+    #[arg(long)]
+    pub no_plot: bool,
+
+    /// also export the results to `--output`, comma separated: coco-results, coco, yolo, voc, csv
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub export: Vec<ExportFormat>,
+
+    /// mask encoding of the COCO exports
+    #[arg(long, value_enum, default_value_t = Segmentation::Rle)]
+    pub segmentation: Segmentation,
+
+    /// COCO annotations JSON of the images, the COCO exports take their image ids from it
+    #[arg(long)]
+    pub coco: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct ValArgs {
+    /// COCO annotations JSON, or the images of a YOLO dataset: file, directory or `*`/`?` pattern
+    #[arg(long)]
+    pub data: String,
+
+    /// image directory of a COCO dataset
+    #[arg(long)]
+    pub images: Option<PathBuf>,
+
+    /// label directory of a YOLO dataset, the `labels` directory next to `images` if unset
+    #[arg(long)]
+    pub labels: Option<PathBuf>,
+
+    /// model family of `--model`
+    #[arg(long, value_enum, default_value_t = ModelKind::Yolo)]
+    pub kind: ModelKind,
+
+    /// RF-DETR config file of an `rf-detr` model, `RFDETR_*` env vars apply on top
+    #[arg(long)]
+    pub rf_detr_config: Option<PathBuf>,
+
+    /// confidence threshold of the evaluated detections, in place of `--conf`
+    #[arg(long, default_value_t = 0.001)]
+    pub min_conf: f32,
+
+    /// metrics to report, comma separated: box, mask, pose; those of the model task if unset
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub metrics: Vec<MetricKind>,
+
+    /// directory receiving `metrics.json`
+    #[arg(long, default_value = "runs/val")]
+    pub output: PathBuf,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct BenchArgs {
+    /// timed batches per configuration
+    #[arg(long, default_value_t = 100)]
+    pub iterations: usize,
+
+    /// untimed batches run first per configuration
+    #[arg(long, default_value_t = 10)]
+    pub warmup: usize,
+
+    /// batch sizes, comma separated; the model batch if unset
+    #[arg(long, value_delimiter = ',')]
+    pub batches: Vec<u32>,
+
+    /// model input sizes of dynamic-size models, comma separated `WIDTHxHEIGHT`; the model input size if unset
+    #[arg(long, value_delimiter = ',')]
+    pub resolutions: Vec<Resolution>,
+
+    /// image file, directory or `*`/`?` pattern used in turn; synthetic images if unset
+    #[arg(long)]
+    pub source: Option<String>,
+
+    /// size of the synthetic images
+    #[arg(long, default_value = "1280x720")]
+    pub image_size: Resolution,
+
+    /// JSON result file
+    #[arg(long, default_value = "runs/bench/bench.json")]
+    pub output: PathBuf,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct VideoArgs {
+    /// `.gif`, `.y4m` or Motion-JPEG `.avi` file, or a directory or `*`/`?` pattern of numbered frames
+    #[arg(long)]
+    pub source: String,
+
+    /// annotated clip, in the format of its extension as for `--source`; `runs/video/<source name>` if unset
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// model family of `--model`
+    #[arg(long, value_enum, default_value_t = ModelKind::Yolo)]
+    pub kind: ModelKind,
+
+    /// RF-DETR config file of an `rf-detr` model, `RFDETR_*` env vars apply on top
+    #[arg(long)]
+    pub rf_detr_config: Option<PathBuf>,
+
+    /// frame rate of image sequences, and of GIF frames without a delay
+    #[arg(long, default_value_t = 30.)]
+    pub fps: f64,
+
+    /// mask encoding of the result file
+    #[arg(long, value_enum, default_value_t = MaskFormat::Rle)]
+    pub mask_format: MaskFormat,
+
+    /// only write the result file, without the annotated clip
+    #[arg(long)]
+    pub no_plot: bool,
 }
@@ -0,0 +1,247 @@
+use clap::{ArgMatches, CommandFactory};
+use rf_detr::config::{parse_enum, read_toml};
+use rf_detr::LoadArgs;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::{Args, ModelSpec, NmsStrategy, VisionError, YOLOTask};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // server settings from a TOML file, every key is optional
+    pub server: ServerSection,
+    pub model: ModelSection,
+    pub device: DeviceSection,
+    pub batch: BatchSection,
+    pub thresholds: ThresholdsSection,
+    pub nms: NmsSection,
+    pub segment: SegmentSection,
+    pub slicing: SlicingSection,
+    pub tta: TtaSection,
+    pub logging: LoggingSection,
+    // models of the registry, `[[models]]` entries
+    pub models: Vec<ModelSpec>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind: Option<SocketAddr>,
+    pub metrics_bind: Option<SocketAddr>,
+    pub http_bind: Option<SocketAddr>,
+    pub model_dir: Option<PathBuf>,
+    pub sessions: Option<usize>,
+    pub max_wait_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelSection {
+    pub path: Option<String>,
+    pub name: Option<String>,
+    pub task: Option<String>,
+    pub nc: Option<u32>,
+    pub nk: Option<u32>,
+    pub nm: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub watch_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceSection {
+    // execution provider: cpu, cuda or trt
+    pub ep: Option<String>,
+    pub device_id: Option<i32>,
+    pub fp16: Option<bool>,
+    pub intra_threads: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchSection {
+    pub size: Option<u32>,
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThresholdsSection {
+    pub conf: Option<f32>,
+    pub iou: Option<f32>,
+    pub kconf: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NmsSection {
+    pub strategy: Option<String>,
+    pub sigma: Option<f32>,
+    pub max_det: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentSection {
+    pub mask_threshold: Option<f32>,
+    pub soft_masks: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlicingSection {
+    pub enabled: Option<bool>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub overlap: Option<f32>,
+    pub full_image: Option<bool>,
+    pub merge: Option<String>,
+    pub merge_iou: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtaSection {
+    pub enabled: Option<bool>,
+    pub scales: Option<Vec<f32>>,
+    pub flip: Option<bool>,
+    pub merge: Option<String>,
+    pub merge_iou: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub profile: Option<bool>,
+    pub plot: Option<bool>,
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, VisionError> {
+        Ok(read_toml(path)?)
+    }
+
+    /// Sets every argument of `args` that `matches` did not take from a flag or an env var.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<(), VisionError> {
+        let unset = |id: &str| rf_detr::config::unset(matches, id);
+        macro_rules! set {
+            ($field:ident, $value:expr) => {
+                rf_detr::set_arg!(args, matches, $field, $value)
+            };
+        }
+
+        let Config {
+            server,
+            model,
+            device,
+            batch,
+            thresholds,
+            nms,
+            segment,
+            slicing,
+            tta,
+            logging,
+            models,
+        } = self;
+
+        set!(bind, server.bind);
+        set!(metrics_bind, server.metrics_bind.map(Some));
+        set!(http_bind, server.http_bind.map(Some));
+        set!(model_dir, server.model_dir.map(Some));
+        set!(sessions, server.sessions);
+        set!(max_wait_ms, server.max_wait_ms);
+
+        set!(model, model.path);
+        set!(name, model.name);
+        set!(task, parse_enum::<YOLOTask>("model.task", model.task)?.map(Some));
+        set!(nc, model.nc.map(Some));
+        set!(nk, model.nk.map(Some));
+        set!(nm, model.nm.map(Some));
+        set!(width, model.width.map(Some));
+        set!(height, model.height.map(Some));
+        set!(watch_ms, model.watch_ms);
+
+        // the provider flags are only taken from the file when none was given
+        if let Some(ep) = device.ep {
+            if unset("cuda") && unset("trt") {
+                (args.cuda, args.trt) = match ep.to_lowercase().as_str() {
+                    "cpu" => (false, false),
+                    "cuda" => (true, false),
+                    "trt" | "tensorrt" => (false, true),
+                    x => {
+                        return Err(VisionError::InvalidArgument(format!(
+                            "Invalid `device.ep`: {}, expected cpu, cuda or trt",
+                            x
+                        )))
+                    }
+                };
+            }
+        }
+        set!(device_id, device.device_id);
+        set!(fp16, device.fp16);
+        set!(intra_threads, device.intra_threads.map(Some));
+
+        set!(batch, batch.size);
+        set!(batch_min, batch.min);
+        set!(batch_max, batch.max);
+
+        set!(conf, thresholds.conf);
+        set!(iou, thresholds.iou);
+        set!(kconf, thresholds.kconf);
+
+        set!(nms, parse_enum::<NmsStrategy>("nms.strategy", nms.strategy)?);
+        set!(nms_sigma, nms.sigma);
+        set!(max_det, nms.max_det);
+
+        set!(mask_threshold, segment.mask_threshold);
+        set!(soft_masks, segment.soft_masks);
+
+        set!(slice, slicing.enabled);
+        set!(slice_width, slicing.width);
+        set!(slice_height, slicing.height);
+        set!(slice_overlap, slicing.overlap);
+        set!(slice_full_image, slicing.full_image);
+        set!(slice_merge, parse_enum::<NmsStrategy>("slicing.merge", slicing.merge)?);
+        set!(slice_merge_iou, slicing.merge_iou);
+
+        set!(tta, tta.enabled);
+        set!(tta_scales, tta.scales);
+        set!(tta_no_flip, tta.flip.map(|flip| !flip));
+        set!(tta_merge, parse_enum::<NmsStrategy>("tta.merge", tta.merge)?);
+        set!(tta_merge_iou, tta.merge_iou);
+
+        set!(profile, logging.profile);
+        set!(plot, logging.plot);
+        set!(otlp_endpoint, logging.otlp_endpoint.map(Some));
+
+        args.models = models;
+        Ok(())
+    }
+}
+
+impl LoadArgs for Args {
+    fn cli() -> clap::Command {
+        // model flags may also follow the subcommand, e.g. `predict --model yolov8n.onnx`
+        Self::command().mut_args(|arg| arg.global(true))
+    }
+
+    type Error = VisionError;
+
+    fn load_config(mut self, matches: &ArgMatches) -> Result<Self, VisionError> {
+        if let Some(path) = self.config.clone() {
+            Config::from_file(&path)?.apply(&mut self, matches)?;
+        }
+        if self.model.is_empty() && self.models.is_empty() {
+            return Err(VisionError::InvalidArgument(
+                "No model, set `--model`, `YOLO_MODEL`, `model.path` or `[[models]]` in the config file"
+                    .to_string(),
+            ));
+        }
+        Ok(self)
+    }
+}
@@ -1,14 +1,32 @@
+use crate::error::{Result, VisionError};
+
+use crate::mask::{EncodedMask, MaskFormat};
+use crate::model::{ClassFilter, RunOptions};
+use crate::nms::{Nms, NmsStrategy};
+use crate::slicing::Slicing;
 use crate::yolo_result::YOLOResult;
 use crate::grpc::{
+    mask::Encoding as ProtoMaskEncoding,
+    CroppedMask as ProtoCroppedMask,
+    InferenceParams as ProtoInferenceParams,
+    Mask as ProtoMask,
+    MaskFormat as ProtoMaskFormat,
+    Polygon as ProtoPolygon,
+    PolygonMask as ProtoPolygonMask,
+    RleMask as ProtoRleMask,
+    SlicingConfig as ProtoSlicingConfig,
+    NmsConfig as ProtoNmsConfig,
     YoloResult as ProtoYoloResult,
     Embedding as ProtoEmbedding,
     Bbox as ProtoBbox,
     KeypointSet as ProtoKeypointSet,
     Point2 as ProtoPoint2,
+    RotatedBbox as ProtoRotatedBbox,
+    Track as ProtoTrack,
 };
 
-/// Converts the internal YOLO result to the gRPC proto message.
-pub fn convert_yolo_result(internal: &YOLOResult) -> ProtoYoloResult {
+/// Converts the internal YOLO result to the gRPC proto message, with masks in `mask_format`.
+pub fn convert_yolo_result(internal: &YOLOResult, mask_format: MaskFormat) -> ProtoYoloResult {
     let mut proto_result = ProtoYoloResult::default();
 
     if let Some(internal_probs) = &internal.probs {
@@ -55,6 +73,198 @@ pub fn convert_yolo_result(internal: &YOLOResult) -> ProtoYoloResult {
         proto_result.keypoints = proto_keypoints;
     }
 
-    proto_result.masks = internal.masks.clone().unwrap_or_default();
+    if let Some(internal_rotated_bboxes) = &internal.rotated_bboxes {
+        let proto_rotated_bboxes = internal_rotated_bboxes
+            .iter()
+            .map(|rbox| ProtoRotatedBbox {
+                cx: rbox.cx(),
+                cy: rbox.cy(),
+                width: rbox.width(),
+                height: rbox.height(),
+                angle: rbox.angle(),
+                id: rbox.id() as u32,
+                confidence: rbox.confidence(),
+            })
+            .collect::<Vec<_>>();
+        proto_result.rotated_bboxes = proto_rotated_bboxes;
+    }
+
+    if let Some(internal_tracks) = &internal.tracks {
+        let proto_tracks = internal_tracks
+            .iter()
+            .map(|track| {
+                let bbox = track.bbox();
+                ProtoTrack {
+                    track_id: track.id(),
+                    bbox: Some(ProtoBbox {
+                        xmin: bbox.xmin(),
+                        ymin: bbox.ymin(),
+                        width: bbox.width(),
+                        height: bbox.height(),
+                        id: bbox.id() as u32,
+                        confidence: bbox.confidence(),
+                    }),
+                    age: track.age(),
+                    trajectory: track
+                        .trajectory()
+                        .iter()
+                        .map(|point| ProtoPoint2 {
+                            x: point.x(),
+                            y: point.y(),
+                            confidence: point.confidence(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        proto_result.tracks = proto_tracks;
+    }
+
+    if let Some((width, height)) = internal.mask_size() {
+        proto_result.mask_width = width;
+        proto_result.mask_height = height;
+    }
+    if let Some(encoded_masks) = internal.encode_masks(mask_format) {
+        for encoded in encoded_masks {
+            let encoding = match encoded {
+                EncodedMask::Raw(data) => {
+                    proto_result.masks.push(data);
+                    continue;
+                }
+                EncodedMask::Rle(rle) => ProtoMaskEncoding::Rle(ProtoRleMask {
+                    height: rle.height(),
+                    width: rle.width(),
+                    counts: rle.counts().clone(),
+                }),
+                EncodedMask::Polygons(polygons) => ProtoMaskEncoding::Polygons(ProtoPolygonMask {
+                    polygons: polygons
+                        .iter()
+                        .map(|polygon| ProtoPolygon {
+                            points: polygon
+                                .iter()
+                                .map(|point| ProtoPoint2 {
+                                    x: point.x(),
+                                    y: point.y(),
+                                    confidence: point.confidence(),
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                }),
+                EncodedMask::Cropped(cropped) => ProtoMaskEncoding::Cropped(ProtoCroppedMask {
+                    x: cropped.x(),
+                    y: cropped.y(),
+                    width: cropped.width(),
+                    height: cropped.height(),
+                    data: cropped.data().clone(),
+                }),
+            };
+            proto_result.encoded_masks.push(ProtoMask {
+                encoding: Some(encoding),
+            });
+        }
+    }
     proto_result
 }
+
+/// Applies the sliced inference overrides of a request on top of the model settings.
+pub fn convert_slicing_config(base: Slicing, config: &ProtoSlicingConfig) -> Result<Slicing> {
+    let mut slicing = base;
+    if let Some(enabled) = config.enabled {
+        slicing.enabled = enabled;
+    }
+    if let Some(tile_width) = config.tile_width {
+        if tile_width == 0 {
+            return Err(VisionError::InvalidArgument("Slice tile_width must be positive".to_string()));
+        }
+        slicing.tile_width = tile_width;
+    }
+    if let Some(tile_height) = config.tile_height {
+        if tile_height == 0 {
+            return Err(VisionError::InvalidArgument("Slice tile_height must be positive".to_string()));
+        }
+        slicing.tile_height = tile_height;
+    }
+    if let Some(overlap) = config.overlap {
+        if !(0.0..1.0).contains(&overlap) {
+            return Err(VisionError::InvalidArgument(format!(
+                "Slice overlap must be within [0, 1), got {}",
+                overlap
+            )));
+        }
+        slicing.overlap = overlap;
+    }
+    if let Some(full_image) = config.full_image {
+        slicing.full_image = full_image;
+    }
+    slicing.merge = NmsStrategy::from_proto(config.merge, base.merge)?;
+    if let Some(merge_iou) = config.merge_iou {
+        if !(0.0..=1.0).contains(&merge_iou) {
+            return Err(VisionError::InvalidArgument(format!(
+                "Slice merge_iou must be within [0, 1], got {}",
+                merge_iou
+            )));
+        }
+        slicing.merge_iou = merge_iou;
+    }
+    Ok(slicing)
+}
+
+/// Applies the thresholds and class filters of a request on top of the model settings.
+pub fn convert_inference_params(base: RunOptions, params: &ProtoInferenceParams) -> Result<RunOptions> {
+    let mut options = base;
+    if let Some(conf) = params.conf {
+        options.conf = convert_threshold("conf", conf)?;
+    }
+    if let Some(iou) = params.iou {
+        options.nms.iou = convert_threshold("iou", iou)?;
+    }
+    if let Some(kconf) = params.kconf {
+        options.kconf = convert_threshold("kconf", kconf)?;
+    }
+    if let Some(max_det) = params.max_det {
+        options.nms.max_det = max_det as usize;
+    }
+    let mut conf = std::collections::HashMap::with_capacity(params.class_conf.len());
+    for (&id, &class_conf) in params.class_conf.iter() {
+        conf.insert(id as usize, convert_threshold("class_conf", class_conf)?);
+    }
+    options.classes = ClassFilter {
+        keep: params.classes.iter().map(|&id| id as usize).collect(),
+        drop: params.exclude_classes.iter().map(|&id| id as usize).collect(),
+        conf,
+    };
+    Ok(options)
+}
+
+fn convert_threshold(name: &str, value: f32) -> Result<f32> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(VisionError::InvalidArgument(format!(
+            "{} must be within [0, 1], got {}",
+            name, value
+        )));
+    }
+    Ok(value)
+}
+
+/// Converts the mask format of a request.
+pub fn convert_mask_format(format: i32) -> Result<MaskFormat> {
+    match ProtoMaskFormat::from_i32(format) {
+        Some(ProtoMaskFormat::MaskRaw) => Ok(MaskFormat::Raw),
+        Some(ProtoMaskFormat::MaskRle) => Ok(MaskFormat::Rle),
+        Some(ProtoMaskFormat::MaskPolygon) => Ok(MaskFormat::Polygon),
+        Some(ProtoMaskFormat::MaskCropped) => Ok(MaskFormat::Cropped),
+        None => Err(VisionError::InvalidArgument(format!("Unknown mask format: {}", format))),
+    }
+}
+
+/// Applies the NMS overrides of a request on top of the model settings.
+pub fn convert_nms_config(base: Nms, config: &ProtoNmsConfig) -> Result<Nms> {
+    // the YOLO and RF-DETR protos each generate their own NmsConfig, copied field by field
+    Ok(base.merge(&rf_detr::grpc::NmsConfig {
+        strategy: config.strategy,
+        iou: config.iou,
+        sigma: config.sigma,
+        max_det: config.max_det,
+    })?)
+}
@@ -0,0 +1,181 @@
+use ab_glyph::FontArc;
+use image::{DynamicImage, Rgb, RgbImage};
+use imageproc::drawing::{draw_hollow_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::eval::MetricKind;
+use crate::predict;
+use crate::{Args, Bbox, LoadArgs, ModelKind, VisionError, YOLOResult, YOLOv8, SKELETON};
+
+// Ultralytics colors, by class id
+const PALETTE: [[u8; 3]; 20] = [
+    [255, 56, 56],
+    [255, 157, 151],
+    [255, 112, 31],
+    [255, 178, 29],
+    [207, 210, 49],
+    [72, 249, 10],
+    [146, 204, 23],
+    [61, 219, 134],
+    [26, 147, 52],
+    [0, 212, 187],
+    [44, 153, 168],
+    [0, 194, 255],
+    [52, 69, 147],
+    [100, 115, 255],
+    [0, 24, 236],
+    [132, 56, 255],
+    [82, 0, 133],
+    [203, 56, 255],
+    [255, 149, 200],
+    [255, 55, 199],
+];
+
+/// The `--model` model of the offline subcommands, YOLO or RF-DETR.
+pub(crate) enum Detector {
+    Yolo(Box<YOLOv8>),
+    // with a runtime driving its async `detect`
+    RfDetr(Box<rf_detr::MyImageProcessor>, tokio::runtime::Runtime),
+}
+
+impl Detector {
+    /// Loads `args.model` as a `kind` model, keeping detections above `args.conf`.
+    pub(crate) fn load(
+        args: &Args,
+        kind: ModelKind,
+        rf_detr_config: Option<&Path>,
+    ) -> Result<Self, VisionError> {
+        match kind {
+            ModelKind::Yolo => {
+                let mut args = args.clone();
+                args.plot = false;
+                let model = YOLOv8::new(args)?;
+                model.summary();
+                Ok(Self::Yolo(Box::new(model)))
+            }
+            ModelKind::RfDetr => {
+                let mut argv: Vec<OsString> =
+                    vec!["rf-detr".into(), "--name".into(), (&args.name).into()];
+                if let Some(config) = rf_detr_config {
+                    argv.extend(["--config".into(), config.into()]);
+                }
+                argv.extend([
+                    "--model".into(),
+                    (&args.model).into(),
+                    "--conf-th".into(),
+                    args.conf.to_string().into(),
+                    "--max-det".into(),
+                    args.max_det.to_string().into(),
+                ]);
+                let processor =
+                    rf_detr::MyImageProcessor::load(rf_detr::Args::try_load_from(argv)?)?;
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                Ok(Self::RfDetr(Box::new(processor), runtime))
+            }
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Result<Vec<MetricKind>, VisionError> {
+        match self {
+            Self::Yolo(model) => MetricKind::of_task(model.task()),
+            Self::RfDetr(..) => Ok(vec![MetricKind::Box]),
+        }
+    }
+
+    /// Class names by id, empty where unknown.
+    pub(crate) fn names(&self) -> Vec<String> {
+        match self {
+            Self::Yolo(model) => model.names().clone(),
+            Self::RfDetr(processor, _) => {
+                let len = processor.names().keys().max().map_or(0, |id| id + 1);
+                let mut names = vec![String::new(); len];
+                for (&id, name) in processor.names() {
+                    names[id] = name.clone();
+                }
+                names
+            }
+        }
+    }
+
+    pub(crate) fn nk(&self) -> usize {
+        match self {
+            Self::Yolo(model) => model.nk() as usize,
+            Self::RfDetr(..) => 0,
+        }
+    }
+
+    pub(crate) fn max_batch(&self) -> usize {
+        match self {
+            Self::Yolo(model) => model.max_batch().max(1) as usize,
+            Self::RfDetr(..) => 1,
+        }
+    }
+
+    /// Results of a batch, a failing image only fails itself.
+    pub(crate) fn run(&mut self, xs: &[DynamicImage]) -> Vec<Result<YOLOResult, VisionError>> {
+        match self {
+            Self::Yolo(model) => predict::run(model, xs),
+            Self::RfDetr(processor, runtime) => {
+                let nms = processor.nms();
+                xs.iter()
+                    .map(|x| {
+                        let (boxes, classes, confs) =
+                            runtime.block_on(processor.detect(x.clone(), &nms))?;
+                        let bboxes = boxes
+                            .into_iter()
+                            .zip(classes)
+                            .zip(confs)
+                            .map(|(([cx, cy, w, h], id), conf)| {
+                                let (cx, cy, w, h) = (cx as f32, cy as f32, w as f32, h as f32);
+                                Bbox::new(cx - w / 2., cy - h / 2., w, h, id.max(0) as usize, conf)
+                            })
+                            .collect();
+                        Ok(YOLOResult::new(None, Some(bboxes), None, None))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Draws a result over its image, with the model's own plot for YOLO.
+    pub(crate) fn plot(
+        &self,
+        y: &YOLOResult,
+        x: &DynamicImage,
+        font: &FontArc,
+    ) -> Result<RgbImage, VisionError> {
+        let processor = match self {
+            Self::Yolo(model) => return model.plot(y, x, &model.options(), Some(&SKELETON), font),
+            Self::RfDetr(processor, _) => processor,
+        };
+        let mut img = x.to_rgb8();
+        let legend_size = img.width().max(img.height()) / 40;
+        for bbox in y.bboxes().into_iter().flatten() {
+            let color = Rgb(PALETTE[bbox.id() % PALETTE.len()]);
+            draw_hollow_rect_mut(
+                &mut img,
+                Rect::at(bbox.xmin() as i32, bbox.ymin() as i32)
+                    .of_size((bbox.width() as u32).max(1), (bbox.height() as u32).max(1)),
+                color,
+            );
+            let name = processor
+                .names()
+                .get(&bbox.id())
+                .map_or("", |name| name.as_str());
+            draw_text_mut(
+                &mut img,
+                color,
+                bbox.xmin() as i32,
+                (bbox.ymin() - legend_size as f32) as i32,
+                legend_size as f32,
+                font,
+                &format!("{} {:.2}", name, bbox.confidence()),
+            );
+        }
+        Ok(img)
+    }
+}
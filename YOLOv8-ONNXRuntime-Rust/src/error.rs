@@ -0,0 +1,110 @@
+use tonic::Status;
+
+/// Errors of model loading, inference and the gRPC service.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VisionError {
+    /// The request is malformed: undecodable image, out-of-range option.
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    /// The model lacks metadata or settings needed to run it.
+    #[error("Model error: {0}")]
+    Model(String),
+    /// No model is loaded under the requested name.
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// A model is already loaded under the requested name.
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    /// A dtype, task or tensor layout this crate can not handle.
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    /// The plotting font could not be found, downloaded or parsed.
+    #[error("Font error: {0}")]
+    Font(String),
+    /// The model is still loading, retry later.
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+    /// Out of memory while running, a smaller load may succeed.
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+    #[error("ONNXRuntime error: {0}")]
+    Ort(String),
+    #[error("IO error: {0}")]
+    Io(String),
+    #[error("Image error: {0}")]
+    Image(String),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl From<ort::Error> for VisionError {
+    fn from(e: ort::Error) -> Self {
+        // allocation failures, e.g. CUDA OOM on a large batch
+        let msg = e.message().to_lowercase();
+        if msg.contains("failed to allocate") || msg.contains("out of memory") {
+            VisionError::ResourceExhausted(e.to_string())
+        } else {
+            VisionError::Ort(e.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for VisionError {
+    fn from(e: std::io::Error) -> Self {
+        VisionError::Io(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for VisionError {
+    fn from(e: image::ImageError) -> Self {
+        VisionError::Image(e.to_string())
+    }
+}
+
+impl From<rf_detr::VisionError> for VisionError {
+    fn from(e: rf_detr::VisionError) -> Self {
+        use rf_detr::VisionError as E;
+        match e {
+            E::InvalidArgument(msg) => VisionError::InvalidArgument(msg),
+            E::Model(msg) => VisionError::Model(msg),
+            E::Unsupported(msg) => VisionError::Unsupported(msg),
+            E::Unavailable(msg) => VisionError::Unavailable(msg),
+            E::ResourceExhausted(msg) => VisionError::ResourceExhausted(msg),
+            E::Ort(msg) => VisionError::Ort(msg),
+            E::Io(msg) => VisionError::Io(msg),
+            E::Internal(msg) => VisionError::Internal(msg),
+        }
+    }
+}
+
+impl From<anyhow::Error> for VisionError {
+    fn from(e: anyhow::Error) -> Self {
+        // keep the variant of a wrapped VisionError
+        match e.downcast_ref::<VisionError>() {
+            Some(e) => e.clone(),
+            None => VisionError::Internal(format!("{:#}", e)),
+        }
+    }
+}
+
+impl From<VisionError> for Status {
+    fn from(e: VisionError) -> Self {
+        let msg = e.to_string();
+        match e {
+            VisionError::InvalidArgument(_) => Status::invalid_argument(msg),
+            VisionError::NotFound(_) => Status::not_found(msg),
+            VisionError::AlreadyExists(_) => Status::already_exists(msg),
+            VisionError::Model(_) | VisionError::Unsupported(_) | VisionError::Font(_) => {
+                Status::failed_precondition(msg)
+            }
+            VisionError::Unavailable(_) => Status::unavailable(msg),
+            VisionError::ResourceExhausted(_) => Status::resource_exhausted(msg),
+            VisionError::Ort(_)
+            | VisionError::Io(_)
+            | VisionError::Image(_)
+            | VisionError::Internal(_) => Status::internal(msg),
+        }
+    }
+}
+
+pub type Result<T, E = VisionError> = std::result::Result<T, E>;
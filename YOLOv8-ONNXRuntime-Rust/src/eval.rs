@@ -0,0 +1,1429 @@
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{GrayImage, Luma};
+use imageproc::drawing::draw_polygon_mut;
+use imageproc::point::Point;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+use crate::utils::find_images;
+use crate::{Bbox, Point2, Rle, VisionError, YOLOResult, YOLOTask, MASK_THRESHOLD};
+
+/// IoU thresholds of mAP@0.5:0.95, the first one is that of mAP@0.5.
+pub const IOU_THRESHOLDS: [f32; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+/// Per-keypoint OKS sigmas of the 17 COCO keypoints.
+pub const COCO_SIGMAS: [f32; 17] = [
+    0.026, 0.025, 0.025, 0.035, 0.035, 0.079, 0.079, 0.072, 0.072, 0.062, 0.062, 0.107, 0.107,
+    0.087, 0.087, 0.089, 0.089,
+];
+
+// detections and IoU counted by the confusion matrix, as in Ultralytics
+const CONFUSION_CONF: f32 = 0.25;
+const CONFUSION_IOU: f32 = 0.45;
+
+// points of the confidence and recall axes of the curves
+const CURVE_POINTS: usize = 1000;
+
+/// Similarity detections are matched to the ground truth by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    // box IoU
+    Box,
+    // mask IoU
+    Mask,
+    // object keypoint similarity
+    Pose,
+}
+
+impl MetricKind {
+    /// Metrics of a model task, `Box` first.
+    pub fn of_task(task: &YOLOTask) -> Result<Vec<Self>, VisionError> {
+        match task {
+            YOLOTask::Detect => Ok(vec![Self::Box]),
+            YOLOTask::Segment => Ok(vec![Self::Box, Self::Mask]),
+            YOLOTask::Pose => Ok(vec![Self::Box, Self::Pose]),
+            task => Err(VisionError::Unsupported(format!(
+                "No detection metrics for {:?} models",
+                task
+            ))),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Box => "Box",
+            Self::Mask => "Mask",
+            Self::Pose => "Pose",
+        }
+    }
+}
+
+/// Mask of an annotated object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TruthMask {
+    // polygons in image coordinates
+    Polygons(Vec<Vec<Point2>>),
+    Rle(Rle),
+}
+
+impl TruthMask {
+    /// Row-major u8 mask of a `width` x `height` image, 255 for foreground.
+    pub fn decode(&self, width: u32, height: u32) -> Vec<u8> {
+        match self {
+            Self::Rle(rle) if rle.width() == width && rle.height() == height => rle.decode(),
+            Self::Rle(rle) => match GrayImage::from_raw(rle.width(), rle.height(), rle.decode()) {
+                Some(image) => {
+                    image::imageops::resize(&image, width, height, FilterType::Nearest).into_raw()
+                }
+                None => vec![0; (width * height) as usize],
+            },
+            Self::Polygons(polygons) => {
+                let mut image = GrayImage::new(width, height);
+                for polygon in polygons {
+                    let mut points: Vec<Point<i32>> = Vec::with_capacity(polygon.len());
+                    for p in polygon {
+                        let p = Point::new(p.x().round() as i32, p.y().round() as i32);
+                        if points.last() != Some(&p) {
+                            points.push(p);
+                        }
+                    }
+                    // `draw_polygon_mut` closes the polygon itself and panics on closed ones
+                    while points.len() > 1 && points.first() == points.last() {
+                        points.pop();
+                    }
+                    if points.len() >= 3 {
+                        draw_polygon_mut(&mut image, &points, Luma([255]));
+                    }
+                }
+                image.into_raw()
+            }
+        }
+    }
+}
+
+/// An annotated object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Truth {
+    // class id and box in image coordinates
+    pub bbox: Bbox,
+    pub mask: Option<TruthMask>,
+    // visibility as the point confidence, 0 when not labeled
+    pub keypoints: Option<Vec<Point2>>,
+}
+
+/// An image and its annotated objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub truths: Vec<Truth>,
+}
+
+/// Ground truth of an evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct Dataset {
+    pub samples: Vec<Sample>,
+    // class names by id, from the COCO categories, empty for YOLO labels
+    pub names: Vec<String>,
+    // crowd annotations of a COCO dataset, left out of the samples
+    pub crowds: usize,
+}
+
+impl Dataset {
+    /// Ultralytics YOLO dataset: the images of `images` (a file, directory or `*`/`?`
+    /// pattern) and a `<stem>.txt` label file per image, in `labels` or in the `labels`
+    /// directory standing in for the last `images` one of the image path. Lines are
+    /// `class cx cy w h`, `class x1 y1 x2 y2 ...` polygons, or a box followed by `nk`
+    /// keypoints `x y [visibility]`, normalized to the image size. Images without a
+    /// label file have no objects.
+    pub fn from_yolo(images: &str, labels: Option<&Path>, nk: usize) -> Result<Self, VisionError> {
+        let mut samples = Vec::new();
+        for path in find_images(images)? {
+            let (width, height) = image::image_dimensions(&path)?;
+            let label = label_path(Path::new(&path), labels);
+            let truths = match std::fs::read_to_string(&label) {
+                Ok(text) => text
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim().is_empty())
+                    .map(|(i, line)| {
+                        parse_yolo_line(line, width, height, nk).map_err(|e| {
+                            VisionError::InvalidArgument(format!(
+                                "{}:{}: {}",
+                                label.display(),
+                                i + 1,
+                                e
+                            ))
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e.into()),
+            };
+            samples.push(Sample {
+                path,
+                width,
+                height,
+                truths,
+            });
+        }
+        Ok(Self {
+            samples,
+            names: vec![],
+            crowds: 0,
+        })
+    }
+
+    /// COCO dataset annotations, with image files relative to `images`. Categories map to
+    /// the model class of the same name when all of them are in `names`, to the class of
+    /// their id otherwise. Crowd annotations are left out, as Ultralytics does when it
+    /// converts COCO labels, so detections on them count as false positives where
+    /// pycocotools would ignore them.
+    pub fn from_coco(json: &Path, images: &Path, names: &[String]) -> Result<Self, VisionError> {
+        let file: CocoFile = serde_json::from_reader(BufReader::new(File::open(json)?))
+            .map_err(|e| VisionError::InvalidArgument(format!("{}: {}", json.display(), e)))?;
+
+        let by_name = !names.is_empty() && file.categories.iter().all(|c| names.contains(&c.name));
+        let mut classes = HashMap::new();
+        let mut dataset_names = Vec::new();
+        for category in &file.categories {
+            let class = if by_name {
+                names
+                    .iter()
+                    .position(|name| name == &category.name)
+                    .unwrap_or_default()
+            } else {
+                category.id as usize
+            };
+            if dataset_names.len() <= class {
+                dataset_names.resize(class + 1, String::new());
+            }
+            dataset_names[class] = category.name.clone();
+            classes.insert(category.id, class);
+        }
+
+        let mut index = HashMap::with_capacity(file.images.len());
+        let mut samples = Vec::with_capacity(file.images.len());
+        for image in file.images {
+            index.insert(image.id, samples.len());
+            samples.push(Sample {
+                path: images.join(&image.file_name).to_string_lossy().into_owned(),
+                width: image.width,
+                height: image.height,
+                truths: vec![],
+            });
+        }
+        let mut crowds = 0;
+        for annotation in file.annotations {
+            if annotation.iscrowd != 0 {
+                crowds += 1;
+                continue;
+            }
+            let Some(&sample) = index.get(&annotation.image_id) else {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Annotation {} of unknown image {}",
+                    annotation.id, annotation.image_id
+                )));
+            };
+            let Some(&class) = classes.get(&annotation.category_id) else {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Annotation {} of unknown category {}",
+                    annotation.id, annotation.category_id
+                )));
+            };
+            let [x, y, w, h] = annotation.bbox;
+            let mask = match annotation.segmentation {
+                None => None,
+                Some(CocoFileSegmentation::Polygons(polygons)) => Some(TruthMask::Polygons(
+                    polygons
+                        .iter()
+                        .map(|polygon| {
+                            polygon
+                                .chunks_exact(2)
+                                .map(|p| Point2::new(p[0], p[1]))
+                                .collect()
+                        })
+                        .collect(),
+                )),
+                Some(CocoFileSegmentation::Rle {
+                    size: [h, w],
+                    counts,
+                }) => Some(TruthMask::Rle(match counts {
+                    CocoFileCounts::Runs(counts) => Rle::new(h, w, counts),
+                    CocoFileCounts::Compressed(s) => Rle::decompress(h, w, &s)?,
+                })),
+            };
+            let keypoints = annotation.keypoints.map(|keypoints| {
+                keypoints
+                    .chunks_exact(3)
+                    .map(|k| Point2::new_with_conf(k[0], k[1], k[2]))
+                    .collect()
+            });
+            samples[sample].truths.push(Truth {
+                bbox: Bbox::new(x, y, w, h, class, 1.),
+                mask,
+                keypoints,
+            });
+        }
+        Ok(Self {
+            samples,
+            names: dataset_names,
+            crowds,
+        })
+    }
+
+    /// Largest class id of the annotations.
+    pub fn max_class(&self) -> Option<usize> {
+        self.samples
+            .iter()
+            .flat_map(|sample| &sample.truths)
+            .map(|truth| truth.bbox.id())
+            .max()
+    }
+}
+
+#[derive(Deserialize)]
+struct CocoFile {
+    images: Vec<CocoFileImage>,
+    #[serde(default)]
+    annotations: Vec<CocoFileAnnotation>,
+    #[serde(default)]
+    categories: Vec<CocoFileCategory>,
+}
+
+#[derive(Deserialize)]
+struct CocoFileImage {
+    id: u64,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct CocoFileAnnotation {
+    #[serde(default)]
+    id: u64,
+    image_id: u64,
+    category_id: u64,
+    bbox: [f32; 4],
+    #[serde(default)]
+    segmentation: Option<CocoFileSegmentation>,
+    #[serde(default)]
+    keypoints: Option<Vec<f32>>,
+    #[serde(default)]
+    iscrowd: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CocoFileSegmentation {
+    Polygons(Vec<Vec<f32>>),
+    // `size` is [height, width]
+    Rle {
+        size: [u32; 2],
+        counts: CocoFileCounts,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CocoFileCounts {
+    Runs(Vec<u32>),
+    Compressed(String),
+}
+
+#[derive(Deserialize)]
+struct CocoFileCategory {
+    id: u64,
+    name: String,
+}
+
+/// Label file of an image: `<labels>/<stem>.txt`, or the image path with its last
+/// `images` directory replaced by `labels` and a `.txt` extension.
+fn label_path(image: &Path, labels: Option<&Path>) -> PathBuf {
+    let file = format!(
+        "{}.txt",
+        image.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    if let Some(labels) = labels {
+        return labels.join(file);
+    }
+    let parent = image.parent().unwrap_or(Path::new(""));
+    let mut components: Vec<Component> = parent.components().collect();
+    if let Some(i) = components.iter().rposition(|c| c.as_os_str() == "images") {
+        components[i] = Component::Normal("labels".as_ref());
+    }
+    components.iter().collect::<PathBuf>().join(file)
+}
+
+fn parse_yolo_line(line: &str, width: u32, height: u32, nk: usize) -> Result<Truth, String> {
+    let mut values = line.split_whitespace();
+    let class = values
+        .next()
+        .and_then(|class| class.parse::<usize>().ok())
+        .ok_or_else(|| format!("Invalid class id in {:?}", line))?;
+    let v = values
+        .map(|value| {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid value {:?}", value))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (w, h) = (width as f32, height as f32);
+    let bbox = |v: &[f32]| {
+        Bbox::new(
+            (v[0] - v[2] / 2.) * w,
+            (v[1] - v[3] / 2.) * h,
+            v[2] * w,
+            v[3] * h,
+            class,
+            1.,
+        )
+    };
+
+    if nk > 0 && (v.len() == 4 + nk * 3 || v.len() == 4 + nk * 2) {
+        let dims = (v.len() - 4) / nk;
+        let keypoints = v[4..]
+            .chunks_exact(dims)
+            .map(|k| {
+                // without a visibility, points at the origin are not labeled
+                let visibility = match k.get(2) {
+                    Some(&visibility) => visibility,
+                    None if k[0] == 0. && k[1] == 0. => 0.,
+                    None => 2.,
+                };
+                Point2::new_with_conf(k[0] * w, k[1] * h, visibility)
+            })
+            .collect();
+        Ok(Truth {
+            bbox: bbox(&v),
+            mask: None,
+            keypoints: Some(keypoints),
+        })
+    } else if v.len() == 4 {
+        Ok(Truth {
+            bbox: bbox(&v),
+            mask: None,
+            keypoints: None,
+        })
+    } else if v.len() >= 6 && v.len() % 2 == 0 {
+        let polygon: Vec<Point2> = v
+            .chunks_exact(2)
+            .map(|p| Point2::new(p[0] * w, p[1] * h))
+            .collect();
+        let (mut x0, mut y0, mut x1, mut y1) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for p in &polygon {
+            x0 = x0.min(p.x());
+            y0 = y0.min(p.y());
+            x1 = x1.max(p.x());
+            y1 = y1.max(p.y());
+        }
+        Ok(Truth {
+            bbox: Bbox::new(x0, y0, x1 - x0, y1 - y0, class, 1.),
+            mask: Some(TruthMask::Polygons(vec![polygon])),
+            keypoints: None,
+        })
+    } else {
+        Err(format!(
+            "{} values is neither a box, a polygon nor {} keypoints",
+            v.len(),
+            nk
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Stats {
+    // confidence, class and whether it is a true positive at each of `IOU_THRESHOLDS`,
+    // per detection
+    detections: Vec<(f32, usize, [bool; 10])>,
+}
+
+/// Matches the detections of each image to its annotated objects and accumulates the
+/// statistics of a `Report`, the way Ultralytics `val` does.
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    kinds: Vec<MetricKind>,
+    sigmas: Vec<f32>,
+    // one per kind
+    stats: Vec<Stats>,
+    // annotated objects and images with any, per class
+    instances: BTreeMap<usize, usize>,
+    class_images: BTreeMap<usize, usize>,
+    // (predicted, true) class counts of the box confusion matrix, `None` is the background
+    confusion: HashMap<(Option<usize>, Option<usize>), u64>,
+    images: usize,
+}
+
+impl Evaluator {
+    /// Evaluates `kinds`, OKS uses the COCO sigmas for 17 keypoints and uniform ones
+    /// for other `nk`.
+    pub fn new(kinds: Vec<MetricKind>, nk: usize) -> Self {
+        let sigmas = if nk == COCO_SIGMAS.len() {
+            COCO_SIGMAS.to_vec()
+        } else {
+            vec![1. / nk.max(1) as f32; nk]
+        };
+        Self {
+            stats: vec![Stats::default(); kinds.len()],
+            kinds,
+            sigmas,
+            instances: BTreeMap::new(),
+            class_images: BTreeMap::new(),
+            confusion: HashMap::new(),
+            images: 0,
+        }
+    }
+
+    pub fn kinds(&self) -> &[MetricKind] {
+        &self.kinds
+    }
+
+    /// Adds the result of an image.
+    pub fn add(&mut self, sample: &Sample, y: &YOLOResult) {
+        self.images += 1;
+        let boxes = y.bboxes().map(Vec::as_slice).unwrap_or_default();
+        let truth_classes: Vec<usize> = sample.truths.iter().map(|t| t.bbox.id()).collect();
+        let classes: Vec<usize> = boxes.iter().map(Bbox::id).collect();
+        for class in &truth_classes {
+            *self.instances.entry(*class).or_default() += 1;
+        }
+        for class in truth_classes.iter().collect::<BTreeSet<_>>() {
+            *self.class_images.entry(*class).or_default() += 1;
+        }
+
+        let box_iou = box_ious(sample, boxes);
+        for (kind, stats) in self.kinds.iter().zip(&mut self.stats) {
+            let iou = match kind {
+                MetricKind::Box => box_iou.clone(),
+                MetricKind::Mask => mask_ious(sample, y, &truth_classes, &classes),
+                MetricKind::Pose => oks(sample, y, &truth_classes, &classes, &self.sigmas),
+            };
+            let tp = match_detections(&iou, &truth_classes, &classes);
+            stats.detections.extend(
+                boxes
+                    .iter()
+                    .zip(tp)
+                    .map(|(bbox, tp)| (bbox.confidence(), bbox.id(), tp)),
+            );
+        }
+        self.confuse(&box_iou, &truth_classes, boxes);
+    }
+
+    fn confuse(&mut self, iou: &[Vec<f32>], truth_classes: &[usize], boxes: &[Bbox]) {
+        let mut pairs = Vec::new();
+        for (i, row) in iou.iter().enumerate() {
+            for (j, &iou) in row.iter().enumerate() {
+                if iou > CONFUSION_IOU && boxes[j].confidence() > CONFUSION_CONF {
+                    pairs.push((iou, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut truth_match = vec![None; truth_classes.len()];
+        let mut box_matched = vec![false; boxes.len()];
+        for (_, i, j) in pairs {
+            if truth_match[i].is_none() && !box_matched[j] {
+                truth_match[i] = Some(boxes[j].id());
+                box_matched[j] = true;
+            }
+        }
+        for (&class, predicted) in truth_classes.iter().zip(truth_match) {
+            *self.confusion.entry((predicted, Some(class))).or_default() += 1;
+        }
+        for (bbox, matched) in boxes.iter().zip(box_matched) {
+            if !matched && bbox.confidence() > CONFUSION_CONF {
+                *self.confusion.entry((Some(bbox.id()), None)).or_default() += 1;
+            }
+        }
+    }
+
+    /// Metrics of the images added so far, `names` maps class ids to names.
+    pub fn finish(&self, names: &[String]) -> Report {
+        let name = |id: usize| names.get(id).filter(|name| !name.is_empty()).cloned();
+        let metrics = self
+            .kinds
+            .iter()
+            .zip(&self.stats)
+            .map(|(&kind, stats)| self.summarize(kind, stats, &name))
+            .collect();
+
+        let mut classes: BTreeSet<usize> = BTreeSet::new();
+        for (predicted, truth) in self.confusion.keys() {
+            classes.extend(predicted.iter().chain(truth));
+        }
+        let classes: Vec<usize> = classes.into_iter().collect();
+        let row = |class: Option<usize>| {
+            classes
+                .iter()
+                .map(|&c| Some(c))
+                .chain([None])
+                .map(|truth| {
+                    self.confusion
+                        .get(&(class, truth))
+                        .copied()
+                        .unwrap_or_default()
+                })
+                .collect()
+        };
+        let confusion_matrix = ConfusionMatrix {
+            names: classes
+                .iter()
+                .map(|&c| name(c).unwrap_or_else(|| c.to_string()))
+                .chain(["background".to_string()])
+                .collect(),
+            matrix: classes
+                .iter()
+                .map(|&c| Some(c))
+                .chain([None])
+                .map(row)
+                .collect(),
+            classes,
+        };
+
+        Report {
+            ap_style: AP_STYLE,
+            images: self.images,
+            instances: self.instances.values().sum(),
+            crowds: 0,
+            metrics,
+            confusion_matrix,
+        }
+    }
+
+    fn summarize(
+        &self,
+        kind: MetricKind,
+        stats: &Stats,
+        name: &impl Fn(usize) -> Option<String>,
+    ) -> KindReport {
+        let mut detections = stats.detections.clone();
+        detections.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let grid: Vec<f32> = (0..CURVE_POINTS)
+            .map(|i| i as f32 / (CURVE_POINTS - 1) as f32)
+            .collect();
+
+        // only classes with annotated objects count, as in Ultralytics
+        let curves: Vec<ClassCurves> = self
+            .instances
+            .iter()
+            .map(|(&class, &instances)| {
+                let detections: Vec<_> = detections.iter().filter(|d| d.1 == class).collect();
+                ClassCurves::new(class, instances, &detections, &grid)
+            })
+            .collect();
+        let mean = |curve: fn(&ClassCurves) -> &Vec<f32>| -> Vec<f32> {
+            (0..CURVE_POINTS)
+                .map(|i| {
+                    curves.iter().fold(0., |sum, c| sum + curve(c)[i]) / curves.len().max(1) as f32
+                })
+                .collect()
+        };
+        let mean_f1 = mean(|c| &c.f1);
+        // the confidence of the best mean F1, smoothed over 0.1 of confidence
+        let smoothed = smooth(&mean_f1, 0.05);
+        let mut best = 0;
+        for (i, &f1) in smoothed.iter().enumerate() {
+            if f1 > smoothed[best] {
+                best = i;
+            }
+        }
+
+        let classes: Vec<ClassReport> = curves
+            .iter()
+            .map(|c| ClassReport {
+                class_id: c.class,
+                name: name(c.class),
+                images: self.class_images.get(&c.class).copied().unwrap_or_default(),
+                instances: c.instances,
+                detections: c.detections,
+                precision: c.precision[best],
+                recall: c.recall[best],
+                f1: c.f1[best],
+                ap50: c.ap[0],
+                ap50_95: c.ap.iter().sum::<f32>() / c.ap.len() as f32,
+                ap: c.ap,
+            })
+            .collect();
+        let average = |value: fn(&ClassReport) -> f32| {
+            classes.iter().fold(0., |sum, c| sum + value(c)) / classes.len().max(1) as f32
+        };
+        let (precision, recall) = (average(|c| c.precision), average(|c| c.recall));
+        KindReport {
+            kind,
+            confidence: grid[best],
+            precision,
+            recall,
+            f1: f1(precision, recall),
+            map50: average(|c| c.ap50),
+            map50_95: average(|c| c.ap50_95),
+            curves: Curves {
+                precision: mean(|c| &c.precision),
+                recall: mean(|c| &c.recall),
+                f1: mean_f1,
+                pr: mean(|c| &c.pr),
+                x: grid,
+            },
+            classes,
+        }
+    }
+}
+
+/// AP and curves of a class, zero without detections.
+struct ClassCurves {
+    class: usize,
+    instances: usize,
+    detections: usize,
+    // at each of `IOU_THRESHOLDS`
+    ap: [f32; 10],
+    // at IoU 0.5, against the confidence grid
+    precision: Vec<f32>,
+    recall: Vec<f32>,
+    f1: Vec<f32>,
+    // precision envelope at IoU 0.5, against the recall grid
+    pr: Vec<f32>,
+}
+
+impl ClassCurves {
+    // `detections` by descending confidence
+    fn new(
+        class: usize,
+        instances: usize,
+        detections: &[&(f32, usize, [bool; 10])],
+        grid: &[f32],
+    ) -> Self {
+        let mut curves = Self {
+            class,
+            instances,
+            detections: detections.len(),
+            ap: [0.; 10],
+            precision: vec![0.; grid.len()],
+            recall: vec![0.; grid.len()],
+            f1: vec![0.; grid.len()],
+            pr: vec![0.; grid.len()],
+        };
+        if detections.is_empty() {
+            return curves;
+        }
+        let confs: Vec<f32> = detections.iter().map(|d| d.0).collect();
+        for t in 0..IOU_THRESHOLDS.len() {
+            let (mut tpc, mut fpc) = (0f32, 0f32);
+            let mut recall = Vec::with_capacity(detections.len());
+            let mut precision = Vec::with_capacity(detections.len());
+            for d in detections {
+                if d.2[t] {
+                    tpc += 1.;
+                } else {
+                    fpc += 1.;
+                }
+                recall.push(tpc / (instances as f32 + 1e-16));
+                precision.push(tpc / (tpc + fpc));
+            }
+            let (ap, envelope) = average_precision(&recall, &precision, grid);
+            curves.ap[t] = ap;
+            if t == 0 {
+                curves.precision = grid
+                    .iter()
+                    .map(|&x| interp_desc(x, &confs, &precision, 1.))
+                    .collect();
+                curves.recall = grid
+                    .iter()
+                    .map(|&x| interp_desc(x, &confs, &recall, 0.))
+                    .collect();
+                curves.f1 = curves
+                    .precision
+                    .iter()
+                    .zip(&curves.recall)
+                    .map(|(&p, &r)| f1(p, r))
+                    .collect();
+                curves.pr = envelope;
+            }
+        }
+        curves
+    }
+}
+
+/// How `Report` computes AP: as Ultralytics `val` does, with COCO crowd annotations left
+/// out of the ground truth rather than ignoring the detections they cover like pycocotools.
+pub const AP_STYLE: &str = "ultralytics";
+
+/// Metrics of an evaluation, as written to `metrics.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    // `AP_STYLE`
+    pub ap_style: &'static str,
+    pub images: usize,
+    // annotated objects
+    pub instances: usize,
+    // crowd annotations left out of the dataset, see `AP_STYLE`
+    pub crowds: usize,
+    // one per evaluated `MetricKind`
+    pub metrics: Vec<KindReport>,
+    pub confusion_matrix: ConfusionMatrix,
+}
+
+/// Metrics of a `MetricKind`. Precision, recall and F1 are those at `confidence`, where
+/// the mean F1 of the classes is best.
+#[derive(Debug, Clone, Serialize)]
+pub struct KindReport {
+    pub kind: MetricKind,
+    pub confidence: f32,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    pub map50: f32,
+    pub map50_95: f32,
+    // classes with annotated objects, by id
+    pub classes: Vec<ClassReport>,
+    pub curves: Curves,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassReport {
+    pub class_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // images with objects of the class
+    pub images: usize,
+    pub instances: usize,
+    pub detections: usize,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    pub ap50: f32,
+    pub ap50_95: f32,
+    // AP at each of `IOU_THRESHOLDS`
+    pub ap: [f32; 10],
+}
+
+/// Mean over the classes of precision, recall and F1 against confidence `x` (at IoU 0.5),
+/// and of precision against recall `x` (the `pr` curve).
+#[derive(Debug, Clone, Serialize)]
+pub struct Curves {
+    pub x: Vec<f32>,
+    pub precision: Vec<f32>,
+    pub recall: Vec<f32>,
+    pub f1: Vec<f32>,
+    pub pr: Vec<f32>,
+}
+
+/// Box detection counts above confidence 0.25 at IoU 0.45, `matrix[predicted][true]`.
+/// The last row and column are the background: missed objects and false detections.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfusionMatrix {
+    // class ids of the rows and columns, before the background
+    pub classes: Vec<usize>,
+    pub names: Vec<String>,
+    pub matrix: Vec<Vec<u64>>,
+}
+
+impl Report {
+    /// Prints the table of Ultralytics `val`: all classes, then one row per class.
+    pub fn print(&self) {
+        let mut header = format!("{:>22}{:>11}{:>11}", "Class", "Images", "Instances");
+        for metrics in &self.metrics {
+            header += &format!(
+                "{:>11}{:>11}{:>11}{:>11}",
+                format!("{}(P", metrics.kind.label()),
+                "R",
+                "mAP50",
+                "mAP50-95)"
+            );
+        }
+        println!("{}", header);
+
+        let row = |name: &str, images: usize, instances: usize, values: Vec<[f32; 4]>| {
+            let mut line = format!("{:>22}{:>11}{:>11}", name, images, instances);
+            for value in values {
+                for v in value {
+                    line += &format!("{:>11.3}", v);
+                }
+            }
+            println!("{}", line);
+        };
+        row(
+            "all",
+            self.images,
+            self.instances,
+            self.metrics
+                .iter()
+                .map(|m| [m.precision, m.recall, m.map50, m.map50_95])
+                .collect(),
+        );
+        let Some(first) = self.metrics.first() else {
+            return;
+        };
+        for (i, class) in first.classes.iter().enumerate() {
+            let name = class
+                .name
+                .clone()
+                .unwrap_or_else(|| class.class_id.to_string());
+            row(
+                &name,
+                class.images,
+                class.instances,
+                self.metrics
+                    .iter()
+                    .map(|m| {
+                        let c = &m.classes[i];
+                        [c.precision, c.recall, c.ap50, c.ap50_95]
+                    })
+                    .collect(),
+            );
+        }
+    }
+}
+
+/// Box IoU of every annotated object (rows) with every detection (columns).
+fn box_ious(sample: &Sample, boxes: &[Bbox]) -> Vec<Vec<f32>> {
+    sample
+        .truths
+        .iter()
+        .map(|truth| {
+            boxes
+                .iter()
+                .map(|bbox| box_iou(&truth.bbox, bbox))
+                .collect()
+        })
+        .collect()
+}
+
+// continuous coordinates, unlike `Bbox::iou`
+fn box_iou(a: &Bbox, b: &Bbox) -> f32 {
+    let w = (a.xmax().min(b.xmax()) - a.xmin().max(b.xmin())).max(0.);
+    let h = (a.ymax().min(b.ymax()) - a.ymin().max(b.ymin())).max(0.);
+    let inter = w * h;
+    let union = a.width() * a.height() + b.width() * b.height() - inter;
+    if union > 0. {
+        inter / union
+    } else {
+        0.
+    }
+}
+
+/// Foreground of a row-major mask, the pixels above `cutoff`, with its pixel count and
+/// bounding rectangle.
+struct Region<'a> {
+    // `width` columns from `origin` of the image
+    data: std::borrow::Cow<'a, [u8]>,
+    width: usize,
+    origin: (usize, usize),
+    cutoff: u8,
+    area: u64,
+    // x0, y0, x1, y1, exclusive ends
+    rect: [usize; 4],
+}
+
+impl<'a> Region<'a> {
+    fn new(
+        data: std::borrow::Cow<'a, [u8]>,
+        width: usize,
+        origin: (usize, usize),
+        cutoff: u8,
+    ) -> Self {
+        let mut area = 0;
+        let mut rect = [usize::MAX, usize::MAX, 0, 0];
+        for (i, &v) in data.iter().enumerate() {
+            if v > cutoff {
+                let (x, y) = (origin.0 + i % width, origin.1 + i / width);
+                area += 1;
+                rect = [
+                    rect[0].min(x),
+                    rect[1].min(y),
+                    rect[2].max(x + 1),
+                    rect[3].max(y + 1),
+                ];
+            }
+        }
+        Self {
+            data,
+            width,
+            origin,
+            cutoff,
+            area,
+            rect,
+        }
+    }
+
+    fn iou(&self, other: &Region) -> f32 {
+        let [x0, y0] = [
+            self.rect[0].max(other.rect[0]),
+            self.rect[1].max(other.rect[1]),
+        ];
+        let [x1, y1] = [
+            self.rect[2].min(other.rect[2]),
+            self.rect[3].min(other.rect[3]),
+        ];
+        let mut inter = 0;
+        for y in y0..y1.max(y0) {
+            if x1 <= x0 {
+                break;
+            }
+            let a = self.row(y, x0, x1);
+            let b = other.row(y, x0, x1);
+            inter += a
+                .iter()
+                .zip(b)
+                .filter(|(&a, &b)| a > self.cutoff && b > other.cutoff)
+                .count() as u64;
+        }
+        let union = self.area + other.area - inter;
+        if union > 0 {
+            inter as f32 / union as f32
+        } else {
+            0.
+        }
+    }
+
+    // columns `x0..x1` of image row `y`, within the foreground rectangle
+    fn row(&self, y: usize, x0: usize, x1: usize) -> &[u8] {
+        let start = (y - self.origin.1) * self.width;
+        &self.data[start + x0 - self.origin.0..start + x1 - self.origin.0]
+    }
+}
+
+/// Mask IoU of the annotated objects with the detections of their class, 0 for other
+/// pairs and objects without a mask.
+fn mask_ious(
+    sample: &Sample,
+    y: &YOLOResult,
+    truth_classes: &[usize],
+    classes: &[usize],
+) -> Vec<Vec<f32>> {
+    let mut iou = vec![vec![0.; classes.len()]; truth_classes.len()];
+    let (Some(masks), Some((mask_w, mask_h))) = (y.masks(), y.mask_size()) else {
+        return iou;
+    };
+    let (w, h) = (sample.width, sample.height);
+    let detections: Vec<Option<Region>> = (0..masks.len())
+        .zip(classes)
+        .map(|(i, class)| {
+            if !truth_classes.contains(class) {
+                return None;
+            }
+            let rect = y.mask_rects.as_ref().and_then(|rects| rects.get(i));
+            if let (Some(&(x0, y0, rw, _)), true) = (rect, (mask_w, mask_h) == (w, h)) {
+                // cropped masks of the image size are used as they are
+                let data = std::borrow::Cow::Borrowed(masks[i].as_slice());
+                let origin = (x0 as usize, y0 as usize);
+                return Some(Region::new(data, rw as usize, origin, y.mask_cutoff()));
+            }
+            let mask = y.full_mask(i)?;
+            let data = if (mask_w, mask_h) == (w, h) {
+                mask
+            } else {
+                let image = GrayImage::from_raw(mask_w, mask_h, mask.into_owned())?;
+                std::borrow::Cow::Owned(
+                    image::imageops::resize(&image, w, h, FilterType::Nearest).into_raw(),
+                )
+            };
+            Some(Region::new(data, w as usize, (0, 0), y.mask_cutoff()))
+        })
+        .collect();
+
+    for (i, truth) in sample.truths.iter().enumerate() {
+        let Some(mask) = &truth.mask else {
+            continue;
+        };
+        if !classes.contains(&truth_classes[i]) {
+            continue;
+        }
+        let region = Region::new(
+            std::borrow::Cow::Owned(mask.decode(w, h)),
+            w as usize,
+            (0, 0),
+            MASK_THRESHOLD,
+        );
+        for (j, detection) in detections.iter().enumerate() {
+            if let (Some(detection), true) = (detection, classes[j] == truth_classes[i]) {
+                iou[i][j] = region.iou(detection);
+            }
+        }
+    }
+    iou
+}
+
+/// Object keypoint similarity of the annotated objects with the detections of their
+/// class. The object area is 0.53 of its box, as in Ultralytics.
+fn oks(
+    sample: &Sample,
+    y: &YOLOResult,
+    truth_classes: &[usize],
+    classes: &[usize],
+    sigmas: &[f32],
+) -> Vec<Vec<f32>> {
+    let mut iou = vec![vec![0.; classes.len()]; truth_classes.len()];
+    let Some(keypoints) = y.keypoints() else {
+        return iou;
+    };
+    for (i, truth) in sample.truths.iter().enumerate() {
+        let Some(truth_points) = &truth.keypoints else {
+            continue;
+        };
+        let area = truth.bbox.width() * truth.bbox.height() * 0.53;
+        for (j, points) in keypoints.iter().enumerate() {
+            if classes[j] != truth_classes[i] {
+                continue;
+            }
+            let (mut sum, mut labeled) = (0., 0.);
+            for (k, (t, p)) in truth_points.iter().zip(points).enumerate() {
+                if t.confidence() == 0. {
+                    continue;
+                }
+                let sigma = sigmas
+                    .get(k)
+                    .copied()
+                    .unwrap_or(1. / truth_points.len() as f32);
+                let d = (t.x() - p.x()).powi(2) + (t.y() - p.y()).powi(2);
+                sum += (-d / ((2. * sigma).powi(2) * (area + 1e-7) * 2.)).exp();
+                labeled += 1.;
+            }
+            iou[i][j] = sum / (labeled + 1e-7);
+        }
+    }
+    iou
+}
+
+/// Whether each detection is a true positive at each of `IOU_THRESHOLDS`: objects and
+/// detections of the same class are paired by descending IoU, each used once.
+fn match_detections(
+    iou: &[Vec<f32>],
+    truth_classes: &[usize],
+    classes: &[usize],
+) -> Vec<[bool; 10]> {
+    let mut tp = vec![[false; 10]; classes.len()];
+    let mut pairs = Vec::new();
+    for (i, row) in iou.iter().enumerate() {
+        for (j, &iou) in row.iter().enumerate() {
+            if iou >= IOU_THRESHOLDS[0] && classes[j] == truth_classes[i] {
+                pairs.push((iou, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (t, &threshold) in IOU_THRESHOLDS.iter().enumerate() {
+        let mut truth_used = vec![false; truth_classes.len()];
+        let mut used = vec![false; classes.len()];
+        for &(iou, i, j) in &pairs {
+            if iou < threshold {
+                break;
+            }
+            if !truth_used[i] && !used[j] {
+                truth_used[i] = true;
+                used[j] = true;
+                tp[j][t] = true;
+            }
+        }
+    }
+    tp
+}
+
+/// AP of a precision-recall curve, by detections of descending confidence: the area under
+/// its precision envelope sampled at 101 recall points, as in Ultralytics. Also returns
+/// the envelope at `grid`.
+fn average_precision(recall: &[f32], precision: &[f32], grid: &[f32]) -> (f32, Vec<f32>) {
+    let mrec: Vec<f32> = [0.].iter().chain(recall).chain(&[1.]).copied().collect();
+    let mut mpre: Vec<f32> = [1.].iter().chain(precision).chain(&[0.]).copied().collect();
+    for i in (0..mpre.len() - 1).rev() {
+        mpre[i] = mpre[i].max(mpre[i + 1]);
+    }
+    let q: Vec<f32> = (0..=100)
+        .map(|x| interp(x as f32 / 100., &mrec, &mpre))
+        .collect();
+    let ap = q.windows(2).map(|q| (q[0] + q[1]) / 2. * 0.01).sum();
+    (ap, grid.iter().map(|&x| interp(x, &mrec, &mpre)).collect())
+}
+
+// `np.interp` over ascending `xp`
+fn interp(x: f32, xp: &[f32], fp: &[f32]) -> f32 {
+    let j = xp.partition_point(|&v| v <= x);
+    if j == 0 {
+        return fp[0];
+    }
+    if j == xp.len() {
+        return fp[j - 1];
+    }
+    let (x0, x1) = (xp[j - 1], xp[j]);
+    fp[j - 1] + (fp[j] - fp[j - 1]) * (x - x0) / (x1 - x0)
+}
+
+// `np.interp` over descending confidences `xp`, `left` above the highest one
+fn interp_desc(x: f32, xp: &[f32], fp: &[f32], left: f32) -> f32 {
+    let k = xp.partition_point(|&c| c >= x);
+    if k == 0 {
+        return left;
+    }
+    if k == xp.len() {
+        return fp[k - 1];
+    }
+    let (c0, c1) = (xp[k - 1], xp[k]);
+    fp[k - 1] + (fp[k] - fp[k - 1]) * (c0 - x) / (c0 - c1)
+}
+
+fn f1(precision: f32, recall: f32) -> f32 {
+    2. * precision * recall / (precision + recall + 1e-16)
+}
+
+/// Box filter over a fraction `f` of the points on each side, edges repeated, as the
+/// `smooth` of Ultralytics.
+fn smooth(y: &[f32], f: f32) -> Vec<f32> {
+    let nf = (y.len() as f32 * f * 2.).round() as usize / 2 + 1;
+    let (Some(&first), Some(&last)) = (y.first(), y.last()) else {
+        return vec![];
+    };
+    let padded: Vec<f32> = std::iter::repeat_n(first, nf / 2)
+        .chain(y.iter().copied())
+        .chain(std::iter::repeat_n(last, nf / 2))
+        .collect();
+    padded
+        .windows(nf)
+        .map(|w| w.iter().sum::<f32>() / nf as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(truths: &[Bbox]) -> Sample {
+        Sample {
+            path: "a.jpg".to_string(),
+            width: 100,
+            height: 100,
+            truths: truths
+                .iter()
+                .map(|bbox| Truth {
+                    bbox: bbox.clone(),
+                    mask: None,
+                    keypoints: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn boxes(bboxes: Vec<Bbox>) -> YOLOResult {
+        YOLOResult::new(None, Some(bboxes), None, None)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn perfect_predictions_have_full_precision_and_recall() {
+        let truths = [
+            Bbox::new(10., 10., 20., 20., 0, 1.),
+            Bbox::new(50., 40., 30., 20., 1, 1.),
+        ];
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        for _ in 0..3 {
+            let detections = truths
+                .iter()
+                .map(|t| Bbox::new(t.xmin(), t.ymin(), t.width(), t.height(), t.id(), 0.9));
+            evaluator.add(&sample(&truths), &boxes(detections.collect()));
+        }
+        let report = evaluator.finish(&[]);
+        assert_eq!((report.images, report.instances), (3, 6));
+        let metrics = &report.metrics[0];
+        assert!(close(metrics.precision, 1.) && close(metrics.recall, 1.));
+        // the last of the 101 recall points is the appended zero precision, so a perfect
+        // AP is 0.995 as in Ultralytics
+        assert!(close(metrics.map50, 0.995) && close(metrics.map50_95, 0.995));
+        for class in &metrics.classes {
+            assert!(class.ap.iter().all(|&ap| close(ap, 0.995)));
+        }
+    }
+
+    #[test]
+    fn false_positives_have_zero_ap() {
+        let truths = [Bbox::new(10., 10., 20., 20., 0, 1.)];
+        let detections = vec![
+            // elsewhere
+            Bbox::new(60., 60., 20., 20., 0, 0.9),
+            // another class
+            Bbox::new(10., 10., 20., 20., 1, 0.8),
+            // below IoU 0.5
+            Bbox::new(20., 20., 20., 20., 0, 0.7),
+        ];
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        evaluator.add(&sample(&truths), &boxes(detections));
+        let metrics = &evaluator.finish(&[]).metrics[0];
+        assert_eq!(metrics.classes.len(), 1);
+        assert_eq!(metrics.classes[0].detections, 2);
+        assert_eq!((metrics.map50, metrics.map50_95), (0., 0.));
+        assert_eq!((metrics.precision, metrics.recall), (0., 0.));
+    }
+
+    #[test]
+    fn average_precision_interpolates_101_recall_points() {
+        // TP, FP, TP over 2 objects: the envelope is 1 up to recall 0.5, then 2/3, then
+        // 0 at recall 1, so AP = 0.49 + 0.01 * (1 + 2/3) / 2 + 0.49 * 2/3 + 0.01 * (2/3) / 2
+        let expected = 0.49 + 0.005 * (5. / 3.) + 0.49 * (2. / 3.) + 0.005 * (2. / 3.);
+        let (ap, envelope) = average_precision(&[0.5, 0.5, 1.], &[1., 0.5, 2. / 3.], &[0.25, 0.75]);
+        assert!(close(ap, expected), "{} != {}", ap, expected);
+        assert!(close(envelope[0], 1.) && close(envelope[1], 2. / 3.));
+
+        let truths = [
+            Bbox::new(0., 0., 10., 10., 0, 1.),
+            Bbox::new(20., 0., 10., 10., 0, 1.),
+        ];
+        let detections = vec![
+            Bbox::new(0., 0., 10., 10., 0, 0.9),
+            Bbox::new(50., 50., 10., 10., 0, 0.8),
+            Bbox::new(20., 0., 10., 10., 0, 0.7),
+        ];
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        evaluator.add(&sample(&truths), &boxes(detections));
+        let metrics = &evaluator.finish(&[]).metrics[0];
+        assert!(close(metrics.map50, expected) && close(metrics.map50_95, expected));
+    }
+
+    #[test]
+    fn crowd_annotations_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("yolov8-rs-eval-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("instances.json");
+        std::fs::write(
+            &json,
+            r#"{
+                "images": [{"id": 7, "file_name": "a.jpg", "width": 100, "height": 100}],
+                "annotations": [
+                    {"id": 1, "image_id": 7, "category_id": 3, "bbox": [10, 10, 20, 20]},
+                    {"id": 2, "image_id": 7, "category_id": 3, "bbox": [50, 50, 40, 40], "iscrowd": 1}
+                ],
+                "categories": [{"id": 3, "name": "car"}]
+            }"#,
+        )
+        .unwrap();
+        let dataset = Dataset::from_coco(&json, &dir, &["person".to_string(), "car".to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let dataset = dataset.unwrap();
+        assert_eq!(dataset.samples.len(), 1);
+        assert_eq!(dataset.crowds, 1);
+        let truths = &dataset.samples[0].truths;
+        assert_eq!(truths.len(), 1);
+        assert_eq!(truths[0].bbox, Bbox::new(10., 10., 20., 20., 1, 1.));
+
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        evaluator.add(
+            &dataset.samples[0],
+            &boxes(vec![Bbox::new(10., 10., 20., 20., 1, 0.9)]),
+        );
+        let report = evaluator.finish(&dataset.names);
+        assert_eq!(report.instances, 1);
+        assert_eq!(report.metrics[0].classes[0].name.as_deref(), Some("car"));
+        assert!(close(report.metrics[0].recall, 1.));
+        assert_eq!(report.ap_style, "ultralytics");
+
+        // Ultralytics-style AP: a detection of the crowd is a false positive, here ranked
+        // first so it halves the precision
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        evaluator.add(
+            &dataset.samples[0],
+            &boxes(vec![
+                Bbox::new(10., 10., 20., 20., 1, 0.8),
+                Bbox::new(50., 50., 40., 40., 1, 0.9),
+            ]),
+        );
+        let report = evaluator.finish(&dataset.names);
+        let car = &report.metrics[0].classes[0];
+        assert_eq!(car.detections, 2);
+        assert!(car.ap50 > 0.45 && car.ap50 < 0.55, "{}", car.ap50);
+    }
+
+    #[test]
+    fn unlabeled_keypoints_are_ignored_by_oks() {
+        let mut sample = sample(&[Bbox::new(0., 0., 50., 50., 0, 1.)]);
+        sample.truths[0].keypoints = Some(vec![
+            Point2::new_with_conf(10., 10., 2.),
+            Point2::new_with_conf(0., 0., 0.),
+        ]);
+        let y = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(0., 0., 50., 50., 0, 0.9)]),
+            Some(vec![vec![
+                Point2::new_with_conf(10., 10., 0.9),
+                Point2::new_with_conf(40., 40., 0.9),
+            ]]),
+            None,
+        );
+        let iou = oks(&sample, &y, &[0], &[0], &[0.5, 0.5]);
+        assert!(close(iou[0][0], 1.), "{}", iou[0][0]);
+    }
+
+    #[test]
+    fn mask_iou_follows_the_cutoff_and_crop_of_masks() {
+        // a 4x4 object in the top left corner of an 8x8 image
+        let object: Vec<u8> = (0..64)
+            .map(|i| if i / 8 < 4 && i % 8 < 4 { 255 } else { 0 })
+            .collect();
+        let mut sample = sample(&[Bbox::new(0., 0., 4., 4., 0, 1.)]);
+        sample.width = 8;
+        sample.height = 8;
+        sample.truths[0].mask = Some(TruthMask::Rle(crate::mask::encode_rle(
+            &object,
+            8,
+            8,
+            MASK_THRESHOLD,
+        )));
+        // probability 0.8 over the object, 0.6 elsewhere
+        let soft: Vec<u8> = object
+            .iter()
+            .map(|&v| if v > 0 { 204 } else { 153 })
+            .collect();
+        let mut y = boxes(vec![Bbox::new(0., 0., 4., 4., 0, 0.9)]);
+        y.masks = Some(vec![soft]);
+        y.mask_size = Some((8, 8));
+
+        y.mask_cutoff = Some((0.7f32 * 255.).round() as u8);
+        assert!(close(mask_ious(&sample, &y, &[0], &[0])[0][0], 1.));
+        y.mask_cutoff = None;
+        assert!(close(mask_ious(&sample, &y, &[0], &[0])[0][0], 0.25));
+
+        // masks cropped to a region, as sliced inference keeps them
+        y.masks = Some(vec![vec![255; 16]]);
+        y.mask_rects = Some(vec![(0, 0, 4, 4)]);
+        assert!(close(mask_ious(&sample, &y, &[0], &[0])[0][0], 1.));
+        y.mask_rects = Some(vec![(2, 2, 4, 4)]);
+        assert!(close(mask_ious(&sample, &y, &[0], &[0])[0][0], 4. / 28.));
+        y.mask_rects = Some(vec![(4, 4, 4, 4)]);
+        assert_eq!(mask_ious(&sample, &y, &[0], &[0])[0][0], 0.);
+    }
+
+    #[test]
+    fn confusion_matrix_counts_by_predicted_and_true_class() {
+        let truths = [
+            Bbox::new(0., 0., 10., 10., 0, 1.),
+            Bbox::new(20., 0., 10., 10., 1, 1.),
+            // missed
+            Bbox::new(40., 0., 10., 10., 0, 1.),
+        ];
+        let detections = vec![
+            Bbox::new(0., 0., 10., 10., 0, 0.9),
+            // the wrong class
+            Bbox::new(20., 0., 10., 10., 0, 0.8),
+            // background
+            Bbox::new(60., 60., 10., 10., 1, 0.6),
+            // below confidence 0.25, not counted
+            Bbox::new(40., 0., 10., 10., 0, 0.1),
+        ];
+        let mut evaluator = Evaluator::new(vec![MetricKind::Box], 0);
+        evaluator.add(&sample(&truths), &boxes(detections));
+        let matrix = evaluator
+            .finish(&["person".to_string(), "car".to_string()])
+            .confusion_matrix;
+        assert_eq!(matrix.classes, vec![0, 1]);
+        assert_eq!(matrix.names, vec!["person", "car", "background"]);
+        // rows are predicted, columns true
+        assert_eq!(
+            matrix.matrix,
+            vec![vec![1, 1, 0], vec![0, 0, 1], vec![1, 0, 0]]
+        );
+    }
+}
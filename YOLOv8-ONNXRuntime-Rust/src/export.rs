@@ -0,0 +1,908 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::mask::{encode_polygons, encode_rle};
+use crate::{Bbox, EncodedMask, MaskFormat, Point2, VisionError, YOLOResult};
+
+/// A labeling-tool format of `Exporter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    // COCO results, a JSON list of scored detections as read by pycocotools `loadRes`
+    CocoResults,
+    // COCO dataset annotations JSON: images, annotations and categories
+    Coco,
+    // Ultralytics YOLO txt, one `labels/<stem>.txt` per image
+    Yolo,
+    // Pascal VOC XML, one `Annotations/<stem>.xml` per image
+    Voc,
+    // one CSV row per detection
+    Csv,
+}
+
+/// Encoding of COCO instance masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Segmentation {
+    // compressed RLE, `{"size": [h, w], "counts": "..."}`
+    #[default]
+    Rle,
+    // outer contours, `[[x1, y1, x2, y2, ...], ...]`
+    Polygon,
+}
+
+/// An image and its result, the input of every export.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageResult<'a> {
+    // image path, the COCO `file_name` and VOC `filename` are its file name
+    pub path: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub result: &'a YOLOResult,
+}
+
+impl ImageResult<'_> {
+    fn file_name(&self) -> String {
+        file_name(self.path)
+    }
+
+    fn stem(&self) -> String {
+        file_stem(self.path)
+    }
+
+    /// Boxes of the result, the enclosing box of rotated ones.
+    fn boxes(&self) -> Vec<Bbox> {
+        let mut boxes = self.result.bboxes().cloned().unwrap_or_default();
+        if let Some(rotated) = self.result.rotated_bboxes() {
+            boxes.extend(rotated.iter().map(|rbox| rbox.bbox()));
+        }
+        boxes
+    }
+}
+
+/// One scored detection of a COCO results file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoResult {
+    pub image_id: u64,
+    pub category_id: usize,
+    // [x, y, width, height], top-left corner
+    pub bbox: [f32; 4],
+    pub score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segmentation: Option<CocoSegmentation>,
+    // [x1, y1, score1, ...]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keypoints: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum CocoSegmentation {
+    Rle { size: [u32; 2], counts: String },
+    Polygons(Vec<Vec<f32>>),
+}
+
+/// A COCO dataset, the annotations of a labeling tool.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoAnnotation {
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: usize,
+    pub bbox: [f32; 4],
+    // mask pixels, or the box area without a mask
+    pub area: f32,
+    pub iscrowd: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segmentation: Option<CocoSegmentation>,
+    // [x1, y1, v1, ...], v is 2 for visible points and 0 otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keypoints: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_keypoints: Option<usize>,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoCategory {
+    pub id: usize,
+    pub name: String,
+    pub supercategory: String,
+}
+
+/// Writes results in labeling-tool formats. COCO category ids are the class ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exporter {
+    // class names by id, ids without a name are written as their number
+    pub names: Vec<String>,
+    pub segmentation: Segmentation,
+    // keypoints below are written as not visible
+    pub kconf: f32,
+}
+
+impl Default for Exporter {
+    fn default() -> Self {
+        Self {
+            names: vec![],
+            segmentation: Segmentation::Rle,
+            kconf: 0.55,
+        }
+    }
+}
+
+impl Exporter {
+    pub fn name(&self, id: usize) -> String {
+        self.names.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    /// Detections of an image as COCO results, `image_id` is the id of its COCO image.
+    pub fn coco_results(&self, image: &ImageResult, image_id: u64) -> Vec<CocoResult> {
+        let (segmentations, keypoints) = (self.segmentations(image), self.keypoints(image));
+        image
+            .boxes()
+            .iter()
+            .enumerate()
+            .map(|(i, bbox)| CocoResult {
+                image_id,
+                category_id: bbox.id(),
+                bbox: xywh(bbox),
+                score: bbox.confidence(),
+                segmentation: segmentations.get(i).map(|(segmentation, _)| segmentation.clone()),
+                keypoints: keypoints.get(i).map(|points| {
+                    points
+                        .iter()
+                        .flat_map(|p| [p.x(), p.y(), p.confidence()])
+                        .collect()
+                }),
+            })
+            .collect()
+    }
+
+    /// An empty COCO dataset with a category per class name.
+    pub fn coco_dataset(&self) -> CocoDataset {
+        CocoDataset {
+            categories: (0..self.names.len()).map(|id| self.category(id)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds an image and its detections to a COCO dataset, annotation ids following its last
+    /// ones. Classes missing from its categories are added.
+    pub fn coco_add(&self, dataset: &mut CocoDataset, image: &ImageResult, image_id: u64) {
+        dataset.images.push(CocoImage {
+            id: image_id,
+            file_name: image.file_name(),
+            width: image.width,
+            height: image.height,
+        });
+
+        let (segmentations, keypoints) = (self.segmentations(image), self.keypoints(image));
+        for (i, bbox) in image.boxes().iter().enumerate() {
+            let id = dataset.annotations.last().map_or(1, |last| last.id + 1);
+            let keypoints = keypoints.get(i).map(|points| self.visible(points));
+            dataset.annotations.push(CocoAnnotation {
+                id,
+                image_id,
+                category_id: bbox.id(),
+                bbox: xywh(bbox),
+                area: segmentations.get(i).map_or(bbox.area(), |&(_, area)| area as f32),
+                iscrowd: 0,
+                segmentation: segmentations.get(i).map(|(segmentation, _)| segmentation.clone()),
+                num_keypoints: keypoints
+                    .as_ref()
+                    .map(|points| points.iter().filter(|p| p.confidence() > 0.).count()),
+                keypoints: keypoints.map(|points| {
+                    points
+                        .iter()
+                        .flat_map(|p| [p.x(), p.y(), p.confidence()])
+                        .collect()
+                }),
+                score: bbox.confidence(),
+            });
+            if !dataset.categories.iter().any(|category| category.id == bbox.id()) {
+                dataset.categories.push(self.category(bbox.id()));
+                dataset.categories.sort_by_key(|category| category.id);
+            }
+        }
+    }
+
+    fn category(&self, id: usize) -> CocoCategory {
+        CocoCategory {
+            id,
+            name: self.name(id),
+            supercategory: String::new(),
+        }
+    }
+
+    /// Ultralytics YOLO txt of an image, coordinates normalized by the image size:
+    /// `class cx cy w h` for boxes, `class x1 y1 ...` with the largest contour for masks,
+    /// `class cx cy w h x1 y1 v1 ...` for keypoints, `class x1 y1 ... x4 y4` for rotated
+    /// boxes, and `confidence name` lines of the top 5 classes for classification.
+    pub fn yolo_txt(&self, image: &ImageResult) -> String {
+        let (w, h) = (image.width.max(1) as f32, image.height.max(1) as f32);
+        let y = image.result;
+        let mut txt = String::new();
+
+        if let Some(probs) = y.probs() {
+            for (id, confidence) in probs.topk(5) {
+                let _ = writeln!(txt, "{:.2} {}", confidence, self.name(id));
+            }
+        }
+
+        let polygons = match (y.masks(), y.mask_size()) {
+            (Some(_), Some((mask_w, mask_h))) => y
+                .encode_masks(MaskFormat::Polygon)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mask| match mask {
+                    EncodedMask::Polygons(polygons) => {
+                        // masks may be smaller than the image
+                        let (sx, sy) = (mask_w as f32, mask_h as f32);
+                        polygons
+                            .into_iter()
+                            .max_by_key(|polygon| polygon.len())
+                            .map(|polygon| (polygon, sx, sy))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let keypoints = y.keypoints();
+        for (i, bbox) in y.bboxes().into_iter().flatten().enumerate() {
+            let _ = write!(txt, "{}", bbox.id());
+            match polygons.get(i) {
+                Some(Some((polygon, sx, sy))) => {
+                    for p in polygon {
+                        let _ = write!(txt, " {:.6} {:.6}", p.x() / sx, p.y() / sy);
+                    }
+                }
+                _ => {
+                    let c = bbox.cxcy();
+                    let _ = write!(
+                        txt,
+                        " {:.6} {:.6} {:.6} {:.6}",
+                        c.x() / w,
+                        c.y() / h,
+                        bbox.width() / w,
+                        bbox.height() / h
+                    );
+                    let points = keypoints.and_then(|keypoints| keypoints.get(i));
+                    for p in points.map(|points| self.visible(points)).unwrap_or_default() {
+                        let _ = write!(txt, " {:.6} {:.6} {}", p.x() / w, p.y() / h, p.confidence());
+                    }
+                }
+            }
+            txt.push('\n');
+        }
+
+        for rbox in y.rotated_bboxes().into_iter().flatten() {
+            let _ = write!(txt, "{}", rbox.id());
+            for p in rbox.vertices() {
+                let _ = write!(txt, " {:.6} {:.6}", p.x() / w, p.y() / h);
+            }
+            txt.push('\n');
+        }
+        txt
+    }
+
+    /// Pascal VOC XML of an image, rotated boxes as their enclosing box.
+    pub fn voc_xml(&self, image: &ImageResult) -> String {
+        let path = Path::new(image.path);
+        let folder = path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .map_or_else(String::new, |dir| dir.to_string_lossy().into_owned());
+        let mut xml = String::from("<annotation>\n");
+        let _ = writeln!(xml, "  <folder>{}</folder>", escape_xml(&folder));
+        let _ = writeln!(xml, "  <filename>{}</filename>", escape_xml(&image.file_name()));
+        let _ = writeln!(xml, "  <path>{}</path>", escape_xml(image.path));
+        xml.push_str("  <source>\n    <database>Unknown</database>\n  </source>\n");
+        let _ = writeln!(
+            xml,
+            "  <size>\n    <width>{}</width>\n    <height>{}</height>\n    <depth>3</depth>\n  </size>",
+            image.width, image.height
+        );
+        xml.push_str("  <segmented>0</segmented>\n");
+        for bbox in image.boxes() {
+            // 1-based pixel coordinates, clipped to the image
+            let clip = |v: f32, max: u32| (v.round() as i64).clamp(1, max.max(1) as i64);
+            let _ = writeln!(
+                xml,
+                "  <object>\n    <name>{}</name>\n    <pose>Unspecified</pose>\n    <truncated>0</truncated>\n    <difficult>0</difficult>\n    <confidence>{:.4}</confidence>\n    <bndbox>\n      <xmin>{}</xmin>\n      <ymin>{}</ymin>\n      <xmax>{}</xmax>\n      <ymax>{}</ymax>\n    </bndbox>\n  </object>",
+                escape_xml(&self.name(bbox.id())),
+                bbox.confidence(),
+                clip(bbox.xmin() + 1., image.width),
+                clip(bbox.ymin() + 1., image.height),
+                clip(bbox.xmax(), image.width),
+                clip(bbox.ymax(), image.height),
+            );
+        }
+        xml.push_str("</annotation>\n");
+        xml
+    }
+
+    /// CSV rows of an image, `CSV_HEADER` columns. Boxes are `xmin, ymin, xmax, ymax`,
+    /// left empty for the top 5 classes of classification.
+    pub fn csv_rows(&self, image: &ImageResult) -> String {
+        let mut csv = String::new();
+        let path = escape_csv(image.path);
+        if let Some(probs) = image.result.probs() {
+            for (id, confidence) in probs.topk(5) {
+                let name = escape_csv(&self.name(id));
+                let _ = writeln!(csv, "{},{},{},{:.4},,,,", path, id, name, confidence);
+            }
+        }
+        for bbox in image.boxes() {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{:.4},{:.2},{:.2},{:.2},{:.2}",
+                path,
+                bbox.id(),
+                escape_csv(&self.name(bbox.id())),
+                bbox.confidence(),
+                bbox.xmin(),
+                bbox.ymin(),
+                bbox.xmax(),
+                bbox.ymax()
+            );
+        }
+        csv
+    }
+
+    /// Instance masks as COCO segmentations with their area, in box order. Each mask is
+    /// encoded once, the area of polygons is the pixel count of the mask.
+    fn segmentations(&self, image: &ImageResult) -> Vec<(CocoSegmentation, u64)> {
+        let y = image.result;
+        let (Some(masks), Some((width, height)), Some(bboxes)) = (y.masks(), y.mask_size(), y.bboxes())
+        else {
+            return vec![];
+        };
+        let cutoff = y.mask_cutoff();
+        (0..masks.len())
+            .zip(bboxes)
+            .filter_map(|(i, _)| {
+                let mask = y.full_mask(i)?;
+                Some(match self.segmentation {
+                    Segmentation::Rle => {
+                        let rle = encode_rle(&mask, width, height, cutoff);
+                        let area = rle.area();
+                        let segmentation = CocoSegmentation::Rle {
+                            size: [height, width],
+                            counts: rle.compress(),
+                        };
+                        (segmentation, area)
+                    }
+                    Segmentation::Polygon => {
+                        let area = mask.iter().filter(|&&v| v > cutoff).count() as u64;
+                        let segmentation = CocoSegmentation::Polygons(
+                            encode_polygons(&mask, width, height, cutoff)
+                                .iter()
+                                .map(|polygon| polygon.iter().flat_map(|p| [p.x(), p.y()]).collect())
+                                .collect(),
+                        );
+                        (segmentation, area)
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn keypoints<'a>(&self, image: &ImageResult<'a>) -> &'a [Vec<Point2>] {
+        image.result.keypoints().map_or(&[], |keypoints| keypoints.as_slice())
+    }
+
+    /// Keypoints with a COCO visibility in place of the confidence: 2 if at least `kconf`,
+    /// else 0 at (0, 0).
+    fn visible(&self, points: &[Point2]) -> Vec<Point2> {
+        points
+            .iter()
+            .map(|p| {
+                if p.confidence() >= self.kconf {
+                    Point2::new_with_conf(p.x(), p.y(), 2.)
+                } else {
+                    Point2::new_with_conf(0., 0., 0.)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Columns of `Exporter::csv_rows`.
+pub const CSV_HEADER: &str = "image,class_id,class_name,confidence,xmin,ymin,xmax,ymax";
+
+/// Writes the images of a run to `dir` in each of `formats`, image by image:
+/// `predictions.json`, `annotations.json`, `labels/<stem>.txt`, `Annotations/<stem>.xml`
+/// and `results.csv`. The JSON files are written by `finish`.
+#[derive(Debug)]
+pub struct ExportWriter {
+    exporter: Exporter,
+    formats: Vec<ExportFormat>,
+    dir: PathBuf,
+    coco_results: Vec<CocoResult>,
+    coco: CocoDataset,
+    csv: Option<BufWriter<File>>,
+    // ids of the images in both COCO files, by file name when read from a COCO dataset
+    image_ids: Option<HashMap<String, u64>>,
+    next_image_id: u64,
+    // images by the stem naming their YOLO and VOC files
+    stems: HashMap<String, String>,
+}
+
+impl ExportWriter {
+    pub fn new(exporter: Exporter, formats: &[ExportFormat], dir: &Path) -> Result<Self, VisionError> {
+        let formats = formats.iter().fold(vec![], |mut unique, &format| {
+            if !unique.contains(&format) {
+                unique.push(format);
+            }
+            unique
+        });
+        std::fs::create_dir_all(dir)?;
+        if formats.contains(&ExportFormat::Yolo) {
+            std::fs::create_dir_all(dir.join("labels"))?;
+        }
+        if formats.contains(&ExportFormat::Voc) {
+            std::fs::create_dir_all(dir.join("Annotations"))?;
+        }
+        let csv = if formats.contains(&ExportFormat::Csv) {
+            let mut csv = BufWriter::new(File::create(dir.join("results.csv"))?);
+            writeln!(csv, "{}", CSV_HEADER)?;
+            Some(csv)
+        } else {
+            None
+        };
+        Ok(Self {
+            coco: exporter.coco_dataset(),
+            exporter,
+            formats,
+            dir: dir.to_path_buf(),
+            coco_results: vec![],
+            csv,
+            image_ids: None,
+            next_image_id: 1,
+            stems: HashMap::new(),
+        })
+    }
+
+    /// Takes the COCO image ids from the `images` of a COCO dataset JSON, matched by file
+    /// name, so the results can be evaluated against it. Images are numbered from 1 otherwise.
+    pub fn read_image_ids(&mut self, json: &Path) -> Result<(), VisionError> {
+        let file: CocoImages = serde_json::from_reader(BufReader::new(File::open(json)?))
+            .map_err(|e| VisionError::InvalidArgument(format!("{}: {}", json.display(), e)))?;
+        let mut ids = HashMap::with_capacity(file.images.len());
+        for image in file.images {
+            let name = file_name(&image.file_name);
+            if ids.insert(name, image.id).is_some() {
+                return Err(VisionError::InvalidArgument(format!(
+                    "{}: several images are named {}",
+                    json.display(),
+                    file_name(&image.file_name)
+                )));
+            }
+        }
+        self.image_ids = Some(ids);
+        Ok(())
+    }
+
+    /// Fails if two of `paths` would write the same YOLO or VOC file, e.g. `a.jpg` and `a.png`.
+    pub fn check_paths(&self, paths: &[String]) -> Result<(), VisionError> {
+        if !self.formats.iter().any(|f| matches!(f, ExportFormat::Yolo | ExportFormat::Voc)) {
+            return Ok(());
+        }
+        let mut stems = self.stems.clone();
+        for path in paths {
+            check_stem(&mut stems, path)?;
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, image: &ImageResult) -> Result<(), VisionError> {
+        if self.formats.iter().any(|f| matches!(f, ExportFormat::Yolo | ExportFormat::Voc)) {
+            check_stem(&mut self.stems, image.path)?;
+        }
+        let image_id = match &self.image_ids {
+            Some(ids) => *ids.get(&image.file_name()).ok_or_else(|| {
+                VisionError::InvalidArgument(format!("{} is not an image of the COCO dataset", image.path))
+            })?,
+            None => {
+                let id = self.next_image_id;
+                self.next_image_id += 1;
+                id
+            }
+        };
+        for format in &self.formats {
+            match format {
+                ExportFormat::CocoResults => {
+                    self.coco_results.extend(self.exporter.coco_results(image, image_id))
+                }
+                ExportFormat::Coco => self.exporter.coco_add(&mut self.coco, image, image_id),
+                ExportFormat::Yolo => std::fs::write(
+                    self.dir.join("labels").join(format!("{}.txt", image.stem())),
+                    self.exporter.yolo_txt(image),
+                )?,
+                ExportFormat::Voc => std::fs::write(
+                    self.dir.join("Annotations").join(format!("{}.xml", image.stem())),
+                    self.exporter.voc_xml(image),
+                )?,
+                ExportFormat::Csv => {
+                    if let Some(csv) = &mut self.csv {
+                        csv.write_all(self.exporter.csv_rows(image).as_bytes())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the COCO files and flushes the CSV.
+    pub fn finish(mut self) -> Result<(), VisionError> {
+        if self.formats.contains(&ExportFormat::CocoResults) {
+            write_json(&self.dir.join("predictions.json"), &self.coco_results)?;
+        }
+        if self.formats.contains(&ExportFormat::Coco) {
+            write_json(&self.dir.join("annotations.json"), &self.coco)?;
+        }
+        if let Some(csv) = &mut self.csv {
+            csv.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The `images` of a COCO dataset JSON, the rest is not read.
+#[derive(Deserialize)]
+struct CocoImages {
+    images: Vec<CocoImageId>,
+}
+
+#[derive(Deserialize)]
+struct CocoImageId {
+    id: u64,
+    file_name: String,
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Records the stem of `path`, failing if another image already has it.
+fn check_stem(stems: &mut HashMap<String, String>, path: &str) -> Result<(), VisionError> {
+    let stem = file_stem(path);
+    match stems.get(&stem) {
+        Some(other) if other != path => Err(VisionError::InvalidArgument(format!(
+            "{} and {} would export to the same {}.txt and {}.xml, rename one of them",
+            other, path, stem, stem
+        ))),
+        _ => {
+            stems.insert(stem, path.to_string());
+            Ok(())
+        }
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), VisionError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut file, value).map_err(|e| VisionError::Io(e.to_string()))?;
+    Ok(file.flush()?)
+}
+
+fn xywh(bbox: &Bbox) -> [f32; 4] {
+    [bbox.xmin(), bbox.ymin(), bbox.width(), bbox.height()]
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{Dataset, TruthMask};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yolov8-rs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    fn rect_mask(width: u32, height: u32, (x0, y0, x1, y1): (u32, u32, u32, u32)) -> Vec<u8> {
+        let mut mask = vec![0u8; (width * height) as usize];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                mask[(y * width + x) as usize] = 255;
+            }
+        }
+        mask
+    }
+
+    #[test]
+    fn yolo_txt_reads_back_as_a_dataset() {
+        let dir = temp_dir("export-yolo");
+        let images = dir.join("images");
+        std::fs::create_dir_all(&images).unwrap();
+        let path = |name: &str| images.join(name).to_string_lossy().into_owned();
+        for name in ["a.png", "b.png", "c.png"] {
+            image::RgbImage::new(64, 48).save(path(name)).unwrap();
+        }
+
+        let boxes = YOLOResult::new(
+            None,
+            Some(vec![
+                Bbox::new(4., 6., 20., 10., 3, 0.9),
+                Bbox::new(30.5, 12.25, 16., 30., 0, 0.4),
+            ]),
+            None,
+            None,
+        );
+        // 17 points, the second one below `kconf`
+        let mut points = vec![Point2::new_with_conf(20., 24., 0.9); 17];
+        points[1] = Point2::new_with_conf(22., 26., 0.1);
+        let pose = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(10., 8., 30., 32., 0, 0.8)]),
+            Some(vec![points]),
+            None,
+        );
+        let mut segment = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(10., 8., 20., 12., 5, 0.7)]),
+            None,
+            Some(vec![rect_mask(64, 48, (10, 8, 30, 20))]),
+        );
+        segment.mask_size = Some((64, 48));
+
+        let mut writer =
+            ExportWriter::new(Exporter::default(), &[ExportFormat::Yolo], &dir).unwrap();
+        for (name, result) in [("a.png", &boxes), ("b.png", &pose), ("c.png", &segment)] {
+            let path = path(name);
+            writer
+                .add(&ImageResult {
+                    path: &path,
+                    width: 64,
+                    height: 48,
+                    result,
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let dataset =
+            Dataset::from_yolo(&images.to_string_lossy(), Some(&dir.join("labels")), 17).unwrap();
+        assert_eq!(dataset.samples.len(), 3);
+        let truths = |name: &str| {
+            &dataset
+                .samples
+                .iter()
+                .find(|sample| sample.path.ends_with(name))
+                .unwrap()
+                .truths
+        };
+
+        let a = truths("a.png");
+        assert_eq!(a.len(), 2);
+        for (truth, bbox) in a.iter().zip(boxes.bboxes().unwrap()) {
+            assert_eq!(truth.bbox.id(), bbox.id());
+            assert!(close(truth.bbox.xmin(), bbox.xmin()) && close(truth.bbox.ymin(), bbox.ymin()));
+            assert!(
+                close(truth.bbox.width(), bbox.width())
+                    && close(truth.bbox.height(), bbox.height())
+            );
+        }
+
+        let b = truths("b.png");
+        assert_eq!(b.len(), 1);
+        assert!(close(b[0].bbox.xmin(), 10.) && close(b[0].bbox.height(), 32.));
+        let keypoints = b[0].keypoints.as_ref().unwrap();
+        assert_eq!(keypoints.len(), 17);
+        assert!(close(keypoints[0].x(), 20.) && close(keypoints[0].y(), 24.));
+        assert_eq!(keypoints[0].confidence(), 2.);
+        assert_eq!(keypoints[1].confidence(), 0.);
+
+        let c = truths("c.png");
+        assert_eq!(c.len(), 1);
+        assert_eq!(c[0].bbox.id(), 5);
+        let Some(mask @ TruthMask::Polygons(_)) = &c[0].mask else {
+            panic!("expected a polygon");
+        };
+        // the contour runs through the outer pixels of the mask
+        let decoded = mask.decode(64, 48);
+        let original = rect_mask(64, 48, (10, 8, 30, 20));
+        let both = decoded
+            .iter()
+            .zip(&original)
+            .filter(|&(&a, &b)| a > 0 && b > 0)
+            .count();
+        let either = decoded
+            .iter()
+            .zip(&original)
+            .filter(|&(&a, &b)| a > 0 || b > 0)
+            .count();
+        assert_eq!(both, 20 * 12);
+        assert_eq!(either, 20 * 12);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn coco_image_ids_come_from_the_dataset() {
+        let dir = temp_dir("export-coco-ids");
+        let json = dir.join("instances.json");
+        std::fs::write(
+            &json,
+            r#"{"images": [
+                {"id": 42, "file_name": "val/b.jpg", "width": 64, "height": 48},
+                {"id": 7, "file_name": "a.jpg", "width": 64, "height": 48}
+            ], "annotations": []}"#,
+        )
+        .unwrap();
+        let result = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(1., 2., 3., 4., 0, 0.5)]),
+            None,
+            None,
+        );
+
+        let formats = [ExportFormat::CocoResults, ExportFormat::Coco];
+        let mut writer = ExportWriter::new(Exporter::default(), &formats, &dir).unwrap();
+        writer.read_image_ids(&json).unwrap();
+        for path in ["images/a.jpg", "images/b.jpg"] {
+            writer
+                .add(&ImageResult {
+                    path,
+                    width: 64,
+                    height: 48,
+                    result: &result,
+                })
+                .unwrap();
+        }
+        assert!(matches!(
+            writer.add(&ImageResult {
+                path: "images/c.jpg",
+                width: 64,
+                height: 48,
+                result: &result,
+            }),
+            Err(VisionError::InvalidArgument(_))
+        ));
+        writer.finish().unwrap();
+
+        let read = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&std::fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+        };
+        let ids = |values: &serde_json::Value, key: &str| -> Vec<u64> {
+            values
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|value| value[key].as_u64().unwrap())
+                .collect()
+        };
+        assert_eq!(ids(&read("predictions.json"), "image_id"), [7, 42]);
+        let annotations = read("annotations.json");
+        assert_eq!(ids(&annotations["images"], "id"), [7, 42]);
+        assert_eq!(ids(&annotations["annotations"], "image_id"), [7, 42]);
+
+        // file names must pick a single image
+        std::fs::write(
+            &json,
+            r#"{"images": [{"id": 1, "file_name": "a/x.jpg"}, {"id": 2, "file_name": "b/x.jpg"}]}"#,
+        )
+        .unwrap();
+        let mut writer = ExportWriter::new(Exporter::default(), &formats, &dir).unwrap();
+        assert!(matches!(
+            writer.read_image_ids(&json),
+            Err(VisionError::InvalidArgument(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn images_of_the_same_stem_are_refused() {
+        let dir = temp_dir("export-stems");
+        let paths = ["d/a.jpg".to_string(), "d/a.png".to_string()];
+        for format in [ExportFormat::Yolo, ExportFormat::Voc] {
+            let writer = ExportWriter::new(Exporter::default(), &[format], &dir).unwrap();
+            assert!(matches!(
+                writer.check_paths(&paths),
+                Err(VisionError::InvalidArgument(_))
+            ));
+            assert!(writer.check_paths(&paths[..1]).is_ok());
+        }
+        let writer = ExportWriter::new(Exporter::default(), &[ExportFormat::Csv], &dir).unwrap();
+        assert!(writer.check_paths(&paths).is_ok());
+
+        let result = YOLOResult::default();
+        let mut writer =
+            ExportWriter::new(Exporter::default(), &[ExportFormat::Yolo], &dir).unwrap();
+        let mut add = |path| {
+            writer.add(&ImageResult {
+                path,
+                width: 8,
+                height: 8,
+                result: &result,
+            })
+        };
+        assert!(add("d/a.jpg").is_ok());
+        assert!(add("d/a.png").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segmentations_have_the_mask_area() {
+        let mut result = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(10., 8., 20., 12., 0, 0.7)]),
+            None,
+            Some(vec![rect_mask(64, 48, (10, 8, 30, 20))]),
+        );
+        result.mask_size = Some((64, 48));
+        let image = ImageResult {
+            path: "a.jpg",
+            width: 64,
+            height: 48,
+            result: &result,
+        };
+        for segmentation in [Segmentation::Rle, Segmentation::Polygon] {
+            let exporter = Exporter {
+                segmentation,
+                ..Default::default()
+            };
+            let segmentations = exporter.segmentations(&image);
+            assert_eq!(segmentations.len(), 1);
+            assert_eq!(segmentations[0].1, 20 * 12);
+            match (&segmentations[0].0, segmentation) {
+                (CocoSegmentation::Rle { size, .. }, Segmentation::Rle) => {
+                    assert_eq!(size, &[48, 64])
+                }
+                (CocoSegmentation::Polygons(polygons), Segmentation::Polygon) => {
+                    assert_eq!(polygons.len(), 1)
+                }
+                _ => panic!("unexpected segmentation"),
+            }
+        }
+    }
+}
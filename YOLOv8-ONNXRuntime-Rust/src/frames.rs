@@ -0,0 +1,1213 @@
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::{
+    AnimationDecoder, Delay, DynamicImage, Frame, Frames, ImageFormat, RgbImage, RgbaImage,
+};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::utils::find_images;
+use crate::VisionError;
+
+/// JPEG quality of the frames of written AVI files.
+pub const AVI_JPEG_QUALITY: u8 = 90;
+
+/// A frame of a clip.
+#[derive(Debug, Clone)]
+pub struct VideoFrame {
+    // position in the clip, from 0
+    pub index: u64,
+    // presentation time since the first frame
+    pub timestamp: Duration,
+    pub image: DynamicImage,
+}
+
+impl VideoFrame {
+    pub fn timestamp_ms(&self) -> f64 {
+        self.timestamp.as_secs_f64() * 1e3
+    }
+}
+
+/// Reads the frames of a clip in presentation order.
+pub trait FrameSource {
+    /// The next frame, `None` after the last one.
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VisionError>;
+
+    /// Frame rate of the clip, nominal for formats timing each frame on its own.
+    fn fps(&self) -> f64;
+
+    /// Number of frames, when known before reading them.
+    fn frame_count(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Writes the frames of a clip.
+pub trait FrameSink {
+    fn write_frame(&mut self, frame: &VideoFrame) -> Result<(), VisionError>;
+
+    /// Writes what is still buffered and completes the file, nothing can be written after.
+    fn finish(&mut self) -> Result<(), VisionError>;
+}
+
+/// Container of a clip, by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    // a directory or `*`/`?` pattern of numbered image files
+    Frames,
+    Gif,
+    Y4m,
+    Avi,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("gif") => Self::Gif,
+            Some("y4m") => Self::Y4m,
+            Some("avi") => Self::Avi,
+            _ => Self::Frames,
+        }
+    }
+}
+
+/// Opens a clip by its extension: `.gif`, `.y4m`, `.avi`, else a directory or `*`/`?` pattern
+/// of frames. Frames of image sequences, and GIF frames without a delay, are `fps` apart.
+pub fn open_source(source: &str, fps: f64) -> Result<Box<dyn FrameSource>, VisionError> {
+    check_fps(fps)?;
+    Ok(match VideoFormat::from_path(Path::new(source)) {
+        VideoFormat::Frames => Box::new(ImageSequenceReader::open(source, fps)?),
+        VideoFormat::Gif => Box::new(GifReader::open(source, fps)?),
+        VideoFormat::Y4m => Box::new(Y4mReader::open(source, fps)?),
+        VideoFormat::Avi => Box::new(AviReader::open(source, fps)?),
+    })
+}
+
+/// Creates a clip of `fps` frames per second by its extension, as `open_source` reads them.
+/// Image sequences are written as numbered JPEG files in the `path` directory.
+pub fn create_sink(path: &Path, fps: f64) -> Result<Box<dyn FrameSink>, VisionError> {
+    check_fps(fps)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(match VideoFormat::from_path(path) {
+        VideoFormat::Frames => Box::new(ImageSequenceWriter::new(path, "jpg")?),
+        VideoFormat::Gif => Box::new(GifWriter::create(path, fps)?),
+        VideoFormat::Y4m => Box::new(Y4mWriter::create(path, fps)?),
+        VideoFormat::Avi => Box::new(AviWriter::create(path, fps, AVI_JPEG_QUALITY)?),
+    })
+}
+
+fn check_fps(fps: f64) -> Result<(), VisionError> {
+    if fps.is_finite() && fps > 0. {
+        Ok(())
+    } else {
+        Err(VisionError::InvalidArgument(format!(
+            "Invalid frame rate {}, expected a positive number",
+            fps
+        )))
+    }
+}
+
+// time of frame `index` at `den / num` seconds per frame
+fn frame_time(index: u64, (num, den): (u32, u32)) -> Duration {
+    Duration::from_secs_f64(index as f64 * den as f64 / num as f64)
+}
+
+// a frame rate as the `num / den` of Y4M and AVI headers, exact for the NTSC rates
+fn rational(fps: f64) -> (u32, u32) {
+    [1, 1001, 1000]
+        .into_iter()
+        .map(|den| ((fps * den as f64).round().max(1.) as u32, den))
+        .find(|&(num, den)| (num as f64 / den as f64 - fps).abs() < 1e-6)
+        .unwrap_or(((fps * 1000.).round().max(1.) as u32, 1000))
+}
+
+/// Image files of a directory or `*`/`?` pattern, in the order of the number in their name,
+/// so `frame_10.jpg` follows `frame_9.jpg`.
+pub struct ImageSequenceReader {
+    paths: Vec<String>,
+    next: usize,
+    fps: f64,
+}
+
+impl ImageSequenceReader {
+    pub fn open(source: &str, fps: f64) -> Result<Self, VisionError> {
+        let mut paths = find_images(source)?;
+        if paths.is_empty() {
+            return Err(VisionError::NotFound(format!("No image in {}", source)));
+        }
+        paths.sort_by_cached_key(|path| frame_key(path));
+        Ok(Self {
+            paths,
+            next: 0,
+            fps,
+        })
+    }
+}
+
+// the file name without its last number, then that number
+fn frame_key(path: &str) -> (String, u64, String) {
+    let name = Path::new(path)
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let end = name
+        .rfind(|c: char| c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    let start = name[..end]
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    let number = name[start..end].parse().unwrap_or(u64::MAX);
+    (
+        format!("{}{}", &name[..start], &name[end..]),
+        number,
+        path.to_string(),
+    )
+}
+
+impl FrameSource for ImageSequenceReader {
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VisionError> {
+        let Some(path) = self.paths.get(self.next) else {
+            return Ok(None);
+        };
+        let index = self.next as u64;
+        self.next += 1;
+        Ok(Some(VideoFrame {
+            index,
+            timestamp: Duration::from_secs_f64(index as f64 / self.fps),
+            image: image::open(path)?,
+        }))
+    }
+
+    fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    fn frame_count(&self) -> Option<u64> {
+        Some(self.paths.len() as u64)
+    }
+}
+
+/// Numbered image files, `000000.jpg`, `000001.jpg`, ..., in a directory.
+pub struct ImageSequenceWriter {
+    dir: PathBuf,
+    // of the files, which gives their format
+    extension: String,
+    written: u64,
+}
+
+impl ImageSequenceWriter {
+    pub fn new(dir: &Path, extension: &str) -> Result<Self, VisionError> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            extension: extension.to_string(),
+            written: 0,
+        })
+    }
+}
+
+impl FrameSink for ImageSequenceWriter {
+    fn write_frame(&mut self, frame: &VideoFrame) -> Result<(), VisionError> {
+        let path = self
+            .dir
+            .join(format!("{:06}.{}", self.written, self.extension));
+        // JPEG has no alpha channel
+        frame.image.to_rgb8().save(path)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), VisionError> {
+        Ok(())
+    }
+}
+
+/// Frames of an animated GIF, each shown for its own delay.
+pub struct GifReader {
+    frames: Frames<'static>,
+    // read on open for the frame rate
+    first: Option<Frame>,
+    index: u64,
+    // timestamp of the next frame
+    elapsed: Duration,
+    // of frames without a delay
+    fps: f64,
+}
+
+impl GifReader {
+    pub fn open(path: &str, fps: f64) -> Result<Self, VisionError> {
+        let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+        let mut frames = decoder.into_frames();
+        let first = frames.next().transpose()?;
+        let fps = match first.as_ref().map(|frame| Duration::from(frame.delay())) {
+            Some(delay) if !delay.is_zero() => 1. / delay.as_secs_f64(),
+            _ => fps,
+        };
+        Ok(Self {
+            frames,
+            first,
+            index: 0,
+            elapsed: Duration::ZERO,
+            fps,
+        })
+    }
+}
+
+impl FrameSource for GifReader {
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VisionError> {
+        let frame = match self.first.take() {
+            Some(frame) => frame,
+            None => match self.frames.next().transpose()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            },
+        };
+        let delay = match Duration::from(frame.delay()) {
+            delay if delay.is_zero() => Duration::from_secs_f64(1. / self.fps),
+            delay => delay,
+        };
+        let (index, timestamp) = (self.index, self.elapsed);
+        self.index += 1;
+        self.elapsed += delay;
+        Ok(Some(VideoFrame {
+            index,
+            timestamp,
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+        }))
+    }
+
+    fn fps(&self) -> f64 {
+        self.fps
+    }
+}
+
+/// An animated GIF looping forever, each frame shown until the timestamp of the next one.
+pub struct GifWriter {
+    encoder: Option<GifEncoder<BufWriter<File>>>,
+    // delay of the last frame
+    fps: f64,
+    // the last frame, written once the next one gives its delay
+    pending: Option<(RgbaImage, Duration)>,
+}
+
+impl GifWriter {
+    pub fn create(path: &Path, fps: f64) -> Result<Self, VisionError> {
+        // speed 10 of 30 rather than the slowest quantization
+        let mut encoder = GifEncoder::new_with_speed(BufWriter::new(File::create(path)?), 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        Ok(Self {
+            encoder: Some(encoder),
+            fps,
+            pending: None,
+        })
+    }
+
+    // GIF delays are in hundredths of a second, rounding each timestamp keeps them from drifting
+    fn write_pending(&mut self, next: Option<Duration>) -> Result<(), VisionError> {
+        let (Some((image, timestamp)), Some(encoder)) =
+            (self.pending.take(), self.encoder.as_mut())
+        else {
+            return Ok(());
+        };
+        let centis = |t: Duration| (t.as_secs_f64() * 100.).round() as u32;
+        let delay = match next {
+            Some(next) => centis(next).saturating_sub(centis(timestamp)),
+            None => (100. / self.fps).round() as u32,
+        };
+        let delay = Delay::from_numer_denom_ms(delay.max(1) * 10, 1);
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+        Ok(())
+    }
+}
+
+impl FrameSink for GifWriter {
+    fn write_frame(&mut self, frame: &VideoFrame) -> Result<(), VisionError> {
+        if self.encoder.is_none() {
+            return Err(VisionError::Internal(
+                "The GIF is already finished".to_string(),
+            ));
+        }
+        self.write_pending(Some(frame.timestamp))?;
+        self.pending = Some((frame.image.to_rgba8(), frame.timestamp));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), VisionError> {
+        self.write_pending(None)?;
+        // the trailer is written as the encoder drops
+        self.encoder = None;
+        Ok(())
+    }
+}
+
+// chroma subsampling of Y4M, 8 bits per sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chroma {
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Chroma {
+    fn parse(colorspace: &str) -> Result<Self, VisionError> {
+        match colorspace {
+            "420" | "420jpeg" | "420paldv" | "420mpeg2" => Ok(Self::C420),
+            "422" => Ok(Self::C422),
+            "444" => Ok(Self::C444),
+            "mono" => Ok(Self::Mono),
+            _ => Err(VisionError::Unsupported(format!(
+                "Y4M colorspace {}, only 8-bit 420, 422, 444 and mono are supported",
+                colorspace
+            ))),
+        }
+    }
+
+    // size of each chroma plane, none for mono
+    fn plane(&self, width: usize, height: usize) -> Option<(usize, usize)> {
+        match self {
+            Self::C420 => Some((width.div_ceil(2), height.div_ceil(2))),
+            Self::C422 => Some((width.div_ceil(2), height)),
+            Self::C444 => Some((width, height)),
+            Self::Mono => None,
+        }
+    }
+}
+
+// BT.601, limited range unless `full`
+fn yuv_to_rgb(y: u8, u: u8, v: u8, full: bool) -> [u8; 3] {
+    let (u, v) = (u as f32 - 128., v as f32 - 128.);
+    let (y, scale) = if full {
+        (y as f32, 1.)
+    } else {
+        ((y as f32 - 16.) * 255. / 219., 255. / 224.)
+    };
+    let (u, v) = (u * scale, v * scale);
+    let channel = |x: f32| x.round().clamp(0., 255.) as u8;
+    [
+        channel(y + 1.402 * v),
+        channel(y - 0.344136 * u - 0.714136 * v),
+        channel(y + 1.772 * u),
+    ]
+}
+
+// BT.601 limited range
+fn rgb_to_yuv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        16. + 0.256788 * r + 0.504129 * g + 0.097906 * b,
+        128. - 0.148223 * r - 0.290993 * g + 0.439216 * b,
+        128. + 0.439216 * r - 0.367788 * g - 0.071427 * b,
+    ]
+}
+
+/// Raw YUV frames of a YUV4MPEG2 file, as written by `ffmpeg -f yuv4mpegpipe`.
+pub struct Y4mReader {
+    reader: BufReader<File>,
+    width: usize,
+    height: usize,
+    chroma: Chroma,
+    // `XCOLORRANGE=FULL`, else limited range
+    full_range: bool,
+    // frame rate as `num / den`
+    rate: (u32, u32),
+    // bytes of a frame, after its `FRAME` line
+    frame_len: usize,
+    index: u64,
+    buffer: Vec<u8>,
+}
+
+impl Y4mReader {
+    pub fn open(path: &str, fps: f64) -> Result<Self, VisionError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = Vec::new();
+        let header_len = reader.read_until(b'\n', &mut header)? as u64;
+        let header = String::from_utf8_lossy(&header);
+        let Some(params) = header.trim_end().strip_prefix("YUV4MPEG2") else {
+            return Err(VisionError::InvalidArgument(format!(
+                "Not a Y4M file: {}",
+                path
+            )));
+        };
+
+        let (mut width, mut height, mut rate) = (0, 0, rational(fps));
+        let (mut chroma, mut full_range) = (Chroma::C420, false);
+        for param in params.split_ascii_whitespace() {
+            let mut chars = param.chars();
+            let (tag, value) = (chars.next(), chars.as_str());
+            match tag {
+                Some('W') => width = value.parse().unwrap_or(0),
+                Some('H') => height = value.parse().unwrap_or(0),
+                Some('F') => {
+                    if let Some((num, den)) = value.split_once(':') {
+                        match (num.parse(), den.parse()) {
+                            (Ok(num), Ok(den)) if num > 0 && den > 0 => rate = (num, den),
+                            _ => {}
+                        }
+                    }
+                }
+                Some('C') => chroma = Chroma::parse(value)?,
+                Some('X') if value.eq_ignore_ascii_case("COLORRANGE=FULL") => full_range = true,
+                _ => {}
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(VisionError::InvalidArgument(format!(
+                "Y4M header without a frame size: {}",
+                path
+            )));
+        }
+        // a frame is read whole, so its size is bounded by the file
+        let frame_len = chroma
+            .plane(width, height)
+            .map_or(Some(0), |(w, h)| w.checked_mul(h)?.checked_mul(2))
+            .and_then(|chroma| width.checked_mul(height)?.checked_add(chroma));
+        let metadata = reader.get_ref().metadata()?;
+        let rest = metadata.len().saturating_sub(header_len);
+        let frame_len = match frame_len {
+            Some(len) if !metadata.is_file() || rest == 0 || len as u64 <= rest => len,
+            _ => {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Y4M frame size {}x{} exceeds the file: {}",
+                    width, height, path
+                )))
+            }
+        };
+        Ok(Self {
+            reader,
+            width,
+            height,
+            chroma,
+            full_range,
+            rate,
+            frame_len,
+            index: 0,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl FrameSource for Y4mReader {
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VisionError> {
+        // `FRAME`, optional parameters, newline
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.starts_with(b"FRAME") {
+            return Err(VisionError::InvalidArgument(format!(
+                "Y4M frame {} without a FRAME header",
+                self.index
+            )));
+        }
+
+        let (width, height) = (self.width, self.height);
+        let plane = self.chroma.plane(width, height);
+        self.buffer.resize(self.frame_len, 0);
+        self.reader.read_exact(&mut self.buffer).map_err(|e| {
+            VisionError::Io(format!("Y4M frame {} is truncated: {}", self.index, e))
+        })?;
+
+        let (luma, chroma) = self.buffer.split_at(width * height);
+        let image = match plane {
+            Some((cw, ch)) => {
+                let (u, v) = chroma.split_at(cw * ch);
+                // nearest chroma sample of each pixel
+                let (sx, sy) = (width.div_ceil(cw), height.div_ceil(ch));
+                RgbImage::from_fn(width as u32, height as u32, |x, y| {
+                    let (x, y) = (x as usize, y as usize);
+                    let c = (y / sy) * cw + x / sx;
+                    image::Rgb(yuv_to_rgb(luma[y * width + x], u[c], v[c], self.full_range))
+                })
+            }
+            None => RgbImage::from_fn(width as u32, height as u32, |x, y| {
+                let l = luma[y as usize * width + x as usize];
+                image::Rgb(yuv_to_rgb(l, 128, 128, self.full_range))
+            }),
+        };
+
+        let index = self.index;
+        self.index += 1;
+        Ok(Some(VideoFrame {
+            index,
+            timestamp: frame_time(index, self.rate),
+            image: DynamicImage::ImageRgb8(image),
+        }))
+    }
+
+    fn fps(&self) -> f64 {
+        self.rate.0 as f64 / self.rate.1 as f64
+    }
+}
+
+/// A YUV4MPEG2 file of 4:2:0 limited range frames, the size of the first frame.
+pub struct Y4mWriter {
+    writer: BufWriter<File>,
+    rate: (u32, u32),
+    // set by the first frame, with the header
+    size: Option<(u32, u32)>,
+}
+
+impl Y4mWriter {
+    pub fn create(path: &Path, fps: f64) -> Result<Self, VisionError> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            rate: rational(fps),
+            size: None,
+        })
+    }
+}
+
+impl FrameSink for Y4mWriter {
+    fn write_frame(&mut self, frame: &VideoFrame) -> Result<(), VisionError> {
+        let image = frame.image.to_rgb8();
+        let (width, height) = image.dimensions();
+        match self.size {
+            Some(size) if size != (width, height) => {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Frame {} is {}x{}, the Y4M clip is {}x{}",
+                    frame.index, width, height, size.0, size.1
+                )))
+            }
+            Some(_) => {}
+            None => {
+                writeln!(
+                    self.writer,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XYSCSS=420JPEG",
+                    width, height, self.rate.0, self.rate.1
+                )?;
+                self.size = Some((width, height));
+            }
+        }
+
+        let yuv: Vec<[f32; 3]> = image
+            .pixels()
+            .map(|p| rgb_to_yuv(p.0.map(|c| c as f32)))
+            .collect();
+        let (width, height) = (width as usize, height as usize);
+        let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+        let sample = |x: f32| x.round().clamp(0., 255.) as u8;
+        let luma: Vec<u8> = yuv.iter().map(|p| sample(p[0])).collect();
+        // chroma averaged over each 2x2 block, clipped at odd edges
+        let (mut u, mut v) = (Vec::with_capacity(cw * ch), Vec::with_capacity(cw * ch));
+        for cy in 0..ch {
+            for cx in 0..cw {
+                let (mut su, mut sv, mut count) = (0., 0., 0.);
+                for y in 2 * cy..(2 * cy + 2).min(height) {
+                    for x in 2 * cx..(2 * cx + 2).min(width) {
+                        let [_, pu, pv] = yuv[y * width + x];
+                        su += pu;
+                        sv += pv;
+                        count += 1.;
+                    }
+                }
+                u.push(sample(su / count));
+                v.push(sample(sv / count));
+            }
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        for plane in [luma, u, v] {
+            self.writer.write_all(&plane)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), VisionError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+// a RIFF chunk header: id and size of the data, without the pad byte of odd sizes
+fn read_chunk(reader: &mut impl Read) -> io::Result<Option<([u8; 4], u32)>> {
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let (id, size) = header.split_at(4);
+    Ok(Some((fourcc(id), u32::from_le_bytes(fourcc(size)))))
+}
+
+fn fourcc(bytes: &[u8]) -> [u8; 4] {
+    [bytes[0], bytes[1], bytes[2], bytes[3]]
+}
+
+fn padded(size: u32) -> u64 {
+    size as u64 + (size & 1) as u64
+}
+
+// bytes of a list after its form id
+fn list_rest(size: u32) -> i64 {
+    padded(size).saturating_sub(4) as i64
+}
+
+/// Reads the `size` bytes of a chunk and skips its padding. The size comes from the file,
+/// so it is checked against the `len` bytes of the file before allocating.
+fn read_data(
+    reader: &mut BufReader<File>,
+    id: [u8; 4],
+    size: u32,
+    len: u64,
+) -> Result<Vec<u8>, VisionError> {
+    let rest = len.saturating_sub(reader.stream_position()?);
+    if size as u64 > rest {
+        return Err(VisionError::InvalidArgument(format!(
+            "AVI chunk {} of {} bytes runs past the end of the file",
+            String::from_utf8_lossy(&id),
+            size
+        )));
+    }
+    let mut data = vec![0; size as usize];
+    reader.read_exact(&mut data)?;
+    reader.seek_relative((size & 1) as i64)?;
+    Ok(data)
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    data.get(offset..offset + 4)
+        .map_or(0, |b| u32::from_le_bytes(fourcc(b)))
+}
+
+/// Motion-JPEG frames of the first video stream of an AVI file, including the `AVIX`
+/// extensions of OpenDML files. Other streams are skipped, empty frame chunks repeat the
+/// previous frame.
+pub struct AviReader {
+    reader: BufReader<File>,
+    // stream number of the frame chunk ids, e.g. `00` of `00dc`
+    stream: [u8; 2],
+    // frame rate as `dwRate / dwScale`
+    rate: (u32, u32),
+    // `dwLength` of the stream, or `dwTotalFrames`
+    frames: Option<u64>,
+    // bytes of the file, bounding the chunk sizes
+    len: u64,
+    index: u64,
+    last: Option<DynamicImage>,
+}
+
+impl AviReader {
+    pub fn open(path: &str, fps: f64) -> Result<Self, VisionError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let metadata = reader.get_ref().metadata()?;
+        let len = if metadata.is_file() {
+            metadata.len()
+        } else {
+            u64::MAX
+        };
+        let mut riff = [0; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"AVI " {
+            return Err(VisionError::InvalidArgument(format!(
+                "Not an AVI file: {}",
+                path
+            )));
+        }
+
+        // headers up to the `movi` list
+        let (mut micros, mut total) = (0, 0);
+        let mut streams = 0u32;
+        let mut video: Option<(u32, (u32, u32), u32)> = None;
+        let mut codec = None;
+        loop {
+            let Some((id, size)) = read_chunk(&mut reader)? else {
+                return Err(VisionError::InvalidArgument(format!(
+                    "AVI without frames: {}",
+                    path
+                )));
+            };
+            if &id == b"LIST" {
+                let mut form = [0; 4];
+                reader.read_exact(&mut form)?;
+                match &form {
+                    b"hdrl" | b"strl" => continue,
+                    b"movi" => break,
+                    _ => {
+                        reader.seek_relative(list_rest(size))?;
+                        continue;
+                    }
+                }
+            }
+            if !matches!(&id, b"avih" | b"strh" | b"strf") {
+                reader.seek_relative(padded(size) as i64)?;
+                continue;
+            }
+            let data = read_data(&mut reader, id, size, len)?;
+            match &id {
+                b"avih" => (micros, total) = (le_u32(&data, 0), le_u32(&data, 16)),
+                b"strh" => {
+                    if data.starts_with(b"vids") && video.is_none() {
+                        let rate = (le_u32(&data, 24), le_u32(&data, 20));
+                        video = Some((streams, rate, le_u32(&data, 32)));
+                        codec = data.get(4..8).map(fourcc);
+                    }
+                    streams += 1;
+                }
+                // the format of the video stream, the last stream header
+                b"strf" if video.is_some_and(|(stream, ..)| stream + 1 == streams) => {
+                    if let Some(compression) = data.get(16..20) {
+                        codec = Some(fourcc(compression));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let Some((stream, rate, length)) = video else {
+            return Err(VisionError::NotFound(format!(
+                "No video stream in {}",
+                path
+            )));
+        };
+        let codec = codec.unwrap_or_default();
+        if !(codec.eq_ignore_ascii_case(b"MJPG") || codec.eq_ignore_ascii_case(b"JPEG")) {
+            return Err(VisionError::Unsupported(format!(
+                "AVI codec {:?}, only Motion-JPEG is supported",
+                String::from_utf8_lossy(&codec)
+            )));
+        }
+        let rate = match rate {
+            (num, den) if num > 0 && den > 0 => (num, den),
+            _ if micros > 0 => (1_000_000, micros),
+            _ => rational(fps),
+        };
+        let frames = [length, total].into_iter().find(|&n| n > 0).map(u64::from);
+        let stream = format!("{:02}", stream % 100).into_bytes();
+        Ok(Self {
+            reader,
+            stream: [stream[0], stream[1]],
+            rate,
+            frames,
+            len,
+            index: 0,
+            last: None,
+        })
+    }
+}
+
+impl FrameSource for AviReader {
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VisionError> {
+        loop {
+            let Some((id, size)) = read_chunk(&mut self.reader)? else {
+                return Ok(None);
+            };
+            if &id == b"LIST" || &id == b"RIFF" {
+                let mut form = [0; 4];
+                self.reader.read_exact(&mut form)?;
+                // frames are in `movi` lists, grouped in `rec ` lists in some files
+                if !matches!(&form, b"movi" | b"rec " | b"AVIX") {
+                    self.reader.seek_relative(list_rest(size))?;
+                }
+                continue;
+            }
+            if id[..2] != self.stream || !matches!(&id[2..], b"dc" | b"db") {
+                self.reader.seek_relative(padded(size) as i64)?;
+                continue;
+            }
+
+            let data = read_data(&mut self.reader, id, size, self.len)?;
+            let index = self.index;
+            self.index += 1;
+            let image = if size == 0 {
+                // a dropped frame, the previous one is shown longer
+                match &self.last {
+                    Some(image) => image.clone(),
+                    None => continue,
+                }
+            } else {
+                let image = image::load_from_memory_with_format(&data, ImageFormat::Jpeg)?;
+                self.last = Some(image.clone());
+                image
+            };
+            return Ok(Some(VideoFrame {
+                index,
+                timestamp: frame_time(index, self.rate),
+                image,
+            }));
+        }
+    }
+
+    fn fps(&self) -> f64 {
+        self.rate.0 as f64 / self.rate.1 as f64
+    }
+
+    fn frame_count(&self) -> Option<u64> {
+        self.frames
+    }
+}
+
+// bytes from `RIFF` to the `movi` id, the size of what `AviWriter::header` writes
+const AVI_HEADER_LEN: u64 = 224;
+
+/// A Motion-JPEG AVI file with an `idx1` index, the size of the first frame. Frames are
+/// written at the constant rate of the file, their timestamps are not kept.
+pub struct AviWriter {
+    writer: BufWriter<File>,
+    rate: (u32, u32),
+    quality: u8,
+    // set by the first frame, with the header
+    size: Option<(u32, u32)>,
+    // offset from the `movi` id and size of every frame chunk
+    index: Vec<(u32, u32)>,
+    // bytes after the `movi` id
+    movi: u64,
+    max_chunk: u32,
+    finished: bool,
+}
+
+impl AviWriter {
+    pub fn create(path: &Path, fps: f64, quality: u8) -> Result<Self, VisionError> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            rate: rational(fps),
+            quality,
+            size: None,
+            index: Vec::new(),
+            movi: 0,
+            max_chunk: 0,
+            finished: false,
+        })
+    }
+
+    // `RIFF AVI`, the `hdrl` list of one MJPEG stream and the `movi` list header
+    fn header(&self, (width, height): (u32, u32), riff: u32) -> Vec<u8> {
+        let frames = self.index.len() as u32;
+        let (rate, scale) = self.rate;
+        let mut h = Vec::with_capacity(AVI_HEADER_LEN as usize);
+        let mut put = |bytes: &[u8]| h.extend_from_slice(bytes);
+        put(b"RIFF");
+        put(&riff.to_le_bytes());
+        put(b"AVI LIST");
+        put(&192u32.to_le_bytes());
+        put(b"hdrlavih");
+        put(&56u32.to_le_bytes());
+        let micros = (1e6 * scale as f64 / rate as f64).round() as u32;
+        // AVIF_HASINDEX, one stream
+        for value in [
+            micros,
+            0,
+            0,
+            0x10,
+            frames,
+            0,
+            1,
+            self.max_chunk,
+            width,
+            height,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            put(&value.to_le_bytes());
+        }
+        put(b"LIST");
+        put(&116u32.to_le_bytes());
+        put(b"strlstrh");
+        put(&56u32.to_le_bytes());
+        put(b"vidsMJPG");
+        // flags, priority and language, initial frames, scale, rate, start, length,
+        // buffer size, quality (default), sample size (variable)
+        for value in [0, 0, 0, scale, rate, 0, frames, self.max_chunk, u32::MAX, 0] {
+            put(&value.to_le_bytes());
+        }
+        for value in [0, 0, width, height] {
+            put(&(value.min(i16::MAX as u32) as i16).to_le_bytes());
+        }
+        put(b"strf");
+        put(&40u32.to_le_bytes());
+        // BITMAPINFOHEADER: size, width, height, 1 plane of 24 bits
+        for value in [40, width, height, 1 | (24 << 16)] {
+            put(&value.to_le_bytes());
+        }
+        put(b"MJPG");
+        for value in [width.saturating_mul(height).saturating_mul(3), 0, 0, 0, 0] {
+            put(&value.to_le_bytes());
+        }
+        put(b"LIST");
+        put(&((4 + self.movi) as u32).to_le_bytes());
+        put(b"movi");
+        h
+    }
+}
+
+impl FrameSink for AviWriter {
+    fn write_frame(&mut self, frame: &VideoFrame) -> Result<(), VisionError> {
+        if self.finished {
+            return Err(VisionError::Internal(
+                "The AVI is already finished".to_string(),
+            ));
+        }
+        let image = frame.image.to_rgb8();
+        let (width, height) = image.dimensions();
+        match self.size {
+            Some(size) if size != (width, height) => {
+                return Err(VisionError::InvalidArgument(format!(
+                    "Frame {} is {}x{}, the AVI clip is {}x{}",
+                    frame.index, width, height, size.0, size.1
+                )))
+            }
+            Some(_) => {}
+            None => {
+                // patched with the final sizes and counts by `finish`
+                let header = self.header((width, height), 0);
+                self.writer.write_all(&header)?;
+                self.size = Some((width, height));
+            }
+        }
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, self.quality).encode_image(&image)?;
+        let size = jpeg.len() as u32;
+        // the RIFF sizes and index offsets are 32 bits
+        let end =
+            AVI_HEADER_LEN + self.movi + 8 + padded(size) + 8 + 16 * (self.index.len() as u64 + 1);
+        if end > u32::MAX as u64 {
+            return Err(VisionError::Unsupported("AVI files over 4 GiB".to_string()));
+        }
+        self.index.push((4 + self.movi as u32, size));
+        self.writer.write_all(b"00dc")?;
+        self.writer.write_all(&size.to_le_bytes())?;
+        self.writer.write_all(&jpeg)?;
+        if size & 1 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.movi += 8 + padded(size);
+        self.max_chunk = self.max_chunk.max(size);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), VisionError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let size = self.size.unwrap_or_default();
+        if self.size.is_none() {
+            self.writer.write_all(&self.header(size, 0))?;
+        }
+        // AVIIF_KEYFRAME, every MJPEG frame is one
+        self.writer.write_all(b"idx1")?;
+        self.writer
+            .write_all(&(16 * self.index.len() as u32).to_le_bytes())?;
+        for &(offset, len) in &self.index {
+            self.writer.write_all(b"00dc")?;
+            for value in [0x10, offset, len] {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        let riff = AVI_HEADER_LEN + self.movi + 8 + 16 * self.index.len() as u64 - 8;
+        let header = self.header(size, riff as u32);
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yolov8-rs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // pseudo-random pixels, from `seed`
+    fn noise(width: u32, height: u32, mut seed: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, _| {
+            image::Rgb([0; 3].map(|_: u8| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            }))
+        }))
+    }
+
+    fn frame(index: u64, image: DynamicImage) -> VideoFrame {
+        VideoFrame {
+            index,
+            timestamp: Duration::ZERO,
+            image,
+        }
+    }
+
+    fn read_all(source: &mut dyn FrameSource) -> Vec<VideoFrame> {
+        std::iter::from_fn(|| source.next_frame().unwrap()).collect()
+    }
+
+    #[test]
+    fn avi_round_trips_odd_chunk_sizes() {
+        let dir = temp_dir("frames-avi");
+        let path = dir.join("clip.avi");
+        let images: Vec<DynamicImage> = (0..8).map(|i| noise(17, 11, i)).collect();
+        let mut writer = AviWriter::create(&path, 25., AVI_JPEG_QUALITY).unwrap();
+        for (i, image) in images.iter().enumerate() {
+            writer.write_frame(&frame(i as u64, image.clone())).unwrap();
+        }
+        writer.finish().unwrap();
+        // the pad byte of odd chunks is skipped by the reader
+        assert!(writer.index.iter().any(|&(_, size)| size % 2 == 1));
+
+        let mut reader = AviReader::open(path.to_str().unwrap(), 1.).unwrap();
+        assert_eq!(reader.frame_count(), Some(8));
+        assert_eq!(reader.fps(), 25.);
+        let frames = read_all(&mut reader);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frames.len(), images.len());
+        for (i, (frame, image)) in frames.iter().zip(&images).enumerate() {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, AVI_JPEG_QUALITY)
+                .encode_image(&image.to_rgb8())
+                .unwrap();
+            let expected = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg).unwrap();
+            assert_eq!(frame.index, i as u64);
+            assert_eq!(frame.timestamp, Duration::from_millis(40 * i as u64));
+            assert_eq!(frame.image.to_rgb8(), expected.to_rgb8());
+        }
+    }
+
+    #[test]
+    fn avi_chunk_sizes_are_bounded_by_the_file() {
+        let dir = temp_dir("frames-avi-chunks");
+        let path = dir.join("clip.avi");
+        let mut writer = AviWriter::create(&path, 25., AVI_JPEG_QUALITY).unwrap();
+        for i in 0..3 {
+            writer
+                .write_frame(&frame(i, noise(16, 8, i as u32)))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let find = |id: &[u8]| bytes.windows(4).position(|w| w == id).unwrap();
+        let first = find(b"00dc");
+        let second = first + 8 + padded(writer.index[0].1) as usize;
+        assert_eq!(&bytes[second..second + 4], b"00dc");
+        let open = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            AviReader::open(path.to_str().unwrap(), 1.)
+        };
+
+        // a frame chunk claiming 4 GB
+        let mut oversized = bytes.clone();
+        oversized[first + 4..first + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = open(&oversized).unwrap();
+        assert!(matches!(
+            reader.next_frame(),
+            Err(VisionError::InvalidArgument(_))
+        ));
+
+        // a file cut in the middle of the second frame
+        let mut reader = open(&bytes[..second + 20]).unwrap();
+        assert_eq!(reader.next_frame().unwrap().unwrap().index, 0);
+        assert!(matches!(
+            reader.next_frame(),
+            Err(VisionError::InvalidArgument(_))
+        ));
+
+        // a stream header claiming more than the file
+        let mut header = bytes.clone();
+        let strh = find(b"strh");
+        header[strh + 4..strh + 8].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+        assert!(matches!(
+            open(&header),
+            Err(VisionError::InvalidArgument(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn y4m_round_trips_odd_sizes() {
+        let dir = temp_dir("frames-y4m");
+        let path = dir.join("clip.y4m");
+        // uniform 2x2 blocks, clipped at the odd right and bottom edges, so the 4:2:0
+        // chroma loses nothing
+        let colors = [[200, 30, 40], [20, 180, 60], [40, 50, 220], [128, 128, 128]];
+        let images: Vec<DynamicImage> = (0..3)
+            .map(|i| {
+                DynamicImage::ImageRgb8(RgbImage::from_fn(7, 5, |x, y| {
+                    image::Rgb(colors[(x / 2 + y / 2 + i) as usize % colors.len()])
+                }))
+            })
+            .collect();
+        let mut writer = Y4mWriter::create(&path, 30000. / 1001.).unwrap();
+        for (i, image) in images.iter().enumerate() {
+            writer.write_frame(&frame(i as u64, image.clone())).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = Y4mReader::open(path.to_str().unwrap(), 1.).unwrap();
+        assert_eq!(reader.rate, (30000, 1001));
+        let frames = read_all(&mut reader);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frames.len(), images.len());
+        for (frame, image) in frames.iter().zip(&images) {
+            let (actual, expected) = (frame.image.to_rgb8(), image.to_rgb8());
+            assert_eq!(actual.dimensions(), (7, 5));
+            for (a, e) in actual.pixels().zip(expected.pixels()) {
+                assert!(
+                    a.0.iter().zip(e.0).all(|(&a, e)| a.abs_diff(e) <= 2),
+                    "{:?} != {:?}",
+                    a,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn y4m_frame_size_is_bounded_by_the_file() {
+        let dir = temp_dir("frames-y4m-size");
+        let path = dir.join("clip.y4m");
+        let open = |header: &str| {
+            let mut data = header.as_bytes().to_vec();
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(&[16; 64]);
+            std::fs::write(&path, data).unwrap();
+            Y4mReader::open(path.to_str().unwrap(), 1.).map(|_| ())
+        };
+        let huge = open("YUV4MPEG2 W100000 H100000 F25:1 C420jpeg\n");
+        let overflow = open(&format!("YUV4MPEG2 W{} H3 F25:1 C444\n", usize::MAX / 2));
+        let fits = open("YUV4MPEG2 W4 H4 F25:1 C420jpeg\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(huge, Err(VisionError::InvalidArgument(_))));
+        assert!(matches!(overflow, Err(VisionError::InvalidArgument(_))));
+        assert!(fits.is_ok());
+    }
+
+    #[test]
+    fn image_sequence_round_trips_in_order() {
+        let dir = temp_dir("frames-sequence");
+        let images: Vec<DynamicImage> = (0..12).map(|i| noise(9, 7, i)).collect();
+        // PNG files keep the pixels
+        let mut writer = ImageSequenceWriter::new(&dir, "png").unwrap();
+        for (i, image) in images.iter().enumerate() {
+            writer.write_frame(&frame(i as u64, image.clone())).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = ImageSequenceReader::open(dir.to_str().unwrap(), 4.).unwrap();
+        assert_eq!(reader.frame_count(), Some(12));
+        let frames = read_all(&mut reader);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frames.len(), images.len());
+        for (i, (frame, image)) in frames.iter().zip(&images).enumerate() {
+            assert_eq!(frame.index, i as u64);
+            assert_eq!(frame.timestamp, Duration::from_millis(250 * i as u64));
+            assert_eq!(frame.image.to_rgb8(), image.to_rgb8());
+        }
+    }
+
+    #[test]
+    fn frames_sort_by_their_number() {
+        let mut paths = vec!["f_10.jpg", "f_9.jpg", "f_100.jpg", "f_1.jpg"];
+        paths.sort_by_cached_key(|path| frame_key(path));
+        assert_eq!(paths, vec!["f_1.jpg", "f_9.jpg", "f_10.jpg", "f_100.jpg"]);
+    }
+}
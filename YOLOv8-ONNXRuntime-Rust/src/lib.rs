@@ -3,6 +3,8 @@
 use std::io::{Read, Write};
 
 pub mod cli;
+pub mod config;
+pub mod error;
 pub mod model;
 pub mod utils;
 pub mod ort_backend;
@@ -10,45 +12,110 @@ pub mod yolo_result;
 pub mod grpc;
 pub mod converter;
 pub mod yolo_service;
-
-pub use crate::cli::Args;
-pub use crate::model::YOLOv8;
+pub mod batcher;
+pub mod tracker;
+pub mod mask;
+pub mod slicing;
+pub mod tta;
+pub mod registry;
+pub mod metrics;
+pub mod rest;
+pub mod predict;
+pub mod export;
+pub mod eval;
+pub mod val;
+pub mod bench;
+mod detector;
+pub mod frames;
+pub mod video;
+
+pub use crate::cli::{Args, BenchArgs, Command, PredictArgs, ValArgs, VideoArgs};
+pub use crate::config::Config;
+pub use crate::error::VisionError;
+pub use crate::model::{ClassFilter, RunOptions, YOLOv8};
 pub use crate::ort_backend::{Batch, OrtBackend, OrtConfig, OrtEP, YOLOTask};
-pub use crate::yolo_result::{Bbox, Embedding, Point2, YOLOResult};
+pub use crate::yolo_result::{Bbox, Embedding, Point2, RotatedBbox, YOLOResult};
 pub use crate::grpc::{
     ProcessImagesRequest, ProcessImagesResponse,
+    StreamFramesRequest, StreamFramesResponse, StreamConfig, Frame,
+    stream_frames_request,
     YoloResult as ProtoYoloResult,
     Embedding as ProtoEmbedding,
     Bbox as ProtoBbox,
     KeypointSet as ProtoKeypointSet,
     Point2 as ProtoPoint2,
-    yolo_service_server
+    RotatedBbox as ProtoRotatedBbox,
+    Track as ProtoTrack,
+    TrackerType as ProtoTrackerType,
+    NmsConfig as ProtoNmsConfig,
+    NmsStrategy as ProtoNmsStrategy,
+    SlicingConfig as ProtoSlicingConfig,
+    InferenceParams as ProtoInferenceParams,
+    Mask as ProtoMask,
+    MaskFormat as ProtoMaskFormat,
+    yolo_service_server,
+    model_registry_server,
+};
+pub use crate::yolo_service::{LazyYoloService, MyYoloService};
+pub use crate::registry::{ModelKind, ModelSpec, MyModelRegistry};
+pub use rf_detr::reload::{self, Watcher};
+pub use crate::metrics::{Metrics, METRICS};
+pub use rf_detr::telemetry::{self, Telemetry};
+pub use rf_detr::LoadArgs;
+pub use crate::batcher::{Batcher, Pending};
+pub use crate::converter::{
+    convert_inference_params, convert_mask_format, convert_nms_config, convert_slicing_config,
+    convert_yolo_result,
+};
+pub use crate::bench::{BenchReport, BenchResult, Latency, Resolution};
+pub use crate::eval::{Dataset, Evaluator, MetricKind, Report};
+pub use crate::export::{ExportFormat, ExportWriter, Exporter, ImageResult, Segmentation};
+pub use crate::frames::{
+    create_sink, open_source, AviReader, AviWriter, FrameSink, FrameSource, GifReader, GifWriter,
+    ImageSequenceReader, ImageSequenceWriter, VideoFormat, VideoFrame, Y4mReader, Y4mWriter,
 };
-pub use crate::yolo_service::MyYoloService;
-pub use crate::converter::convert_yolo_result;
+pub use crate::mask::{CroppedMask, EncodedMask, MaskFormat, Rle, MASK_THRESHOLD};
+pub use rf_detr::nms::{self, Nms, NmsItem, NmsStrategy};
+pub use crate::slicing::{Slicing, Tile};
+pub use crate::tta::{Augment, Tta, COCO_FLIP_INDEX};
+pub use crate::tracker::{ByteTrack, Sort, Track, Tracker, TrackerKind};
+pub use crate::video::{run_clip, FrameRecord, VideoSummary};
 
 pub fn non_max_suppression(
     xs: &mut Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)>,
     iou_threshold: f32,
 ) {
-    xs.sort_by(|b1, b2| b2.0.confidence().partial_cmp(&b1.0.confidence()).unwrap());
-
-    let mut current_index = 0;
-    for index in 0..xs.len() {
-        let mut drop = false;
-        for prev_index in 0..current_index {
-            let iou = xs[prev_index].0.iou(&xs[index].0);
-            if iou > iou_threshold {
-                drop = true;
-                break;
-            }
-        }
-        if !drop {
-            xs.swap(current_index, index);
-            current_index += 1;
+    nms::greedy(xs, iou_threshold, |x| x.0.confidence(), |a, b| a.0.iou(&b.0));
+}
+
+pub fn non_max_suppression_rotated(xs: &mut Vec<RotatedBbox>, iou_threshold: f32) {
+    nms::greedy(xs, iou_threshold, RotatedBbox::confidence, RotatedBbox::iou);
+}
+
+/// Rotated boxes only have the greedy strategies, across or within classes.
+pub fn check_rotated_nms(strategy: NmsStrategy) -> Result<(), VisionError> {
+    match strategy {
+        NmsStrategy::Agnostic | NmsStrategy::ClassAware => Ok(()),
+        _ => Err(VisionError::InvalidArgument(format!(
+            "NMS strategy {:?} is not supported for rotated boxes, use agnostic or class-aware",
+            strategy
+        ))),
+    }
+}
+
+/// Greedy NMS of rotated boxes with the settings of `nms`, see `check_rotated_nms`.
+pub fn apply_rotated_nms(xs: &mut Vec<RotatedBbox>, nms: &Nms) {
+    let class_aware = nms.strategy == NmsStrategy::ClassAware;
+    nms::greedy(xs, nms.iou, RotatedBbox::confidence, |a, b| {
+        if class_aware && a.id() != b.id() {
+            f32::NEG_INFINITY
+        } else {
+            a.iou(b)
         }
+    });
+    if nms.max_det > 0 {
+        xs.truncate(nms.max_det);
     }
-    xs.truncate(current_index);
 }
 
 pub fn gen_time_string(delimiter: &str) -> String {
@@ -80,7 +147,7 @@ pub const SKELETON: [(usize, usize); 16] = [
     (14, 16),
 ];
 
-pub fn check_font(font: &str) -> rusttype::Font<'static> {
+pub fn check_font(font: &str) -> Result<rusttype::Font<'static>, VisionError> {
     // check then load font
 
     // ultralytics font path
@@ -90,7 +157,11 @@ pub fn check_font(font: &str) -> rusttype::Font<'static> {
             d.push(font);
             d
         }
-        None => panic!("Unsupported operating system. Now support Linux, MacOS, Windows."),
+        None => {
+            return Err(VisionError::Font(
+                "Unsupported operating system. Now support Linux, MacOS, Windows.".to_string(),
+            ))
+        }
     };
 
     // current font path
@@ -102,75 +173,55 @@ pub fn check_font(font: &str) -> rusttype::Font<'static> {
     } else if font_path_current.exists() {
         font_path_current
     } else {
-        println!("Downloading font...");
-        let source_url = "https://ultralytics.com/assets/Arial.ttf";
-        let resp = ureq::get(source_url)
-            .timeout(std::time::Duration::from_secs(500))
-            .call()
-            .unwrap_or_else(|err| panic!("> Failed to download font: {source_url}: {err:?}"));
-
-        // read to buffer
-        let mut buffer = vec![];
-        let total_size = resp
-            .header("Content-Length")
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap();
-        let _reader = resp
-            .into_reader()
-            .take(total_size)
-            .read_to_end(&mut buffer)
-            .unwrap();
+        let buffer = download_font()?;
 
         // save
-        let _path = std::fs::File::create(font).unwrap();
+        let _path = std::fs::File::create(font)?;
         let mut writer = std::io::BufWriter::new(_path);
-        writer.write_all(&buffer).unwrap();
+        writer.write_all(&buffer)?;
         println!("Font saved at: {:?}", font_path_current.display());
         font_path_current
     };
 
     // load font
-    let buffer = std::fs::read(font_path).unwrap();
-    rusttype::Font::try_from_vec(buffer).unwrap()
+    let buffer = std::fs::read(&font_path)?;
+    rusttype::Font::try_from_vec(buffer)
+        .ok_or_else(|| VisionError::Font(format!("Invalid font file: {}", font_path.display())))
+}
+
+fn download_font() -> Result<Vec<u8>, VisionError> {
+    println!("Downloading font...");
+    let source_url = "https://ultralytics.com/assets/Arial.ttf";
+    let resp = ureq::get(source_url)
+        .timeout(std::time::Duration::from_secs(500))
+        .call()
+        .map_err(|err| VisionError::Font(format!("Failed to download font: {source_url}: {err}")))?;
+
+    // read to buffer
+    let mut buffer = vec![];
+    let total_size = resp
+        .header("Content-Length")
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(|| VisionError::Font(format!("No Content-Length from {source_url}")))?;
+    resp.into_reader().take(total_size).read_to_end(&mut buffer)?;
+    Ok(buffer)
 }
 
 use ab_glyph::FontArc;
-pub fn load_font() -> FontArc {
+pub fn load_font() -> Result<FontArc, VisionError> {
     use std::path::Path;
     let font_path = Path::new("./font/Arial.ttf");
-    match font_path.try_exists() {
-        Ok(true) => {
-            let buffer = std::fs::read(font_path).unwrap();
-            FontArc::try_from_vec(buffer).unwrap()
-        }
-        Ok(false) => {
-            std::fs::create_dir_all("./font").unwrap();
-            println!("Downloading font...");
-            let source_url = "https://ultralytics.com/assets/Arial.ttf";
-            let resp = ureq::get(source_url)
-                .timeout(std::time::Duration::from_secs(500))
-                .call()
-                .unwrap_or_else(|err| panic!("> Failed to download font: {source_url}: {err:?}"));
-
-            // read to buffer
-            let mut buffer = vec![];
-            let total_size = resp
-                .header("Content-Length")
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap();
-            let _reader = resp
-                .into_reader()
-                .take(total_size)
-                .read_to_end(&mut buffer)
-                .unwrap();
-            // save
-            let mut fd = std::fs::File::create(font_path).unwrap();
-            fd.write_all(&buffer).unwrap();
-            println!("Font saved at: {:?}", font_path.display());
-            FontArc::try_from_vec(buffer).unwrap()
-        }
-        Err(e) => {
-            panic!("Failed to load font {}", e);
-        }
-    }
+    let buffer = if font_path.try_exists()? {
+        std::fs::read(font_path)?
+    } else {
+        std::fs::create_dir_all("./font")?;
+        let buffer = download_font()?;
+        // save
+        let mut fd = std::fs::File::create(font_path)?;
+        fd.write_all(&buffer)?;
+        println!("Font saved at: {:?}", font_path.display());
+        buffer
+    };
+    FontArc::try_from_vec(buffer)
+        .map_err(|e| VisionError::Font(format!("Invalid font file {}: {}", font_path.display(), e)))
 }
@@ -1,33 +1,131 @@
-use clap::Parser;
-
 use std::error::Error;
+use std::sync::Arc;
 use tonic::transport::Server;
+use tonic_health::server::health_reporter;
+use tonic_health::ServingStatus;
+use tracing::error;
 
 use yolov8_rs::{
-    Args, YOLOv8,
+    Args, Command, LoadArgs,
+    grpc::FILE_DESCRIPTOR_SET,
     yolo_service_server::YoloServiceServer,
-    MyYoloService
+    model_registry_server::ModelRegistryServer,
+    bench, predict, rest, telemetry, val, video, LazyYoloService, MyModelRegistry, MyYoloService
 };
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Parse command line arguments and initialize the YOLOv8 model once.
-    let args = Args::parse();
+    // Parse command line arguments, env vars and the config file.
+    let args = Args::load()?;
+    // Log lines and trace spans, flushed to the OTLP collector on exit.
+    let _telemetry = telemetry::init("yolov8-rs", args.otlp_endpoint.as_deref())?;
+
+    match args.command.clone() {
+        // Run the model over image files, no server.
+        Some(Command::Predict(predict_args)) => {
+            tokio::task::spawn_blocking(move || predict::predict(&args, &predict_args)).await??;
+            Ok(())
+        }
+        // Evaluate the model against an annotated dataset, no server.
+        Some(Command::Val(val_args)) => {
+            tokio::task::spawn_blocking(move || val::val(&args, &val_args)).await??;
+            Ok(())
+        }
+        // Time the model stages, no server.
+        Some(Command::Bench(bench_args)) => {
+            tokio::task::spawn_blocking(move || bench::bench(&args, &bench_args)).await??;
+            Ok(())
+        }
+        // Run the model over the frames of a clip, no server.
+        Some(Command::Video(video_args)) => {
+            tokio::task::spawn_blocking(move || video::video(&args, &video_args)).await??;
+            Ok(())
+        }
+        Some(Command::Serve) | None => serve(args).await,
+    }
+}
+
+async fn serve(args: Args) -> Result<(), Box<dyn Error>> {
+    let addr = args.bind;
+
+    // Health reports NOT_SERVING until the models are loaded and warmed up, the server
+    // as a whole (the empty service name) too.
+    let (mut health, health_service) = health_reporter();
+    health.set_service_status("", ServingStatus::NotServing).await;
+    health.set_not_serving::<YoloServiceServer<LazyYoloService>>().await;
+    health.set_not_serving::<ModelRegistryServer<MyModelRegistry>>().await;
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
 
-    let model = YOLOv8::new(args)
-        .map_err(|e| format!("Error creating model: {:?}", e))?;
-    model.summary();
+    let yolo_service = LazyYoloService::new();
+    let registry = Arc::new(MyModelRegistry::new(args.model_dir.clone()));
 
-    // Create the service with the pre-initialized model.
-    let yolo_service = MyYoloService::new(model);
-    let addr = "[::1]:50051".parse()?;
-    println!("YOLOService server listening on {}", addr);
+    // Serve Prometheus metrics on their own HTTP port, if enabled.
+    if let Some(metrics_bind) = args.metrics_bind {
+        tokio::spawn(async move {
+            if let Err(e) = yolov8_rs::metrics::serve(metrics_bind).await {
+                error!(error = %e, "Metrics endpoint failed");
+            }
+        });
+    }
+
+    // Serve the same inference as HTTP/JSON on its own port, if enabled.
+    if let Some(http_bind) = args.http_bind {
+        let yolo = (!args.model.is_empty()).then(|| yolo_service.clone());
+        let router = rest::router(yolo, Arc::clone(&registry));
+        tokio::spawn(async move {
+            if let Err(e) = rest::serve(http_bind, router).await {
+                error!(error = %e, "HTTP gateway failed");
+            }
+        });
+    }
 
     // Start the gRPC server.
-    Server::builder()
-        .add_service(YoloServiceServer::new(yolo_service))
-        .serve(addr)
-        .await?;
+    let serve = async {
+        println!("YOLOService server listening on {}", addr);
+        let yolo_service = (!args.model.is_empty()).then(|| YoloServiceServer::new(yolo_service.clone()));
+        Server::builder()
+            .trace_fn(telemetry::grpc_span)
+            .add_service(health_service)
+            .add_service(reflection)
+            .add_optional_service(yolo_service)
+            .add_service(ModelRegistryServer::from_arc(Arc::clone(&registry)))
+            .serve(addr)
+            .await?;
+        Ok::<_, Box<dyn Error>>(())
+    };
+
+    // Load the models meanwhile.
+    let load = async {
+        // The `--model` model serves YOLOService, one ORT session per worker thread.
+        let mut watcher = None;
+        if !args.model.is_empty() {
+            let load_args = args.clone();
+            let service = tokio::task::spawn_blocking(move || MyYoloService::load(&load_args))
+                .await?
+                .map_err(|e| format!("Error creating model: {}", e))?;
+            // Reload the model when its file changes, kept alive while serving.
+            watcher = service.watch();
+            yolo_service.set(service);
+            health.set_serving::<YoloServiceServer<LazyYoloService>>().await;
+        }
+
+        // The `[[models]]` of the config file serve ModelRegistry.
+        for spec in args.models.clone() {
+            registry
+                .load(spec)
+                .await
+                .map_err(|e| format!("Error creating model: {}", e))?;
+        }
+        health.set_serving::<ModelRegistryServer<MyModelRegistry>>().await;
+        health.set_service_status("", ServingStatus::Serving).await;
+        Ok::<_, Box<dyn Error>>(watcher)
+    };
+
+    // A model failing to load stops the server.
+    let ((), _watcher) = tokio::try_join!(serve, load)?;
 
     Ok(())
 }
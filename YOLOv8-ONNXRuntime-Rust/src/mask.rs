@@ -0,0 +1,381 @@
+use clap::ValueEnum;
+use image::GrayImage;
+use imageproc::contours::{find_contours_with_threshold, BorderType};
+use imageproc::geometry::approximate_polygon_dp;
+use imageproc::point::Point;
+
+use crate::{Bbox, Point2, VisionError};
+
+/// Mask pixels above this value are foreground when a mask is binarized.
+pub const MASK_THRESHOLD: u8 = 127;
+
+/// Max distance (pixels) between a traced contour and its simplified polygon.
+const POLYGON_EPSILON: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MaskFormat {
+    // full-resolution u8 mask per instance
+    #[default]
+    Raw,
+    // COCO-style uncompressed run-length encoding
+    Rle,
+    // outer contours traced from the mask
+    Polygon,
+    // u8 mask cropped to the instance bbox
+    Cropped,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Rle {
+    // column-major runs alternating background / foreground, starting with background
+    height: u32,
+    width: u32,
+    counts: Vec<u32>,
+}
+
+impl Rle {
+    /// Runs of a `height` x `width` mask, as in `counts` of an uncompressed COCO RLE.
+    pub fn new(height: u32, width: u32, counts: Vec<u32>) -> Self {
+        Self { height, width, counts }
+    }
+
+    /// Parses the compressed `counts` string of pycocotools (`rleFrString`), the inverse
+    /// of `compress`.
+    pub fn decompress(height: u32, width: u32, s: &str) -> Result<Self, VisionError> {
+        let invalid = || VisionError::InvalidArgument(format!("Invalid compressed RLE: {:?}", s));
+        let mut counts: Vec<u32> = Vec::new();
+        let mut chars = s.bytes();
+        while let Some(first) = chars.next() {
+            let mut c = first;
+            let mut x: i64 = 0;
+            let mut k = 0;
+            loop {
+                if !(48..48 + 64).contains(&c) || k > 12 {
+                    return Err(invalid());
+                }
+                let v = (c - 48) as i64;
+                x |= (v & 0x1f) << (5 * k);
+                k += 1;
+                if v & 0x20 == 0 {
+                    if v & 0x10 != 0 {
+                        x |= -1 << (5 * k);
+                    }
+                    break;
+                }
+                c = chars.next().ok_or_else(invalid)?;
+            }
+            if counts.len() > 2 {
+                x += counts[counts.len() - 2] as i64;
+            }
+            counts.push(u32::try_from(x).map_err(|_| invalid())?);
+        }
+        Ok(Self { height, width, counts })
+    }
+
+    /// Row-major u8 mask, 255 for foreground. Runs past `height * width` are dropped.
+    pub fn decode(&self) -> Vec<u8> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let mut data = vec![0u8; w * h];
+        let mut i = 0;
+        for (n, &count) in self.counts.iter().enumerate() {
+            let end = (i + count as usize).min(w * h);
+            if n % 2 == 1 {
+                // column-major index to row-major
+                for j in i..end {
+                    data[(j % h) * w + j / h] = 255;
+                }
+            }
+            i = end;
+        }
+        data
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn counts(&self) -> &Vec<u32> {
+        &self.counts
+    }
+
+    /// Foreground pixels.
+    pub fn area(&self) -> u64 {
+        self.counts.iter().skip(1).step_by(2).map(|&n| n as u64).sum()
+    }
+
+    /// The compressed `counts` string of pycocotools (`rleToString` of its `maskApi.c`),
+    /// the form COCO result files and `loadRes` expect.
+    pub fn compress(&self) -> String {
+        let mut s = String::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            // counts past the second are stored as the difference with the count two back
+            let mut x = count as i64;
+            if i > 2 {
+                x -= self.counts[i - 2] as i64;
+            }
+            loop {
+                let mut c = x & 0x1f;
+                x >>= 5;
+                let more = if c & 0x10 != 0 { x != -1 } else { x != 0 };
+                if more {
+                    c |= 0x20;
+                }
+                s.push((c as u8 + 48) as char);
+                if !more {
+                    break;
+                }
+            }
+        }
+        s
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CroppedMask {
+    // row-major u8 mask of the region at (x, y) in the image
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl CroppedMask {
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodedMask {
+    Raw(Vec<u8>),
+    Rle(Rle),
+    Polygons(Vec<Vec<Point2>>),
+    Cropped(CroppedMask),
+}
+
+impl EncodedMask {
+    /// Encodes a row-major `width` x `height` mask, binarized above `cutoff` for RLE and
+    /// polygons. Raw and cropped masks keep their values.
+    pub fn encode(
+        mask: &[u8],
+        width: u32,
+        height: u32,
+        bbox: &Bbox,
+        format: MaskFormat,
+        cutoff: u8,
+    ) -> Self {
+        match format {
+            MaskFormat::Raw => EncodedMask::Raw(mask.to_vec()),
+            MaskFormat::Rle => EncodedMask::Rle(encode_rle(mask, width, height, cutoff)),
+            MaskFormat::Polygon => {
+                EncodedMask::Polygons(encode_polygons(mask, width, height, cutoff))
+            }
+            MaskFormat::Cropped => EncodedMask::Cropped(crop_mask(mask, width, height, bbox)),
+        }
+    }
+}
+
+/// Runs of the pixels above `cutoff`.
+pub fn encode_rle(mask: &[u8], width: u32, height: u32, cutoff: u8) -> Rle {
+    let (w, h) = (width as usize, height as usize);
+    let mut counts = Vec::new();
+    let mut current = false;
+    let mut run = 0u32;
+    for x in 0..w {
+        for y in 0..h {
+            let on = mask[y * w + x] > cutoff;
+            if on != current {
+                counts.push(run);
+                current = on;
+                run = 0;
+            }
+            run += 1;
+        }
+    }
+    counts.push(run);
+    Rle {
+        height,
+        width,
+        counts,
+    }
+}
+
+/// Outer contours of the pixels above `cutoff`.
+pub fn encode_polygons(mask: &[u8], width: u32, height: u32, cutoff: u8) -> Vec<Vec<Point2>> {
+    let Some(image) = GrayImage::from_raw(width, height, mask.to_vec()) else {
+        return vec![];
+    };
+    find_contours_with_threshold::<i32>(&image, cutoff)
+        .into_iter()
+        .filter(|contour| contour.border_type == BorderType::Outer)
+        .map(|contour| {
+            let points: Vec<Point<i32>> = contour.points;
+            if points.len() > 2 {
+                approximate_polygon_dp(&points, POLYGON_EPSILON, true)
+            } else {
+                points
+            }
+        })
+        .filter(|points| points.len() > 2)
+        .map(|points| {
+            points
+                .iter()
+                .map(|p| Point2::new(p.x as f32, p.y as f32))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn crop_mask(mask: &[u8], width: u32, height: u32, bbox: &Bbox) -> CroppedMask {
+    let x0 = (bbox.xmin().floor().max(0.) as u32).min(width);
+    let y0 = (bbox.ymin().floor().max(0.) as u32).min(height);
+    let x1 = (bbox.xmax().ceil().max(0.) as u32).clamp(x0, width);
+    let y1 = (bbox.ymax().ceil().max(0.) as u32).clamp(y0, height);
+    let mut data = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+    for y in y0..y1 {
+        let row = (y * width) as usize;
+        data.extend_from_slice(&mask[row + x0 as usize..row + x1 as usize]);
+    }
+    CroppedMask {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 x 10, foreground rows and columns 2..5, row-major
+    fn square() -> Vec<u8> {
+        (0..100)
+            .map(|i| {
+                if (2..5).contains(&(i / 10)) && (2..5).contains(&(i % 10)) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rle_runs_are_column_major() {
+        let rle = encode_rle(&square(), 10, 10, MASK_THRESHOLD);
+        assert_eq!(rle.counts(), &vec![22, 3, 7, 3, 7, 3, 55]);
+        assert_eq!(rle.area(), 9);
+        assert_eq!(rle.decode(), square());
+    }
+
+    #[test]
+    fn rle_matches_pycocotools_strings() {
+        // mask.encode(np.asfortranarray(m))["counts"] of pycocotools
+        let ones = Rle::new(2, 2, vec![0, 4]);
+        assert_eq!(ones.compress(), "04");
+        assert_eq!(Rle::decompress(2, 2, "04").unwrap(), ones);
+
+        let rle = encode_rle(&square(), 10, 10, MASK_THRESHOLD);
+        assert_eq!(rle.compress(), "f037000`1");
+        assert_eq!(Rle::decompress(10, 10, "f037000`1").unwrap(), rle);
+    }
+
+    #[test]
+    fn rle_compress_round_trips() {
+        // large runs and negative deltas between counts two apart
+        let rle = Rle::new(1000, 1000, vec![5, 100_000, 2, 3, 999_000, 1, 890]);
+        assert_eq!(Rle::decompress(1000, 1000, &rle.compress()).unwrap(), rle);
+
+        let mut seed = 7u32;
+        for (width, height) in [(1, 1), (3, 5), (17, 9), (64, 48)] {
+            for _ in 0..10 {
+                let mask: Vec<u8> = (0..width * height)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                        if (seed >> 16).is_multiple_of(3) {
+                            255
+                        } else {
+                            0
+                        }
+                    })
+                    .collect();
+                let rle = encode_rle(&mask, width, height, MASK_THRESHOLD);
+                let decoded = Rle::decompress(height, width, &rle.compress()).unwrap();
+                assert_eq!(decoded, rle);
+                assert_eq!(decoded.decode(), mask);
+            }
+        }
+    }
+
+    #[test]
+    fn soft_masks_encode_above_their_cutoff() {
+        // probability 0.4 over rows and columns 1..8, 0.8 over the square of 2..5
+        let soft: Vec<u8> = square()
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| match (i / 10, i % 10) {
+                _ if v > 0 => 204,
+                (1..8, 1..8) => 102,
+                _ => 0,
+            })
+            .collect();
+        let bbox = Bbox::new(1., 1., 7., 7., 0, 0.9);
+        // at a threshold of 0.6 both encodings keep the square only
+        let cutoff = (0.6f32 * 255.).round() as u8;
+        let EncodedMask::Rle(rle) =
+            EncodedMask::encode(&soft, 10, 10, &bbox, MaskFormat::Rle, cutoff)
+        else {
+            panic!("not an RLE");
+        };
+        assert_eq!(rle, encode_rle(&square(), 10, 10, MASK_THRESHOLD));
+        let polygons = encode_polygons(&soft, 10, 10, cutoff);
+        assert_eq!(polygons, encode_polygons(&square(), 10, 10, MASK_THRESHOLD));
+        assert_eq!(polygons.len(), 1);
+        // at 0.3 both keep the larger square
+        let cutoff = (0.3f32 * 255.).round() as u8;
+        assert_eq!(encode_rle(&soft, 10, 10, cutoff).area(), 49);
+        let xs: Vec<f32> = encode_polygons(&soft, 10, 10, cutoff)[0]
+            .iter()
+            .map(Point2::x)
+            .collect();
+        assert_eq!(xs.iter().copied().fold(f32::INFINITY, f32::min), 1.);
+        assert_eq!(xs.iter().copied().fold(0., f32::max), 7.);
+        // raw and cropped masks keep the probabilities
+        let EncodedMask::Cropped(cropped) =
+            EncodedMask::encode(&soft, 10, 10, &bbox, MaskFormat::Cropped, cutoff)
+        else {
+            panic!("not a cropped mask");
+        };
+        assert_eq!(cropped.data()[0], 102);
+    }
+
+    #[test]
+    fn rle_decompress_rejects_bad_strings() {
+        assert!(Rle::decompress(2, 2, "0 4").is_err());
+        // a continuation bit with nothing after it
+        assert!(Rle::decompress(2, 2, "f").is_err());
+    }
+}
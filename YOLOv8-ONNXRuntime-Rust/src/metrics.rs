@@ -0,0 +1,9 @@
+// shared with RF-DETR, so one process exposes a single set of metric families
+pub use rf_detr::metrics::{gather, serve, Metrics, METRICS};
+
+use crate::YOLOResult;
+
+/// Boxes, or rotated boxes, of a result.
+pub fn detections(y: &YOLOResult) -> usize {
+    y.bboxes().map_or(0, |xs| xs.len()) + y.rotated_bboxes().map_or(0, |xs| xs.len())
+}
@@ -1,20 +1,56 @@
 #![allow(clippy::type_complexity)]
 
 use ab_glyph::FontArc;
-use anyhow::Result;
-use image::{DynamicImage, GenericImageView, ImageBuffer};
-use ndarray::{s, Array, Axis, IxDyn};
+use anyhow::{anyhow, Result};
+use fast_image_resize::images::Image;
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use image::{DynamicImage, ImageBuffer, RgbImage};
+use ndarray::{s, Array, Array2, ArrayView, ArrayView3, Axis, IxDyn};
 use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use tracing::{info, info_span};
 
 use crate::{
-    gen_time_string, load_font, non_max_suppression, Args, Batch, Bbox, Embedding, OrtBackend,
-    OrtConfig, OrtEP, Point2, YOLOResult, YOLOTask, SKELETON,
+    apply_rotated_nms, check_rotated_nms, gen_time_string, load_font, Args, Batch,
+    Bbox, Embedding, Nms, OrtBackend, OrtConfig, OrtEP, Point2, RotatedBbox, Slicing, Tta,
+    VisionError, YOLOResult, YOLOTask, SKELETON,
 };
+use crate::metrics::METRICS;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunOptions {
+    // per-image settings, from the model or from a request
+    pub nms: Nms,
+    pub slicing: Slicing,
+    pub conf: f32,
+    pub kconf: f32,
+    pub classes: ClassFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClassFilter {
+    // class ids to keep (empty keeps all) or drop, and per-class confidence thresholds
+    pub keep: Vec<usize>,
+    pub drop: Vec<usize>,
+    pub conf: HashMap<usize, f32>,
+}
+
+impl ClassFilter {
+    /// Whether a detection of class `id` passes, `conf` is the threshold of classes without their own.
+    pub fn accepts(&self, id: usize, confidence: f32, conf: f32) -> bool {
+        confidence >= self.conf.get(&id).copied().unwrap_or(conf)
+            && (self.keep.is_empty() || self.keep.contains(&id))
+            && !self.drop.contains(&id)
+    }
+}
 
 pub struct YOLOv8 {
     // YOLOv8 model for all yolo-tasks
     engine: OrtBackend,
+    name: String,
+    input: Array<f32, IxDyn>,
     nc: u32,
     nk: u32,
     nm: u32,
@@ -24,7 +60,11 @@ pub struct YOLOv8 {
     task: YOLOTask,
     conf: f32,
     kconf: f32,
-    iou: f32,
+    mask_threshold: f32,
+    soft_masks: bool,
+    nms: Nms,
+    slicing: Slicing,
+    tta: Tta,
     names: Vec<String>,
     color_palette: Vec<(u8, u8, u8)>,
     profile: bool,
@@ -32,7 +72,7 @@ pub struct YOLOv8 {
 }
 
 impl YOLOv8 {
-    pub fn new(config: Args) -> Result<Self> {
+    pub fn new(config: Args) -> Result<Self, VisionError> {
         // execution provider
         let ep = if config.trt {
             OrtEP::Trt(config.device_id)
@@ -57,6 +97,7 @@ impl YOLOv8 {
             task: config.task,
             trt_fp16: config.fp16,
             image_size: (config.height, config.width),
+            intra_threads: config.intra_threads,
         };
         let engine = OrtBackend::build(ort_args)?;
 
@@ -67,27 +108,31 @@ impl YOLOv8 {
             engine.width(),
             engine.task(),
         );
-        let nc = engine.nc().or(config.nc).unwrap_or_else(|| {
-            panic!("Failed to get num_classes, make it explicit with `--nc`");
-        });
+        let nc = engine.nc().or(config.nc).ok_or_else(|| {
+            VisionError::Model("Failed to get num_classes, make it explicit with `--nc`".to_string())
+        })?;
         let (nk, nm) = match task {
             YOLOTask::Pose => {
-                let nk = engine.nk().or(config.nk).unwrap_or_else(|| {
-                    panic!("Failed to get num_keypoints, make it explicit with `--nk`");
-                });
+                let nk = engine.nk().or(config.nk).ok_or_else(|| {
+                    VisionError::Model(
+                        "Failed to get num_keypoints, make it explicit with `--nk`".to_string(),
+                    )
+                })?;
                 (nk, 0)
             }
             YOLOTask::Segment => {
-                let nm = engine.nm().or(config.nm).unwrap_or_else(|| {
-                    panic!("Failed to get num_masks, make it explicit with `--nm`");
-                });
+                let nm = engine.nm().or(config.nm).ok_or_else(|| {
+                    VisionError::Model("Failed to get num_masks, make it explicit with `--nm`".to_string())
+                })?;
                 (0, nm)
             }
             _ => (0, 0),
         };
 
-        // class names
-        let names = engine.names().unwrap_or(vec!["Unknown".to_string()]);
+        // class names, one per class so ids from the model always index them
+        let names = engine
+            .names()
+            .unwrap_or_else(|| vec!["Unknown".to_string(); nc as usize]);
 
         // color palette
         let mut rng = thread_rng();
@@ -102,12 +147,37 @@ impl YOLOv8 {
             })
             .collect();
 
-        Ok(Self {
+        let model = Self {
             engine,
+            name: config.name,
+            input: Array::zeros(IxDyn(&[0])),
             names,
             conf: config.conf,
             kconf: config.kconf,
-            iou: config.iou,
+            mask_threshold: config.mask_threshold,
+            soft_masks: config.soft_masks,
+            nms: Nms {
+                strategy: config.nms,
+                iou: config.iou,
+                sigma: config.nms_sigma,
+                max_det: config.max_det,
+            },
+            slicing: Slicing {
+                enabled: config.slice,
+                tile_width: config.slice_width,
+                tile_height: config.slice_height,
+                overlap: config.slice_overlap,
+                full_image: config.slice_full_image,
+                merge: config.slice_merge,
+                merge_iou: config.slice_merge_iou,
+            },
+            tta: Tta {
+                enabled: config.tta,
+                flip: !config.tta_no_flip,
+                scales: config.tta_scales,
+                merge: config.tta_merge,
+                merge_iou: config.tta_merge_iou,
+            },
             color_palette,
             profile: config.profile,
             plot: config.plot,
@@ -118,7 +188,9 @@ impl YOLOv8 {
             width,
             batch,
             task,
-        })
+        };
+        model.check_options(&model.options())?;
+        Ok(model)
     }
 
     pub fn scale_wh(&self, w0: f32, h0: f32, w1: f32, h1: f32) -> (f32, f32, f32) {
@@ -126,73 +198,240 @@ impl YOLOv8 {
         (r, (w0 * r).round(), (h0 * r).round())
     }
 
-    pub fn preprocess(&mut self, xs: &Vec<DynamicImage>) -> Result<Array<f32, IxDyn>> {
-        let mut ys =
-            Array::ones((xs.len(), 3, self.height() as usize, self.width() as usize)).into_dyn();
-        ys.fill(144.0 / 255.0);
-        for (idx, x) in xs.iter().enumerate() {
-            let img = match self.task() {
-                YOLOTask::Classify => x.resize_exact(
-                    self.width(),
-                    self.height(),
-                    image::imageops::FilterType::Triangle,
-                ),
-                _ => {
-                    let (w0, h0) = x.dimensions();
-                    let w0 = w0 as f32;
-                    let h0 = h0 as f32;
-                    let (_, w_new, h_new) =
-                        self.scale_wh(w0, h0, self.width() as f32, self.height() as f32); // f32 round
-                    x.resize_exact(
-                        w_new as u32,
-                        h_new as u32,
-                        if let YOLOTask::Segment = self.task() {
-                            image::imageops::FilterType::CatmullRom
+    pub fn preprocess(&mut self, xs: &[DynamicImage]) -> Result<()> {
+        // pad up to the engine batch, padded rows stay filled with the letterbox color
+        let bs = if self.engine.is_batch_dynamic() {
+            xs.len().max(self.engine.batch_min() as usize)
+        } else {
+            xs.len().max(self.batch() as usize)
+        };
+        let (h, w) = (self.height() as usize, self.width() as usize);
+
+        // reuse the input buffer while the batch shape is unchanged
+        let mut input = std::mem::take(&mut self.input);
+        if input.shape() != [bs, 3, h, w] {
+            input = Array::zeros((bs, 3, h, w)).into_dyn();
+        }
+        let buffer = input
+            .as_slice_mut()
+            .ok_or_else(|| anyhow!("Input buffer is not contiguous"))?;
+
+        const PAD: f32 = 144.0 / 255.0;
+        let plane = 3 * h * w;
+        let (used, padded) = buffer.split_at_mut(xs.len() * plane);
+        padded.fill(PAD);
+
+        // one image per rayon task: SIMD resize, then u8 HWC -> f32 CHW
+        let task = self.task().clone();
+        let filled = used
+            .par_chunks_mut(plane)
+            .zip(xs.par_iter())
+            .try_for_each(|(ys, x)| -> Result<()> {
+                let (w_new, h_new, filter) = match task {
+                    YOLOTask::Classify => (w, h, FilterType::Bilinear),
+                    _ => {
+                        let (_, w_new, h_new) = self.scale_wh(
+                            x.width() as f32,
+                            x.height() as f32,
+                            w as f32,
+                            h as f32,
+                        ); // f32 round
+                        let filter = if let YOLOTask::Segment = task {
+                            FilterType::CatmullRom
                         } else {
-                            image::imageops::FilterType::Triangle
-                        },
-                    )
+                            FilterType::Bilinear
+                        };
+                        ((w_new as usize).min(w), (h_new as usize).min(h), filter)
+                    }
+                };
+
+                let rgb;
+                let src = match x {
+                    DynamicImage::ImageRgb8(_) => x,
+                    _ => {
+                        rgb = DynamicImage::ImageRgb8(x.to_rgb8());
+                        &rgb
+                    }
+                };
+                let mut dst = Image::new(w_new as u32, h_new as u32, PixelType::U8x3);
+                let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(filter));
+                Resizer::new().resize(src, &mut dst, &options)?;
+
+                ys.fill(PAD);
+                let (r, gb) = ys.split_at_mut(h * w);
+                let (g, b) = gb.split_at_mut(h * w);
+                for (y, row) in dst.buffer().chunks_exact(w_new * 3).enumerate() {
+                    let offset = y * w;
+                    let (r, g, b) = (
+                        &mut r[offset..offset + w_new],
+                        &mut g[offset..offset + w_new],
+                        &mut b[offset..offset + w_new],
+                    );
+                    for (i, rgb) in row.chunks_exact(3).enumerate() {
+                        r[i] = rgb[0] as f32 / 255.0;
+                        g[i] = rgb[1] as f32 / 255.0;
+                        b[i] = rgb[2] as f32 / 255.0;
+                    }
                 }
-            };
+                Ok(())
+            });
+        self.input = input;
+        filled
+    }
 
-            for (x, y, rgb) in img.pixels() {
-                let x = x as usize;
-                let y = y as usize;
-                let [r, g, b, _] = rgb.0;
-                ys[[idx, 0, y, x]] = (r as f32) / 255.0;
-                ys[[idx, 1, y, x]] = (g as f32) / 255.0;
-                ys[[idx, 2, y, x]] = (b as f32) / 255.0;
-            }
+    pub fn run(&mut self, xs: &[DynamicImage]) -> Result<Vec<YOLOResult>, VisionError> {
+        let options = vec![self.options(); xs.len()];
+        self.run_with(xs, &options)
+    }
+
+    #[tracing::instrument(name = "run", skip_all, fields(model = %self.name, images = xs.len()))]
+    pub fn run_with(
+        &mut self,
+        xs: &[DynamicImage],
+        options: &[RunOptions],
+    ) -> Result<Vec<YOLOResult>, VisionError> {
+        // options holds the settings of each image
+        for options in options {
+            self.check_options(options)?;
         }
+        let ys = if self.is_tta() {
+            xs.iter()
+                .zip(options)
+                .map(|(x, options)| self.run_tta(x, options))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            self.run_images(xs, options)?
+        };
 
+        // plot and save
+        if self.plot {
+            self.plot_and_save(&ys, xs, options, Some(&SKELETON))?;
+        }
         Ok(ys)
     }
 
-    pub fn run(&mut self, xs: &Vec<DynamicImage>) -> Result<Vec<YOLOResult>> {
+    /// Rejects invalid NMS settings and the NMS strategies the task cannot apply, rotated
+    /// boxes only have greedy NMS. Flipped TTA copies need the COCO keypoint layout.
+    fn check_options(&self, options: &RunOptions) -> Result<()> {
+        options.nms.check()?;
+        if self.task == YOLOTask::Pose {
+            self.tta.check_keypoints(self.nk as usize)?;
+        }
+        if self.task != YOLOTask::Obb {
+            return Ok(());
+        }
+        check_rotated_nms(options.nms.strategy)?;
+        if options.slicing.enabled {
+            check_rotated_nms(options.slicing.merge)?;
+        }
+        if self.tta.enabled {
+            check_rotated_nms(self.tta.merge)?;
+        }
+        Ok(())
+    }
+
+    fn run_images(&mut self, xs: &[DynamicImage], options: &[RunOptions]) -> Result<Vec<YOLOResult>> {
+        // sliced images one by one, whole images batched
+        let mut ys = Vec::with_capacity(xs.len());
+        let mut start = 0;
+        while start < xs.len() {
+            if self.is_sliced(&options[start]) {
+                ys.push(self.run_sliced(&xs[start], &options[start])?);
+                start += 1;
+                continue;
+            }
+
+            // consecutive whole images, in engine-sized batches
+            let end = (start..xs.len())
+                .find(|&i| self.is_sliced(&options[i]))
+                .unwrap_or(xs.len());
+            let n = self.max_batch() as usize;
+            for (xs, options) in xs[start..end].chunks(n).zip(options[start..end].chunks(n)) {
+                ys.extend(self.run_batch(xs, options)?);
+            }
+            start = end;
+        }
+        Ok(ys)
+    }
+
+    fn is_tta(&self) -> bool {
+        self.tta.enabled && !matches!(self.task(), YOLOTask::Classify)
+    }
+
+    fn run_tta(&mut self, x: &DynamicImage, options: &RunOptions) -> Result<YOLOResult> {
+        // run every augmented copy, map the results back to the input, then fuse them
+        let t = std::time::Instant::now();
+        let augments = self.tta.augments();
+        let _span = info_span!("tta", augments = augments.len()).entered();
+        let copies: Vec<DynamicImage> = augments.iter().map(|a| a.apply(x)).collect();
+        let ys = self.run_images(&copies, &vec![options.clone(); copies.len()])?;
+        let size = (x.width(), x.height());
+        let ys = augments
+            .iter()
+            .zip(ys)
+            .map(|(a, y)| a.invert(y, size))
+            .collect();
+        let y = self.tta.merge(ys, size, options.nms, options.conf);
+        if self.profile {
+            info!(augments = augments.len(), elapsed = ?t.elapsed(), "Model TTA");
+        }
+        Ok(y)
+    }
+
+    fn is_sliced(&self, options: &RunOptions) -> bool {
+        options.slicing.enabled && !matches!(self.task(), YOLOTask::Classify)
+    }
+
+    fn run_sliced(&mut self, x: &DynamicImage, options: &RunOptions) -> Result<YOLOResult> {
+        // run every tile, and optionally the whole image, then merge in image coordinates
+        let t = std::time::Instant::now();
+        let slicing = options.slicing;
+        let tiles = slicing.tiles(x.width(), x.height());
+        let _span = info_span!("sliced", tiles = tiles.len()).entered();
+        let n = self.max_batch() as usize;
+        let mut ys = Vec::with_capacity(tiles.len() + 1);
+        for chunk in tiles.chunks(n) {
+            let crops: Vec<DynamicImage> = chunk
+                .iter()
+                .map(|&(x0, y0, w, h)| x.crop_imm(x0, y0, w, h))
+                .collect();
+            let options = vec![options.clone(); crops.len()];
+            ys.extend(self.run_batch(&crops, &options)?.into_iter().zip(chunk.iter().copied()));
+        }
+        if slicing.full_image {
+            let y = self.run_batch(std::slice::from_ref(x), std::slice::from_ref(options))?;
+            ys.extend(y.into_iter().map(|y| (y, (0, 0, x.width(), x.height()))));
+        }
+        let y = slicing.merge(ys, (x.width(), x.height()), options.nms, options.conf);
+        if self.profile {
+            info!(tiles = tiles.len(), elapsed = ?t.elapsed(), "Model Sliced");
+        }
+        Ok(y)
+    }
+
+    fn run_batch(&mut self, xs: &[DynamicImage], options: &[RunOptions]) -> Result<Vec<YOLOResult>> {
         // pre-process
         let t_pre = std::time::Instant::now();
-        let xs_ = self.preprocess(xs)?;
+        info_span!("preprocess", batch = xs.len()).in_scope(|| self.preprocess(xs))?;
+        METRICS.observe_stage(&self.name, "preprocess", t_pre.elapsed());
         if self.profile {
-            println!("[Model Preprocess]: {:?}", t_pre.elapsed());
+            info!(elapsed = ?t_pre.elapsed(), "Model Preprocess");
         }
 
         // run
         let t_run = std::time::Instant::now();
-        let ys = self.engine.run(xs_, self.profile)?;
+        let ys = info_span!("inference").in_scope(|| self.engine.run(self.input.view(), self.profile))?;
+        METRICS.observe_stage(&self.name, "inference", t_run.elapsed());
         if self.profile {
-            println!("[Model Inference]: {:?}", t_run.elapsed());
+            info!(elapsed = ?t_run.elapsed(), "Model Inference");
         }
 
         // post-process
         let t_post = std::time::Instant::now();
-        let ys = self.postprocess(ys, xs)?;
+        let ys = info_span!("postprocess").in_scope(|| self.postprocess(ys, xs, options))?;
+        METRICS.observe_stage(&self.name, "postprocess", t_post.elapsed());
         if self.profile {
-            println!("[Model Postprocess]: {:?}", t_post.elapsed());
-        }
-
-        // plot and save
-        if self.plot {
-            self.plot_and_save(&ys, xs, Some(&SKELETON));
+            info!(elapsed = ?t_post.elapsed(), "Model Postprocess");
         }
         Ok(ys)
     }
@@ -201,11 +440,12 @@ impl YOLOv8 {
         &self,
         xs: Vec<Array<f32, IxDyn>>,
         xs0: &[DynamicImage],
+        options: &[RunOptions],
     ) -> Result<Vec<YOLOResult>> {
         if let YOLOTask::Classify = self.task() {
             let mut ys = Vec::new();
             let preds = &xs[0];
-            for batch in preds.axis_iter(Axis(0)) {
+            for batch in preds.axis_iter(Axis(0)).take(xs0.len()) {
                 ys.push(YOLOResult::new(
                     Some(Embedding::new(batch.into_owned())),
                     None,
@@ -226,8 +466,10 @@ impl YOLOv8 {
                 }
             };
             let mut ys = Vec::new();
-            for (idx, anchor) in preds.axis_iter(Axis(0)).enumerate() {
+            for (idx, anchor) in preds.axis_iter(Axis(0)).take(xs0.len()).enumerate() {
                 // [bs, 4 + nc + nm, anchors]
+                let options = &options[idx];
+
                 // input image
                 let width_original = xs0[idx].width() as f32;
                 let height_original = xs0[idx].height() as f32;
@@ -236,6 +478,7 @@ impl YOLOv8 {
 
                 // save each result
                 let mut data: Vec<(Bbox, Option<Vec<Point2>>, Option<Vec<f32>>)> = Vec::new();
+                let mut data_obb: Vec<RotatedBbox> = Vec::new();
                 for pred in anchor.axis_iter(Axis(1)) {
                     // split preds for different tasks
                     let bbox = pred.slice(s![0..CXYWH_OFFSET]);
@@ -262,8 +505,23 @@ impl YOLOv8 {
                         .reduce(|max, x| if x.1 > max.1 { x } else { max })
                         .unwrap(); // definitely will not panic!
 
-                    // confidence filter
-                    if confidence < self.conf {
+                    // confidence and class filter
+                    if !options.classes.accepts(id, confidence, options.conf) {
+                        continue;
+                    }
+
+                    // obb: angle follows the class scores, [bs, 4 + nc + 1, anchors]
+                    if let YOLOTask::Obb = self.task() {
+                        let angle = pred[CXYWH_OFFSET + self.nc() as usize];
+                        data_obb.push(RotatedBbox::new(
+                            (bbox[0] / ratio).max(0.0f32).min(width_original),
+                            (bbox[1] / ratio).max(0.0f32).min(height_original),
+                            bbox[2] / ratio,
+                            bbox[3] / ratio,
+                            angle,
+                            id,
+                            confidence,
+                        ));
                         continue;
                     }
 
@@ -292,7 +550,7 @@ impl YOLOv8 {
                                 let kx = kpts[KPT_STEP * i] / ratio;
                                 let ky = kpts[KPT_STEP * i + 1] / ratio;
                                 let kconf = kpts[KPT_STEP * i + 2];
-                                if kconf < self.kconf {
+                                if kconf < options.kconf {
                                     kpts_.push(Point2::default());
                                 } else {
                                     kpts_.push(Point2::new_with_conf(
@@ -313,78 +571,36 @@ impl YOLOv8 {
                 }
 
                 // nms
-                non_max_suppression(&mut data, self.iou);
+                let nms = options.nms;
+                nms.apply(&mut data, options.conf);
+                apply_rotated_nms(&mut data_obb, &nms);
 
                 // decode
                 let mut y_bboxes: Vec<Bbox> = Vec::new();
                 let mut y_kpts: Vec<Vec<Point2>> = Vec::new();
-                let mut y_masks: Vec<Vec<u8>> = Vec::new();
+                let mut y_coefs: Vec<Vec<f32>> = Vec::new();
                 for elem in data.into_iter() {
                     if let Some(kpts) = elem.1 {
                         y_kpts.push(kpts)
                     }
-
-                    // decode masks
                     if let Some(coefs) = elem.2 {
-                        let proto = protos.unwrap().slice(s![idx, .., .., ..]);
-                        let (nm, nh, nw) = proto.dim();
-
-                        // coefs * proto -> mask
-                        let coefs = Array::from_shape_vec((1, nm), coefs)?; // (n, nm)
-
-                        let proto = proto.to_owned();
-                        let proto = proto.to_shape((nm, nh * nw))?; // (nm, nh*nw)
-                        let mask = coefs.dot(&proto); // (nh, nw, n)
-                        let mask = mask.to_shape((nh, nw, 1))?;
-
-                        // build image from ndarray
-                        let mask_im: ImageBuffer<image::Luma<_>, Vec<f32>> =
-                            match ImageBuffer::from_raw(
-                                nw as u32,
-                                nh as u32,
-                                mask.to_owned().into_raw_vec_and_offset().0,
-                            ) {
-                                Some(image) => image,
-                                None => panic!("can not create image from ndarray"),
-                            };
-                        let mut mask_im = image::DynamicImage::from(mask_im); // -> dyn
-
-                        // rescale masks
-                        let (_, w_mask, h_mask) =
-                            self.scale_wh(width_original, height_original, nw as f32, nh as f32);
-                        let mask_cropped = mask_im.crop(0, 0, w_mask as u32, h_mask as u32);
-                        let mask_original = mask_cropped.resize_exact(
-                            // resize_to_fill
-                            width_original as u32,
-                            height_original as u32,
-                            match self.task() {
-                                YOLOTask::Segment => image::imageops::FilterType::CatmullRom,
-                                _ => image::imageops::FilterType::Triangle,
-                            },
-                        );
-
-                        // crop-mask with bbox
-                        let mut mask_original_cropped = mask_original.into_luma8();
-                        for y in 0..height_original as usize {
-                            for x in 0..width_original as usize {
-                                if x < elem.0.xmin() as usize
-                                    || x > elem.0.xmax() as usize
-                                    || y < elem.0.ymin() as usize
-                                    || y > elem.0.ymax() as usize
-                                {
-                                    mask_original_cropped.put_pixel(
-                                        x as u32,
-                                        y as u32,
-                                        image::Luma([0u8]),
-                                    );
-                                }
-                            }
-                        }
-                        y_masks.push(mask_original_cropped.into_raw());
+                        y_coefs.push(coefs)
                     }
                     y_bboxes.push(elem.0);
                 }
 
+                // decode masks
+                let y_masks = match protos {
+                    Some(protos) if !y_coefs.is_empty() => self.decode_masks(
+                        protos.slice(s![idx, .., .., ..]),
+                        y_coefs,
+                        &y_bboxes,
+                        (width_original as usize, height_original as usize),
+                        ratio,
+                    )?,
+                    _ => Vec::new(),
+                };
+
                 // save each result
                 let y = YOLOResult {
                     probs: None,
@@ -398,11 +614,29 @@ impl YOLOv8 {
                     } else {
                         None
                     },
+                    mask_size: if !y_masks.is_empty() {
+                        Some((width_original as u32, height_original as u32))
+                    } else {
+                        None
+                    },
+                    mask_rects: None,
+                    // soft masks hold probabilities, binary ones are already cut
+                    mask_cutoff: self
+                        .soft_masks
+                        .then(|| (self.mask_threshold * 255.).round() as u8),
                     masks: if !y_masks.is_empty() {
                         Some(y_masks)
                     } else {
                         None
                     },
+                    rotated_bboxes: if !data_obb.is_empty() {
+                        Some(data_obb)
+                    } else {
+                        None
+                    },
+                    tracks: None,
+                    frame_index: None,
+                    timestamp_ms: None,
                 };
                 ys.push(y);
             }
@@ -411,138 +645,269 @@ impl YOLOv8 {
         }
     }
 
+    fn decode_masks(
+        &self,
+        protos: ArrayView3<f32>,
+        coefs: Vec<Vec<f32>>,
+        bboxes: &[Bbox],
+        (w0, h0): (usize, usize),
+        ratio: f32,
+    ) -> Result<Vec<Vec<u8>>> {
+        // all instances in one matmul: (n, nm) x (nm, nh * nw) -> (n, nh * nw)
+        let (nm, nh, nw) = protos.dim();
+        let n = coefs.len();
+        let coefs = Array2::from_shape_vec((n, nm), coefs.concat())?;
+        let protos = protos.to_shape((nm, nh * nw))?;
+        let logits = coefs.dot(&protos);
+
+        // original image -> proto space, the letterboxed image sits at the top-left
+        let sx = ratio * nw as f32 / self.width() as f32;
+        let sy = ratio * nh as f32 / self.height() as f32;
+
+        let mut masks = Vec::with_capacity(n);
+        for (logits, bbox) in logits.axis_iter(Axis(0)).zip(bboxes.iter()) {
+            let mut mask = vec![0u8; w0 * h0];
+
+            // bbox in the original image
+            let x0 = (bbox.xmin().floor().max(0.) as usize).min(w0);
+            let y0 = (bbox.ymin().floor().max(0.) as usize).min(h0);
+            let x1 = (bbox.xmax().ceil().max(0.) as usize).clamp(x0, w0);
+            let y1 = (bbox.ymax().ceil().max(0.) as usize).clamp(y0, h0);
+
+            // bbox in proto space, one cell of margin for interpolation
+            let px0 = ((x0 as f32 * sx).floor() as usize).saturating_sub(1).min(nw - 1);
+            let py0 = ((y0 as f32 * sy).floor() as usize).saturating_sub(1).min(nh - 1);
+            let px1 = ((x1 as f32 * sx).ceil() as usize + 1).clamp(px0 + 1, nw);
+            let py1 = ((y1 as f32 * sy).ceil() as usize + 1).clamp(py0 + 1, nh);
+
+            // sigmoid on the cropped logits only
+            let logits = logits.to_shape((nh, nw))?;
+            let probs = logits
+                .slice(s![py0..py1, px0..px1])
+                .mapv(|x| 1. / (1. + (-x).exp()));
+            let (ch, cw) = probs.dim();
+
+            // bilinear upsampling of the crop into the bbox
+            for y in y0..y1 {
+                let fy = ((y as f32 + 0.5) * sy - 0.5 - py0 as f32).clamp(0., (ch - 1) as f32);
+                let (iy, dy) = (fy as usize, fy.fract());
+                let iy1 = (iy + 1).min(ch - 1);
+                for x in x0..x1 {
+                    let fx = ((x as f32 + 0.5) * sx - 0.5 - px0 as f32).clamp(0., (cw - 1) as f32);
+                    let (ix, dx) = (fx as usize, fx.fract());
+                    let ix1 = (ix + 1).min(cw - 1);
+                    let p = (probs[[iy, ix]] * (1. - dx) + probs[[iy, ix1]] * dx) * (1. - dy)
+                        + (probs[[iy1, ix]] * (1. - dx) + probs[[iy1, ix1]] * dx) * dy;
+                    mask[y * w0 + x] = if self.soft_masks {
+                        (p * 255.).round() as u8
+                    } else if p > self.mask_threshold {
+                        255
+                    } else {
+                        0
+                    };
+                }
+            }
+            masks.push(mask);
+        }
+        Ok(masks)
+    }
+
     pub fn plot_and_save(
         &self,
         ys: &[YOLOResult],
         xs0: &[DynamicImage],
+        options: &[RunOptions],
         skeletons: Option<&[(usize, usize)]>,
-    ) {
+    ) -> Result<(), VisionError> {
         // check font then load
-        let font: FontArc = load_font();
-        for (_idb, (img0, y)) in xs0.iter().zip(ys.iter()).enumerate() {
-            let mut img = img0.to_rgb8();
-
-            // draw for classifier
-            if let Some(probs) = y.probs() {
-                for (i, k) in probs.topk(5).iter().enumerate() {
-                    let legend = format!("{} {:.2}%", self.names[k.0], k.1);
-                    let scale = 32;
-                    let legend_size = img.width().max(img.height()) / scale;
-                    let x = img.width() / 20;
-                    let y = img.height() / 20 + i as u32 * legend_size;
-
-                    imageproc::drawing::draw_text_mut(
+        let font: FontArc = load_font()?;
+        for ((img0, y), options) in xs0.iter().zip(ys.iter()).zip(options) {
+            let img = self.plot(y, img0, options, skeletons, &font)?;
+
+            // mkdir and save
+            let mut runs = PathBuf::from("runs");
+            if !runs.exists() {
+                std::fs::create_dir_all(&runs)?;
+            }
+            runs.push(format!("{}.jpg", gen_time_string("-")));
+            img.save(runs)?;
+        }
+        Ok(())
+    }
+
+    /// Draws a result over its image, with the keypoint threshold of the `options` it ran with.
+    pub fn plot(
+        &self,
+        y: &YOLOResult,
+        img0: &DynamicImage,
+        options: &RunOptions,
+        skeletons: Option<&[(usize, usize)]>,
+        font: &FontArc,
+    ) -> Result<RgbImage, VisionError> {
+        let mut img = img0.to_rgb8();
+
+        // draw for classifier
+        if let Some(probs) = y.probs() {
+            for (i, k) in probs.topk(5).iter().enumerate() {
+                let legend = format!("{} {:.2}%", self.names[k.0], k.1);
+                let scale = 32;
+                let legend_size = img.width().max(img.height()) / scale;
+                let x = img.width() / 20;
+                let y = img.height() / 20 + i as u32 * legend_size;
+
+                imageproc::drawing::draw_text_mut(
+                    &mut img,
+                    image::Rgb([0, 255, 0]),
+                    x as i32,
+                    y as i32,
+                    legend_size as f32,
+                    font,
+                    &legend,
+                );
+            }
+        }
+
+        // draw bboxes & keypoints
+        if let Some(bboxes) = y.bboxes() {
+            for bbox in bboxes.iter() {
+                // rect
+                imageproc::drawing::draw_hollow_rect_mut(
+                    &mut img,
+                    imageproc::rect::Rect::at(bbox.xmin() as i32, bbox.ymin() as i32)
+                        .of_size(bbox.width() as u32, bbox.height() as u32),
+                    image::Rgb(self.color_palette[bbox.id()].into()),
+                );
+
+                // text
+                let legend = format!("{} {:.2}%", self.names[bbox.id()], bbox.confidence());
+                let scale = 40;
+                let legend_size = img.width().max(img.height()) / scale;
+                imageproc::drawing::draw_text_mut(
+                    &mut img,
+                    image::Rgb(self.color_palette[bbox.id()].into()),
+                    bbox.xmin() as i32,
+                    (bbox.ymin() - legend_size as f32) as i32,
+                    legend_size as f32,
+                    font,
+                    &legend,
+                );
+            }
+        }
+
+        // draw rotated bboxes
+        if let Some(rotated_bboxes) = y.rotated_bboxes() {
+            for rbox in rotated_bboxes.iter() {
+                let color = image::Rgb(self.color_palette[rbox.id()].into());
+                let vertices = rbox.vertices();
+                for i in 0..vertices.len() {
+                    let (p, q) = (&vertices[i], &vertices[(i + 1) % vertices.len()]);
+                    imageproc::drawing::draw_line_segment_mut(
                         &mut img,
-                        image::Rgb([0, 255, 0]),
-                        x as i32,
-                        y as i32,
-                        legend_size as f32,
-                        &font,
-                        &legend,
+                        (p.x(), p.y()),
+                        (q.x(), q.y()),
+                        color,
                     );
                 }
+
+                // text, anchored at the top-most vertex
+                let Some(top) = vertices.iter().min_by(|a, b| a.y().total_cmp(&b.y())) else {
+                    continue;
+                };
+                let legend = format!("{} {:.2}%", self.names[rbox.id()], rbox.confidence());
+                let scale = 40;
+                let legend_size = img.width().max(img.height()) / scale;
+                imageproc::drawing::draw_text_mut(
+                    &mut img,
+                    color,
+                    top.x() as i32,
+                    (top.y() - legend_size as f32) as i32,
+                    legend_size as f32,
+                    font,
+                    &legend,
+                );
             }
+        }
 
-            // draw bboxes & keypoints
-            if let Some(bboxes) = y.bboxes() {
-                for (_idx, bbox) in bboxes.iter().enumerate() {
-                    // rect
-                    imageproc::drawing::draw_hollow_rect_mut(
-                        &mut img,
-                        imageproc::rect::Rect::at(bbox.xmin() as i32, bbox.ymin() as i32)
-                            .of_size(bbox.width() as u32, bbox.height() as u32),
-                        image::Rgb(self.color_palette[bbox.id()].into()),
-                    );
+        // draw kpts
+        if let Some(keypoints) = y.keypoints() {
+            for kpts in keypoints.iter() {
+                for kpt in kpts.iter() {
+                    // filter
+                    if kpt.confidence() < options.kconf {
+                        continue;
+                    }
 
-                    // text
-                    let legend = format!("{} {:.2}%", self.names[bbox.id()], bbox.confidence());
-                    let scale = 40;
-                    let legend_size = img.width().max(img.height()) / scale;
-                    imageproc::drawing::draw_text_mut(
+                    // draw point
+                    imageproc::drawing::draw_filled_circle_mut(
                         &mut img,
-                        image::Rgb(self.color_palette[bbox.id()].into()),
-                        bbox.xmin() as i32,
-                        (bbox.ymin() - legend_size as f32) as i32,
-                        legend_size as f32,
-                        &font,
-                        &legend,
+                        (kpt.x() as i32, kpt.y() as i32),
+                        2,
+                        image::Rgb([0, 255, 0]),
                     );
                 }
-            }
 
-            // draw kpts
-            if let Some(keypoints) = y.keypoints() {
-                for kpts in keypoints.iter() {
-                    for kpt in kpts.iter() {
-                        // filter
-                        if kpt.confidence() < self.kconf {
+                // draw skeleton if has
+                if let Some(skeletons) = skeletons {
+                    for &(idx1, idx2) in skeletons.iter() {
+                        let kpt1 = &kpts[idx1];
+                        let kpt2 = &kpts[idx2];
+                        if kpt1.confidence() < options.kconf || kpt2.confidence() < options.kconf {
                             continue;
                         }
-
-                        // draw point
-                        imageproc::drawing::draw_filled_circle_mut(
+                        imageproc::drawing::draw_line_segment_mut(
                             &mut img,
-                            (kpt.x() as i32, kpt.y() as i32),
-                            2,
-                            image::Rgb([0, 255, 0]),
+                            (kpt1.x(), kpt1.y()),
+                            (kpt2.x(), kpt2.y()),
+                            image::Rgb([233, 14, 57]),
                         );
                     }
-
-                    // draw skeleton if has
-                    if let Some(skeletons) = skeletons {
-                        for &(idx1, idx2) in skeletons.iter() {
-                            let kpt1 = &kpts[idx1];
-                            let kpt2 = &kpts[idx2];
-                            if kpt1.confidence() < self.kconf || kpt2.confidence() < self.kconf {
-                                continue;
-                            }
-                            imageproc::drawing::draw_line_segment_mut(
-                                &mut img,
-                                (kpt1.x(), kpt1.y()),
-                                (kpt2.x(), kpt2.y()),
-                                image::Rgb([233, 14, 57]),
-                            );
-                        }
-                    }
                 }
             }
+        }
 
-            // draw mask
-            if let Some(masks) = y.masks() {
-                for (mask, _bbox) in masks.iter().zip(y.bboxes().unwrap().iter()) {
-                    let mask_nd: ImageBuffer<image::Luma<_>, Vec<u8>> =
-                        match ImageBuffer::from_vec(img.width(), img.height(), mask.to_vec()) {
-                            Some(image) => image,
-                            None => panic!("can not crate image from ndarray"),
-                        };
+        // draw mask
+        if let Some(masks) = y.masks() {
+            let cutoff = y.mask_cutoff();
+            for mask in (0..masks.len()).filter_map(|i| y.full_mask(i)) {
+                let mask_nd: ImageBuffer<image::Luma<_>, Vec<u8>> =
+                    match ImageBuffer::from_vec(img.width(), img.height(), mask.into_owned()) {
+                        Some(image) => image,
+                        None => {
+                            return Err(VisionError::Internal(
+                                "Mask size does not match the image".to_string(),
+                            ))
+                        }
+                    };
 
-                    for _x in 0..img.width() {
-                        for _y in 0..img.height() {
-                            let mask_p = imageproc::drawing::Canvas::get_pixel(&mask_nd, _x, _y);
-                            if mask_p.0[0] > 0 {
-                                let mut img_p = imageproc::drawing::Canvas::get_pixel(&img, _x, _y);
-                                // img_p.0[2] = self.color_palette[bbox.id()].2 / 2;
-                                // img_p.0[1] = self.color_palette[bbox.id()].1 / 2;
-                                // img_p.0[0] = self.color_palette[bbox.id()].0 / 2;
-                                img_p.0[2] /= 2;
-                                img_p.0[1] = 255 - (255 - img_p.0[2]) / 2;
-                                img_p.0[0] /= 2;
-                                imageproc::drawing::Canvas::draw_pixel(&mut img, _x, _y, img_p)
-                            }
+                for _x in 0..img.width() {
+                    for _y in 0..img.height() {
+                        let mask_p = imageproc::drawing::Canvas::get_pixel(&mask_nd, _x, _y);
+                        if mask_p.0[0] > cutoff {
+                            let mut img_p = imageproc::drawing::Canvas::get_pixel(&img, _x, _y);
+                            // img_p.0[2] = self.color_palette[bbox.id()].2 / 2;
+                            // img_p.0[1] = self.color_palette[bbox.id()].1 / 2;
+                            // img_p.0[0] = self.color_palette[bbox.id()].0 / 2;
+                            img_p.0[2] /= 2;
+                            img_p.0[1] = 255 - (255 - img_p.0[2]) / 2;
+                            img_p.0[0] /= 2;
+                            imageproc::drawing::Canvas::draw_pixel(&mut img, _x, _y, img_p)
                         }
                     }
                 }
             }
-
-            // mkdir and save
-            let mut runs = PathBuf::from("runs");
-            if !runs.exists() {
-                std::fs::create_dir_all(&runs).unwrap();
-            }
-            runs.push(gen_time_string("-"));
-            let saveout = format!("{}.jpg", runs.to_str().unwrap());
-            let _ = img.save(saveout);
         }
+
+        Ok(img)
+    }
+
+    pub fn warmup(&mut self) -> Result<(), VisionError> {
+        // a blank image through every stage, a broken model fails here instead of on a request
+        let x = DynamicImage::new_rgb8(self.width(), self.height());
+        let options = self.options();
+        self.preprocess(std::slice::from_ref(&x))?;
+        let ys = self.engine.run(self.input.view(), false)?;
+        self.postprocess(ys, &[x], &[options])?;
+        Ok(())
     }
 
     pub fn summary(&self) {
@@ -553,6 +918,7 @@ impl YOLOv8 {
             > Dtype: {:?}\n\
             > Batch: {} ({}), Height: {} ({}), Width: {} ({})\n\
             > nc: {} nk: {}, nm: {}, conf: {}, kconf: {}, iou: {}\n\
+            > NMS: {:?}, max_det: {}\n\
             ",
             self.task(),
             match self.engine.author().zip(self.engine.version()) {
@@ -589,7 +955,9 @@ impl YOLOv8 {
             self.nm(),
             self.conf,
             self.kconf,
-            self.iou,
+            self.nms.iou,
+            self.nms.strategy,
+            self.nms.max_det,
         );
     }
 
@@ -597,6 +965,11 @@ impl YOLOv8 {
         &self.engine
     }
 
+    /// The input batch filled by the last `preprocess`.
+    pub fn input(&self) -> ArrayView<'_, f32, IxDyn> {
+        self.input.view()
+    }
+
     pub fn conf(&self) -> f32 {
         self.conf
     }
@@ -614,7 +987,41 @@ impl YOLOv8 {
     }
 
     pub fn iou(&self) -> f32 {
-        self.iou
+        self.nms.iou
+    }
+
+    pub fn nms(&self) -> Nms {
+        self.nms
+    }
+
+    pub fn set_nms(&mut self, val: Nms) {
+        self.nms = val;
+    }
+
+    pub fn slicing(&self) -> Slicing {
+        self.slicing
+    }
+
+    pub fn set_slicing(&mut self, val: Slicing) {
+        self.slicing = val;
+    }
+
+    pub fn tta(&self) -> &Tta {
+        &self.tta
+    }
+
+    pub fn set_tta(&mut self, val: Tta) {
+        self.tta = val;
+    }
+
+    pub fn options(&self) -> RunOptions {
+        RunOptions {
+            nms: self.nms,
+            slicing: self.slicing,
+            conf: self.conf,
+            kconf: self.kconf,
+            classes: ClassFilter::default(),
+        }
     }
 
     pub fn task(&self) -> &YOLOTask {
@@ -625,6 +1032,15 @@ impl YOLOv8 {
         self.batch
     }
 
+    pub fn max_batch(&self) -> u32 {
+        // largest batch a single engine run accepts
+        if self.engine.is_batch_dynamic() {
+            self.engine.batch_max().max(1)
+        } else {
+            self.batch
+        }
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -648,4 +1064,8 @@ impl YOLOv8 {
     pub fn names(&self) -> &Vec<String> {
         &self.names
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
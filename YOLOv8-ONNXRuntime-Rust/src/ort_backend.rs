@@ -1,7 +1,6 @@
-use anyhow::Result;
 use clap::ValueEnum;
 use half::f16;
-use ndarray::{Array, CowArray, IxDyn};
+use ndarray::{Array, ArrayView, CowArray, IxDyn};
 use ort::execution_providers::{
     CPUExecutionProvider, CUDAExecutionProvider, ExecutionProvider, ExecutionProviderDispatch,
     TensorRTExecutionProvider,
@@ -10,6 +9,10 @@ use ort::{session::Session, session::builder::SessionBuilder};
 use ort::value::ValueType;
 use ort::tensor::TensorElementType;
 use regex::Regex;
+use tracing::info;
+
+use crate::error::{Result, VisionError};
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum YOLOTask {
     // YOLO tasks
@@ -17,6 +20,7 @@ pub enum YOLOTask {
     Detect,
     Pose,
     Segment,
+    Obb,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -55,32 +59,21 @@ pub struct OrtInputs {
 }
 
 impl OrtInputs {
-    pub fn new(session: &Session) -> Self {
-        let mut shapes = Vec::new();
-        let mut dtypes = Vec::new();
-        let mut names = Vec::new();
-        for i in session.inputs.iter() {
-            /*             let shape: Vec<i32> = i
-                .dimensions()
-                .map(|x| if let Some(x) = x { x as i32 } else { -1i32 })
-                .collect();
-            shapes.push(shape); */
-            if let ort::value::ValueType::Tensor { ty, dimensions, .. } = &i.input_type {
-                dtypes.push(ty.clone());
-                let shape = dimensions.clone();
-                shapes.push(shape);
-            } else {
-                panic!("Unsupported data format, {} - {}", file!(), line!());
-            }
-            //dtypes.push(i.input_type);
-            names.push(i.name.clone());
+    pub fn new(session: &Session) -> Result<Self> {
+        let (shapes, dtypes, names) = OrtBackend::fetch_inputs_from_session(session)?;
+        // images in, NCHW
+        if shapes.first().map(|shape| shape.len()) != Some(4) {
+            return Err(VisionError::Unsupported(format!(
+                "Expected one NCHW image input, got shapes {:?}",
+                shapes
+            )));
         }
-        Self {
+        Ok(Self {
             shapes,
             dtypes,
             names,
             ..Default::default()
-        }
+        })
     }
 }
 
@@ -93,6 +86,7 @@ pub struct OrtConfig {
     pub trt_fp16: bool,
     pub batch: Batch,
     pub image_size: (Option<u32>, Option<u32>),
+    pub intra_threads: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -103,6 +97,8 @@ pub struct OrtBackend {
     ep: OrtEP,
     batch: Batch,
     inputs: OrtInputs,
+    output_shapes: Vec<Vec<i64>>,
+    output_dtypes: Vec<TensorElementType>,
 }
 
 impl OrtBackend {
@@ -118,18 +114,19 @@ impl OrtBackend {
         //let session = SessionBuilder::new(&env)?.with_model_from_file(&args.f)?;
 
         // get inputs
-        let mut inputs = OrtInputs::new(&session);
+        let mut inputs = OrtInputs::new(&session)?;
 
         // batch size
         let mut batch = args.batch;
         let batch = if inputs.shapes[0][0] == -1 {
             batch
         } else {
-            assert_eq!(
-                inputs.shapes[0][0] as u32, batch.opt,
-                "Expected batch size: {}, got {}. Try using `--batch {}`.",
-                inputs.shapes[0][0] as u32, batch.opt, inputs.shapes[0][0] as u32
-            );
+            if inputs.shapes[0][0] as u32 != batch.opt {
+                return Err(VisionError::Model(format!(
+                    "Expected batch size: {}, got {}. Try using `--batch {}`.",
+                    inputs.shapes[0][0], batch.opt, inputs.shapes[0][0]
+                )));
+            }
             batch.opt = inputs.shapes[0][0] as u32;
             batch
         };
@@ -138,7 +135,11 @@ impl OrtBackend {
         let height = if inputs.shapes[0][2] == -1 {
             match args.image_size.0 {
                 Some(height) => height,
-                None => panic!("Failed to get model height. Make it explicit with `--height`"),
+                None => {
+                    return Err(VisionError::Model(
+                        "Failed to get model height. Make it explicit with `--height`".to_string(),
+                    ))
+                }
             }
         } else {
             inputs.shapes[0][2] as u32
@@ -146,7 +147,11 @@ impl OrtBackend {
         let width = if inputs.shapes[0][3] == -1 {
             match args.image_size.1 {
                 Some(width) => width,
-                None => panic!("Failed to get model width. Make it explicit with `--width`"),
+                None => {
+                    return Err(VisionError::Model(
+                        "Failed to get model width. Make it explicit with `--width`".to_string(),
+                    ))
+                }
             }
         } else {
             inputs.shapes[0][3] as u32
@@ -156,7 +161,7 @@ impl OrtBackend {
         // build provider
         let (ep, provider) = match args.ep {
             OrtEP::CUDA(device_id) => Self::set_ep_cuda(device_id),
-            OrtEP::Trt(device_id) => Self::set_ep_trt(device_id, args.trt_fp16, &batch, &inputs),
+            OrtEP::Trt(device_id) => Self::set_ep_trt(device_id, args.trt_fp16, &batch, &inputs)?,
             _ => (
                 OrtEP::CPU,
                 ExecutionProviderDispatch::from(CPUExecutionProvider::default()),
@@ -164,59 +169,113 @@ impl OrtBackend {
         };
 
         // build session again with the new provider
-        let session = SessionBuilder::new()?
+        let mut sessionbuilder = SessionBuilder::new()?
             // .with_optimization_level(ort::session::builder::GraphOptimizationLevel::Level3)?
-            .with_execution_providers([provider])?
-            .commit_from_file(args.f)?;
+            .with_execution_providers([provider])?;
+        if let Some(intra_threads) = args.intra_threads {
+            sessionbuilder = sessionbuilder.with_intra_threads(intra_threads)?;
+        }
+        let session = sessionbuilder.commit_from_file(args.f)?;
 
         // task: using given one or guessing
         let task = match args.task {
             Some(task) => task,
             None => match session.metadata() {
-                Err(_) => panic!("No metadata found. Try making it explicit by `--task`"),
+                Err(_) => {
+                    return Err(VisionError::Model(
+                        "No metadata found. Try making it explicit by `--task`".to_string(),
+                    ))
+                }
                 Ok(metadata) => match metadata.custom("task") {
-                    Err(_) => panic!("Can not get custom value. Try making it explicit by `--task`"),
+                    Err(_) => {
+                        return Err(VisionError::Model(
+                            "Can not get custom value. Try making it explicit by `--task`".to_string(),
+                        ))
+                    }
                     Ok(value) => match value {
-                        None => panic!("No corresponding value of `task` found in metadata. Make it explicit by `--task`"),
+                        None => return Err(VisionError::Model(
+                            "No corresponding value of `task` found in metadata. Make it explicit by `--task`".to_string(),
+                        )),
                         Some(task) => match task.as_str() {
                             "classify" => YOLOTask::Classify,
                             "detect" => YOLOTask::Detect,
                             "pose" => YOLOTask::Pose,
                             "segment" => YOLOTask::Segment,
-                            x => todo!("{:?} is not supported for now!", x),
+                            "obb" => YOLOTask::Obb,
+                            x => {
+                                return Err(VisionError::Unsupported(format!(
+                                    "{:?} is not supported for now!",
+                                    x
+                                )))
+                            }
                         },
                     },
                 },
             },
         };
 
+        // outputs: segment models also output mask prototypes
+        let (output_shapes, output_dtypes) = Self::fetch_outputs_from_session(&session)?;
+        let expected = if task == YOLOTask::Segment { 2 } else { 1 };
+        if output_shapes.len() < expected || output_shapes.iter().take(expected).any(|s| s.len() < 2) {
+            return Err(VisionError::Model(format!(
+                "Unexpected output shapes for {:?}: {:?}",
+                task, output_shapes
+            )));
+        }
+
         Ok(Self {
             session,
             task,
             ep,
             batch,
             inputs,
+            output_shapes,
+            output_dtypes,
         })
     }
 
     pub fn fetch_inputs_from_session(
         session: &Session,
-    ) -> (Vec<Vec<i64>>, Vec<TensorElementType>, Vec<String>) {
+    ) -> Result<(Vec<Vec<i64>>, Vec<TensorElementType>, Vec<String>)> {
         // get inputs attrs from ONNX model
         let mut shapes = Vec::new();
         let mut dtypes = Vec::new();
         let mut names = Vec::new();
         for i in session.inputs.iter() {
             if let ort::value::ValueType::Tensor { ty, dimensions, .. } = &i.input_type {
-                dtypes.push(ty.clone());
+                dtypes.push(*ty);
                 let shape = dimensions.clone();
                 shapes.push(shape);
             } else {
-                panic!("Unsupported data format, {} - {}", file!(), line!());
+                return Err(VisionError::Unsupported(format!(
+                    "Input `{}` is not a tensor",
+                    i.name
+                )));
             }
             names.push(i.name.clone());
         }
-        (shapes, dtypes, names)
+        Ok((shapes, dtypes, names))
+    }
+
+    pub fn fetch_outputs_from_session(
+        session: &Session,
+    ) -> Result<(Vec<Vec<i64>>, Vec<TensorElementType>)> {
+        // get outputs attrs from ONNX model
+        let mut shapes = Vec::new();
+        let mut dtypes = Vec::new();
+        for output in session.outputs.iter() {
+            if let ValueType::Tensor { ty, dimensions, .. } = &output.output_type {
+                dtypes.push(*ty);
+                shapes.push(dimensions.clone());
+            } else {
+                return Err(VisionError::Unsupported(format!(
+                    "Output `{}` is not a tensor",
+                    output.name
+                )));
+            }
+        }
+        Ok((shapes, dtypes))
     }
 
     pub fn set_ep_cuda(device_id: i32) -> (OrtEP, ExecutionProviderDispatch) {
@@ -240,7 +299,7 @@ impl OrtBackend {
         fp16: bool,
         batch: &Batch,
         inputs: &OrtInputs,
-    ) -> (OrtEP, ExecutionProviderDispatch) {
+    ) -> Result<(OrtEP, ExecutionProviderDispatch)> {
         // set TensorRT
         let trt_provider = TensorRTExecutionProvider::default().with_device_id(device_id);
 
@@ -248,10 +307,10 @@ impl OrtBackend {
         if let Ok(true) = trt_provider.is_available() {
             let (height, width) = (inputs.sizes[0][0], inputs.sizes[0][1]);
             if inputs.dtypes[0] == TensorElementType::Float16 && !fp16 {
-                panic!(
+                return Err(VisionError::Model(format!(
                     "Dtype mismatch! Expected: Float32, got: {:?}. You should use `--fp16`",
                     inputs.dtypes[0]
-                );
+                )));
             }
             // dynamic shape: input_tensor_1:dim_1xdim_2x...,input_tensor_2:dim_3xdim_4x...,...
             let mut opt_string = String::new();
@@ -275,13 +334,13 @@ impl OrtBackend {
                 .with_profile_max_shapes(max_string)
                 .with_fp16(fp16)
                 .with_timing_cache(true);
-            (
+            Ok((
                 OrtEP::Trt(device_id),
                 ExecutionProviderDispatch::from(trt_provider),
-            )
+            ))
         } else {
             println!("> TensorRT is not available! Try using CUDA...");
-            Self::set_ep_cuda(device_id)
+            Ok(Self::set_ep_cuda(device_id))
         }
     }
 
@@ -289,120 +348,100 @@ impl OrtBackend {
         // fetch value from onnx model file by key
         match self.session.metadata() {
             Err(_) => None,
-            Ok(metadata) => match metadata.custom(key) {
-                Err(_) => None,
-                Ok(value) => value,
-            },
+            Ok(metadata) => metadata.custom(key).unwrap_or_default(),
         }
     }
 
-    pub fn run(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
+    pub fn run(&self, xs: ArrayView<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
         // ORT inference
         match self.dtype() {
             TensorElementType::Float16 => self.run_fp16(xs, profile),
             TensorElementType::Float32 => self.run_fp32(xs, profile),
-            _ => todo!(),
+            x => Err(VisionError::Unsupported(format!("Input dtype {:?}", x))),
         }
     }
 
-    pub fn run_fp16(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
+    #[tracing::instrument(name = "ort", skip_all, fields(dtype = "f16"))]
+    pub fn run_fp16(&self, xs: ArrayView<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
         // f32->f16
         let t = std::time::Instant::now();
         let xs = xs.mapv(f16::from_f32);
         if profile {
-            println!("[ORT f32->f16]: {:?}", t.elapsed());
+            info!(step = "f32->f16", elapsed = ?t.elapsed(), "ORT");
         }
 
         // h2d
         let t = std::time::Instant::now();
         let xs = CowArray::from(xs);
         if profile {
-            println!("[ORT H2D]: {:?}", t.elapsed());
+            info!(step = "h2d", elapsed = ?t.elapsed(), "ORT");
         }
 
         // run
         let t = std::time::Instant::now();
         let ys = self.session.run(ort::inputs![xs.view()]?)?;
         if profile {
-            println!("[ORT Inference]: {:?}", t.elapsed());
+            info!(step = "inference", elapsed = ?t.elapsed(), "ORT");
         }
 
         // d2h
-        Ok(ys
-            .iter()
+        ys.iter()
             .map(|(_k, v)| {
                 // d2h
                 let t = std::time::Instant::now();
-                let v = v.try_extract_tensor().unwrap();
+                let v = v.try_extract_tensor()?;
                 //let v = v.try_extract::<_>().unwrap().view().clone().into_owned();
                 if profile {
-                    println!("[ORT D2H]: {:?}", t.elapsed());
+                    info!(step = "d2h", elapsed = ?t.elapsed(), "ORT");
                 }
 
                 // f16->f32
                 let t_ = std::time::Instant::now();
                 let v = v.mapv(f16::to_f32);
                 if profile {
-                    println!("[ORT f16->f32]: {:?}", t_.elapsed());
+                    info!(step = "f16->f32", elapsed = ?t_.elapsed(), "ORT");
                 }
-                v
+                Ok(v)
             })
-            .collect::<Vec<Array<_, _>>>())
+            .collect()
     }
 
-    pub fn run_fp32(&self, xs: Array<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
+    #[tracing::instrument(name = "ort", skip_all, fields(dtype = "f32"))]
+    pub fn run_fp32(&self, xs: ArrayView<f32, IxDyn>, profile: bool) -> Result<Vec<Array<f32, IxDyn>>> {
         // h2d
         let t = std::time::Instant::now();
         let xs = CowArray::from(xs);
         if profile {
-            println!("[ORT H2D]: {:?}", t.elapsed());
+            info!(step = "h2d", elapsed = ?t.elapsed(), "ORT");
         }
 
         // run
         let t = std::time::Instant::now();
         let ys = self.session.run(ort::inputs![xs.view()]?)?;
         if profile {
-            println!("[ORT Inference]: {:?}", t.elapsed());
+            info!(step = "inference", elapsed = ?t.elapsed(), "ORT");
         }
 
         // d2h
-        Ok(ys
-            .iter()
+        ys.iter()
             .map(|(_k, v)| {
                 let t = std::time::Instant::now();
-                let v = v.try_extract_tensor::<f32>().unwrap().into_owned();
+                let v = v.try_extract_tensor::<f32>()?.into_owned();
                 //let x = x.try_extract::<_>().unwrap().view().clone().into_owned();
                 if profile {
-                    println!("[ORT D2H]: {:?}", t.elapsed());
+                    info!(step = "d2h", elapsed = ?t.elapsed(), "ORT");
                 }
-                v
+                Ok(v)
             })
-            .collect::<Vec<Array<_, _>>>())
+            .collect()
     }
 
-    pub fn output_shapes(&self) -> Vec<Vec<i64>> {
-        let mut shapes = Vec::new();
-        for output in &self.session.outputs {
-            if let ValueType::Tensor { ty: _, dimensions, .. } = &output.output_type {
-                let shape = dimensions.clone();
-                shapes.push(shape);
-            } else {
-                panic!("not support data format, {} - {}", file!(), line!());
-            }
-        }
-        shapes
+    pub fn output_shapes(&self) -> &Vec<Vec<i64>> {
+        &self.output_shapes
     }
 
-    pub fn output_dtypes(&self) -> Vec<TensorElementType> {
-        let mut dtypes = Vec::new();
-        for output in &self.session.outputs {
-            if let ValueType::Tensor { ty, dimensions: _, .. } = &output.output_type {
-                dtypes.push(ty.clone());
-            } else {
-                panic!("not support data format, {} - {}", file!(), line!());
-            }
-        }
-        dtypes
+    pub fn output_dtypes(&self) -> &Vec<TensorElementType> {
+        &self.output_dtypes
     }
 
     pub fn input_shapes(&self) -> &Vec<Vec<i64>> {
@@ -441,6 +480,14 @@ impl OrtBackend {
         self.batch.opt
     }
 
+    pub fn batch_min(&self) -> u32 {
+        self.batch.min
+    }
+
+    pub fn batch_max(&self) -> u32 {
+        self.batch.max
+    }
+
     pub fn is_batch_dynamic(&self) -> bool {
         self.input_shapes()[0][0] == -1
     }
@@ -475,8 +522,8 @@ impl OrtBackend {
             None => None,
             Some(kpt_string) => {
                 let re = Regex::new(r"([0-9]+), ([0-9]+)").unwrap();
-                let caps = re.captures(&kpt_string).unwrap();
-                Some(caps.get(1).unwrap().as_str().parse::<u32>().unwrap())
+                let caps = re.captures(&kpt_string)?;
+                caps.get(1)?.as_str().parse::<u32>().ok()
             }
         }
     }
@@ -518,6 +565,14 @@ impl OrtBackend {
                         Some((self.output_shapes()[0][1] - self.output_shapes()[1][1]) as u32 - 4)
                     }
                 }
+                YOLOTask::Obb => {
+                    if self.output_shapes()[0][1] == -1 {
+                        None
+                    } else {
+                        // cxywhclssangle
+                        Some(self.output_shapes()[0][1] as u32 - 4 - 1)
+                    }
+                }
             },
         }
     }
@@ -533,7 +588,7 @@ impl OrtBackend {
     pub fn na(&self) -> Option<u32> {
         // num_anchors
         match self.task() {
-            YOLOTask::Segment | YOLOTask::Detect | YOLOTask::Pose => {
+            YOLOTask::Segment | YOLOTask::Detect | YOLOTask::Pose | YOLOTask::Obb => {
                 if self.output_shapes()[0][2] == -1 {
                     None
                 } else {
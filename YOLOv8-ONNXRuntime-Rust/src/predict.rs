@@ -0,0 +1,211 @@
+use ab_glyph::FontArc;
+use image::DynamicImage;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli::PredictArgs;
+use crate::metrics::detections;
+use crate::rest::JsonResult;
+use crate::utils::find_images;
+use crate::{
+    load_font, Args, ExportWriter, Exporter, ImageResult, VisionError, YOLOResult, YOLOv8, SKELETON,
+};
+
+/// Result file of one image, `<output>/<file name>.json`.
+#[derive(Debug, Serialize)]
+pub struct PredictRecord {
+    pub source: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(flatten)]
+    pub result: JsonResult,
+}
+
+/// Images of a `predict` run, by outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PredictSummary {
+    pub done: usize,
+    pub skipped: usize,
+}
+
+/// Runs the `--model` model over the images of `predict.source`, up to `max_batch` per
+/// engine run, and writes an annotated image and a JSON result file per image to
+/// `predict.output`, plus the `predict.export` formats. Images failing to decode, run or
+/// save are reported and skipped.
+pub fn predict(args: &Args, predict: &PredictArgs) -> Result<PredictSummary, VisionError> {
+    if args.model.is_empty() {
+        return Err(VisionError::InvalidArgument(
+            "`predict` runs the `--model` model, none is set".to_string(),
+        ));
+    }
+    let sources = find_images(&predict.source)?;
+    if sources.is_empty() {
+        return Err(VisionError::NotFound(format!("No image in {}", predict.source)));
+    }
+    std::fs::create_dir_all(&predict.output)?;
+    check_output(&sources, &predict.output)?;
+
+    // annotated images go to the output directory instead of `runs`
+    let mut args = args.clone();
+    args.plot = false;
+    let mut model = YOLOv8::new(args)?;
+    model.summary();
+    let font = if predict.no_plot { None } else { Some(load_font()?) };
+    let mut export = if predict.export.is_empty() {
+        None
+    } else {
+        let exporter = Exporter {
+            names: model.names().clone(),
+            segmentation: predict.segmentation,
+            kconf: model.kconf(),
+        };
+        let mut export = ExportWriter::new(exporter, &predict.export, &predict.output)?;
+        if let Some(coco) = &predict.coco {
+            export.read_image_ids(coco)?;
+        }
+        export.check_paths(&sources)?;
+        Some(export)
+    };
+
+    let total = sources.len();
+    let mut summary = PredictSummary::default();
+    let skip = |summary: &mut PredictSummary, source: &str, e: VisionError| {
+        summary.skipped += 1;
+        println!(
+            "[Predict]: {}/{} skipped {}: {}",
+            summary.done + summary.skipped,
+            total,
+            source,
+            e
+        );
+    };
+    for chunk in sources.chunks(model.max_batch() as usize) {
+        // the images that decode still share a batch
+        let mut paths = Vec::with_capacity(chunk.len());
+        let mut xs = Vec::with_capacity(chunk.len());
+        for source in chunk {
+            match image::open(source) {
+                Ok(x) => {
+                    paths.push(source);
+                    xs.push(x);
+                }
+                Err(e) => skip(&mut summary, source, e.into()),
+            }
+        }
+        if xs.is_empty() {
+            continue;
+        }
+
+        let t = Instant::now();
+        let ys = run(&mut model, &xs);
+        let elapsed = t.elapsed() / xs.len() as u32;
+        for ((source, x), y) in paths.into_iter().zip(&xs).zip(ys) {
+            let saved = y.and_then(|y| {
+                save(&model, predict, font.as_ref(), source, x, &y)?;
+                if let Some(export) = &mut export {
+                    export.add(&ImageResult {
+                        path: source,
+                        width: x.width(),
+                        height: x.height(),
+                        result: &y,
+                    })?;
+                }
+                Ok(y)
+            });
+            match saved {
+                Ok(y) => {
+                    summary.done += 1;
+                    println!(
+                        "[Predict]: {}/{} {}: {} detections ({:.1?})",
+                        summary.done + summary.skipped,
+                        total,
+                        source,
+                        detections(&y),
+                        elapsed
+                    );
+                }
+                Err(e) => skip(&mut summary, source, e),
+            }
+        }
+    }
+
+    if let Some(export) = export {
+        export.finish()?;
+    }
+    println!(
+        "[Predict]: {} images done, {} skipped, results in {}",
+        summary.done,
+        summary.skipped,
+        predict.output.display()
+    );
+    Ok(summary)
+}
+
+/// Refuses an `output` directory holding any of the `sources`, whose annotated copies
+/// would overwrite them.
+fn check_output(sources: &[String], output: &Path) -> Result<(), VisionError> {
+    let output = output.canonicalize()?;
+    for source in sources {
+        let dir = match Path::new(source).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if dir.canonicalize()? == output {
+            return Err(VisionError::InvalidArgument(format!(
+                "--output {} holds the source image {}, pick another directory",
+                output.display(),
+                source
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the annotated image, unless `font` is `None`, and the result file of an image.
+fn save(
+    model: &YOLOv8,
+    predict: &PredictArgs,
+    font: Option<&FontArc>,
+    source: &str,
+    x: &DynamicImage,
+    y: &YOLOResult,
+) -> Result<(), VisionError> {
+    let path = Path::new(source);
+    let Some(name) = path.file_name() else {
+        return Err(VisionError::InvalidArgument(format!("Not a file: {}", source)));
+    };
+    if let Some(font) = font {
+        model.plot(y, x, &model.options(), Some(&SKELETON), font)?.save(predict.output.join(name))?;
+    }
+
+    let record = PredictRecord {
+        source: source.to_string(),
+        width: x.width(),
+        height: x.height(),
+        result: JsonResult::new(y, predict.mask_format, |id| model.names().get(id).cloned()),
+    };
+    // `a.jpg.json`, apart from the result of `a.png`
+    let file = File::create(predict.output.join(format!("{}.json", name.to_string_lossy())))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &record)
+        .map_err(|e| VisionError::Io(e.to_string()))
+}
+
+/// Results of a batch, one image at a time if the batch fails so a bad image only
+/// fails itself.
+pub(crate) fn run(model: &mut YOLOv8, xs: &[DynamicImage]) -> Vec<Result<YOLOResult, VisionError>> {
+    match model.run(xs) {
+        Ok(ys) => ys.into_iter().map(Ok).collect(),
+        Err(_) if xs.len() > 1 => xs.iter().map(|x| run_one(model, x)).collect(),
+        Err(e) => vec![Err(e)],
+    }
+}
+
+fn run_one(model: &mut YOLOv8, x: &DynamicImage) -> Result<YOLOResult, VisionError> {
+    model
+        .run(std::slice::from_ref(x))?
+        .pop()
+        .ok_or_else(|| VisionError::Internal("The model returned no result".to_string()))
+}
@@ -0,0 +1,619 @@
+use clap::ValueEnum;
+use image::DynamicImage;
+use serde::Deserialize;
+use std::collections::hash_map::{Entry, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use tonic::{async_trait, Request, Response, Status};
+use tracing::{info, Instrument, Span};
+
+use crate::grpc::{
+    model_registry_server::ModelRegistry, ListModelsRequest, ListModelsResponse,
+    ModelInfo as ProtoModelInfo, ModelKind as ProtoModelKind, ModelSpec as ProtoModelSpec,
+    PredictRequest, ReloadModelRequest, UnloadModelRequest, UnloadModelResponse,
+};
+use crate::{
+    convert_inference_params, convert_mask_format, convert_nms_config, convert_yolo_result, Args,
+    Bbox, LoadArgs, MyYoloService, ProcessImagesResponse, ProtoInferenceParams, ProtoNmsConfig,
+    ProtoSlicingConfig, RunOptions, VisionError, Watcher, YOLOResult,
+};
+use crate::metrics::METRICS;
+
+/// Model family of a registry entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelKind {
+    Yolo,
+    RfDetr,
+}
+
+/// A named model of the registry, a `[[models]]` entry of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSpec {
+    pub name: String,
+    pub kind: ModelKind,
+    // ONNX model path, may be left out when set in `config`
+    #[serde(default)]
+    pub path: Option<String>,
+    // server config file of the model kind, the `YOLO_*` and `RFDETR_*` env vars of the
+    // server do not apply to registry entries
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+}
+
+impl ModelSpec {
+    fn argv(&self) -> Vec<OsString> {
+        // command line of the model kind
+        let mut argv = vec![OsString::from(match self.kind {
+            ModelKind::Yolo => "yolov8-rs",
+            ModelKind::RfDetr => "rf-detr",
+        })];
+        argv.push("--name".into());
+        argv.push(self.name.as_str().into());
+        if let Some(config) = &self.config {
+            argv.push("--config".into());
+            argv.push(config.into());
+        }
+        if let Some(path) = &self.path {
+            argv.push("--model".into());
+            argv.push(path.into());
+        }
+        argv
+    }
+
+    /// Settings of a YOLO entry, from its own fields only and never from the env vars.
+    fn yolo_args(&self) -> Result<Args, VisionError> {
+        Args::try_load_without_env(self.argv())
+    }
+
+    /// Settings of an RF-DETR entry, see `yolo_args`.
+    fn rf_detr_args(&self) -> Result<rf_detr::Args, VisionError> {
+        Ok(rf_detr::Args::try_load_without_env(self.argv())?)
+    }
+
+    /// Model path the entry loads, its own or the one of its config file.
+    fn model(&self) -> Result<String, VisionError> {
+        match self.kind {
+            ModelKind::Yolo => Ok(self.yolo_args()?.model),
+            ModelKind::RfDetr => Ok(self.rf_detr_args()?.model),
+        }
+    }
+}
+
+type Models = HashMap<String, Arc<Model>>;
+
+enum Backend {
+    Yolo(Box<MyYoloService>),
+    RfDetr(Arc<rf_detr::MyImageProcessor>),
+}
+
+pub(crate) struct Model {
+    kind: ModelKind,
+    path: String,
+    backend: Backend,
+    // reloads the model on file changes, stops with the model
+    watcher: Option<Watcher>,
+}
+
+impl Model {
+    fn load(spec: &ModelSpec) -> Result<Self, VisionError> {
+        // blocking, ORT sessions are built here
+        match spec.kind {
+            ModelKind::Yolo => {
+                let args = spec.yolo_args()?;
+                Ok(Self {
+                    kind: spec.kind,
+                    backend: Backend::Yolo(Box::new(MyYoloService::load(&args)?)),
+                    path: args.model,
+                    watcher: None,
+                })
+            }
+            ModelKind::RfDetr => {
+                let args = spec.rf_detr_args()?;
+                Ok(Self {
+                    kind: spec.kind,
+                    path: args.model.clone(),
+                    backend: Backend::RfDetr(Arc::new(rf_detr::MyImageProcessor::load(args)?)),
+                    watcher: None,
+                })
+            }
+        }
+    }
+
+    fn watch(&self) -> Option<Watcher> {
+        match &self.backend {
+            Backend::Yolo(service) => service.watch(),
+            Backend::RfDetr(processor) => {
+                let watch_ms = processor.args().watch_ms;
+                if watch_ms == 0 {
+                    return None;
+                }
+                let processor = Arc::clone(processor);
+                Some(Watcher::spawn(
+                    PathBuf::from(&self.path),
+                    Duration::from_millis(watch_ms),
+                    move || {
+                        let processor = Arc::clone(&processor);
+                        async move { processor.reload().await }
+                    },
+                ))
+            }
+        }
+    }
+
+    async fn reload(&self) -> Result<(), VisionError> {
+        match &self.backend {
+            Backend::Yolo(service) => service.reload().await,
+            Backend::RfDetr(processor) => Ok(processor.reload().await?),
+        }
+    }
+
+    pub(crate) async fn predict(
+        &self,
+        xs: Vec<DynamicImage>,
+        nms: Option<ProtoNmsConfig>,
+        slicing: Option<ProtoSlicingConfig>,
+        params: Option<ProtoInferenceParams>,
+    ) -> Result<Vec<YOLOResult>, VisionError> {
+        let processor = match &self.backend {
+            Backend::Yolo(service) => {
+                return service
+                    .predict(xs, nms.as_ref(), slicing.as_ref(), params.as_ref())
+                    .await
+            }
+            Backend::RfDetr(processor) => Arc::clone(processor),
+        };
+        if slicing.is_some() {
+            return Err(VisionError::InvalidArgument(
+                "Sliced inference is not supported by RF-DETR models".to_string(),
+            ));
+        }
+
+        let mut run_nms = match &nms {
+            Some(nms) => convert_nms_config(processor.nms(), nms)?,
+            None => processor.nms(),
+        };
+        // conf and class filters apply to detections above the model conf
+        let mut options = RunOptions::default();
+        if let Some(params) = &params {
+            options = convert_inference_params(options, params)?;
+            if params.iou.is_some() {
+                run_nms.iou = options.nms.iou;
+            }
+            if let Some(max_det) = params.max_det {
+                run_nms.max_det = max_det as usize;
+            }
+        }
+
+        // one task per image, so a pool of sessions runs them in parallel
+        let tasks: Vec<_> = xs
+            .into_iter()
+            .map(|x| {
+                let processor = Arc::clone(&processor);
+                tokio::spawn(
+                    async move { processor.detect(x, &run_nms).await }.instrument(Span::current()),
+                )
+            })
+            .collect();
+        let mut ys = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let (boxes, classes, confs) = task
+                .await
+                .map_err(|e| VisionError::Internal(format!("Inference task failed: {}", e)))??;
+            let bboxes: Vec<Bbox> = boxes
+                .into_iter()
+                .zip(classes)
+                .zip(confs)
+                .filter(|&((_, id), conf)| {
+                    options
+                        .classes
+                        .accepts(id.max(0) as usize, conf, options.conf)
+                })
+                .map(|(([cx, cy, w, h], id), conf)| {
+                    let (cx, cy, w, h) = (cx as f32, cy as f32, w as f32, h as f32);
+                    Bbox::new(cx - w / 2., cy - h / 2., w, h, id.max(0) as usize, conf)
+                })
+                .collect();
+            METRICS.observe_detections(&processor.args().name, bboxes.len());
+            ys.push(YOLOResult::new(
+                None,
+                if bboxes.is_empty() {
+                    None
+                } else {
+                    Some(bboxes)
+                },
+                None,
+                None,
+            ));
+        }
+        Ok(ys)
+    }
+
+    /// Name of a class id, if the model knows it.
+    pub(crate) fn class_name(&self, id: usize) -> Option<String> {
+        match &self.backend {
+            Backend::Yolo(service) => service.names().get(id).cloned(),
+            Backend::RfDetr(processor) => processor.class_name(id).map(str::to_string),
+        }
+    }
+
+    fn info(&self, name: &str) -> ProtoModelInfo {
+        ProtoModelInfo {
+            name: name.to_string(),
+            kind: match self.kind {
+                ModelKind::Yolo => ProtoModelKind::ModelYolo,
+                ModelKind::RfDetr => ProtoModelKind::ModelRfDetr,
+            } as i32,
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// Serves named YOLO and RF-DETR models, results share the YOLO result schema.
+#[derive(Default)]
+pub struct MyModelRegistry {
+    models: RwLock<Models>,
+    // where LoadModel requests may load files from, LoadModel is disabled without it
+    model_dir: Option<PathBuf>,
+}
+
+impl MyModelRegistry {
+    /// A registry whose LoadModel RPC loads files from `model_dir` only.
+    pub fn new(model_dir: Option<PathBuf>) -> Self {
+        Self {
+            models: RwLock::default(),
+            model_dir,
+        }
+    }
+
+    /// Loads a model in the background, fails if its name is taken.
+    pub async fn load(&self, spec: ModelSpec) -> Result<ProtoModelInfo, VisionError> {
+        if spec.name.is_empty() {
+            return Err(VisionError::InvalidArgument(
+                "Model name is empty".to_string(),
+            ));
+        }
+        self.check_free(&spec.name)?;
+        let mut model = {
+            let spec = spec.clone();
+            tokio::task::spawn_blocking(move || Model::load(&spec))
+                .await
+                .map_err(|e| VisionError::Internal(format!("Model loading failed: {}", e)))??
+        };
+
+        model.watcher = model.watch();
+
+        // a concurrent load may have taken the name meanwhile
+        let info = model.info(&spec.name);
+        match self.write()?.entry(spec.name) {
+            Entry::Occupied(entry) => return Err(already_loaded(entry.key())),
+            Entry::Vacant(entry) => entry.insert(Arc::new(model)),
+        };
+        info!(model = %info.name, path = %info.path, "Registry loaded model");
+        Ok(info)
+    }
+
+    /// Reloads a model from its file, see `MyYoloService::reload`.
+    pub async fn reload(&self, name: &str) -> Result<ProtoModelInfo, VisionError> {
+        let model = self.get(name)?;
+        model.reload().await?;
+        info!(model = %name, path = %model.path, "Registry reloaded model");
+        Ok(model.info(name))
+    }
+
+    /// Unloads a model, queued requests still complete on it.
+    pub fn unload(&self, name: &str) -> Result<(), VisionError> {
+        match self.write()?.remove(name) {
+            Some(_) => {
+                info!(model = %name, "Registry unloaded model");
+                Ok(())
+            }
+            None => Err(VisionError::NotFound(format!("No model named {}", name))),
+        }
+    }
+
+    /// Loaded models, sorted by name.
+    pub fn list(&self) -> Result<Vec<ProtoModelInfo>, VisionError> {
+        let models = self.read()?;
+        let mut infos: Vec<_> = models
+            .iter()
+            .map(|(name, model)| model.info(name))
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(infos)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Result<Arc<Model>, VisionError> {
+        self.read()?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| VisionError::NotFound(format!("No model named {}", name)))
+    }
+
+    fn check_free(&self, name: &str) -> Result<(), VisionError> {
+        if self.read()?.contains_key(name) {
+            return Err(already_loaded(name));
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Models>, VisionError> {
+        self.models
+            .read()
+            .map_err(|e| VisionError::Internal(format!("Model registry poisoned: {}", e)))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Models>, VisionError> {
+        self.models
+            .write()
+            .map_err(|e| VisionError::Internal(format!("Model registry poisoned: {}", e)))
+    }
+}
+
+/// Resolves a file of a LoadModel request, relative paths from `model_dir`. Fails unless
+/// the file lies inside `model_dir`, clients cannot load anything else from the server.
+fn confine(model_dir: Option<&Path>, path: &str) -> Result<String, VisionError> {
+    let Some(model_dir) = model_dir else {
+        return Err(VisionError::Unsupported(
+            "LoadModel is disabled, start the server with `--model-dir`".to_string(),
+        ));
+    };
+    let model_dir = model_dir.canonicalize().map_err(|e| {
+        VisionError::Internal(format!("Invalid model dir {}: {}", model_dir.display(), e))
+    })?;
+    let resolved = model_dir
+        .join(path)
+        .canonicalize()
+        .map_err(|e| VisionError::NotFound(format!("{}: {}", path, e)))?;
+    if !resolved.starts_with(&model_dir) {
+        return Err(VisionError::InvalidArgument(format!(
+            "{} is outside the model dir",
+            path
+        )));
+    }
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+fn already_loaded(name: &str) -> VisionError {
+    VisionError::AlreadyExists(format!("Model {} is already loaded", name))
+}
+
+#[async_trait]
+impl ModelRegistry for MyModelRegistry {
+    async fn predict(
+        &self,
+        request: Request<PredictRequest>,
+    ) -> Result<Response<ProcessImagesResponse>, Status> {
+        let req = request.into_inner();
+        let model = self.get(&req.model);
+        // unknown names are not labels, they would grow the metrics without bound
+        let name = if model.is_ok() { req.model.as_str() } else { "" };
+        let result = async {
+            let model = model?;
+            let mask_format = convert_mask_format(req.mask_format)?;
+
+            let mut xs = Vec::with_capacity(req.images.len());
+            for image_data in &req.images {
+                let t_decode = Instant::now();
+                let x = image::load_from_memory(image_data).map_err(|e| {
+                    VisionError::InvalidArgument(format!("Failed to decode image: {}", e))
+                })?;
+                METRICS.observe_stage(name, "decode", t_decode.elapsed());
+                xs.push(x);
+            }
+
+            let ys = model
+                .predict(xs, req.nms.clone(), req.slicing.clone(), req.params.clone())
+                .await?;
+            let results = ys
+                .iter()
+                .map(|y| convert_yolo_result(y, mask_format))
+                .collect();
+            Ok::<_, Status>(Response::new(ProcessImagesResponse { results }))
+        }
+        .await;
+        METRICS.observe_request(name, "Predict", &result);
+        result
+    }
+
+    async fn list_models(
+        &self,
+        _request: Request<ListModelsRequest>,
+    ) -> Result<Response<ListModelsResponse>, Status> {
+        Ok(Response::new(ListModelsResponse {
+            models: self.list()?,
+        }))
+    }
+
+    async fn load_model(
+        &self,
+        request: Request<ProtoModelSpec>,
+    ) -> Result<Response<ProtoModelInfo>, Status> {
+        let spec = request.into_inner();
+        let kind = match ProtoModelKind::from_i32(spec.kind) {
+            Some(ProtoModelKind::ModelYolo) => ModelKind::Yolo,
+            Some(ProtoModelKind::ModelRfDetr) => ModelKind::RfDetr,
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown model kind: {}",
+                    spec.kind
+                )))
+            }
+        };
+        // only files of the model dir, the paths come from the client
+        let model_dir = self.model_dir.as_deref();
+        let confined = |s: String| {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                confine(model_dir, &s).map(Some)
+            }
+        };
+        let mut spec = ModelSpec {
+            name: spec.name,
+            kind,
+            path: confined(spec.path)?,
+            config: confined(spec.config)?.map(PathBuf::from),
+        };
+        // the config file may name any model, a relative one is taken from the model dir too
+        spec.path = confined(spec.model()?)?;
+        Ok(Response::new(self.load(spec).await?))
+    }
+
+    async fn unload_model(
+        &self,
+        request: Request<UnloadModelRequest>,
+    ) -> Result<Response<UnloadModelResponse>, Status> {
+        self.unload(&request.into_inner().name)?;
+        Ok(Response::new(UnloadModelResponse {}))
+    }
+
+    async fn reload_model(
+        &self,
+        request: Request<ReloadModelRequest>,
+    ) -> Result<Response<ProtoModelInfo>, Status> {
+        Ok(Response::new(
+            self.reload(&request.into_inner().name).await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yolov8-rs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entries_ignore_server_env_vars() {
+        let dir = temp_dir("registry-env");
+        let config = dir.join("entry.toml");
+        std::fs::write(&config, "[model]\npath = \"from-config.onnx\"\n").unwrap();
+        std::env::set_var("YOLO_MODEL", "from-env.onnx");
+        std::env::set_var("YOLO_CONF", "0.9");
+        std::env::set_var("RFDETR_MODEL", "from-env.onnx");
+
+        let spec = |kind, path: Option<&str>, config: Option<&Path>| ModelSpec {
+            name: "entry".to_string(),
+            kind,
+            path: path.map(str::to_string),
+            config: config.map(Path::to_path_buf),
+        };
+        let yolo = |path, config| spec(ModelKind::Yolo, path, config).yolo_args();
+        let rf_detr = |path, config| spec(ModelKind::RfDetr, path, config).rf_detr_args();
+
+        // the path of the entry, else the one of its config file, never the env var
+        let args = yolo(Some("entry.onnx"), None).unwrap();
+        assert_eq!(args.model, "entry.onnx");
+        assert_eq!(args.conf, 0.3);
+        assert_eq!(yolo(None, Some(&config)).unwrap().model, "from-config.onnx");
+        assert!(yolo(None, None).is_err());
+        assert_eq!(
+            rf_detr(Some("entry.onnx"), None).unwrap().model,
+            "entry.onnx"
+        );
+        assert_eq!(
+            rf_detr(None, Some(&config)).unwrap().model,
+            "from-config.onnx"
+        );
+
+        std::env::remove_var("YOLO_MODEL");
+        std::env::remove_var("YOLO_CONF");
+        std::env::remove_var("RFDETR_MODEL");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_model_paths_stay_in_model_dir() {
+        let dir = temp_dir("registry-dir");
+        let models = dir.join("models");
+        std::fs::create_dir_all(&models).unwrap();
+        std::fs::write(models.join("a.onnx"), b"").unwrap();
+        std::fs::write(dir.join("outside.onnx"), b"").unwrap();
+
+        let inside = confine(Some(&models), "a.onnx").unwrap();
+        assert_eq!(
+            PathBuf::from(inside),
+            models.join("a.onnx").canonicalize().unwrap()
+        );
+        let absolute = models.join("a.onnx").to_string_lossy().into_owned();
+        assert!(confine(Some(&models), &absolute).is_ok());
+
+        let outside = dir.join("outside.onnx").to_string_lossy().into_owned();
+        assert!(matches!(
+            confine(Some(&models), &outside),
+            Err(VisionError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            confine(Some(&models), "../outside.onnx"),
+            Err(VisionError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            confine(Some(&models), "missing.onnx"),
+            Err(VisionError::NotFound(_))
+        ));
+        assert!(matches!(
+            confine(None, "a.onnx"),
+            Err(VisionError::Unsupported(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_model_configs_stay_in_model_dir() {
+        let dir = temp_dir("registry-config");
+        let models = dir.join("models");
+        std::fs::create_dir_all(&models).unwrap();
+        std::fs::write(dir.join("outside.onnx"), b"").unwrap();
+        let outside = dir
+            .join("outside.onnx")
+            .to_string_lossy()
+            .replace('\\', "/");
+        std::fs::write(
+            models.join("up.toml"),
+            "[model]\npath = \"../outside.onnx\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            models.join("absolute.toml"),
+            format!("[model]\npath = \"{}\"\n", outside),
+        )
+        .unwrap();
+        // relative to the model dir, not to the working directory of the server
+        std::fs::write(
+            models.join("missing.toml"),
+            "[model]\npath = \"missing.onnx\"\n",
+        )
+        .unwrap();
+
+        let registry = MyModelRegistry::new(Some(models));
+        for kind in [ProtoModelKind::ModelYolo, ProtoModelKind::ModelRfDetr] {
+            let load = |config: &str| {
+                registry.load_model(Request::new(ProtoModelSpec {
+                    name: "entry".to_string(),
+                    kind: kind as i32,
+                    path: String::new(),
+                    config: config.to_string(),
+                }))
+            };
+            let code = |r: Result<Response<ProtoModelInfo>, Status>| r.unwrap_err().code();
+            assert_eq!(code(load("up.toml").await), tonic::Code::InvalidArgument);
+            assert_eq!(
+                code(load("absolute.toml").await),
+                tonic::Code::InvalidArgument
+            );
+            assert_eq!(code(load("missing.toml").await), tonic::Code::NotFound);
+        }
+        assert!(registry.list().unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+use axum::body::Body;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::Request;
+use axum::middleware;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tonic::Status;
+use tracing::info_span;
+
+use rf_detr::rest::{read_images, ErrorResponse, JsonBox, MAX_BODY_BYTES};
+pub use rf_detr::rest::serve;
+
+use crate::mask::{EncodedMask, MaskFormat};
+use crate::metrics::METRICS;
+use crate::{LazyYoloService, MyModelRegistry, Point2, ProtoInferenceParams, VisionError, YOLOResult};
+
+/// Query parameters of a predict call, the fields of `InferenceParams` plus the mask format.
+/// Class lists are comma separated, e.g. `?conf=0.4&classes=0,2&mask_format=polygon`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PredictQuery {
+    pub conf: Option<f32>,
+    pub iou: Option<f32>,
+    pub kconf: Option<f32>,
+    pub max_det: Option<u32>,
+    pub classes: Option<String>,
+    pub exclude_classes: Option<String>,
+    // raw, rle, polygon or cropped; rle by default
+    pub mask_format: Option<String>,
+}
+
+impl PredictQuery {
+    fn params(&self) -> Result<Option<ProtoInferenceParams>, VisionError> {
+        let Self { conf, iou, kconf, max_det, classes, exclude_classes, .. } = self;
+        if conf.is_none()
+            && iou.is_none()
+            && kconf.is_none()
+            && max_det.is_none()
+            && classes.is_none()
+            && exclude_classes.is_none()
+        {
+            return Ok(None);
+        }
+        Ok(Some(ProtoInferenceParams {
+            conf: *conf,
+            iou: *iou,
+            kconf: *kconf,
+            max_det: *max_det,
+            classes: parse_classes("classes", classes.as_deref())?,
+            exclude_classes: parse_classes("exclude_classes", exclude_classes.as_deref())?,
+            class_conf: HashMap::new(),
+        }))
+    }
+
+    fn mask_format(&self) -> Result<MaskFormat, VisionError> {
+        match self.mask_format.as_deref() {
+            None => Ok(MaskFormat::Rle),
+            Some(format) => MaskFormat::from_str(format, true).map_err(|_| {
+                VisionError::InvalidArgument(format!("Unknown mask format: {}", format))
+            }),
+        }
+    }
+}
+
+fn parse_classes(name: &str, value: Option<&str>) -> Result<Vec<u32>, VisionError> {
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
+    value
+        .split(',')
+        .map(|id| {
+            id.trim().parse().map_err(|_| {
+                VisionError::InvalidArgument(format!("Invalid class id in {}: {:?}", name, id))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonResponse {
+    pub results: Vec<JsonResult>,
+}
+
+/// `YOLOResult` as JSON, empty fields are left out.
+#[derive(Debug, Default, Serialize)]
+pub struct JsonResult {
+    // top 5 classes of a classification model
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<JsonClass>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub boxes: Vec<JsonBox>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rotated_boxes: Vec<JsonRotatedBox>,
+    // one set per box, in box order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<Vec<JsonPoint>>,
+    // one mask per box, in box order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub masks: Vec<JsonMask>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonClass {
+    pub class_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRotatedBox {
+    pub cx: f32,
+    pub cy: f32,
+    pub width: f32,
+    pub height: f32,
+    // radians around the center
+    pub angle: f32,
+    pub class_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonPoint {
+    pub x: f32,
+    pub y: f32,
+    pub confidence: f32,
+}
+
+/// An encoded mask, `{"rle": {...}}`, `{"polygons": [...]}`, `{"cropped": {...}}` or
+/// `{"raw": {...}}`. Pixel data is base64.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonMask {
+    // pycocotools uncompressed RLE, `size` is [height, width]
+    Rle { size: [u32; 2], counts: Vec<u32> },
+    Polygons(Vec<Vec<[f32; 2]>>),
+    Cropped { x: u32, y: u32, width: u32, height: u32, data: String },
+    Raw { width: u32, height: u32, data: String },
+}
+
+impl JsonResult {
+    /// Converts a result, `class_name` maps a class id to its name.
+    pub fn new(
+        y: &YOLOResult,
+        format: MaskFormat,
+        class_name: impl Fn(usize) -> Option<String>,
+    ) -> Self {
+        let point = |p: &Point2| JsonPoint {
+            x: p.x(),
+            y: p.y(),
+            confidence: p.confidence(),
+        };
+        let (width, height) = y.mask_size().unwrap_or_default();
+        Self {
+            classes: y
+                .probs()
+                .map(|probs| probs.topk(5))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(class_id, confidence)| JsonClass {
+                    class_id,
+                    class_name: class_name(class_id),
+                    confidence,
+                })
+                .collect(),
+            boxes: y
+                .bboxes()
+                .into_iter()
+                .flatten()
+                .map(|b| JsonBox {
+                    x: b.xmin(),
+                    y: b.ymin(),
+                    width: b.width(),
+                    height: b.height(),
+                    class_id: b.id(),
+                    class_name: class_name(b.id()),
+                    confidence: b.confidence(),
+                })
+                .collect(),
+            rotated_boxes: y
+                .rotated_bboxes()
+                .into_iter()
+                .flatten()
+                .map(|b| JsonRotatedBox {
+                    cx: b.cx(),
+                    cy: b.cy(),
+                    width: b.width(),
+                    height: b.height(),
+                    angle: b.angle(),
+                    class_id: b.id(),
+                    class_name: class_name(b.id()),
+                    confidence: b.confidence(),
+                })
+                .collect(),
+            keypoints: y
+                .keypoints()
+                .into_iter()
+                .flatten()
+                .map(|points| points.iter().map(point).collect())
+                .collect(),
+            masks: y
+                .encode_masks(format)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mask| match mask {
+                    EncodedMask::Rle(rle) => JsonMask::Rle {
+                        size: [rle.height(), rle.width()],
+                        counts: rle.counts().clone(),
+                    },
+                    EncodedMask::Polygons(polygons) => JsonMask::Polygons(
+                        polygons
+                            .iter()
+                            .map(|polygon| polygon.iter().map(|p| [p.x(), p.y()]).collect())
+                            .collect(),
+                    ),
+                    EncodedMask::Cropped(crop) => JsonMask::Cropped {
+                        x: crop.x(),
+                        y: crop.y(),
+                        width: crop.width(),
+                        height: crop.height(),
+                        data: BASE64.encode(crop.data()),
+                    },
+                    EncodedMask::Raw(data) => JsonMask::Raw {
+                        width,
+                        height,
+                        data: BASE64.encode(data),
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Gateway {
+    // the `--model` model, `None` without one
+    yolo: Option<LazyYoloService>,
+    registry: Arc<MyModelRegistry>,
+}
+
+/// JSON counterparts of `YOLOService::ProcessImages` and `ModelRegistry::Predict`:
+/// `POST /v1/predict` and `POST /v1/models/{name}/predict`.
+pub fn router(yolo: Option<LazyYoloService>, registry: Arc<MyModelRegistry>) -> Router {
+    Router::new()
+        .route("/v1/predict", post(predict))
+        .route("/v1/models/:name/predict", post(predict_model))
+        .layer(middleware::from_fn(rf_detr::rest::trace))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(Gateway { yolo, registry })
+}
+
+async fn predict(
+    State(gateway): State<Gateway>,
+    query: Result<Query<PredictQuery>, QueryRejection>,
+    request: Request<Body>,
+) -> Result<Json<JsonResponse>, ErrorResponse> {
+    let Some(yolo) = &gateway.yolo else {
+        return Err(error(VisionError::NotFound(
+            "No --model is served, predict with /v1/models/{name}/predict".to_string(),
+        )));
+    };
+    let service = yolo.get().map_err(error)?;
+    let name = service.name();
+    let result = async {
+        let Query(query) = query.map_err(invalid_query)?;
+        let format = query.mask_format()?;
+        let params = query.params()?;
+        let xs = decode_images(name, request).await?;
+        let ys = service.predict(xs, None, None, params.as_ref()).await?;
+        let names = service.names();
+        let results = ys
+            .iter()
+            .map(|y| JsonResult::new(y, format, |id| names.get(id).cloned()))
+            .collect();
+        Ok::<_, VisionError>(JsonResponse { results })
+    }
+    .await
+    .map_err(Status::from);
+    METRICS.observe_request(name, "HttpPredict", &result);
+    Ok(Json(result.map_err(ErrorResponse)?))
+}
+
+async fn predict_model(
+    State(gateway): State<Gateway>,
+    Path(name): Path<String>,
+    query: Result<Query<PredictQuery>, QueryRejection>,
+    request: Request<Body>,
+) -> Result<Json<JsonResponse>, ErrorResponse> {
+    let model = gateway.registry.get(&name);
+    // unknown names are not labels, they would grow the metrics without bound
+    let label = if model.is_ok() { name.as_str() } else { "" };
+    let result = async {
+        let model = model?;
+        let Query(query) = query.map_err(invalid_query)?;
+        let format = query.mask_format()?;
+        let params = query.params()?;
+        let xs = decode_images(label, request).await?;
+        let ys = model.predict(xs, None, None, params).await?;
+        let results = ys
+            .iter()
+            .map(|y| JsonResult::new(y, format, |id| model.class_name(id)))
+            .collect();
+        Ok::<_, VisionError>(JsonResponse { results })
+    }
+    .await
+    .map_err(Status::from);
+    METRICS.observe_request(label, "HttpPredict", &result);
+    Ok(Json(result.map_err(ErrorResponse)?))
+}
+
+fn invalid_query(e: QueryRejection) -> VisionError {
+    VisionError::InvalidArgument(e.body_text())
+}
+
+fn error(e: VisionError) -> ErrorResponse {
+    ErrorResponse(e.into())
+}
+
+async fn decode_images(
+    model: &str,
+    request: Request<Body>,
+) -> Result<Vec<DynamicImage>, VisionError> {
+    let mut xs = Vec::new();
+    for image in read_images(request).await? {
+        let t_decode = Instant::now();
+        let x = info_span!("decode", bytes = image.len())
+            .in_scope(|| image::load_from_memory(&image))
+            .map_err(|e| VisionError::InvalidArgument(format!("Failed to decode image: {}", e)))?;
+        METRICS.observe_stage(model, "decode", t_decode.elapsed());
+        xs.push(x);
+    }
+    Ok(xs)
+}
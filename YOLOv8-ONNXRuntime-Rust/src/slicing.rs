@@ -0,0 +1,318 @@
+use crate::{apply_rotated_nms, Bbox, Nms, NmsItem, NmsStrategy, Point2, RotatedBbox, YOLOResult};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slicing {
+    // SAHI-style sliced inference settings
+    pub enabled: bool,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub overlap: f32,
+    pub full_image: bool,
+    pub merge: NmsStrategy,
+    pub merge_iou: f32,
+}
+
+impl Default for Slicing {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tile_width: 640,
+            tile_height: 640,
+            overlap: 0.2,
+            full_image: false,
+            merge: NmsStrategy::ClassAware,
+            merge_iou: 0.5,
+        }
+    }
+}
+
+/// A region of the full image: (x, y, width, height).
+pub type Tile = (u32, u32, u32, u32);
+
+impl Slicing {
+    /// Overlapping tiles covering a `width` x `height` image, row by row.
+    /// The last tile of a row or column is aligned to the image border.
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<Tile> {
+        let xs = Self::starts(width, self.tile_width, self.overlap);
+        let ys = Self::starts(height, self.tile_height, self.overlap);
+        let (tw, th) = (self.tile_width.min(width), self.tile_height.min(height));
+        ys.iter()
+            .flat_map(|&y| xs.iter().map(move |&x| (x, y, tw, th)))
+            .collect()
+    }
+
+    fn starts(size: u32, tile: u32, overlap: f32) -> Vec<u32> {
+        if tile == 0 || size <= tile {
+            return vec![0];
+        }
+        let step = ((tile as f32 * (1. - overlap.clamp(0., 0.95))) as u32).max(1);
+        let mut starts: Vec<u32> = (0..size - tile).step_by(step as usize).collect();
+        starts.push(size - tile);
+        starts
+    }
+
+    /// Maps per-tile results back to the full image and merges them.
+    pub fn merge(
+        &self,
+        ys: Vec<(YOLOResult, Tile)>,
+        size: (u32, u32),
+        nms: Nms,
+        conf: f32,
+    ) -> YOLOResult {
+        let merge = Nms {
+            strategy: self.merge,
+            iou: self.merge_iou,
+            ..nms
+        };
+        merge_results(ys, size, merge, conf)
+    }
+}
+
+/// Shifts each result by the offset of its tile, then merges all of them with `merge`.
+/// Masks stay cropped to their tile, as `mask_rects` of a `width` x `height` result.
+pub fn merge_results(
+    ys: Vec<(YOLOResult, Tile)>,
+    (width, height): (u32, u32),
+    merge: Nms,
+    conf: f32,
+) -> YOLOResult {
+    let mut pieces = Vec::new();
+    let mut rotated = Vec::new();
+    // the same for every result of a model
+    let mut mask_cutoff = None;
+    for (mut y, (x0, y0, tw, th)) in ys {
+        let (dx, dy) = (x0 as f32, y0 as f32);
+        let masks = y.take_full_masks();
+        let YOLOResult {
+            bboxes,
+            keypoints,
+            mask_cutoff: cutoff,
+            rotated_bboxes,
+            ..
+        } = y;
+        mask_cutoff = mask_cutoff.or(cutoff);
+
+        let mut keypoints = keypoints.map(|kpts| kpts.into_iter());
+        let mut masks = masks.map(|masks| masks.into_iter());
+        for bbox in bboxes.unwrap_or_default() {
+            let keypoints = keypoints.as_mut().and_then(|kpts| kpts.next()).map(|kpts| {
+                kpts.into_iter()
+                    .map(|p| {
+                        // missing keypoints stay at the default position
+                        if p == Point2::default() {
+                            p
+                        } else {
+                            Point2::new_with_conf(p.x() + dx, p.y() + dy, p.confidence())
+                        }
+                    })
+                    .collect()
+            });
+            let mask = masks
+                .as_mut()
+                .and_then(|masks| masks.next())
+                .map(|data| (data, (x0, y0, tw, th)));
+            pieces.push(Piece {
+                bbox: Bbox::new(
+                    bbox.xmin() + dx,
+                    bbox.ymin() + dy,
+                    bbox.width(),
+                    bbox.height(),
+                    bbox.id(),
+                    bbox.confidence(),
+                ),
+                keypoints,
+                mask,
+            });
+        }
+
+        for rbox in rotated_bboxes.unwrap_or_default() {
+            rotated.push(RotatedBbox::new(
+                rbox.cx() + dx,
+                rbox.cy() + dy,
+                rbox.width(),
+                rbox.height(),
+                rbox.angle(),
+                rbox.id(),
+                rbox.confidence(),
+            ));
+        }
+    }
+
+    // merge across tiles
+    merge.apply(&mut pieces, conf);
+    apply_rotated_nms(&mut rotated, &merge);
+
+    // full-size masks of large images take too much memory, they are pasted on demand
+    let mut y_bboxes = Vec::new();
+    let mut y_kpts = Vec::new();
+    let mut y_masks = Vec::new();
+    let mut mask_rects = Vec::new();
+    for piece in pieces {
+        if let Some(kpts) = piece.keypoints {
+            y_kpts.push(kpts);
+        }
+        if let Some((data, tile)) = piece.mask {
+            y_masks.push(data);
+            mask_rects.push(tile);
+        }
+        y_bboxes.push(piece.bbox);
+    }
+
+    YOLOResult {
+        probs: None,
+        bboxes: (!y_bboxes.is_empty()).then_some(y_bboxes),
+        keypoints: (!y_kpts.is_empty()).then_some(y_kpts),
+        mask_size: (!y_masks.is_empty()).then_some((width, height)),
+        mask_rects: (!mask_rects.is_empty()).then_some(mask_rects),
+        mask_cutoff,
+        masks: (!y_masks.is_empty()).then_some(y_masks),
+        rotated_bboxes: (!rotated.is_empty()).then_some(rotated),
+        tracks: None,
+        frame_index: None,
+        timestamp_ms: None,
+    }
+}
+
+struct Piece {
+    // a detection of one tile, mask kept in tile space until it survives the merge
+    bbox: Bbox,
+    keypoints: Option<Vec<Point2>>,
+    mask: Option<(Vec<u8>, Tile)>,
+}
+
+impl NmsItem for Piece {
+    fn confidence(&self) -> f32 {
+        self.bbox.confidence()
+    }
+
+    fn set_confidence(&mut self, confidence: f32) {
+        self.bbox.set_confidence(confidence);
+    }
+
+    fn class_id(&self) -> i64 {
+        NmsItem::class_id(&self.bbox)
+    }
+
+    fn corners(&self) -> [f32; 4] {
+        self.bbox.corners()
+    }
+
+    fn set_corners(&mut self, corners: [f32; 4]) {
+        self.bbox.set_corners(corners);
+    }
+
+    fn iou(&self, other: &Self) -> f32 {
+        self.bbox.iou(&other.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodedMask, MaskFormat};
+
+    #[test]
+    fn tiles_overlap_and_end_at_the_border() {
+        let slicing = Slicing::default();
+        // steps of 640 * 0.8 = 512, the last tile of a row or column against the border
+        let tiles = slicing.tiles(1500, 700);
+        let expected: Vec<Tile> = [0, 60]
+            .into_iter()
+            .flat_map(|y| [0, 512, 860].map(|x| (x, y, 640, 640)))
+            .collect();
+        assert_eq!(tiles, expected);
+        // smaller images are a single tile
+        assert_eq!(slicing.tiles(300, 200), vec![(0, 0, 300, 200)]);
+        assert_eq!(slicing.tiles(640, 640), vec![(0, 0, 640, 640)]);
+
+        let slicing = Slicing {
+            tile_width: 100,
+            tile_height: 50,
+            overlap: 0.5,
+            ..Slicing::default()
+        };
+        let tiles = slicing.tiles(230, 50);
+        assert_eq!(
+            tiles,
+            vec![
+                (0, 0, 100, 50),
+                (50, 0, 100, 50),
+                (100, 0, 100, 50),
+                (130, 0, 100, 50)
+            ]
+        );
+    }
+
+    // a `size` x `size` tile result with a box of `rect` and its mask
+    fn tile_result(size: u32, rect: (u32, u32, u32, u32), confidence: f32) -> YOLOResult {
+        let (x, y, w, h) = rect;
+        let mask = (0..size * size)
+            .map(|i| {
+                let (px, py) = (i % size, i / size);
+                if (x..x + w).contains(&px) && (y..y + h).contains(&py) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let bbox = Bbox::new(x as f32, y as f32, w as f32, h as f32, 0, confidence);
+        let mut result = YOLOResult::new(
+            None,
+            Some(vec![bbox]),
+            Some(vec![vec![
+                Point2::new_with_conf(x as f32 + 1., y as f32 + 1., 0.9),
+                Point2::default(),
+            ]]),
+            Some(vec![mask]),
+        );
+        result.mask_size = Some((size, size));
+        result
+    }
+
+    #[test]
+    fn merge_maps_tiles_back_to_the_image() {
+        let slicing = Slicing::default();
+        let ys = vec![
+            // the same object, seen by both tiles
+            (tile_result(100, (85, 10, 10, 10), 0.9), (0, 0, 100, 100)),
+            (tile_result(100, (5, 10, 10, 10), 0.8), (80, 0, 100, 100)),
+            // an object of the second tile only
+            (tile_result(100, (50, 60, 20, 10), 0.7), (80, 0, 100, 100)),
+        ];
+        let y = slicing.merge(ys, (180, 100), Nms::default(), 0.25);
+
+        let bboxes = y.bboxes().unwrap();
+        assert_eq!(bboxes.len(), 2);
+        assert_eq!(bboxes[0], Bbox::new(85., 10., 10., 10., 0, 0.9));
+        assert_eq!(bboxes[1], Bbox::new(130., 60., 20., 10., 0, 0.7));
+        let keypoints = y.keypoints().unwrap();
+        assert_eq!(keypoints[1][0], Point2::new_with_conf(131., 61., 0.9));
+        // missing keypoints are not shifted
+        assert_eq!(keypoints[1][1], Point2::default());
+
+        // masks stay the size of their tile
+        assert_eq!(y.mask_size(), Some((180, 100)));
+        assert_eq!(
+            y.mask_rects,
+            Some(vec![(0, 0, 100, 100), (80, 0, 100, 100)])
+        );
+        assert!(y
+            .masks()
+            .unwrap()
+            .iter()
+            .all(|mask| mask.len() == 100 * 100));
+        let full = y.full_mask(1).unwrap();
+        assert_eq!(full.len(), 180 * 100);
+        for (i, &v) in full.iter().enumerate() {
+            let (x, y) = (i % 180, i / 180);
+            let inside = (130..150).contains(&x) && (60..70).contains(&y);
+            assert_eq!(v, if inside { 255 } else { 0 }, "({}, {})", x, y);
+        }
+        let Some(EncodedMask::Rle(rle)) = y.encode_masks(MaskFormat::Rle).map(|mut m| m.remove(1))
+        else {
+            panic!("no RLE");
+        };
+        assert_eq!((rle.width(), rle.height(), rle.area()), (180, 100, 200));
+    }
+}
@@ -0,0 +1,754 @@
+// the Kalman and assignment maths reads best with explicit indices
+#![allow(clippy::needless_range_loop)]
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{Bbox, Point2};
+
+/// Keeps object identities stable across the frames of one video stream.
+pub trait Tracker: Send {
+    /// Feeds the detections of the next frame, returns the tracks confirmed on it.
+    fn update(&mut self, detections: &[Bbox]) -> Vec<Track>;
+
+    /// Drops every track, e.g. on a scene cut. Ids start over from 1, as in a new tracker.
+    fn reset(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TrackerKind {
+    // multi-object trackers
+    Sort,
+    ByteTrack,
+}
+
+impl TrackerKind {
+    pub fn build(&self) -> Box<dyn Tracker> {
+        match self {
+            TrackerKind::Sort => Box::new(Sort::default()),
+            TrackerKind::ByteTrack => Box::new(ByteTrack::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Track {
+    // an object followed across frames
+    id: u64,
+    bbox: Bbox,
+    age: u32,
+    trajectory: Vec<Point2>,
+}
+
+impl Track {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn bbox(&self) -> &Bbox {
+        &self.bbox
+    }
+
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+
+    pub fn trajectory(&self) -> &Vec<Point2> {
+        &self.trajectory
+    }
+}
+
+const STD_WEIGHT_POSITION: f32 = 1. / 20.;
+const STD_WEIGHT_VELOCITY: f32 = 1. / 160.;
+
+/// Constant-velocity Kalman filter over `[cx, cy, w, h, vcx, vcy, vw, vh]`.
+/// Noise scales with the box size, as in ByteTrack.
+#[derive(Debug, Clone)]
+struct KalmanBoxFilter {
+    mean: [f32; 8],
+    covariance: [[f32; 8]; 8],
+}
+
+impl KalmanBoxFilter {
+    fn new(bbox: &Bbox) -> Self {
+        let (w, h) = (bbox.width(), bbox.height());
+        let mut mean = [0.; 8];
+        mean[..4].copy_from_slice(&Self::measurement(bbox));
+        let std = [
+            2. * STD_WEIGHT_POSITION * w,
+            2. * STD_WEIGHT_POSITION * h,
+            2. * STD_WEIGHT_POSITION * w,
+            2. * STD_WEIGHT_POSITION * h,
+            10. * STD_WEIGHT_VELOCITY * w,
+            10. * STD_WEIGHT_VELOCITY * h,
+            10. * STD_WEIGHT_VELOCITY * w,
+            10. * STD_WEIGHT_VELOCITY * h,
+        ];
+        let mut covariance = [[0.; 8]; 8];
+        for i in 0..8 {
+            covariance[i][i] = std[i] * std[i];
+        }
+        Self { mean, covariance }
+    }
+
+    fn measurement(bbox: &Bbox) -> [f32; 4] {
+        let c = bbox.cxcy();
+        [c.x(), c.y(), bbox.width(), bbox.height()]
+    }
+
+    fn predict(&mut self) {
+        let (w, h) = (self.mean[2], self.mean[3]);
+        let std = [
+            STD_WEIGHT_POSITION * w,
+            STD_WEIGHT_POSITION * h,
+            STD_WEIGHT_POSITION * w,
+            STD_WEIGHT_POSITION * h,
+            STD_WEIGHT_VELOCITY * w,
+            STD_WEIGHT_VELOCITY * h,
+            STD_WEIGHT_VELOCITY * w,
+            STD_WEIGHT_VELOCITY * h,
+        ];
+
+        // x = F x
+        for i in 0..4 {
+            self.mean[i] += self.mean[i + 4];
+        }
+
+        // P = F P F^T + Q, with F = [[I, I], [0, I]]
+        let p = self.covariance;
+        let mut fp = p;
+        for i in 0..4 {
+            for j in 0..8 {
+                fp[i][j] += p[i + 4][j];
+            }
+        }
+        let mut fpf = fp;
+        for i in 0..8 {
+            for j in 0..4 {
+                fpf[i][j] += fp[i][j + 4];
+            }
+        }
+        for i in 0..8 {
+            fpf[i][i] += std[i] * std[i];
+        }
+        self.covariance = fpf;
+    }
+
+    fn update(&mut self, bbox: &Bbox) {
+        let z = Self::measurement(bbox);
+        let (w, h) = (self.mean[2], self.mean[3]);
+        let std = [
+            STD_WEIGHT_POSITION * w,
+            STD_WEIGHT_POSITION * h,
+            STD_WEIGHT_POSITION * w,
+            STD_WEIGHT_POSITION * h,
+        ];
+
+        // S = H P H^T + R, H selects the first 4 states
+        let mut s = [[0.; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                s[i][j] = self.covariance[i][j];
+            }
+            s[i][i] += std[i] * std[i];
+        }
+        let Some(s_inv) = invert4(&s) else {
+            return;
+        };
+
+        // K = P H^T S^-1
+        let mut k = [[0.; 4]; 8];
+        for i in 0..8 {
+            for j in 0..4 {
+                k[i][j] = (0..4).map(|l| self.covariance[i][l] * s_inv[l][j]).sum();
+            }
+        }
+
+        // x = x + K (z - H x)
+        let innovation: Vec<f32> = (0..4).map(|i| z[i] - self.mean[i]).collect();
+        for i in 0..8 {
+            self.mean[i] += (0..4).map(|j| k[i][j] * innovation[j]).sum::<f32>();
+        }
+
+        // P = P - K H P
+        let p = self.covariance;
+        for i in 0..8 {
+            for j in 0..8 {
+                self.covariance[i][j] -= (0..4).map(|l| k[i][l] * p[l][j]).sum::<f32>();
+            }
+        }
+    }
+
+    fn bbox(&self) -> Bbox {
+        let [cx, cy, w, h, ..] = self.mean;
+        Bbox::new_from_xywh(cx - w / 2., cy - h / 2., w, h)
+    }
+}
+
+fn invert4(m: &[[f32; 4]; 4]) -> Option<[[f32; 4]; 4]> {
+    // Gauss-Jordan elimination with partial pivoting
+    let mut a = *m;
+    let mut inv = [[0.; 4]; 4];
+    for (i, row) in inv.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&x, &y| a[x][col].abs().total_cmp(&a[y][col].abs()))?;
+        if a[pivot][col].abs() < f32::EPSILON {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let d = a[col][col];
+        for j in 0..4 {
+            a[col][j] /= d;
+            inv[col][j] /= d;
+        }
+        for row in 0..4 {
+            if row != col {
+                let f = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+/// Minimum-cost assignment (Hungarian algorithm). Pairs costing more than `max_cost`
+/// are rejected. Returns matches `(row, col)`, unmatched rows and unmatched columns.
+fn linear_assignment(
+    cost: &[Vec<f32>],
+    n_cols: usize,
+    max_cost: f32,
+) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+    let n_rows = cost.len();
+    if n_rows == 0 || n_cols == 0 {
+        return (vec![], (0..n_rows).collect(), (0..n_cols).collect());
+    }
+
+    // the algorithm needs rows <= cols, transpose otherwise
+    let transposed = n_rows > n_cols;
+    let (n, m) = if transposed { (n_cols, n_rows) } else { (n_rows, n_cols) };
+    let at = |i: usize, j: usize| -> f64 {
+        if transposed {
+            cost[j][i] as f64
+        } else {
+            cost[i][j] as f64
+        }
+    };
+
+    // potentials u, v and column -> row matching p (1-indexed, 0 is a sentinel)
+    let mut u = vec![0f64; n + 1];
+    let mut v = vec![0f64; m + 1];
+    let mut p = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = at(i0 - 1, j - 1) - u[i0] - v[j];
+                    if cur < minv[j] {
+                        minv[j] = cur;
+                        way[j] = j0;
+                    }
+                    if minv[j] < delta {
+                        delta = minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    let mut row_matched = vec![false; n_rows];
+    let mut col_matched = vec![false; n_cols];
+    for j in 1..=m {
+        if p[j] == 0 {
+            continue;
+        }
+        let (row, col) = if transposed { (j - 1, p[j] - 1) } else { (p[j] - 1, j - 1) };
+        if cost[row][col] <= max_cost {
+            matches.push((row, col));
+            row_matched[row] = true;
+            col_matched[col] = true;
+        }
+    }
+    let unmatched_rows = (0..n_rows).filter(|&i| !row_matched[i]).collect();
+    let unmatched_cols = (0..n_cols).filter(|&j| !col_matched[j]).collect();
+    (matches, unmatched_rows, unmatched_cols)
+}
+
+fn iou_cost(tracks: &[&TrackState], detections: &[&Bbox], class_aware: bool) -> Vec<Vec<f32>> {
+    tracks
+        .iter()
+        .map(|track| {
+            let predicted = track.filter.bbox();
+            detections
+                .iter()
+                .map(|det| {
+                    if class_aware && det.id() != track.last.id() {
+                        1.
+                    } else {
+                        1. - predicted.iou(det)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    // lifecycle of a track
+    New,
+    Tracked,
+    Lost,
+}
+
+#[derive(Debug, Clone)]
+struct TrackState {
+    id: u64,
+    filter: KalmanBoxFilter,
+    last: Bbox,
+    status: Status,
+    age: u32,
+    hits: u32,
+    time_since_update: u32,
+    trajectory: Vec<Point2>,
+}
+
+impl TrackState {
+    fn new(id: u64, detection: &Bbox) -> Self {
+        Self {
+            id,
+            filter: KalmanBoxFilter::new(detection),
+            last: detection.clone(),
+            status: Status::New,
+            age: 0,
+            hits: 1,
+            time_since_update: 0,
+            trajectory: vec![detection.cxcy()],
+        }
+    }
+
+    fn predict(&mut self) {
+        self.filter.predict();
+        self.age += 1;
+        self.time_since_update += 1;
+    }
+
+    fn update(&mut self, detection: &Bbox, max_trajectory: usize) {
+        self.filter.update(detection);
+        self.last = detection.clone();
+        self.hits += 1;
+        self.time_since_update = 0;
+        self.trajectory.push(self.filter.bbox().cxcy());
+        if self.trajectory.len() > max_trajectory {
+            self.trajectory.remove(0);
+        }
+    }
+
+    fn output(&self) -> Track {
+        let estimate = self.filter.bbox();
+        Track {
+            id: self.id,
+            bbox: Bbox::new(
+                estimate.xmin(),
+                estimate.ymin(),
+                estimate.width(),
+                estimate.height(),
+                self.last.id(),
+                self.last.confidence(),
+            ),
+            age: self.age,
+            trajectory: self.trajectory.clone(),
+        }
+    }
+}
+
+/// SORT: Kalman prediction plus Hungarian matching on IoU.
+#[derive(Debug, Clone)]
+pub struct Sort {
+    pub max_age: u32,
+    pub min_hits: u32,
+    pub iou_threshold: f32,
+    pub class_aware: bool,
+    pub max_trajectory: usize,
+    tracks: Vec<TrackState>,
+    frame_count: u32,
+    next_id: u64,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Self::new(30, 3, 0.3)
+    }
+}
+
+impl Sort {
+    pub fn new(max_age: u32, min_hits: u32, iou_threshold: f32) -> Self {
+        Self {
+            max_age,
+            min_hits,
+            iou_threshold,
+            class_aware: true,
+            max_trajectory: 30,
+            tracks: Vec::new(),
+            frame_count: 0,
+            next_id: 1,
+        }
+    }
+}
+
+impl Tracker for Sort {
+    fn update(&mut self, detections: &[Bbox]) -> Vec<Track> {
+        self.frame_count += 1;
+        for track in self.tracks.iter_mut() {
+            track.predict();
+        }
+
+        let dets: Vec<&Bbox> = detections.iter().collect();
+        let tracks: Vec<&TrackState> = self.tracks.iter().collect();
+        let cost = iou_cost(&tracks, &dets, self.class_aware);
+        let (matches, _, unmatched_dets) =
+            linear_assignment(&cost, dets.len(), 1. - self.iou_threshold);
+
+        for (t, d) in matches {
+            self.tracks[t].update(&detections[d], self.max_trajectory);
+        }
+        for d in unmatched_dets {
+            self.tracks.push(TrackState::new(self.next_id, &detections[d]));
+            self.next_id += 1;
+        }
+        self.tracks.retain(|t| t.time_since_update <= self.max_age);
+
+        self.tracks
+            .iter()
+            .filter(|t| {
+                t.time_since_update == 0
+                    && (t.hits >= self.min_hits || self.frame_count <= self.min_hits)
+            })
+            .map(TrackState::output)
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.tracks.clear();
+        self.frame_count = 0;
+        self.next_id = 1;
+    }
+}
+
+/// ByteTrack: associates high-score detections first, then recovers occluded
+/// tracks with the low-score ones that plain SORT would throw away.
+#[derive(Debug, Clone)]
+pub struct ByteTrack {
+    pub track_thresh: f32,
+    pub low_thresh: f32,
+    pub new_track_thresh: f32,
+    pub match_thresh: f32,
+    pub track_buffer: u32,
+    pub class_aware: bool,
+    pub max_trajectory: usize,
+    tracks: Vec<TrackState>,
+    frame_count: u32,
+    next_id: u64,
+}
+
+impl Default for ByteTrack {
+    fn default() -> Self {
+        Self::new(0.5, 0.1, 0.6, 0.8, 30)
+    }
+}
+
+impl ByteTrack {
+    pub fn new(
+        track_thresh: f32,
+        low_thresh: f32,
+        new_track_thresh: f32,
+        match_thresh: f32,
+        track_buffer: u32,
+    ) -> Self {
+        Self {
+            track_thresh,
+            low_thresh,
+            new_track_thresh,
+            match_thresh,
+            track_buffer,
+            class_aware: true,
+            max_trajectory: 30,
+            tracks: Vec::new(),
+            frame_count: 0,
+            next_id: 1,
+        }
+    }
+
+    fn associate(
+        &mut self,
+        track_ids: &[usize],
+        detections: &[&Bbox],
+        max_cost: f32,
+    ) -> (Vec<usize>, Vec<usize>) {
+        // match tracks (by index into self.tracks) with detections, update the matched ones
+        let tracks: Vec<&TrackState> = track_ids.iter().map(|&i| &self.tracks[i]).collect();
+        let cost = iou_cost(&tracks, detections, self.class_aware);
+        let (matches, unmatched_tracks, unmatched_dets) =
+            linear_assignment(&cost, detections.len(), max_cost);
+        for (t, d) in matches {
+            let track = &mut self.tracks[track_ids[t]];
+            track.update(detections[d], self.max_trajectory);
+            track.status = Status::Tracked;
+        }
+        (
+            unmatched_tracks.into_iter().map(|t| track_ids[t]).collect(),
+            unmatched_dets,
+        )
+    }
+}
+
+impl Tracker for ByteTrack {
+    fn update(&mut self, detections: &[Bbox]) -> Vec<Track> {
+        self.frame_count += 1;
+        for track in self.tracks.iter_mut() {
+            track.predict();
+        }
+
+        let high: Vec<&Bbox> = detections
+            .iter()
+            .filter(|d| d.confidence() >= self.track_thresh)
+            .collect();
+        let low: Vec<&Bbox> = detections
+            .iter()
+            .filter(|d| d.confidence() >= self.low_thresh && d.confidence() < self.track_thresh)
+            .collect();
+
+        // 1. confirmed and lost tracks vs high-score detections
+        let pool: Vec<usize> = (0..self.tracks.len())
+            .filter(|&i| self.tracks[i].status != Status::New)
+            .collect();
+        let (unmatched_tracks, unmatched_high) = self.associate(&pool, &high, self.match_thresh);
+
+        // 2. remaining tracked (not lost) tracks vs low-score detections
+        let remaining: Vec<usize> = unmatched_tracks
+            .into_iter()
+            .filter(|&i| self.tracks[i].status == Status::Tracked)
+            .collect();
+        let (unmatched_tracks, _) = self.associate(&remaining, &low, 0.5);
+        for i in unmatched_tracks {
+            self.tracks[i].status = Status::Lost;
+        }
+
+        // 3. unconfirmed tracks vs the high-score detections left over
+        let high: Vec<&Bbox> = unmatched_high.into_iter().map(|d| high[d]).collect();
+        let unconfirmed: Vec<usize> = (0..self.tracks.len())
+            .filter(|&i| self.tracks[i].status == Status::New && self.tracks[i].time_since_update > 0)
+            .collect();
+        let (unmatched_unconfirmed, unmatched_high) = self.associate(&unconfirmed, &high, 0.7);
+
+        // 4. start tracks from confident unmatched detections
+        for d in unmatched_high {
+            if high[d].confidence() >= self.new_track_thresh {
+                let mut track = TrackState::new(self.next_id, high[d]);
+                if self.frame_count == 1 {
+                    track.status = Status::Tracked;
+                }
+                self.tracks.push(track);
+                self.next_id += 1;
+            }
+        }
+
+        // 5. drop unconfirmed tracks that missed and lost tracks past the buffer
+        let mut index = 0;
+        self.tracks.retain(|t| {
+            let expired = t.status == Status::Lost && t.time_since_update > self.track_buffer;
+            let keep = !(unmatched_unconfirmed.contains(&index) || expired);
+            index += 1;
+            keep
+        });
+
+        self.tracks
+            .iter()
+            .filter(|t| t.status == Status::Tracked && t.time_since_update == 0)
+            .map(TrackState::output)
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.tracks.clear();
+        self.frame_count = 0;
+        self.next_id = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(x: f32, y: f32, conf: f32) -> Bbox {
+        Bbox::new(x, y, 50., 50., 0, conf)
+    }
+
+    fn ids(tracks: &[Track]) -> Vec<u64> {
+        let mut ids: Vec<u64> = tracks.iter().map(Track::id).collect();
+        ids.sort();
+        ids
+    }
+
+    // lowest total cost over every way of pairing min(rows, cols) rows and columns
+    fn brute_force(cost: &[Vec<f32>], row: usize, used: &mut Vec<bool>, left: usize) -> f32 {
+        if left == 0 {
+            return 0.;
+        }
+        if row == cost.len() {
+            return f32::INFINITY;
+        }
+        // either leave this row out, if enough rows remain, or pair it with a free column
+        let mut best = if cost.len() - row > left {
+            brute_force(cost, row + 1, used, left)
+        } else {
+            f32::INFINITY
+        };
+        for col in 0..used.len() {
+            if !used[col] {
+                used[col] = true;
+                best = best.min(cost[row][col] + brute_force(cost, row + 1, used, left - 1));
+                used[col] = false;
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn hungarian_matches_brute_force() {
+        let mut seed = 12345u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as f32 / 65536.
+        };
+        for rows in 1..=4 {
+            for cols in 1..=4 {
+                for _ in 0..20 {
+                    let cost: Vec<Vec<f32>> = (0..rows)
+                        .map(|_| (0..cols).map(|_| random()).collect())
+                        .collect();
+                    let (matches, unmatched_rows, unmatched_cols) =
+                        linear_assignment(&cost, cols, f32::INFINITY);
+                    assert_eq!(matches.len(), rows.min(cols));
+                    assert_eq!(unmatched_rows.len(), rows - matches.len());
+                    assert_eq!(unmatched_cols.len(), cols - matches.len());
+
+                    let total: f32 = matches.iter().map(|&(r, c)| cost[r][c]).sum();
+                    let best = brute_force(&cost, 0, &mut vec![false; cols], rows.min(cols));
+                    assert!((total - best).abs() < 1e-5, "{:?}: {} != {}", cost, total, best);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hungarian_rejects_pairs_above_max_cost() {
+        let cost = vec![vec![0.1, 0.9], vec![0.9, 0.8]];
+        let (matches, unmatched_rows, unmatched_cols) = linear_assignment(&cost, 2, 0.5);
+        assert_eq!(matches, vec![(0, 0)]);
+        assert_eq!(unmatched_rows, vec![1]);
+        assert_eq!(unmatched_cols, vec![1]);
+    }
+
+    #[test]
+    fn track_survives_max_age_misses_then_drops() {
+        let mut sort = Sort::new(2, 1, 0.3);
+        let id = sort.update(&[bbox(100., 100., 0.9)])[0].id();
+
+        // missed for max_age frames, the same object keeps its id
+        for _ in 0..2 {
+            assert!(sort.update(&[]).is_empty());
+        }
+        assert_eq!(ids(&sort.update(&[bbox(100., 100., 0.9)])), vec![id]);
+
+        // one miss more than max_age, the track is gone and the object gets a new id
+        for _ in 0..3 {
+            sort.update(&[]);
+        }
+        assert!(sort.tracks.is_empty());
+        assert_eq!(ids(&sort.update(&[bbox(100., 100., 0.9)])), vec![id + 1]);
+    }
+
+    #[test]
+    fn ids_stay_stable_across_crossing_boxes() {
+        for mut tracker in [TrackerKind::Sort.build(), TrackerKind::ByteTrack.build()] {
+            let mut first: Option<(u64, u64)> = None;
+            for frame in 0..=20 {
+                let step = frame as f32 * 10.;
+                // one box moves right, the other left, overlapping half way
+                let tracks = tracker.update(&[bbox(step, 0., 0.9), bbox(200. - step, 30., 0.9)]);
+                assert_eq!(tracks.len(), 2);
+                let right = tracks.iter().find(|t| t.bbox().ymin() < 15.).unwrap();
+                let left = tracks.iter().find(|t| t.bbox().ymin() >= 15.).unwrap();
+                assert_ne!(right.id(), left.id());
+                match first {
+                    None => first = Some((right.id(), left.id())),
+                    Some(ids) => assert_eq!(ids, (right.id(), left.id()), "frame {}", frame),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bytetrack_recovers_low_score_detections() {
+        let mut tracker = ByteTrack::default();
+        let id = tracker.update(&[bbox(100., 100., 0.9)])[0].id();
+
+        // occluded: the score drops under track_thresh but stays above low_thresh
+        for frame in 0..5 {
+            let x = 100. + frame as f32;
+            let tracks = tracker.update(&[bbox(x, 100., 0.3)]);
+            assert_eq!(ids(&tracks), vec![id]);
+        }
+        assert_eq!(ids(&tracker.update(&[bbox(105., 100., 0.9)])), vec![id]);
+
+        // a low-score detection alone never starts a track
+        assert!(tracker.update(&[bbox(100., 100., 0.9), bbox(400., 400., 0.3)]).len() == 1);
+        assert_eq!(tracker.tracks.len(), 1);
+    }
+
+    #[test]
+    fn reset_starts_ids_over() {
+        for mut tracker in [TrackerKind::Sort.build(), TrackerKind::ByteTrack.build()] {
+            tracker.update(&[bbox(0., 0., 0.9), bbox(200., 200., 0.9)]);
+            tracker.reset();
+            assert_eq!(ids(&tracker.update(&[bbox(0., 0., 0.9)])), vec![1]);
+        }
+    }
+}
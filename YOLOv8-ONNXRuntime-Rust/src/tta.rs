@@ -0,0 +1,354 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage};
+
+use crate::slicing::merge_results;
+use crate::{Bbox, Nms, NmsStrategy, Point2, RotatedBbox, VisionError, YOLOResult};
+
+/// COCO-pose keypoint order after a horizontal flip, left and right parts swapped.
+pub const COCO_FLIP_INDEX: [usize; 17] = [0, 2, 1, 4, 3, 6, 5, 8, 7, 10, 9, 12, 11, 14, 13, 16, 15];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tta {
+    // test-time augmentation settings
+    pub enabled: bool,
+    pub flip: bool,
+    pub scales: Vec<f32>,
+    pub merge: NmsStrategy,
+    pub merge_iou: f32,
+}
+
+impl Default for Tta {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flip: true,
+            scales: vec![1.0, 0.83, 0.67],
+            merge: NmsStrategy::Wbf,
+            merge_iou: 0.55,
+        }
+    }
+}
+
+impl Tta {
+    /// Augmented copies run per image: every scale, each one also flipped when enabled.
+    pub fn augments(&self) -> Vec<Augment> {
+        let scales = if self.scales.is_empty() {
+            vec![1.0]
+        } else {
+            self.scales.clone()
+        };
+        let flips: &[bool] = if self.flip { &[false, true] } else { &[false] };
+        scales
+            .iter()
+            .flat_map(|&scale| flips.iter().map(move |&flip| Augment { scale, flip }))
+            .collect()
+    }
+
+    /// Fuses results already mapped back to the `width` x `height` input.
+    pub fn merge(&self, ys: Vec<YOLOResult>, (width, height): (u32, u32), nms: Nms, conf: f32) -> YOLOResult {
+        let merge = Nms {
+            strategy: self.merge,
+            iou: self.merge_iou,
+            ..nms
+        };
+        let ys = ys.into_iter().map(|y| (y, (0, 0, width, height))).collect();
+        merge_results(ys, (width, height), merge, conf)
+    }
+
+    /// Flipped copies swap left and right keypoints, which is only known for the COCO-pose layout.
+    pub fn check_keypoints(&self, nk: usize) -> Result<(), VisionError> {
+        if self.enabled && self.flip && nk != COCO_FLIP_INDEX.len() {
+            return Err(VisionError::InvalidArgument(format!(
+                "TTA flips need the {}-point COCO keypoint layout, got {} keypoints, use --tta-no-flip",
+                COCO_FLIP_INDEX.len(),
+                nk
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Augment {
+    // a resized and optionally mirrored copy of the input
+    pub scale: f32,
+    pub flip: bool,
+}
+
+impl Augment {
+    /// Size of the copy of a `width` x `height` input.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            ((width as f32 * self.scale).round() as u32).max(1),
+            ((height as f32 * self.scale).round() as u32).max(1),
+        )
+    }
+
+    pub fn apply(&self, x: &DynamicImage) -> DynamicImage {
+        let (w, h) = self.size(x.width(), x.height());
+        let x = if (w, h) == (x.width(), x.height()) {
+            x.clone()
+        } else {
+            x.resize_exact(w, h, FilterType::Triangle)
+        };
+        if self.flip {
+            x.fliph()
+        } else {
+            x
+        }
+    }
+
+    /// Maps a result of the copy back to the `width` x `height` input.
+    pub fn invert(&self, mut y: YOLOResult, (width, height): (u32, u32)) -> YOLOResult {
+        let (w, h) = self.size(width, height);
+        let (sx, sy) = (width as f32 / w as f32, height as f32 / h as f32);
+        let mirror = |x: f32| if self.flip { w as f32 - x } else { x };
+        let masks = y.take_full_masks();
+
+        let bboxes = y.bboxes.map(|bboxes| {
+            bboxes
+                .into_iter()
+                .map(|b| {
+                    let xmin = if self.flip { w as f32 - b.xmax() } else { b.xmin() };
+                    Bbox::new(
+                        xmin * sx,
+                        b.ymin() * sy,
+                        b.width() * sx,
+                        b.height() * sy,
+                        b.id(),
+                        b.confidence(),
+                    )
+                })
+                .collect()
+        });
+
+        let keypoints = y.keypoints.map(|keypoints| {
+            keypoints
+                .into_iter()
+                .map(|kpts| {
+                    let kpts = if self.flip { flip_keypoints(kpts) } else { kpts };
+                    kpts.into_iter()
+                        .map(|p| {
+                            // missing keypoints stay at the default position
+                            if p == Point2::default() {
+                                p
+                            } else {
+                                Point2::new_with_conf(mirror(p.x()) * sx, p.y() * sy, p.confidence())
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        });
+
+        let rotated_bboxes = y.rotated_bboxes.map(|rboxes| {
+            rboxes
+                .into_iter()
+                .map(|r| {
+                    // mirroring reverses the direction of rotation
+                    let angle = if self.flip { -r.angle() } else { r.angle() };
+                    RotatedBbox::new(
+                        mirror(r.cx()) * sx,
+                        r.cy() * sy,
+                        r.width() * sx,
+                        r.height() * sy,
+                        angle,
+                        r.id(),
+                        r.confidence(),
+                    )
+                })
+                .collect()
+        });
+
+        let (mw, mh) = y.mask_size.unwrap_or((w, h));
+        let masks: Option<Vec<Vec<u8>>> = masks.map(|masks| {
+            masks
+                .into_iter()
+                .filter_map(|mask| {
+                    let mut mask = GrayImage::from_raw(mw, mh, mask)?;
+                    if self.flip {
+                        imageops::flip_horizontal_in_place(&mut mask);
+                    }
+                    if (mw, mh) != (width, height) {
+                        mask = imageops::resize(&mask, width, height, FilterType::Triangle);
+                    }
+                    Some(mask.into_raw())
+                })
+                .collect()
+        });
+
+        YOLOResult {
+            probs: y.probs,
+            bboxes,
+            keypoints,
+            mask_size: masks.is_some().then_some((width, height)),
+            mask_rects: None,
+            mask_cutoff: y.mask_cutoff,
+            masks,
+            rotated_bboxes,
+            tracks: None,
+            frame_index: None,
+            timestamp_ms: None,
+        }
+    }
+}
+
+fn flip_keypoints(kpts: Vec<Point2>) -> Vec<Point2> {
+    // other layouts are rejected by `Tta::check_keypoints`, left as they are here
+    if kpts.len() != COCO_FLIP_INDEX.len() {
+        return kpts;
+    }
+    COCO_FLIP_INDEX.iter().map(|&i| kpts[i].clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn flipped_and_scaled_results_map_back() {
+        // a 100x60 input seen as a flipped half-size copy, x' = 50 - x / 2 and y' = y / 2
+        let augment = Augment {
+            scale: 0.5,
+            flip: true,
+        };
+        assert_eq!(augment.size(100, 60), (50, 30));
+
+        // the box (10, 20, 30, 10), the left eye at (15, 22) and the right eye at (35, 22);
+        // the mirrored person has its eyes swapped in the copy
+        let mut kpts = vec![Point2::default(); 17];
+        kpts[1] = Point2::new_with_conf(50. - 35. / 2., 11., 0.9);
+        kpts[2] = Point2::new_with_conf(50. - 15. / 2., 11., 0.8);
+        let mut mask = vec![0u8; 50 * 30];
+        for y in 10..15 {
+            for x in 30..45 {
+                mask[y * 50 + x] = 255;
+            }
+        }
+        let mut y = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(30., 10., 15., 5., 3, 0.7)]),
+            Some(vec![kpts]),
+            Some(vec![mask]),
+        );
+        y.mask_size = Some((50, 30));
+        y.rotated_bboxes = Some(vec![RotatedBbox::new(35., 12., 10., 4., 0.3, 1, 0.6)]);
+
+        let y = augment.invert(y, (100, 60));
+        let b = &y.bboxes.as_ref().unwrap()[0];
+        assert!(close(b.xmin(), 10.) && close(b.ymin(), 20.));
+        assert!(close(b.width(), 30.) && close(b.height(), 10.));
+        assert_eq!((b.id(), b.confidence()), (3, 0.7));
+
+        let kpts = &y.keypoints.as_ref().unwrap()[0];
+        assert!(close(kpts[1].x(), 15.) && close(kpts[1].y(), 22.) && kpts[1].confidence() == 0.8);
+        assert!(close(kpts[2].x(), 35.) && close(kpts[2].y(), 22.) && kpts[2].confidence() == 0.9);
+        assert_eq!(kpts[0], Point2::default());
+
+        let r = &y.rotated_bboxes.as_ref().unwrap()[0];
+        assert!(close(r.cx(), 30.) && close(r.cy(), 24.) && close(r.angle(), -0.3));
+        assert!(close(r.width(), 20.) && close(r.height(), 8.));
+
+        // the mask is back at full size, away from the resampled edges
+        assert_eq!(y.mask_size, Some((100, 60)));
+        let mask = &y.masks.as_ref().unwrap()[0];
+        assert_eq!(mask.len(), 100 * 60);
+        assert_eq!(mask[25 * 100 + 25], 255);
+        assert_eq!(mask[22 * 100 + 12], 255);
+        assert_eq!(mask[25 * 100 + 50], 0);
+        assert_eq!(mask[25 * 100 + 5], 0);
+        assert_eq!(mask[40 * 100 + 25], 0);
+    }
+
+    #[test]
+    fn unflipped_copies_only_rescale() {
+        let augment = Augment {
+            scale: 2.0,
+            flip: false,
+        };
+        let mut kpts = vec![Point2::default(); 17];
+        kpts[1] = Point2::new_with_conf(30., 44., 0.9);
+        let y = YOLOResult::new(
+            None,
+            Some(vec![Bbox::new(20., 40., 60., 20., 0, 0.5)]),
+            Some(vec![kpts]),
+            None,
+        );
+        let y = augment.invert(y, (100, 60));
+        let b = &y.bboxes.as_ref().unwrap()[0];
+        assert!(close(b.xmin(), 10.) && close(b.ymin(), 20.));
+        assert!(close(b.width(), 30.) && close(b.height(), 10.));
+        let kpts = &y.keypoints.as_ref().unwrap()[0];
+        assert!(close(kpts[1].x(), 15.) && close(kpts[1].y(), 22.));
+        assert!(y.masks.is_none() && y.mask_size.is_none());
+    }
+
+    #[test]
+    fn flips_swap_left_and_right_coco_keypoints() {
+        let kpts: Vec<Point2> = (0..17).map(|i| Point2::new(i as f32, 0.)).collect();
+        let flipped: Vec<f32> = flip_keypoints(kpts).iter().map(|p| p.x()).collect();
+        let expected: Vec<f32> = COCO_FLIP_INDEX.iter().map(|&i| i as f32).collect();
+        assert_eq!(flipped, expected);
+        // nose in place, eyes, ears and the rest of the pairs swapped
+        assert_eq!(&flipped[..5], &[0., 2., 1., 4., 3.]);
+    }
+
+    #[test]
+    fn flips_need_the_coco_keypoint_layout() {
+        let tta = Tta {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(tta.check_keypoints(17).is_ok());
+        assert!(matches!(
+            tta.check_keypoints(5),
+            Err(VisionError::InvalidArgument(_))
+        ));
+        let no_flip = Tta {
+            flip: false,
+            ..tta.clone()
+        };
+        assert!(no_flip.check_keypoints(5).is_ok());
+        let disabled = Tta {
+            enabled: false,
+            ..tta
+        };
+        assert!(disabled.check_keypoints(5).is_ok());
+    }
+
+    #[test]
+    fn augments_cover_every_scale_and_flip() {
+        let tta = Tta {
+            scales: vec![1.0, 0.5],
+            ..Default::default()
+        };
+        let augments = tta.augments();
+        assert_eq!(augments.len(), 4);
+        assert_eq!(
+            augments[1],
+            Augment {
+                scale: 1.0,
+                flip: true
+            }
+        );
+        let img = DynamicImage::new_rgb8(100, 60);
+        assert_eq!(augments[3].apply(&img).width(), 50);
+        assert_eq!(augments[3].apply(&img).height(), 30);
+        let none = Tta {
+            scales: vec![],
+            flip: false,
+            ..tta
+        };
+        assert_eq!(
+            none.augments(),
+            vec![Augment {
+                scale: 1.0,
+                flip: false
+            }]
+        );
+    }
+}
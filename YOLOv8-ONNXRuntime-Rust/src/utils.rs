@@ -33,4 +33,62 @@ pub fn is_valid_image(file_path: &String) -> bool {
     };
 
     ext_valid && size_valid
+}
+
+/// Image files of `source`, sorted: the file itself, the images of a directory, or the
+/// images whose name matches a `*`/`?` pattern in the last component, e.g. `data/*.jpg`.
+pub fn find_images(source: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(source);
+    let name = path.file_name().map(|name| name.to_string_lossy());
+    let mut files = if path.is_dir() {
+        get_all_files(path)?
+    } else if path.is_file() {
+        return Ok(vec![source.to_string()]);
+    } else if let Some(pattern) = name.filter(|name| name.contains(['*', '?'])) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        get_all_files(dir)?
+            .into_iter()
+            .filter(|file| {
+                Path::new(file)
+                    .file_name()
+                    .is_some_and(|name| wildcard_match(&pattern, &name.to_string_lossy()))
+            })
+            .collect()
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No such file or directory: {}", source),
+        ));
+    };
+    files.retain(is_valid_image);
+    files.sort();
+    Ok(files)
+}
+
+/// Matches `name` against `pattern`, where `*` is any run of characters and `?` any one.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and of the name when it was reached, to backtrack to
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // let the `*` take one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
\ No newline at end of file
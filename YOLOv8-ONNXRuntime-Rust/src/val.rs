@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli::ValArgs;
+use crate::detector::Detector;
+use crate::eval::{Dataset, Evaluator, Report};
+use crate::{Args, VisionError, YOLOResult};
+
+/// Runs the `--model` model over the dataset of `val`, matches its detections to the
+/// annotations, prints the metrics and writes them to `<val.output>/metrics.json`.
+/// Images failing to decode or run are reported and count as images without detections.
+pub fn val(args: &Args, val: &ValArgs) -> Result<Report, VisionError> {
+    if args.model.is_empty() {
+        return Err(VisionError::InvalidArgument(
+            "`val` runs the `--model` model, none is set".to_string(),
+        ));
+    }
+    let mut args = args.clone();
+    args.conf = val.min_conf;
+    let mut detector = Detector::load(&args, val.kind, val.rf_detr_config.as_deref())?;
+    let kinds = if val.metrics.is_empty() {
+        detector.metrics()?
+    } else {
+        val.metrics.clone()
+    };
+    let model_names = detector.names();
+    let dataset = if val.data.ends_with(".json") {
+        let Some(images) = &val.images else {
+            return Err(VisionError::InvalidArgument(
+                "A COCO dataset needs its image directory, set `--images`".to_string(),
+            ));
+        };
+        Dataset::from_coco(Path::new(&val.data), images, &model_names)?
+    } else {
+        Dataset::from_yolo(&val.data, val.labels.as_deref(), detector.nk())?
+    };
+    if dataset.samples.is_empty() {
+        return Err(VisionError::NotFound(format!("No image in {}", val.data)));
+    }
+    std::fs::create_dir_all(&val.output)?;
+
+    // the model names, the dataset ones for classes the model does not name
+    let len = model_names.len().max(dataset.names.len());
+    let names: Vec<String> = (0..len)
+        .map(|id| match model_names.get(id) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => dataset.names.get(id).cloned().unwrap_or_default(),
+        })
+        .collect();
+
+    let total = dataset.samples.len();
+    println!("[Val]: {} images, {:?} metrics", total, kinds);
+    let mut evaluator = Evaluator::new(kinds, detector.nk());
+    let (mut done, mut failed) = (0, 0);
+    let step = (total / 10).max(1);
+    let t = Instant::now();
+    for chunk in dataset.samples.chunks(detector.max_batch()) {
+        // the images that decode still share a batch
+        let mut samples = Vec::with_capacity(chunk.len());
+        let mut xs = Vec::with_capacity(chunk.len());
+        for sample in chunk {
+            match image::open(&sample.path) {
+                Ok(x) => {
+                    samples.push(sample);
+                    xs.push(x);
+                }
+                Err(e) => {
+                    failed += 1;
+                    println!("[Val]: failed {}: {}", sample.path, e);
+                    evaluator.add(sample, &YOLOResult::default());
+                }
+            }
+        }
+        if !xs.is_empty() {
+            for (sample, y) in samples.into_iter().zip(detector.run(&xs)) {
+                let y = y.unwrap_or_else(|e| {
+                    failed += 1;
+                    println!("[Val]: failed {}: {}", sample.path, e);
+                    YOLOResult::default()
+                });
+                evaluator.add(sample, &y);
+            }
+        }
+
+        let before = done;
+        done += chunk.len();
+        if before / step != done / step || done == total {
+            println!("[Val]: {}/{} images ({:.1?})", done, total, t.elapsed());
+        }
+    }
+
+    let mut report = evaluator.finish(&names);
+    report.crowds = dataset.crowds;
+    report.print();
+    if report.crowds > 0 {
+        // pycocotools ignores the detections of crowds, its mAP is higher
+        println!(
+            "[Val]: {} crowd annotations left out, detections on them are false positives as in Ultralytics",
+            report.crowds
+        );
+    }
+    let path = val.output.join("metrics.json");
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &report)
+        .map_err(|e| VisionError::Io(e.to_string()))?;
+    println!(
+        "[Val]: {} images in {:.1?}, {} failed, metrics in {}",
+        total,
+        t.elapsed(),
+        failed,
+        path.display()
+    );
+    Ok(report)
+}
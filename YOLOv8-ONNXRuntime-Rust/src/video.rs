@@ -0,0 +1,193 @@
+use image::DynamicImage;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::cli::VideoArgs;
+use crate::detector::Detector;
+use crate::frames::{create_sink, open_source, FrameSource, VideoFrame};
+use crate::metrics::detections;
+use crate::predict;
+use crate::rest::JsonResult;
+use crate::{load_font, Args, VisionError, YOLOResult, YOLOv8};
+
+/// A line of the result file of a `video` run: a frame and its result.
+#[derive(Debug, Serialize)]
+pub struct FrameRecord {
+    pub frame_index: u64,
+    pub timestamp_ms: f64,
+    #[serde(flatten)]
+    pub result: JsonResult,
+}
+
+/// Frames of a `video` run, by outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VideoSummary {
+    pub frames: u64,
+    pub failed: u64,
+}
+
+/// Runs `model` over every frame of `source`, up to `max_batch` frames per engine run, and
+/// hands each frame and its result, with the frame index and timestamp set, to `each` in
+/// frame order. A frame failing to run only fails itself.
+pub fn run_clip(
+    model: &mut YOLOv8,
+    source: &mut dyn FrameSource,
+    mut each: impl FnMut(VideoFrame, Result<YOLOResult, VisionError>) -> Result<(), VisionError>,
+) -> Result<(), VisionError> {
+    let batch = model.max_batch().max(1) as usize;
+    loop {
+        let (frames, xs) = next_batch(source, batch)?;
+        if frames.is_empty() {
+            return Ok(());
+        }
+        for (frame, y) in frames.into_iter().zip(predict::run(model, &xs)) {
+            let y = y.map(|y| stamp(y, &frame));
+            each(frame, y)?;
+        }
+    }
+}
+
+/// Runs the `--model` model over the frames of `video.source` and writes the annotated clip
+/// to `video.output`, in the format of its extension, and a JSON line per frame with its
+/// index, timestamp and result next to it, `<output>.jsonl`. Frames failing to run are
+/// reported and kept without annotations.
+pub fn video(args: &Args, video: &VideoArgs) -> Result<VideoSummary, VisionError> {
+    if args.model.is_empty() {
+        return Err(VisionError::InvalidArgument(
+            "`video` runs the `--model` model, none is set".to_string(),
+        ));
+    }
+    let output = video
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output(&video.source));
+    if output == Path::new(&video.source) {
+        return Err(VisionError::InvalidArgument(format!(
+            "`--output` would overwrite the source {}",
+            video.source
+        )));
+    }
+    let mut source = open_source(&video.source, video.fps)?;
+    let mut detector = Detector::load(args, video.kind, video.rf_detr_config.as_deref())?;
+    let names = detector.names();
+    let font = if video.no_plot {
+        None
+    } else {
+        Some(load_font()?)
+    };
+    let mut sink = match &font {
+        Some(_) => Some(create_sink(&output, source.fps())?),
+        None => None,
+    };
+    let path = output.with_extension("jsonl");
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut results = BufWriter::new(File::create(&path)?);
+
+    let total = source.frame_count();
+    println!(
+        "[Video]: {} at {:.2} fps, {} frames",
+        video.source,
+        source.fps(),
+        total.map_or("unknown".to_string(), |total| total.to_string())
+    );
+    let step = total.map_or(100, |total| (total / 10).max(1));
+    let mut summary = VideoSummary::default();
+    let t = Instant::now();
+    loop {
+        let (frames, xs) = next_batch(source.as_mut(), detector.max_batch())?;
+        if frames.is_empty() {
+            break;
+        }
+        let ys = detector.run(&xs);
+        for (frame, y) in frames.into_iter().zip(ys) {
+            let y = match y {
+                Ok(y) => y,
+                Err(e) => {
+                    summary.failed += 1;
+                    println!("[Video]: frame {} failed: {}", frame.index, e);
+                    YOLOResult::default()
+                }
+            };
+            let y = stamp(y, &frame);
+
+            let record = FrameRecord {
+                frame_index: frame.index,
+                timestamp_ms: frame.timestamp_ms(),
+                result: JsonResult::new(&y, video.mask_format, |id| {
+                    names.get(id).filter(|name| !name.is_empty()).cloned()
+                }),
+            };
+            serde_json::to_writer(&mut results, &record)
+                .map_err(|e| VisionError::Io(e.to_string()))?;
+            results.write_all(b"\n")?;
+            if let (Some(sink), Some(font)) = (sink.as_mut(), font.as_ref()) {
+                let image = DynamicImage::ImageRgb8(detector.plot(&y, &frame.image, font)?);
+                sink.write_frame(&VideoFrame { image, ..frame })?;
+            }
+
+            summary.frames += 1;
+            if summary.frames % step == 0 {
+                println!(
+                    "[Video]: {}/{} frames, {} detections on the last ({:.1?})",
+                    summary.frames,
+                    total.map_or("?".to_string(), |total| total.to_string()),
+                    detections(&y),
+                    t.elapsed()
+                );
+            }
+        }
+    }
+
+    if let Some(sink) = sink.as_mut() {
+        sink.finish()?;
+    }
+    results.flush()?;
+    let elapsed = t.elapsed();
+    println!(
+        "[Video]: {} frames in {:.1?} ({:.1} fps), {} failed, {}results in {}",
+        summary.frames,
+        elapsed,
+        summary.frames as f64 / elapsed.as_secs_f64().max(1e-9),
+        summary.failed,
+        sink.map_or(String::new(), |_| format!("clip in {}, ", output.display())),
+        path.display()
+    );
+    Ok(summary)
+}
+
+// up to `batch` frames, with their images to run; none at the end of the clip
+fn next_batch(
+    source: &mut dyn FrameSource,
+    batch: usize,
+) -> Result<(Vec<VideoFrame>, Vec<DynamicImage>), VisionError> {
+    let mut frames = Vec::with_capacity(batch);
+    while frames.len() < batch {
+        match source.next_frame()? {
+            Some(frame) => frames.push(frame),
+            None => break,
+        }
+    }
+    let xs = frames.iter().map(|frame| frame.image.clone()).collect();
+    Ok((frames, xs))
+}
+
+fn stamp(mut y: YOLOResult, frame: &VideoFrame) -> YOLOResult {
+    y.frame_index = Some(frame.index);
+    y.timestamp_ms = Some(frame.timestamp_ms());
+    y
+}
+
+// `runs/video/<source name>`, the directory name for a pattern
+fn default_output(source: &str) -> PathBuf {
+    let path = Path::new(source);
+    let name = match path.file_name() {
+        Some(name) if !name.to_string_lossy().contains(['*', '?']) => Some(name),
+        _ => path.parent().and_then(|dir| dir.file_name()),
+    };
+    Path::new("runs/video").join(name.unwrap_or("video".as_ref()))
+}
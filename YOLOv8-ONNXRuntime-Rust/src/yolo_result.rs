@@ -1,12 +1,42 @@
 use ndarray::{Array, Axis, IxDyn};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
-#[derive(Clone, PartialEq, Default)]
+use crate::mask::{EncodedMask, MaskFormat, MASK_THRESHOLD};
+use crate::nms::NmsItem;
+use crate::tracker::Track;
+
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct YOLOResult {
-    // YOLO tasks results of an image
+    // YOLO tasks results of an image, tasks without results are left out of the serialized form
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub probs: Option<Embedding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub bboxes: Option<Vec<Bbox>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub keypoints: Option<Vec<Vec<Point2>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub masks: Option<Vec<Vec<u8>>>,
+    // (width, height) of every mask
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_size: Option<(u32, u32)>,
+    // region (x, y, width, height) of each mask within `mask_size`, for masks kept cropped,
+    // masks cover all of `mask_size` without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_rects: Option<Vec<(u32, u32, u32, u32)>>,
+    // foreground cutoff of soft masks, whose pixels are probabilities
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_cutoff: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotated_bboxes: Option<Vec<RotatedBbox>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracks: Option<Vec<Track>>,
+    // position and presentation time of the frame, for the frames of a clip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_ms: Option<f64>,
 }
 
 impl std::fmt::Debug for YOLOResult {
@@ -18,6 +48,10 @@ impl std::fmt::Debug for YOLOResult {
             )
             .field("Bboxes", &self.bboxes)
             .field("Keypoints", &self.keypoints)
+            .field("RotatedBboxes", &self.rotated_bboxes)
+            .field("Tracks", &self.tracks)
+            .field("FrameIndex", &self.frame_index)
+            .field("TimestampMs", &self.timestamp_ms)
             .field(
                 "Masks",
                 &format_args!("{:?}", self.masks().map(|masks| masks.len())),
@@ -38,6 +72,13 @@ impl YOLOResult {
             bboxes,
             keypoints,
             masks,
+            mask_size: None,
+            mask_rects: None,
+            mask_cutoff: None,
+            rotated_bboxes: None,
+            tracks: None,
+            frame_index: None,
+            timestamp_ms: None,
         }
     }
 
@@ -53,6 +94,59 @@ impl YOLOResult {
         self.masks.as_ref()
     }
 
+    pub fn mask_size(&self) -> Option<(u32, u32)> {
+        self.mask_size
+    }
+
+    /// Mask `i` over all of `mask_size`, pasted from its region if it is kept cropped.
+    pub fn full_mask(&self, i: usize) -> Option<Cow<'_, [u8]>> {
+        let mask = self.masks()?.get(i)?;
+        match self.mask_rects.as_ref().and_then(|rects| rects.get(i)) {
+            Some(&rect) => Some(Cow::Owned(paste_mask(mask, rect, self.mask_size?))),
+            None => Some(Cow::Borrowed(mask)),
+        }
+    }
+
+    /// Takes the masks out, each over all of `mask_size`.
+    pub fn take_full_masks(&mut self) -> Option<Vec<Vec<u8>>> {
+        let masks = self.masks.take()?;
+        let Some(rects) = self.mask_rects.take() else {
+            return Some(masks);
+        };
+        let size = self.mask_size?;
+        Some(
+            masks
+                .iter()
+                .zip(rects)
+                .map(|(mask, rect)| paste_mask(mask, rect, size))
+                .collect(),
+        )
+    }
+
+    /// Mask pixels above this value are foreground: the threshold of soft masks, else
+    /// `MASK_THRESHOLD`.
+    pub fn mask_cutoff(&self) -> u8 {
+        self.mask_cutoff.unwrap_or(MASK_THRESHOLD)
+    }
+
+    /// Masks in the requested format, each paired with the bbox of the same index.
+    pub fn encode_masks(&self, format: MaskFormat) -> Option<Vec<EncodedMask>> {
+        let (masks, (width, height), bboxes) = (self.masks()?, self.mask_size?, self.bboxes()?);
+        let cutoff = self.mask_cutoff();
+        // cropped masks are pasted one at a time
+        Some(
+            (0..masks.len())
+                .zip(bboxes.iter())
+                .filter_map(|(i, bbox)| {
+                    let mask = self.full_mask(i)?;
+                    Some(EncodedMask::encode(
+                        &mask, width, height, bbox, format, cutoff,
+                    ))
+                })
+                .collect(),
+        )
+    }
+
     pub fn bboxes(&self) -> Option<&Vec<Bbox>> {
         self.bboxes.as_ref()
     }
@@ -60,9 +154,41 @@ impl YOLOResult {
     pub fn bboxes_mut(&mut self) -> Option<&mut Vec<Bbox>> {
         self.bboxes.as_mut()
     }
+
+    pub fn rotated_bboxes(&self) -> Option<&Vec<RotatedBbox>> {
+        self.rotated_bboxes.as_ref()
+    }
+
+    pub fn tracks(&self) -> Option<&Vec<Track>> {
+        self.tracks.as_ref()
+    }
+
+    pub fn frame_index(&self) -> Option<u64> {
+        self.frame_index
+    }
+
+    pub fn timestamp_ms(&self) -> Option<f64> {
+        self.timestamp_ms
+    }
+}
+
+// a `width` x `height` mask with the row-major `mask` of `rect` pasted in, 0 elsewhere
+fn paste_mask(
+    mask: &[u8],
+    (x0, y0, w, h): (u32, u32, u32, u32),
+    (width, height): (u32, u32),
+) -> Vec<u8> {
+    let mut full = vec![0u8; (width * height) as usize];
+    if w > 0 {
+        for (row, src) in mask.chunks_exact(w as usize).take(h as usize).enumerate() {
+            let start = (y0 as usize + row) * width as usize + x0 as usize;
+            full[start..start + w as usize].copy_from_slice(src);
+        }
+    }
+    full
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct Point2 {
     // A point2d with x, y, conf
     x: f32,
@@ -96,7 +222,7 @@ impl Point2 {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct Embedding {
     // An float32 n-dims tensor
     data: Array<f32, IxDyn>,
@@ -118,7 +244,7 @@ impl Embedding {
             .enumerate()
             .map(|(a, b)| (a, *b))
             .collect::<Vec<_>>();
-        probs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        probs.sort_by(|a, b| b.1.total_cmp(&a.1));
         let mut topk = Vec::new();
         for &(id, confidence) in probs.iter().take(k) {
             topk.push((id, confidence));
@@ -136,7 +262,7 @@ impl Embedding {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct Bbox {
     // a bounding box around an object
     xmin: f32,
@@ -213,6 +339,10 @@ impl Bbox {
         self.confidence
     }
 
+    pub fn set_confidence(&mut self, confidence: f32) {
+        self.confidence = confidence;
+    }
+
     pub fn area(&self) -> f32 {
         self.width * self.height
     }
@@ -233,3 +363,242 @@ impl Bbox {
         self.intersection_area(another) / self.union(another)
     }
 }
+
+impl NmsItem for Bbox {
+    fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    fn set_confidence(&mut self, confidence: f32) {
+        self.confidence = confidence;
+    }
+
+    fn class_id(&self) -> i64 {
+        self.id as i64
+    }
+
+    fn corners(&self) -> [f32; 4] {
+        [self.xmin, self.ymin, self.xmax(), self.ymax()]
+    }
+
+    fn set_corners(&mut self, [xmin, ymin, xmax, ymax]: [f32; 4]) {
+        self.xmin = xmin;
+        self.ymin = ymin;
+        self.width = xmax - xmin;
+        self.height = ymax - ymin;
+    }
+
+    // keeps the +1 pixel convention of `Bbox::iou`
+    fn iou(&self, other: &Self) -> f32 {
+        Bbox::iou(self, other)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RotatedBbox {
+    // an oriented bounding box, angle in radians (clockwise in image coords)
+    cx: f32,
+    cy: f32,
+    width: f32,
+    height: f32,
+    angle: f32,
+    id: usize,
+    confidence: f32,
+}
+
+impl RotatedBbox {
+    pub fn new(
+        cx: f32,
+        cy: f32,
+        width: f32,
+        height: f32,
+        angle: f32,
+        id: usize,
+        confidence: f32,
+    ) -> Self {
+        Self {
+            cx,
+            cy,
+            width,
+            height,
+            angle,
+            id,
+            confidence,
+        }
+    }
+
+    pub fn cx(&self) -> f32 {
+        self.cx
+    }
+
+    pub fn cy(&self) -> f32 {
+        self.cy
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn cxcy(&self) -> Point2 {
+        Point2::new(self.cx, self.cy)
+    }
+
+    pub fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    pub fn vertices(&self) -> [Point2; 4] {
+        // corners in order: (-w, -h), (+w, -h), (+w, +h), (-w, +h) rotated around the center
+        let (sin, cos) = self.angle.sin_cos();
+        let (hw, hh) = (self.width / 2., self.height / 2.);
+        [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)].map(|(dx, dy)| {
+            Point2::new(
+                self.cx + dx * cos - dy * sin,
+                self.cy + dx * sin + dy * cos,
+            )
+        })
+    }
+
+    pub fn bbox(&self) -> Bbox {
+        // axis-aligned box enclosing the rotated one
+        let vertices = self.vertices();
+        let xmin = vertices.iter().map(|p| p.x()).fold(f32::INFINITY, f32::min);
+        let ymin = vertices.iter().map(|p| p.y()).fold(f32::INFINITY, f32::min);
+        let xmax = vertices.iter().map(|p| p.x()).fold(f32::NEG_INFINITY, f32::max);
+        let ymax = vertices.iter().map(|p| p.y()).fold(f32::NEG_INFINITY, f32::max);
+        Bbox::new(xmin, ymin, xmax - xmin, ymax - ymin, self.id, self.confidence)
+    }
+
+    pub fn intersection_area(&self, another: &RotatedBbox) -> f32 {
+        // a degenerate box covers nothing, and its edges clip nothing either
+        if !(self.area() > 0. && another.area() > 0.) {
+            return 0.;
+        }
+        // Sutherland-Hodgman: clip self's polygon by each edge of another (both convex)
+        let mut polygon: Vec<Point2> = self.vertices().to_vec();
+        let clip = another.vertices();
+        for i in 0..clip.len() {
+            if polygon.is_empty() {
+                break;
+            }
+            let (a, b) = (&clip[i], &clip[(i + 1) % clip.len()]);
+            let inside = |p: &Point2| {
+                (b.x() - a.x()) * (p.y() - a.y()) - (b.y() - a.y()) * (p.x() - a.x()) >= 0.
+            };
+            let intersect = |p: &Point2, q: &Point2| {
+                let (dx, dy) = (q.x() - p.x(), q.y() - p.y());
+                let denom = (b.x() - a.x()) * dy - (b.y() - a.y()) * dx;
+                let t = ((b.y() - a.y()) * (p.x() - a.x()) - (b.x() - a.x()) * (p.y() - a.y()))
+                    / denom;
+                Point2::new(p.x() + t * dx, p.y() + t * dy)
+            };
+            let input = std::mem::take(&mut polygon);
+            for j in 0..input.len() {
+                let (p, q) = (&input[j], &input[(j + 1) % input.len()]);
+                match (inside(p), inside(q)) {
+                    (true, true) => polygon.push(q.clone()),
+                    (true, false) => polygon.push(intersect(p, q)),
+                    (false, true) => {
+                        polygon.push(intersect(p, q));
+                        polygon.push(q.clone());
+                    }
+                    (false, false) => {}
+                }
+            }
+        }
+
+        // shoelace
+        let n = polygon.len();
+        if n < 3 {
+            return 0.;
+        }
+        let twice_area: f32 = (0..n)
+            .map(|i| {
+                let (p, q) = (&polygon[i], &polygon[(i + 1) % n]);
+                p.x() * q.y() - q.x() * p.y()
+            })
+            .sum();
+        twice_area.abs() / 2.
+    }
+
+    pub fn union(&self, another: &RotatedBbox) -> f32 {
+        self.area() + another.area() - self.intersection_area(another)
+    }
+
+    pub fn iou(&self, another: &RotatedBbox) -> f32 {
+        let union = self.union(another);
+        if union <= 0. {
+            0.
+        } else {
+            self.intersection_area(another) / union
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+    fn rotated(cx: f32, cy: f32, width: f32, height: f32, angle: f32) -> RotatedBbox {
+        RotatedBbox::new(cx, cy, width, height, angle, 0, 1.)
+    }
+
+    #[test]
+    fn rotated_iou_of_identical_boxes_is_one() {
+        let a = rotated(10., 20., 8., 4., 0.3);
+        assert!((a.iou(&a.clone()) - 1.).abs() < 1e-5);
+        assert!((a.intersection_area(&a) - 32.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rotated_iou_of_disjoint_boxes_is_zero() {
+        let (a, b) = (rotated(0., 0., 2., 2., 0.2), rotated(10., 0., 2., 2., 1.));
+        assert_eq!(a.intersection_area(&b), 0.);
+        assert_eq!(a.iou(&b), 0.);
+    }
+
+    #[test]
+    fn rotated_iou_of_square_and_diamond_is_closed_form() {
+        // the overlap of a unit square and itself turned 45 degrees is a regular octagon of
+        // area 2(sqrt(2) - 1), and the IoU 1/sqrt(2)
+        let square = rotated(5., 5., 1., 1., 0.);
+        let diamond = rotated(5., 5., 1., 1., FRAC_PI_4);
+        let octagon = 2. * (SQRT_2 - 1.);
+        assert!((square.intersection_area(&diamond) - octagon).abs() < 1e-5);
+        assert!((diamond.intersection_area(&square) - octagon).abs() < 1e-5);
+        assert!((square.iou(&diamond) - 1. / SQRT_2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rotated_iou_of_degenerate_boxes_is_zero() {
+        let a = rotated(0., 0., 4., 4., 0.5);
+        for b in [
+            rotated(0., 0., 0., 4., 0.5),
+            rotated(0., 0., 4., 0., 0.),
+            rotated(0., 0., 0., 0., 0.),
+        ] {
+            for iou in [a.iou(&b), b.iou(&a), b.iou(&b)] {
+                assert_eq!(iou, 0.);
+            }
+            assert_eq!(a.intersection_area(&b), 0.);
+            assert_eq!(b.intersection_area(&a), 0.);
+        }
+    }
+}
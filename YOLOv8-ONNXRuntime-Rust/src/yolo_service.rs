@@ -1,39 +1,198 @@
-use tokio::sync::Mutex;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use std::fmt;
 use image::DynamicImage;
-use tonic::{Request, Response, Status, async_trait};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming, async_trait};
+use tracing::{info, info_span, Instrument, Span};
 
 use crate::{
-    YOLOv8,
+    Args, Batcher, Pending, RunOptions, YOLOResult, YOLOv8,
     ProcessImagesRequest, ProcessImagesResponse,
+    StreamFramesRequest, StreamFramesResponse, StreamConfig,
+    ProtoInferenceParams, ProtoNmsConfig, ProtoSlicingConfig, ProtoTrackerType, TrackerKind,
+    stream_frames_request::Payload,
     yolo_service_server::YoloService,
-    convert_yolo_result
+    convert_inference_params, convert_mask_format, convert_nms_config, convert_slicing_config,
+    convert_yolo_result, VisionError, Watcher,
 };
+use crate::metrics::METRICS;
+
+/// Frames queued per stream when the client does not set `max_in_flight`.
+const DEFAULT_MAX_IN_FLIGHT: usize = 4;
 
 /// The YOLO gRPC service.
+#[derive(Debug, Clone)]
 pub struct MyYoloService {
-    model: Mutex<YOLOv8>,
+    batcher: Batcher,
+    // model label of the metrics
+    name: String,
+    // class names by id
+    names: Vec<String>,
+    // model settings, the base of per-request overrides
+    options: RunOptions,
+    // where the models were loaded from, `None` when built by the caller
+    args: Option<Arc<Args>>,
+    reloading: Arc<tokio::sync::Mutex<()>>,
 }
 
-// Custom Debug implementation that doesn't try to print the inner YOLOv8 model.
-impl fmt::Debug for MyYoloService {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("MyYoloService")
-            .field("model", &"YOLOv8 (not Debug)")
-            .finish()
+impl MyYoloService {
+    /// Creates a new service instance with the provided models, one per ORT session.
+    /// Images are batched across requests for at most `max_wait`.
+    pub fn new(models: Vec<YOLOv8>, max_wait: Duration) -> Result<Self, VisionError> {
+        let options = models.first().map(|model| model.options()).unwrap_or_default();
+        let name = models.first().map_or_else(String::new, |model| model.name().to_string());
+        let names = models.first().map(|model| model.names().clone()).unwrap_or_default();
+        Ok(Self {
+            batcher: Batcher::new(models, max_wait)?,
+            name,
+            names,
+            options,
+            args: None,
+            reloading: Arc::new(tokio::sync::Mutex::new(())),
+        })
     }
-}
 
-impl MyYoloService {
-    /// Creates a new service instance with the provided model.
-    pub fn new(model: YOLOv8) -> Self {
-        Self {
-            model: Mutex::new(model),
+    /// Loads `args.sessions` models of `args.model`.
+    pub fn load(args: &Args) -> Result<Self, VisionError> {
+        let models = load_models(args)?;
+        models[0].summary();
+        let mut service = Self::new(models, Duration::from_millis(args.max_wait_ms))?;
+        service.args = Some(Arc::new(args.clone()));
+        Ok(service)
+    }
+
+    /// Loads the model file again and swaps it in once warmed up, images already queued
+    /// finish on the old models. On failure the old models keep serving.
+    pub async fn reload(&self) -> Result<(), VisionError> {
+        let Some(args) = self.args.clone() else {
+            return Err(VisionError::Unsupported(
+                "The service was not loaded from a model path".to_string(),
+            ));
+        };
+        let _reloading = self.reloading.lock().await;
+        let models = tokio::task::spawn_blocking(move || load_models(&args))
+            .await
+            .map_err(|e| VisionError::Internal(format!("Model loading failed: {}", e)))??;
+        self.batcher.swap(models)
+    }
+
+    /// Reloads the model whenever its file changes, if `watch_ms` is set.
+    pub fn watch(&self) -> Option<Watcher> {
+        let args = self.args.as_ref().filter(|args| args.watch_ms > 0)?;
+        let service = self.clone();
+        Some(Watcher::spawn(
+            PathBuf::from(&args.model),
+            Duration::from_millis(args.watch_ms),
+            move || {
+                let service = service.clone();
+                async move { service.reload().await }
+            },
+        ))
+    }
+
+    /// Model label of the metrics.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Class names by id, empty if the model has no metadata.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Runs decoded images with the overrides of a request.
+    pub async fn predict(
+        &self,
+        xs: Vec<DynamicImage>,
+        nms: Option<&ProtoNmsConfig>,
+        slicing: Option<&ProtoSlicingConfig>,
+        params: Option<&ProtoInferenceParams>,
+    ) -> Result<Vec<YOLOResult>, VisionError> {
+        let options = self.request_options(nms, slicing, params)?;
+        self.batcher.submit_all(xs, options).await
+    }
+
+    /// Settings of a request, `None` keeps the model settings.
+    fn request_options(
+        &self,
+        nms: Option<&ProtoNmsConfig>,
+        slicing: Option<&ProtoSlicingConfig>,
+        params: Option<&ProtoInferenceParams>,
+    ) -> Result<Option<RunOptions>, VisionError> {
+        if nms.is_none() && slicing.is_none() && params.is_none() {
+            return Ok(None);
+        }
+        let mut options = self.options.clone();
+        if let Some(nms) = nms {
+            options.nms = convert_nms_config(options.nms, nms)?;
+        }
+        if let Some(slicing) = slicing {
+            options.slicing = convert_slicing_config(options.slicing, slicing)?;
+        }
+        // after the NMS config, its iou and max_det take precedence
+        if let Some(params) = params {
+            options = convert_inference_params(options, params)?;
         }
+        Ok(Some(options))
+    }
+}
+
+/// `YOLOService` answering `UNAVAILABLE` until its model is set, so the server can
+/// report health while the model loads.
+#[derive(Debug, Clone, Default)]
+pub struct LazyYoloService(Arc<OnceLock<MyYoloService>>);
+
+impl LazyYoloService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts serving `service`, only the first call has an effect.
+    pub fn set(&self, service: MyYoloService) {
+        let _ = self.0.set(service);
+    }
+
+    pub(crate) fn get(&self) -> Result<&MyYoloService, VisionError> {
+        self.0
+            .get()
+            .ok_or_else(|| VisionError::Unavailable("The model is still loading".to_string()))
     }
 }
 
+#[async_trait]
+impl YoloService for LazyYoloService {
+    async fn process_images(
+        &self,
+        request: Request<ProcessImagesRequest>,
+    ) -> Result<Response<ProcessImagesResponse>, Status> {
+        self.get()?.process_images(request).await
+    }
+
+    type StreamFramesStream = <MyYoloService as YoloService>::StreamFramesStream;
+
+    async fn stream_frames(
+        &self,
+        request: Request<Streaming<StreamFramesRequest>>,
+    ) -> Result<Response<Self::StreamFramesStream>, Status> {
+        self.get()?.stream_frames(request).await
+    }
+}
+
+fn load_models(args: &Args) -> Result<Vec<YOLOv8>, VisionError> {
+    // one model (ORT session) per worker thread, each warmed up before serving
+    let mut models = Vec::with_capacity(args.sessions.max(1));
+    for _ in 0..args.sessions.max(1) {
+        let mut model = YOLOv8::new(args.clone())?;
+        model.warmup()?;
+        models.push(model);
+    }
+    Ok(models)
+}
+
 #[async_trait]
 impl YoloService for MyYoloService {
     async fn process_images(
@@ -41,29 +200,157 @@ impl YoloService for MyYoloService {
         request: Request<ProcessImagesRequest>,
     ) -> Result<Response<ProcessImagesResponse>, Status> {
         let req = request.into_inner();
-        let mut results = Vec::new();
+        let result = async {
+            let mask_format = convert_mask_format(req.mask_format)?;
 
-        // Process each image in the request.
-        for image_data in req.images {
-            let dynamic_image: DynamicImage = image::load_from_memory(&image_data)
-                .map_err(|e| Status::invalid_argument(format!("Failed to decode image: {}", e)))?;
+            // Decode every image in the request up front.
+            let mut xs = Vec::with_capacity(req.images.len());
+            for image_data in &req.images {
+                let t_decode = Instant::now();
+                let dynamic_image: DynamicImage = info_span!("decode", bytes = image_data.len())
+                    .in_scope(|| image::load_from_memory(image_data))
+                    .map_err(|e| {
+                        VisionError::InvalidArgument(format!("Failed to decode image: {}", e))
+                    })?;
+                METRICS.observe_stage(&self.name, "decode", t_decode.elapsed());
+                xs.push(dynamic_image);
+            }
 
-            let xs = vec![dynamic_image];
+            // Queue them together so they share batches with each other and with concurrent requests.
+            let ys = self
+                .predict(xs, req.nms.as_ref(), req.slicing.as_ref(), req.params.as_ref())
+                .await?;
 
-            // Lock the model for exclusive mutable access.
-            let ys = {
-                let mut model = self.model.lock().await;
-                model.run(&xs).map_err(|e| {
-                    Status::internal(format!("Model run failed: {}", e))
-                })?
+            let results = ys.iter().map(|y| convert_yolo_result(y, mask_format)).collect();
+            let response = ProcessImagesResponse { results };
+            Ok::<_, Status>(Response::new(response))
+        }
+        .await;
+        METRICS.observe_request(&self.name, "ProcessImages", &result);
+        result
+    }
+
+    type StreamFramesStream =
+        Pin<Box<dyn Stream<Item = Result<StreamFramesResponse, Status>> + Send + 'static>>;
+
+    async fn stream_frames(
+        &self,
+        request: Request<Streaming<StreamFramesRequest>>,
+    ) -> Result<Response<Self::StreamFramesStream>, Status> {
+        let mut inbound = request.into_inner();
+        let result = async {
+            // The first message configures the stream.
+            let config = match inbound.message().await? {
+                Some(StreamFramesRequest { payload: Some(Payload::Config(config)) }) => config,
+                _ => {
+                    return Err(Status::invalid_argument(
+                        "The first message of a frame stream must be a StreamConfig",
+                    ))
+                }
+            };
+            let options = self.request_options(config.nms.as_ref(), config.slicing.as_ref(), config.params.as_ref())?;
+            let mask_format = convert_mask_format(config.mask_format)?;
+            let StreamConfig { stream_id, max_in_flight, tracker, .. } = config;
+            let max_in_flight = match max_in_flight {
+                0 => DEFAULT_MAX_IN_FLIGHT,
+                n => n as usize,
+            };
+            // Each stream owns its tracker, fed in frame order by the writer.
+            let mut tracker = match ProtoTrackerType::from_i32(tracker) {
+                Some(ProtoTrackerType::TrackerNone) => None,
+                Some(ProtoTrackerType::TrackerSort) => Some(TrackerKind::Sort.build()),
+                Some(ProtoTrackerType::TrackerByteTrack) => Some(TrackerKind::ByteTrack.build()),
+                None => {
+                    return Err(Status::invalid_argument(format!("Unknown tracker: {}", tracker)))
+                }
             };
 
-            let result = convert_yolo_result(&ys[0]);
+            // Reader: decode and queue frames, bounded by `max_in_flight`.
+            // Writer: await results in queue order, so responses keep the frame order.
+            let (pending_tx, mut pending_rx) =
+                mpsc::channel::<Result<(u64, i64, Pending), Status>>(max_in_flight);
+            let (out_tx, out_rx) = mpsc::channel(max_in_flight);
 
-            results.push(result);
-        }
+            let batcher = self.batcher.clone();
+            let name = self.name.clone();
+            tokio::spawn(async move {
+                loop {
+                    let frame = match inbound.message().await {
+                        Ok(Some(StreamFramesRequest { payload: Some(Payload::Frame(frame)) })) => frame,
+                        Ok(Some(_)) => {
+                            let status = Status::invalid_argument(
+                                "Only frames may follow the StreamConfig message",
+                            );
+                            let _ = pending_tx.send(Err(status)).await;
+                            break;
+                        }
+                        Ok(None) => break,
+                        Err(status) => {
+                            let _ = pending_tx.send(Err(status)).await;
+                            break;
+                        }
+                    };
 
-        let response = ProcessImagesResponse { results };
-        Ok(Response::new(response))
+                    // one span per frame, the parent of the batch it runs in
+                    let queued = {
+                        let _frame = info_span!("frame", sequence = frame.sequence).entered();
+                        let t_decode = Instant::now();
+                        let decoded = info_span!("decode", bytes = frame.image.len())
+                            .in_scope(|| image::load_from_memory(&frame.image))
+                            .inspect(|_| METRICS.observe_stage(&name, "decode", t_decode.elapsed()));
+                        match decoded {
+                            Ok(image) => match batcher.enqueue(image, options.clone()) {
+                                Ok(pending) => Ok((frame.sequence, frame.timestamp_ms, pending)),
+                                Err(e) => Err(Status::from(e)),
+                            },
+                            Err(e) => Err(Status::invalid_argument(format!(
+                                "Failed to decode frame {}: {}",
+                                frame.sequence, e
+                            ))),
+                        }
+                    };
+                    let failed = queued.is_err();
+                    if pending_tx.send(queued).await.is_err() || failed {
+                        break;
+                    }
+                }
+            }.instrument(Span::current()));
+
+            tokio::spawn(async move {
+                let mut frames = 0usize;
+                while let Some(queued) = pending_rx.recv().await {
+                    let response = match queued {
+                        Ok((sequence, timestamp_ms, pending)) => match pending.wait().await {
+                            Ok(mut y) => {
+                                if let Some(tracker) = tracker.as_mut() {
+                                    let detections = y.bboxes().cloned().unwrap_or_default();
+                                    y.tracks = Some(tracker.update(&detections));
+                                }
+                                Ok(StreamFramesResponse {
+                                    sequence,
+                                    timestamp_ms,
+                                    result: Some(convert_yolo_result(&y, mask_format)),
+                                })
+                            }
+                            Err(e) => Err(Status::from(e)),
+                        },
+                        Err(status) => Err(status),
+                    };
+                    let failed = response.is_err();
+                    if out_tx.send(response).await.is_err() || failed {
+                        break;
+                    }
+                    frames += 1;
+                }
+                if !stream_id.is_empty() {
+                    info!(stream = %stream_id, frames, "Stream closed");
+                }
+            }.instrument(Span::current()));
+
+            Ok::<_, Status>(Response::new(Box::pin(ReceiverStream::new(out_rx)) as Self::StreamFramesStream))
+        }
+        .await;
+        METRICS.observe_request(&self.name, "StreamFrames", &result);
+        result
     }
 }
@@ -82,7 +82,11 @@ def run():
     image_bytes = encoded_image.tobytes()
 
     # Create the request with a list of images (here, just one).
-    request = yolo_pb2.ProcessImagesRequest(images=[image_bytes])
+    # Masks come back as COCO RLE instead of full-resolution bitmaps.
+    request = yolo_pb2.ProcessImagesRequest(
+        images=[image_bytes],
+        mask_format=yolo_pb2.MASK_RLE,
+    )
     
     # Call the ProcessImages RPC.
     response = stub.ProcessImages(request)
@@ -106,7 +110,11 @@ def run():
                 for pt in kp_set.points:
                     print(f"  Point: x={pt.x}, y={pt.y}, confidence={pt.confidence}")
         if result.masks:
-            print("Masks (byte lengths):", [len(mask) for mask in result.masks])
+            print(f"Masks ({result.mask_width}x{result.mask_height}, byte lengths):",
+                  [len(mask) for mask in result.masks])
+        if result.encoded_masks:
+            print("RLE masks (run counts):",
+                  [len(mask.rle.counts) for mask in result.encoded_masks])
         image, _ = draw_bboxes(frame=image, bboxes=result.bboxes)
         cv2.imshow("Video Stream", image)
 
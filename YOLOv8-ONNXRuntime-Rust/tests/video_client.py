@@ -65,6 +65,43 @@ def draw_bboxes(frame, bboxes, class_names = [], class_colors={}):
 
     return frame, class_colors
 
+def frame_requests(cap, frames):
+    """
+    Yields the stream config, then one Frame message per decoded video frame.
+    Sent frames are kept in `frames` so results can be drawn on them.
+    """
+    yield yolo_pb2.StreamFramesRequest(
+        config=yolo_pb2.StreamConfig(
+            stream_id="video_client",
+            max_in_flight=4,
+            tracker=yolo_pb2.TRACKER_BYTE_TRACK,
+        )
+    )
+
+    sequence = 0
+    fps = cap.get(cv2.CAP_PROP_FPS) or 30.0
+    while True:
+        ret, frame = cap.read()
+        if not ret:
+            break
+
+        # Encode the frame as JPEG
+        success, encoded_image = cv2.imencode('.jpg', frame)
+        if not success:
+            print("Warning: Failed to encode frame, skipping.")
+            continue
+
+        frames[sequence] = frame
+        yield yolo_pb2.StreamFramesRequest(
+            frame=yolo_pb2.Frame(
+                sequence=sequence,
+                timestamp_ms=int(sequence * 1000 / fps),
+                image=encoded_image.tobytes(),
+            )
+        )
+        sequence += 1
+
+
 def run():
     # Create a channel and stub to connect to the server.
     channel = grpc.insecure_channel('localhost:50051')
@@ -80,55 +117,48 @@ def run():
         print("Error: Could not open video.")
         exit()
 
-    frames_bytes = []
+    frames = {}
     class_colors = {}
 
-    while True:
-        ret, frame = cap.read()
-        if not ret:
-            break
+    # One bidirectional stream for the whole video; results come back in frame order.
+    responses = stub.StreamFrames(frame_requests(cap, frames))
+    for response in responses:
+        frame = frames.pop(response.sequence)
+        result = response.result
 
-        # Encode the frame as JPEG
-        success, encoded_image = cv2.imencode('.jpg', frame)
-        if not success:
-            print("Warning: Failed to encode frame, skipping.")
-            continue
-
-        # Convert the encoded image to bytes
-        frame_bytes = encoded_image.tobytes()
-    
-        # Create the request with a list of images (here, just one).
-        request = yolo_pb2.ProcessImagesRequest(images=[frame_bytes])
-        
-        # Call the ProcessImages RPC.
-        response = stub.ProcessImages(request)
-        
         # Print the results.
-        print("Received YOLO results:")
-        for i, result in enumerate(response.results):
-            print(f"\nResult for image {i}:")
-            if result.HasField("probs"):
-                print("Embedding data:", result.probs.data)
-                print("Embedding shape:", result.probs.shape)
-            if result.bboxes:
-                print("Bounding boxes:")
-                frame, class_colors = draw_bboxes(frame=frame, bboxes=result.bboxes, class_colors=class_colors)
-                for bbox in result.bboxes:
-                    print(f"  Bbox: xmin={bbox.xmin}, ymin={bbox.ymin}, "
-                        f"width={bbox.width}, height={bbox.height}, "
-                        f"id={bbox.id}, confidence={bbox.confidence}")
-            if result.keypoints:
-                print("Keypoints:")
-                for kp_set in result.keypoints:
-                    for pt in kp_set.points:
-                        print(f"  Point: x={pt.x}, y={pt.y}, confidence={pt.confidence}")
-            if result.masks:
-                print("Masks (byte lengths):", [len(mask) for mask in result.masks])
-            cv2.imshow("Video Stream", frame)
-
-            # Wait for 30ms and check if 'q' is pressed to quit.
-            if cv2.waitKey(30) & 0xFF == ord('q'):
-                break
+        print(f"\nResult for frame {response.sequence} ({response.timestamp_ms} ms):")
+        if result.HasField("probs"):
+            print("Embedding data:", result.probs.data)
+            print("Embedding shape:", result.probs.shape)
+        if result.bboxes:
+            print("Bounding boxes:")
+            frame, class_colors = draw_bboxes(frame=frame, bboxes=result.bboxes, class_colors=class_colors)
+            for bbox in result.bboxes:
+                print(f"  Bbox: xmin={bbox.xmin}, ymin={bbox.ymin}, "
+                    f"width={bbox.width}, height={bbox.height}, "
+                    f"id={bbox.id}, confidence={bbox.confidence}")
+        if result.keypoints:
+            print("Keypoints:")
+            for kp_set in result.keypoints:
+                for pt in kp_set.points:
+                    print(f"  Point: x={pt.x}, y={pt.y}, confidence={pt.confidence}")
+        if result.tracks:
+            print("Tracks:")
+            for track in result.tracks:
+                points = [(int(p.x), int(p.y)) for p in track.trajectory]
+                for p0, p1 in zip(points, points[1:]):
+                    cv2.line(frame, p0, p1, (0, 255, 255), 2)
+                print(f"  Track {track.track_id}: age={track.age}, "
+                    f"class={track.bbox.id}, trajectory={len(points)} points")
+        if result.masks:
+            print("Masks (byte lengths):", [len(mask) for mask in result.masks])
+        cv2.imshow("Video Stream", frame)
+
+        # Wait for 30ms and check if 'q' is pressed to quit.
+        if cv2.waitKey(30) & 0xFF == ord('q'):
+            responses.cancel()
+            break
     # Release resources.
     cap.release()
     cv2.destroyAllWindows()